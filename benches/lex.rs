@@ -0,0 +1,45 @@
+//! Measures how fast the lexer tokenizes a large generated source, and how much memory it
+//! holds on to besides the source itself. Run with `cargo bench --bench lex`.
+
+use std::{hint::black_box, path::PathBuf, rc::Rc, time::Instant};
+
+use istind::analyze::lex::Lexer;
+
+const FUNCTIONS: usize = 50_000;
+const RUNS: u32 = 10;
+
+fn main() {
+    let source: String = (0..FUNCTIONS)
+        .map(|i| {
+            format!(
+                "// function number {i}, with ünïcode in its comment\n\
+                 fn f{i}(x: i64) -> i64 {{\n    y := x * {i} + 3;\n    return y;\n}}\n"
+            )
+        })
+        .collect();
+    let path = Rc::new(PathBuf::from("bench"));
+
+    let mut tokens = 0;
+    let start = Instant::now();
+    for _ in 0..RUNS {
+        let lexer = Lexer::new(path.clone(), &source).unwrap();
+        tokens = 0;
+        for token in lexer {
+            black_box(token.unwrap());
+            tokens += 1;
+        }
+    }
+    let elapsed = start.elapsed() / RUNS;
+
+    let mib = source.len() as f64 / (1024.0 * 1024.0);
+    println!("source:     {:.2} MiB, {} tokens", mib, tokens);
+    println!(
+        "time:       {:.2?} per run, {:.1} MiB/s",
+        elapsed,
+        mib / elapsed.as_secs_f64()
+    );
+    println!(
+        "lexer size: {} bytes, borrowing the source",
+        size_of::<Lexer>()
+    );
+}
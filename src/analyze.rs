@@ -1,12 +1,17 @@
 use std::{
+    borrow::Cow,
+    env,
     fmt::{self, Display},
-    fs, io,
+    fs,
+    io::{self, IsTerminal},
     ops::{Deref, Range},
     path::PathBuf,
     rc::Rc,
 };
 
-use ariadne::{Cache, ColorGenerator, Label, Report, ReportBuilder, ReportKind, Source};
+use ariadne::{
+    Cache, ColorGenerator, Config, IndexType, Label, Report, ReportBuilder, ReportKind, Source,
+};
 
 pub mod ast;
 pub mod lex;
@@ -14,12 +19,26 @@ pub mod semantics;
 
 pub type Span = (Rc<PathBuf>, Range<usize>);
 
+/// Columns a tab advances to the next multiple of, unless configured otherwise.
+pub const DEFAULT_TAB_WIDTH: usize = 4;
+
 #[derive(Debug)]
-pub struct Error(Box<Report<'static, Span>>);
+pub struct Error {
+    report: Box<Report<'static, Span>>,
+    code: Option<ErrorCode>,
+}
 
 impl Error {
     pub fn new(report: Report<'static, Span>) -> Self {
-        Self(Box::new(report))
+        Self {
+            report: Box::new(report),
+            code: None,
+        }
+    }
+
+    /// The code the error was reported with, if any.
+    pub fn code(&self) -> Option<ErrorCode> {
+        self.code
     }
 }
 
@@ -27,18 +46,21 @@ impl Deref for Error {
     type Target = Report<'static, Span>;
 
     fn deref(&self) -> &Self::Target {
-        self.0.as_ref()
+        self.report.as_ref()
     }
 }
 
 pub struct ErrorBuilder<'c> {
     builder: ReportBuilder<'static, Span>,
+    code: Option<ErrorCode>,
+    is_warning: bool,
     context: &'c mut ErrorContext,
 }
 
 impl<'c> ErrorBuilder<'c> {
     pub fn with_code(mut self, code: ErrorCode) -> Self {
         self.builder = self.builder.with_code(code);
+        self.code = Some(code);
         self
     }
 
@@ -58,57 +80,174 @@ impl<'c> ErrorBuilder<'c> {
         self
     }
 
+    pub fn with_help(mut self, help: impl ToString) -> Self {
+        self.builder.add_help(help.to_string());
+        self
+    }
+
     pub fn report(self) {
-        let error = Error::new(self.builder.finish());
-        self.context.errors.push(error);
+        let error = Error {
+            code: self.code,
+            ..Error::new(self.builder.finish())
+        };
+        if self.is_warning {
+            self.context.warnings.push(error);
+        } else {
+            self.context.errors.push(error);
+        }
     }
 
     pub fn finish(self) -> Error {
-        Error::new(self.builder.finish())
+        Error {
+            code: self.code,
+            ..Error::new(self.builder.finish())
+        }
+    }
+}
+
+/// Whether diagnostics are printed with colors.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    /// Use colors unless `NO_COLOR` is set or stderr is not a terminal.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                env::var_os("NO_COLOR").is_none_or(|v| v.is_empty()) && io::stderr().is_terminal()
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Wording of the diagnostics that are reported from many places. Embedders can replace any of
+/// these, for example to translate them, and keep the defaults for the rest.
+#[derive(Debug, Clone)]
+pub struct Messages {
+    pub unexpected_token: Cow<'static, str>,
+    pub unexpected_eof: Cow<'static, str>,
+    /// Label on the end of the source.
+    pub unexpected_eof_label: Cow<'static, str>,
+    pub missing_semicolon: Cow<'static, str>,
+    /// Label on where the semicolon should go.
+    pub missing_semicolon_label: Cow<'static, str>,
+    pub undeclared_variable: Cow<'static, str>,
+    /// Label on the use of the variable.
+    pub undeclared_variable_label: Cow<'static, str>,
+}
+
+impl Default for Messages {
+    fn default() -> Self {
+        Self {
+            unexpected_token: Cow::Borrowed("unexpected token"),
+            unexpected_eof: Cow::Borrowed("unexpected end of file"),
+            unexpected_eof_label: Cow::Borrowed("why stop here??"),
+            missing_semicolon: Cow::Borrowed("expected semicolon"),
+            missing_semicolon_label: Cow::Borrowed("insert the semicolon dummy"),
+            undeclared_variable: Cow::Borrowed("undeclared variable"),
+            undeclared_variable_label: Cow::Borrowed("this guy doesn't exist"),
+        }
     }
 }
 
 pub struct ErrorContext {
     color_gen: ColorGenerator,
+    color: ColorChoice,
+    messages: Rc<Messages>,
+    tab_width: usize,
     errors: Vec<Error>,
+    /// Diagnostics that are printed, but do not stop compilation.
+    warnings: Vec<Error>,
 }
 
 impl ErrorContext {
     pub fn new() -> Self {
+        Self::with_color(ColorChoice::default())
+    }
+
+    pub fn with_color(color: ColorChoice) -> Self {
+        Self::with_messages(color, Rc::default())
+    }
+
+    pub fn with_messages(color: ColorChoice, messages: Rc<Messages>) -> Self {
         Self {
             color_gen: ColorGenerator::new(),
+            color,
+            messages,
+            tab_width: DEFAULT_TAB_WIDTH,
             errors: Vec::new(),
+            warnings: Vec::new(),
         }
     }
 
+    pub fn color(&self) -> ColorChoice {
+        self.color
+    }
+
+    pub fn messages(&self) -> &Rc<Messages> {
+        &self.messages
+    }
+
+    /// Sets the width tabs are expanded to when drawing the source under a diagnostic, so the
+    /// labels line up with the code as shown in an editor.
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = tab_width;
+        self
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.tab_width
+    }
+
     pub fn unexpected_token(&mut self, span: Span, message: impl ToString) -> ErrorBuilder<'_> {
+        let messages = self.messages.clone();
         self.error(span.clone())
             .with_code(ErrorCode::UnexpectedToken)
-            .with_message("unexpected token")
+            .with_message(&messages.unexpected_token)
             .with_label(span, message)
     }
 
     pub fn unexpected_eof(&mut self, span: Span) -> ErrorBuilder<'_> {
+        let messages = self.messages.clone();
         self.error(span.clone())
             .with_code(ErrorCode::UnexpectedToken)
-            .with_message("unexpected end of file")
-            .with_label(span, "why stop here??")
+            .with_message(&messages.unexpected_eof)
+            .with_label(span, &messages.unexpected_eof_label)
+    }
+
+    /// A missing semicolon that should go right before `span`. Labels are left to the caller.
+    pub fn missing_semicolon(&mut self, span: Span) -> ErrorBuilder<'_> {
+        let messages = self.messages.clone();
+        self.error(span)
+            .with_code(ErrorCode::MissingSemicolon)
+            .with_message(&messages.missing_semicolon)
     }
 
     pub fn error(&mut self, span: Span) -> ErrorBuilder<'_> {
-        let builder = Report::build(ReportKind::Error, span);
+        let builder = Report::build(ReportKind::Error, span).with_config(self.config());
 
         ErrorBuilder {
             builder,
+            code: None,
+            is_warning: false,
             context: self,
         }
     }
 
     pub fn warn(&mut self, span: Span) -> ErrorBuilder<'_> {
-        let builder = Report::build(ReportKind::Warning, span);
+        let builder = Report::build(ReportKind::Warning, span).with_config(self.config());
 
         ErrorBuilder {
             builder,
+            code: None,
+            is_warning: true,
             context: self,
         }
     }
@@ -124,6 +263,24 @@ impl ErrorContext {
     pub fn take_errors(&mut self) -> ErrorVec {
         ErrorVec(std::mem::take(&mut self.errors))
     }
+
+    pub fn take_warnings(&mut self) -> ErrorVec {
+        ErrorVec(std::mem::take(&mut self.warnings))
+    }
+
+    fn config(&self) -> Config {
+        // spans are byte offsets into the source
+        Config::default()
+            .with_color(self.color.enabled())
+            .with_index_type(IndexType::Byte)
+            .with_tab_width(self.tab_width)
+    }
+}
+
+impl Default for ErrorContext {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[repr(u32)]
@@ -131,6 +288,11 @@ impl ErrorContext {
 pub enum ErrorCode {
     MissingSemicolon,
     UnexpectedToken,
+    /// A character that does not start any token.
+    UnexpectedCharacter,
+    InvalidEscape,
+    /// A number literal with a bad suffix, or that does not fit its type.
+    InvalidLiteral,
 }
 
 impl Display for ErrorCode {
@@ -153,6 +315,23 @@ impl ErrorVec {
         }
     }
 
+    /// Prints all errors to stderr, like [dump](Self::dump), but shows `source` for the spans
+    /// in `name` instead of reading a file. For source that was never in a file, like stdin.
+    pub fn dump_with_source(&self, name: &Rc<PathBuf>, source: &str) {
+        let source = Source::from(source.to_owned());
+        for error in &self.0 {
+            let files = Files {
+                buffer: None,
+                snippet: Some((name.clone(), source.clone())),
+            };
+            error
+                .eprint(files)
+                .expect("couldn't print error message to stderr");
+
+            eprintln!();
+        }
+    }
+
     pub fn len(&self) -> usize {
         self.0.len()
     }
@@ -161,6 +340,10 @@ impl ErrorVec {
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Error> {
+        self.0.iter()
+    }
 }
 
 impl From<Error> for ErrorVec {
@@ -178,6 +361,8 @@ impl fmt::Debug for ErrorVec {
 #[derive(Default)]
 struct Files {
     buffer: Option<Source>,
+    /// Source served for one path instead of reading it.
+    snippet: Option<(Rc<PathBuf>, Source)>,
 }
 
 impl Cache<Rc<PathBuf>> for Files {
@@ -187,6 +372,12 @@ impl Cache<Rc<PathBuf>> for Files {
         &mut self,
         path: &Rc<PathBuf>,
     ) -> Result<&ariadne::Source<Self::Storage>, impl fmt::Debug> {
+        if let Some((name, source)) = &self.snippet
+            && name == path
+        {
+            return Ok(source);
+        }
+
         self.buffer = Some(Source::from(fs::read_to_string(path.as_ref())?));
         Ok::<_, io::Error>(self.buffer.as_ref().unwrap())
     }
@@ -196,3 +387,37 @@ impl Cache<Rc<PathBuf>> for Files {
         Some(path.display())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(color: ColorChoice) -> String {
+        let path = Rc::new(env::temp_dir().join(format!("istind_color_{:?}.is", color)));
+        fs::write(path.as_ref(), "fn main() -> i64 { return x; }").unwrap();
+
+        let mut ctx = ErrorContext::with_color(color);
+        let error = ctx
+            .error((path.clone(), 26..27))
+            .with_message("unknown variable")
+            .with_label((path.clone(), 26..27), "not found in this scope")
+            .finish();
+
+        let mut out = Vec::new();
+        error.write(Files::default(), &mut out).unwrap();
+
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn no_color_has_no_escape_codes() {
+        let out = render(ColorChoice::Never);
+        assert!(out.contains("unknown variable"));
+        assert!(!out.contains('\x1b'));
+    }
+
+    #[test]
+    fn color_has_escape_codes() {
+        assert!(render(ColorChoice::Always).contains('\x1b'));
+    }
+}
@@ -29,6 +29,53 @@ impl AST {
         })
     }
 
+    /// Lists the signature of every function and forward declaration in this AST.
+    ///
+    /// ```
+    /// use std::{path::PathBuf, rc::Rc};
+    /// use istind::analyze::{ast::parse::Parser, lex::Lexer, semantics::SemanticType};
+    ///
+    /// let name = Rc::new(PathBuf::from("main"));
+    /// let source = "
+    ///     fn add(a: i64, b: i64) -> i64 { return a + b; }
+    ///     fn main() -> i64 { return add(1, 2); }
+    /// ";
+    ///
+    /// let lexer = Lexer::new(name.clone(), source).unwrap();
+    /// let ast = Parser::new(name, lexer).into_ast().unwrap();
+    ///
+    /// let sigs = ast.function_signatures();
+    /// assert_eq!(sigs.len(), 2);
+    /// assert_eq!(sigs[0].name, "add");
+    /// assert_eq!(sigs[0].args, vec![SemanticType::I64, SemanticType::I64]);
+    /// assert_eq!(sigs[1].name, "main");
+    /// assert_eq!(sigs[1].ret_type, SemanticType::I64);
+    /// ```
+    pub fn function_signatures(&self) -> Vec<FunctionSig> {
+        self.items
+            .iter()
+            .filter_map(|i| match i {
+                Item::Function {
+                    name,
+                    args,
+                    ret_type,
+                    ..
+                }
+                | Item::ForwardDecl {
+                    name,
+                    args,
+                    ret_type,
+                    ..
+                } => Some(FunctionSig {
+                    name: name.clone(),
+                    args: args.iter().map(|(_, typ, _)| typ.clone()).collect(),
+                    ret_type: ret_type.clone(),
+                }),
+                Item::ExternLib(_) => None,
+            })
+            .collect()
+    }
+
     pub fn mangle(&mut self, lib: &str) {
         for item in self.items.iter_mut() {
             match item {
@@ -41,6 +88,33 @@ impl AST {
     }
 }
 
+/// The name, parameter types and return type of a function.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionSig {
+    pub name: String,
+    pub args: Vec<SemanticType>,
+    pub ret_type: SemanticType,
+}
+
+/// An `@name` written before a function.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Attribute {
+    /// `@inline`: calls are always inlined when inlining is enabled.
+    Inline,
+    /// `@noinline`: calls are never inlined.
+    NoInline,
+}
+
+impl Attribute {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "inline" => Some(Self::Inline),
+            "noinline" => Some(Self::NoInline),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum Item {
     Function {
@@ -49,6 +123,10 @@ pub enum Item {
         body: Vec<Statement>,
         ret_type: SemanticType,
         decl_span: Span,
+        /// Declared with `const fn`, so calls with constant arguments are evaluated at compile
+        /// time.
+        is_const: bool,
+        attributes: Vec<Attribute>,
     },
     ForwardDecl {
         name: String,
@@ -71,16 +149,37 @@ pub enum Statement {
         expr: Expression,
         var_span: Span,
     },
+    /// `else if` is an `If` on its own in `otherwise`.
     If {
         guard: Expression,
         body: Vec<Statement>,
+        otherwise: Option<Vec<Statement>>,
     },
     Return(Expression),
+    /// The last expression of a function body when it has no semicolon, which is returned.
+    Tail(Expression),
     Expr(Expression),
     WhileLoop {
         guard: Expression,
         body: Vec<Statement>,
     },
+    /// A block that can be left early with `break label;`.
+    Labeled {
+        label: String,
+        body: Vec<Statement>,
+        label_span: Span,
+    },
+    Break {
+        label: String,
+        span: Span,
+    },
+    /// Runs the body of the case matching `value`, or `default` if no case does.
+    Switch {
+        value: Expression,
+        /// The value of each case, with the span of the pattern.
+        cases: Vec<(i64, Span, Vec<Statement>)>,
+        default: Option<Vec<Statement>>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -103,9 +202,38 @@ pub struct Expression {
     pub span: Span,
 }
 
+impl Expression {
+    /// Whether evaluating this has no effect other than producing its value, so it can be
+    /// evaluated even when its value ends up unused, or skipped when it is never used.
+    /// Dereferences count as effects, since the pointer may only be valid when the value is
+    /// needed.
+    pub fn is_pure(&self) -> bool {
+        match &self.inner {
+            ExprInner::Const(..)
+            | ExprInner::Float(_)
+            | ExprInner::Character(_)
+            | ExprInner::String(_)
+            | ExprInner::Bool(_)
+            | ExprInner::Variable(_)
+            | ExprInner::Pointer(_) => true,
+            ExprInner::Deref(..) | ExprInner::FnCall(..) => false,
+            ExprInner::Arithmetic(a, b, ..) | ExprInner::Comparison(a, b, ..) => {
+                a.is_pure() && b.is_pure()
+            }
+            ExprInner::Ternary(cond, a, b) => cond.is_pure() && a.is_pure() && b.is_pure(),
+            ExprInner::Negation(expr, _)
+            | ExprInner::Cast(expr, ..)
+            | ExprInner::Field(expr, ..) => expr.is_pure(),
+            ExprInner::Record(fields) => fields.iter().all(Expression::is_pure),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum ExprInner {
-    Const(i64),
+    /// An integer literal, with the type from its suffix. Unsuffixed literals are `i64`.
+    Const(i64, Option<SemanticType>),
+    Float(f64),
     Character(char),
     String(String),
     Bool(bool),
@@ -114,12 +242,29 @@ pub enum ExprInner {
     Pointer(String),
     Deref(String, Option<SemanticType>),
 
-    Arithmetic(Box<Expression>, Box<Expression>, ArithmeticOp, Option<Sign>),
+    /// `-expr`. The type of the operand is filled in by semantic analysis.
+    Negation(Box<Expression>, Option<SemanticType>),
+    /// The type of the operands is filled in by semantic analysis.
+    Arithmetic(
+        Box<Expression>,
+        Box<Expression>,
+        ArithmeticOp,
+        Option<SemanticType>,
+    ),
     Comparison(Box<Expression>, Box<Expression>, CompareOp, Option<Sign>),
+    /// `cond ? a : b`
+    Ternary(Box<Expression>, Box<Expression>, Box<Expression>),
 
-    Cast(Box<Expression>, SemanticType),
+    /// A cast to the given type. The type cast from is filled in by semantic analysis.
+    Cast(Box<Expression>, SemanticType, Option<SemanticType>),
 
     FnCall(String, Vec<Expression>),
+
+    /// `(a, b)`, a record of two fields.
+    Record(Vec<Expression>),
+    /// `record.x` or `record.0`, a field of a record by its name or position. The position is
+    /// filled in by the analyzer.
+    Field(Box<Expression>, String, Option<usize>),
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -128,6 +273,8 @@ pub enum ArithmeticOp {
     Sub,
     Mult,
     Div,
+    /// The remainder of a division, which takes the sign of the dividend.
+    Mod,
 }
 
 #[derive(Debug, Clone, Copy)]
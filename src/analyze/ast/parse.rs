@@ -1,27 +1,29 @@
 use std::{ops::Range, path::PathBuf, rc::Rc};
 
 use crate::analyze::{
-    Error, ErrorCode, ErrorContext, ErrorVec, Span,
+    Error, ErrorContext, ErrorVec, Span,
     ast::{
-        AST, ArithmeticOp, Assignable, CompareOp, ExprInner, Expression, Item, SemanticType,
-        Statement,
+        AST, ArithmeticOp, Assignable, Attribute, CompareOp, ExprInner, Expression, Item,
+        SemanticType, Statement,
     },
     lex::{
         Lexer,
         token::{Keyword, Operator, Token},
     },
+    semantics::RecordField,
 };
 
-pub struct Parser {
+pub struct Parser<'src> {
     err_ctx: ErrorContext,
     src_path: Rc<PathBuf>,
-    lexer: Lexer,
+    lexer: Lexer<'src>,
 }
 
-impl Parser {
-    pub fn new(src_path: Rc<PathBuf>, lexer: Lexer) -> Self {
+impl<'src> Parser<'src> {
+    pub fn new(src_path: Rc<PathBuf>, lexer: Lexer<'src>) -> Self {
         Self {
-            err_ctx: ErrorContext::new(),
+            err_ctx: ErrorContext::with_messages(lexer.color(), lexer.messages().clone())
+                .with_tab_width(lexer.tab_width()),
             src_path,
             lexer,
         }
@@ -43,6 +45,29 @@ impl Parser {
         Ok(ast)
     }
 
+    /// Parses the whole source as a single expression.
+    pub fn into_expr(mut self) -> Result<Expression, ErrorVec> {
+        let result = self
+            .parse_expr()
+            .and_then(|expr| match self.lexer.current() {
+                Some((_, range)) => Err(self
+                    .err_ctx
+                    .unexpected_token(self.span(range.clone()), "expected end of expression")
+                    .finish()),
+                None => Ok(expr),
+            });
+
+        let mut errors = self.err_ctx.take_errors();
+        match result {
+            Ok(expr) if errors.is_empty() => Ok(expr),
+            Ok(_) => Err(errors),
+            Err(err) => {
+                errors.0.push(err);
+                Err(errors)
+            }
+        }
+    }
+
     fn parse(&mut self, ast: &mut AST) -> Result<(), Error> {
         while self.lexer.current().is_some() {
             let item = self.parse_item()?;
@@ -63,6 +88,8 @@ impl Parser {
     }
 
     fn parse_item(&mut self) -> Result<Item, Error> {
+        let attributes = self.parse_attributes()?;
+
         let (token, range) = self.expect_take_current()?;
         let Token::Keyword(keyword) = token else {
             return Err(self
@@ -72,9 +99,17 @@ impl Parser {
         };
 
         match keyword {
-            Keyword::Function => self.parse_function(range.start),
+            Keyword::Function => self.parse_function(range.start, false, attributes),
+            Keyword::Const => {
+                self.expect_token(Token::Keyword(Keyword::Function), "expected fn")?;
+                self.parse_function(range.start, true, attributes)
+            }
             Keyword::Use => unimplemented!(),
-            Keyword::Extern => self.parse_extern(),
+            Keyword::Extern if attributes.is_empty() => self.parse_extern(),
+            Keyword::Extern => Err(self
+                .err_ctx
+                .unexpected_token(self.span(range), "expected fn after attributes")
+                .finish()),
             _ => Err(self
                 .err_ctx
                 .unexpected_token(self.span(range), "expected function or extern import")
@@ -82,6 +117,28 @@ impl Parser {
         }
     }
 
+    /// Parses the `@name` attributes before an item.
+    fn parse_attributes(&mut self) -> Result<Vec<Attribute>, Error> {
+        let mut attributes = Vec::new();
+        while let Some((Token::Attribute(name), range)) = self.lexer.current() {
+            let span = self.span(range.clone());
+            match Attribute::parse(name) {
+                Some(attribute) => attributes.push(attribute),
+                None => {
+                    self.err_ctx
+                        .error(span.clone())
+                        .with_message("unknown attribute")
+                        .with_label(span, "expected @inline or @noinline")
+                        .report();
+                }
+            }
+
+            self.lexer.lex_one()?;
+        }
+
+        Ok(attributes)
+    }
+
     fn parse_extern(&mut self) -> Result<Item, Error> {
         match self.parse_extern_inner() {
             Ok(item) => Ok(item),
@@ -107,7 +164,12 @@ impl Parser {
         Ok(Item::ExternLib(lib))
     }
 
-    fn parse_function(&mut self, decl_start: usize) -> Result<Item, Error> {
+    fn parse_function(
+        &mut self,
+        decl_start: usize,
+        is_const: bool,
+        attributes: Vec<Attribute>,
+    ) -> Result<Item, Error> {
         let (token, range) = self.expect_take_current()?;
 
         let name = match token {
@@ -144,6 +206,25 @@ impl Parser {
         if matches!(self.lexer.current(), Some((Token::Semicolon, _))) {
             self.lexer.lex_one()?;
 
+            if is_const {
+                self.err_ctx
+                    .error(decl_span.clone())
+                    .with_message("const function without a body")
+                    .with_label(decl_span.clone(), "only defined functions can be const")
+                    .report();
+            }
+
+            if !attributes.is_empty() {
+                self.err_ctx
+                    .error(decl_span.clone())
+                    .with_message("attributes on a function without a body")
+                    .with_label(
+                        decl_span.clone(),
+                        "only defined functions can have attributes",
+                    )
+                    .report();
+            }
+
             Ok(Item::ForwardDecl {
                 name,
                 args,
@@ -151,7 +232,13 @@ impl Parser {
                 decl_span,
             })
         } else {
-            let body = self.parse_block()?;
+            // `=> expr;` is short for a body returning `expr`
+            let body = if matches!(self.lexer.current(), Some((Token::FatArrow, _))) {
+                self.lexer.lex_one()?;
+                vec![self.parse_return()?]
+            } else {
+                self.parse_block_with_tail(true)?
+            };
 
             Ok(Item::Function {
                 name,
@@ -159,6 +246,8 @@ impl Parser {
                 body,
                 ret_type,
                 decl_span,
+                is_const,
+                attributes,
             })
         }
     }
@@ -203,6 +292,14 @@ impl Parser {
                 self.lexer.lex_one()?;
                 Ok(SemanticType::Unit)
             }
+            Token::LeftParenthesis => {
+                let first = self.parse_record_field()?;
+                self.expect_token(Token::Comma, "expected comma")?;
+                let second = self.parse_record_field()?;
+                self.expect_token(Token::RightParenthesis, "expected closing parenthesis")?;
+
+                Ok(SemanticType::Record(vec![first, second]))
+            }
             _ => Err(self
                 .err_ctx
                 .unexpected_token(self.span(range), "expected argument type")
@@ -210,7 +307,29 @@ impl Parser {
         }
     }
 
+    /// Parses the type of a record field, which may be named as in `x: i64`.
+    fn parse_record_field(&mut self) -> Result<RecordField, Error> {
+        if let Some((Token::Ident(name), _)) = self.lexer.current().cloned()
+            && matches!(self.lexer.peek(), Some((Token::Colon, _)))
+        {
+            self.lexer.lex_two()?;
+            let typ = self.parse_type()?;
+            return Ok(RecordField {
+                name: Some(name),
+                typ,
+            });
+        }
+
+        self.parse_type().map(RecordField::unnamed)
+    }
+
     fn parse_block(&mut self) -> Result<Vec<Statement>, Error> {
+        self.parse_block_with_tail(false)
+    }
+
+    /// Parses a block, which may end in an expression without a semicolon if `allow_tail` is
+    /// set.
+    fn parse_block_with_tail(&mut self, allow_tail: bool) -> Result<Vec<Statement>, Error> {
         self.expect_token(Token::LeftCurlyBracket, "expected block")?;
 
         let mut statements = Vec::new();
@@ -221,6 +340,13 @@ impl Parser {
             }
 
             match self.parse_statement() {
+                Ok(Statement::Tail(expr)) if !allow_tail => {
+                    let pos = expr.span.1.end;
+                    self.err_ctx
+                        .missing_semicolon(self.span(pos..(pos + 1)))
+                        .with_label(expr.span.clone(), "only a function body can end in a value")
+                        .report();
+                }
                 Ok(stmt) => statements.push(stmt),
                 Err(err) => {
                     self.err_ctx.report(err);
@@ -238,9 +364,24 @@ impl Parser {
         if let Token::Keyword(keyword) = token {
             self.lexer.take_current()?;
             self.parse_keyword(keyword, range.clone())
+        } else if let Token::Ident(label) = token
+            && matches!(self.lexer.peek(), Some((Token::Colon, _)))
+        {
+            self.lexer.lex_two()?;
+            let body = self.parse_block()?;
+
+            Ok(Statement::Labeled {
+                label,
+                body,
+                label_span: self.span(range),
+            })
         } else {
             let expr = self.parse_expr()?;
 
+            if matches!(self.lexer.current(), Some((Token::RightCurlyBracket, _))) {
+                return Ok(Statement::Tail(expr));
+            }
+
             match self.lexer.take_current()? {
                 Some((Token::Semicolon, _)) => Ok(Statement::Expr(expr)),
                 Some((Token::Assign, _)) => {
@@ -297,6 +438,9 @@ impl Parser {
             Keyword::Return => self.parse_return(),
             Keyword::If => self.parse_if(),
             Keyword::While => self.parse_while_loop(),
+            Keyword::Break => self.parse_break(range),
+            Keyword::Switch => self.parse_switch(),
+            Keyword::Let => self.parse_let(),
             _ => Err(self
                 .err_ctx
                 .unexpected_token(self.span(range), "unexpected keyword")
@@ -304,6 +448,27 @@ impl Parser {
         }
     }
 
+    /// Parses `let var = expr;`, which declares `var` the same way as `var := expr;`.
+    fn parse_let(&mut self) -> Result<Statement, Error> {
+        let (token, range) = self.expect_take_current()?;
+        let Token::Ident(var) = token else {
+            return Err(self
+                .err_ctx
+                .unexpected_token(self.span(range), "expected variable name")
+                .finish());
+        };
+
+        self.expect_token(Token::Assign, "expected '='")?;
+        let expr = self.parse_expr()?;
+        self.expect_semicolon()?;
+
+        Ok(Statement::Declare {
+            var,
+            expr,
+            var_span: self.span(range),
+        })
+    }
+
     fn parse_return(&mut self) -> Result<Statement, Error> {
         let expr = self.parse_expr()?;
         self.expect_semicolon()?;
@@ -311,11 +476,29 @@ impl Parser {
         Ok(Statement::Return(expr))
     }
 
+    /// Parses `if guard { ... }`, optionally followed by `else { ... }` or `else if`.
     fn parse_if(&mut self) -> Result<Statement, Error> {
         let guard = self.parse_expr()?;
         let body = self.parse_block()?;
 
-        Ok(Statement::If { guard, body })
+        let otherwise = if let Some((Token::Keyword(Keyword::Else), _)) = self.lexer.current() {
+            self.lexer.take_current()?;
+
+            if let Some((Token::Keyword(Keyword::If), _)) = self.lexer.current() {
+                self.lexer.take_current()?;
+                Some(vec![self.parse_if()?])
+            } else {
+                Some(self.parse_block()?)
+            }
+        } else {
+            None
+        };
+
+        Ok(Statement::If {
+            guard,
+            body,
+            otherwise,
+        })
     }
 
     fn parse_while_loop(&mut self) -> Result<Statement, Error> {
@@ -325,45 +508,111 @@ impl Parser {
         Ok(Statement::WhileLoop { guard, body })
     }
 
-    fn parse_expr(&mut self) -> Result<Expression, Error> {
-        let mut lhs = self.parse_single_expr()?;
+    /// Parses `switch value { 1 => { ... }, _ => { ... } }`. The commas between cases are
+    /// optional.
+    fn parse_switch(&mut self) -> Result<Statement, Error> {
+        let value = self.parse_expr()?;
+        self.expect_token(Token::LeftCurlyBracket, "expected switch cases")?;
+
+        let mut cases = Vec::new();
+        let mut default = None;
+        loop {
+            let (token, range) = self.expect_take_current()?;
+            let case = match token {
+                Token::RightCurlyBracket => break,
+                Token::Number(n, _) => Some((n, self.span(range.clone()))),
+                Token::Ident(ident) if ident == "_" => None,
+                _ => {
+                    return Err(self
+                        .err_ctx
+                        .unexpected_token(self.span(range), "expected integer or '_'")
+                        .finish());
+                }
+            };
 
-        if let Some((Token::Operator(op), _)) = self.lexer.current() {
-            let mut op = *op;
+            self.expect_token(Token::FatArrow, "expected '=>'")?;
+            let body = self.parse_block()?;
 
-            let left_bind_power = op.precedence();
+            match case {
+                Some((n, span)) => cases.push((n, span, body)),
+                None if default.is_some() => {
+                    let span = self.span(range);
+                    self.err_ctx
+                        .error(span.clone())
+                        .with_message("duplicate default case")
+                        .with_label(span, "switch already has a default case")
+                        .report();
+                }
+                None => default = Some(body),
+            }
 
-            self.lexer.take_current()?;
+            if matches!(self.lexer.current(), Some((Token::Comma, _))) {
+                self.lexer.take_current()?;
+            }
+        }
 
-            let right_side = match self.lexer.peek() {
-                Some((Token::Operator(next_op), _)) => Some((next_op.precedence(), *next_op)),
-                _ => None,
-            };
+        Ok(Statement::Switch {
+            value,
+            cases,
+            default,
+        })
+    }
 
-            let rhs = if let Some((right_bind_power, next_op)) = right_side
-                && right_bind_power < left_bind_power
-            {
-                let rhs = self.parse_single_expr()?;
-                lhs = Expression {
-                    inner: self.bind_expr(op, lhs, rhs),
-                    span: self.span(0..1),
-                };
+    fn parse_break(&mut self, break_range: Range<usize>) -> Result<Statement, Error> {
+        let (token, range) = self.expect_take_current()?;
+        let Token::Ident(label) = token else {
+            return Err(self
+                .err_ctx
+                .unexpected_token(self.span(range), "expected label")
+                .finish());
+        };
 
-                self.lexer.lex_one()?;
-                op = next_op;
+        self.expect_semicolon()?;
 
-                self.parse_expr()?
-            } else {
-                self.parse_expr()?
-            };
+        Ok(Statement::Break {
+            label,
+            span: self.span(break_range.start..range.end),
+        })
+    }
 
-            let span = self.span((lhs.span.1.start)..(rhs.span.1.end));
-            let expr_type = self.bind_expr(op, lhs, rhs);
+    /// Parses an expression. The conditional `cond ? a : b` binds looser than any operator,
+    /// and nests to the right.
+    fn parse_expr(&mut self) -> Result<Expression, Error> {
+        let cond = self.parse_binary_expr(0)?;
 
-            return Ok(Expression {
-                inner: expr_type,
+        if !matches!(self.lexer.current(), Some((Token::Question, _))) {
+            return Ok(cond);
+        }
+
+        self.lexer.take_current()?;
+        let then = self.parse_expr()?;
+        self.expect_token(Token::Colon, "expected ':' to separate the branches")?;
+        let otherwise = self.parse_expr()?;
+
+        let span = self.span(cond.span.1.start..otherwise.span.1.end);
+        Ok(Expression {
+            inner: ExprInner::Ternary(Box::new(cond), Box::new(then), Box::new(otherwise)),
+            span,
+        })
+    }
+
+    /// Parses operators binding at least as tightly as `min_precedence`. Operators of equal
+    /// precedence associate to the left.
+    fn parse_binary_expr(&mut self, min_precedence: i32) -> Result<Expression, Error> {
+        let mut lhs = self.parse_single_expr()?;
+
+        while let Some((Token::Operator(op), _)) = self.lexer.current()
+            && op.precedence() >= min_precedence
+        {
+            let op = *op;
+            self.lexer.take_current()?;
+
+            let rhs = self.parse_binary_expr(op.precedence() + 1)?;
+            let span = self.span((lhs.span.1.start)..(rhs.span.1.end));
+            lhs = Expression {
+                inner: self.bind_expr(op, lhs, rhs),
                 span,
-            });
+            };
         }
 
         Ok(lhs)
@@ -383,6 +632,9 @@ impl Parser {
             Operator::Slash => {
                 ExprInner::Arithmetic(Box::new(lhs), Box::new(rhs), ArithmeticOp::Div, None)
             }
+            Operator::Percent => {
+                ExprInner::Arithmetic(Box::new(lhs), Box::new(rhs), ArithmeticOp::Mod, None)
+            }
             Operator::Equal => {
                 ExprInner::Comparison(Box::new(lhs), Box::new(rhs), CompareOp::Equal, None)
             }
@@ -410,10 +662,20 @@ impl Parser {
     fn parse_single_expr(&mut self) -> Result<Expression, Error> {
         let token = self.expect_take_current()?;
         let expr = match token {
-            (Token::Number(num), range) => Expression {
-                inner: ExprInner::Const(num),
+            (Token::Number(num, typ), range) => Expression {
+                inner: ExprInner::Const(num, typ),
                 span: self.span(range),
             },
+            (Token::Operator(Operator::Minus), minus_range) => {
+                // binds tighter than any binary operator, so `-a * b` is `(-a) * b`
+                let operand = self.parse_single_expr()?;
+                let span = self.span(minus_range.start..operand.span.1.end);
+
+                return Ok(Expression {
+                    inner: ExprInner::Negation(Box::new(operand), None),
+                    span,
+                });
+            }
             (Token::Reference, ref_range) => {
                 let (token, var_range) = self.expect_take_current()?;
                 let Token::Ident(var) = token else {
@@ -449,6 +711,10 @@ impl Parser {
                 }
             }
             (Token::Ident(ident), range) => self.parse_ident_expr(ident, range)?,
+            (Token::Float(num), range) => Expression {
+                inner: ExprInner::Float(num),
+                span: self.span(range),
+            },
             (Token::Character(c), range) => Expression {
                 inner: ExprInner::Character(c),
                 span: self.span(range),
@@ -461,6 +727,25 @@ impl Parser {
                 inner: ExprInner::Bool(b),
                 span: self.span(range),
             },
+            (Token::LeftParenthesis, range) => {
+                let expr = self.parse_expr()?;
+
+                let inner = if let Some((Token::Comma, _)) = self.lexer.current() {
+                    self.lexer.lex_one()?;
+                    let second = self.parse_expr()?;
+                    ExprInner::Record(vec![expr, second])
+                } else {
+                    expr.inner
+                };
+
+                let close =
+                    self.expect_token(Token::RightParenthesis, "expected closing parenthesis")?;
+
+                Expression {
+                    inner,
+                    span: self.span(range.start..close.end),
+                }
+            }
             (_, range) => {
                 return Err(self
                     .err_ctx
@@ -469,6 +754,28 @@ impl Parser {
             }
         };
 
+        let mut expr = expr;
+        while let Some((Token::Dot, _)) = self.lexer.current() {
+            self.lexer.lex_one()?;
+            let (token, range) = self.expect_take_current()?;
+            let field = match token {
+                Token::Ident(name) => name,
+                Token::Number(position, None) => position.to_string(),
+                _ => {
+                    return Err(self
+                        .err_ctx
+                        .unexpected_token(self.span(range), "expected field name")
+                        .finish());
+                }
+            };
+
+            let span = self.span(expr.span.1.start..range.end);
+            expr = Expression {
+                inner: ExprInner::Field(Box::new(expr), field, None),
+                span,
+            };
+        }
+
         if let Some((Token::Keyword(Keyword::As), _)) = self.lexer.current() {
             self.lexer.lex_one()?;
             let typ = self.parse_type()?;
@@ -478,7 +785,7 @@ impl Parser {
             let span = self.span(start..end);
 
             return Ok(Expression {
-                inner: ExprInner::Cast(Box::new(expr), typ),
+                inner: ExprInner::Cast(Box::new(expr), typ, None),
                 span,
             });
         }
@@ -491,6 +798,8 @@ impl Parser {
         mut ident: String,
         range: Range<usize>,
     ) -> Result<Expression, Error> {
+        let mut end = range.end;
+
         while matches!(self.lexer.current(), Some((Token::PathSeparator, _))) {
             self.lexer.lex_one()?;
             let (token, range) = self.expect_take_current()?;
@@ -503,6 +812,7 @@ impl Parser {
 
             ident.push_str("::");
             ident.push_str(&sub_ident);
+            end = range.end;
         }
 
         if matches!(self.lexer.current(), Some((Token::LeftParenthesis, _))) {
@@ -510,16 +820,17 @@ impl Parser {
 
             let args = self.parse_call_args()?;
 
-            self.expect_token(Token::RightParenthesis, "expected closing parenthesis")?;
+            let closing =
+                self.expect_token(Token::RightParenthesis, "expected closing parenthesis")?;
 
             Ok(Expression {
                 inner: ExprInner::FnCall(ident, args),
-                span: self.span((range.start)..(self.lexer.last_token_end())),
+                span: self.span(range.start..closing.end),
             })
         } else {
             Ok(Expression {
                 inner: ExprInner::Variable(ident),
-                span: self.span((range.start)..(self.lexer.last_token_end())),
+                span: self.span(range.start..end),
             })
         }
     }
@@ -542,11 +853,21 @@ impl Parser {
         Ok(args)
     }
 
-    fn expect_token(&mut self, token: Token, message: impl ToString) -> Result<(), Error> {
+    /// Takes the current token if it is `token`, and returns its range.
+    fn expect_token(
+        &mut self,
+        token: Token,
+        message: impl ToString,
+    ) -> Result<Range<usize>, Error> {
         self.expect_matches(|t| t == &token, message)
     }
 
-    fn expect_matches<F>(&mut self, matches: F, message: impl ToString) -> Result<(), Error>
+    /// Takes the current token if it matches, and returns its range.
+    fn expect_matches<F>(
+        &mut self,
+        matches: F,
+        message: impl ToString,
+    ) -> Result<Range<usize>, Error>
     where
         F: FnOnce(&Token) -> bool,
     {
@@ -558,7 +879,7 @@ impl Parser {
                 .finish());
         }
 
-        Ok(())
+        Ok(range)
     }
 
     fn expect_semicolon(&mut self) -> Result<(), Error> {
@@ -569,11 +890,10 @@ impl Parser {
                 .unwrap_or(self.lexer.cur_token_start());
 
             let insert_span = self.span((pos - 1)..pos);
+            let label = self.err_ctx.messages().missing_semicolon_label.clone();
             self.err_ctx
-                .error(self.span(pos..(pos + 1)))
-                .with_code(ErrorCode::MissingSemicolon)
-                .with_message("expected semicolon")
-                .with_label(insert_span, "insert the semicolon dummy")
+                .missing_semicolon(self.span(pos..(pos + 1)))
+                .with_label(insert_span, label)
                 .report();
         }
 
@@ -597,3 +917,282 @@ impl Parser {
         self.span((end - 1)..end)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parser(source: &str) -> Parser<'_> {
+        let path = Rc::new(PathBuf::from("main"));
+        let lexer = Lexer::new(path.clone(), source).unwrap();
+        Parser::new(path, lexer)
+    }
+
+    /// Writes arithmetic on constants with every operation in parentheses.
+    fn shape(expr: &Expression) -> String {
+        match &expr.inner {
+            ExprInner::Const(n, _) => n.to_string(),
+            ExprInner::Negation(expr, _) => format!("-{}", shape(expr)),
+            ExprInner::Arithmetic(lhs, rhs, op, _) => {
+                let op = match op {
+                    ArithmeticOp::Add => "+",
+                    ArithmeticOp::Sub => "-",
+                    ArithmeticOp::Mult => "*",
+                    ArithmeticOp::Div => "/",
+                    ArithmeticOp::Mod => "%",
+                };
+                format!("({} {} {})", shape(lhs), op, shape(rhs))
+            }
+            inner => panic!("unexpected expression {:?}", inner),
+        }
+    }
+
+    #[test]
+    fn same_precedence_is_left_associative() {
+        assert_eq!(
+            shape(&parser("2 - 3 - 4").parse_expr().unwrap()),
+            "((2 - 3) - 4)"
+        );
+        assert_eq!(
+            shape(&parser("8 / 4 / 2").parse_expr().unwrap()),
+            "((8 / 4) / 2)"
+        );
+        assert_eq!(
+            shape(&parser("9 % 4 * 2").parse_expr().unwrap()),
+            "((9 % 4) * 2)"
+        );
+    }
+
+    #[test]
+    fn multiplication_binds_tighter_than_addition() {
+        assert_eq!(
+            shape(&parser("2 + 3 * 4").parse_expr().unwrap()),
+            "(2 + (3 * 4))"
+        );
+        assert_eq!(
+            shape(&parser("2 * 3 - 8 / 4").parse_expr().unwrap()),
+            "((2 * 3) - (8 / 4))"
+        );
+        assert_eq!(
+            shape(&parser("1 + 7 % 4").parse_expr().unwrap()),
+            "(1 + (7 % 4))"
+        );
+    }
+
+    #[test]
+    fn negation_binds_tighter_than_binary_operators() {
+        assert_eq!(shape(&parser("1 - -2").parse_expr().unwrap()), "(1 - -2)");
+        assert_eq!(shape(&parser("- -3").parse_expr().unwrap()), "--3");
+        assert_eq!(shape(&parser("-2 * 3").parse_expr().unwrap()), "(-2 * 3)");
+    }
+
+    #[test]
+    fn call_span_at_eof() {
+        let expr = parser("foo()").parse_expr().unwrap();
+
+        assert!(matches!(expr.inner, ExprInner::FnCall(ref name, _) if name == "foo"));
+        assert_eq!(expr.span.1, 0..5);
+    }
+
+    #[test]
+    fn call_span_with_args() {
+        let expr = parser("std::write(1, x, 2);").parse_expr().unwrap();
+
+        assert_eq!(expr.span.1, 0..19);
+    }
+
+    #[test]
+    fn trailing_expression_is_tail() {
+        let ast = parser("fn main() -> i64 { x := 40; x + 2 }")
+            .into_ast()
+            .unwrap();
+
+        let Item::Function { body, .. } = &ast.items[0] else {
+            panic!("expected a function");
+        };
+        assert!(matches!(body.last(), Some(Statement::Tail(_))));
+    }
+
+    #[test]
+    fn else_if_nests_in_otherwise() {
+        let ast =
+            parser("fn f(x: i64) { if x < 0 { f(0); } else if x < 5 { f(1); } else { f(2); } }")
+                .into_ast()
+                .unwrap();
+
+        let Item::Function { body, .. } = &ast.items[0] else {
+            panic!("expected a function");
+        };
+        let Some(Statement::If {
+            otherwise: Some(otherwise),
+            ..
+        }) = body.first()
+        else {
+            panic!("expected an if with an else branch");
+        };
+        assert!(matches!(
+            otherwise.as_slice(),
+            [Statement::If {
+                otherwise: Some(_),
+                ..
+            }]
+        ));
+    }
+
+    #[test]
+    fn trailing_expression_only_in_function_body() {
+        let ast = parser("fn main() -> i64 { x := 1; if x == 1 { x } return x; }").into_ast();
+
+        assert!(ast.is_err());
+    }
+
+    #[test]
+    fn attributes_are_attached_to_function() {
+        let ast = parser("@noinline @inline const fn f() -> i64 { return 1; }")
+            .into_ast()
+            .unwrap();
+
+        let Item::Function {
+            attributes,
+            is_const,
+            ..
+        } = &ast.items[0]
+        else {
+            panic!("expected a function");
+        };
+        assert_eq!(attributes, &[Attribute::NoInline, Attribute::Inline]);
+        assert!(is_const);
+    }
+
+    #[test]
+    fn unknown_attribute() {
+        assert!(parser("@fast fn f() {}").into_ast().is_err());
+        assert!(parser("@inline extern std;").into_ast().is_err());
+    }
+
+    #[test]
+    fn expression_body_is_implicit_return() {
+        let bodies: Vec<String> = [
+            "fn answer() -> i64 { return 42; }",
+            "fn answer() -> i64 => 42;",
+        ]
+        .into_iter()
+        .map(|source| {
+            let ast = parser(source).into_ast().unwrap();
+            let Item::Function { name, body, .. } = &ast.items[0] else {
+                panic!("expected a function");
+            };
+            assert_eq!(name, "answer");
+
+            let [Statement::Return(expr)] = body.as_slice() else {
+                panic!("expected a single return, got {:?}", body);
+            };
+            format!("{:?}", expr.inner)
+        })
+        .collect();
+
+        assert_eq!(bodies[0], bodies[1]);
+    }
+
+    #[test]
+    fn expression_body_needs_semicolon() {
+        assert!(parser("fn answer() -> i64 => 42").into_ast().is_err());
+    }
+
+    #[test]
+    fn let_declares_like_walrus() {
+        // lined up so the expressions start at the same offset, since their spans are compared
+        let declarations: Vec<String> = ["fn f() { let a = 5 + x; }", "fn f() {    a := 5 + x; }"]
+            .into_iter()
+            .map(|source| {
+                let ast = parser(source).into_ast().unwrap();
+                let Item::Function { body, .. } = &ast.items[0] else {
+                    panic!("expected a function");
+                };
+
+                let [Statement::Declare { var, expr, .. }] = body.as_slice() else {
+                    panic!("expected a single declaration, got {:?}", body);
+                };
+                format!("{} {:?}", var, expr.inner)
+            })
+            .collect();
+
+        assert!(declarations[0].starts_with("a "));
+        assert_eq!(declarations[0], declarations[1]);
+    }
+
+    #[test]
+    fn let_needs_name_and_value() {
+        assert!(parser("fn f() { let = 5; }").into_ast().is_err());
+        assert!(parser("fn f() { let a := 5; }").into_ast().is_err());
+        assert!(parser("fn f() { let a; }").into_ast().is_err());
+    }
+
+    fn returned_expr(source: &str) -> ExprInner {
+        let ast = parser(source).into_ast().unwrap();
+        let Item::Function { body, .. } = &ast.items[0] else {
+            panic!("expected a function");
+        };
+        let [Statement::Return(expr)] = body.as_slice() else {
+            panic!("expected a single return, got {:?}", body);
+        };
+        expr.inner.clone()
+    }
+
+    #[test]
+    fn record_type_with_named_field() {
+        let ast = parser("fn f() -> (x: i64, bool) => (1, true);")
+            .into_ast()
+            .unwrap();
+
+        let Item::Function {
+            ret_type: SemanticType::Record(fields),
+            ..
+        } = &ast.items[0]
+        else {
+            panic!("expected a function returning a record");
+        };
+        assert_eq!(
+            fields.iter().map(|f| f.name.as_deref()).collect::<Vec<_>>(),
+            [Some("x"), None]
+        );
+        assert_eq!(fields[0].typ, SemanticType::I64);
+        assert_eq!(fields[1].typ, SemanticType::Bool);
+    }
+
+    #[test]
+    fn record_literal() {
+        let ExprInner::Record(fields) = returned_expr("fn f() -> (i64, bool) => (1, true);") else {
+            panic!("expected a record");
+        };
+        assert_eq!(fields.len(), 2);
+    }
+
+    #[test]
+    fn field_of_variable() {
+        let ExprInner::Field(record, field, None) = returned_expr("fn f() -> i64 => r.x;") else {
+            panic!("expected a field access");
+        };
+        assert!(matches!(record.inner, ExprInner::Variable(ref var) if var == "r"));
+        assert_eq!(field, "x");
+    }
+
+    #[test]
+    fn field_of_call() {
+        let ExprInner::Field(call, field, None) = returned_expr("fn f() -> i64 => foo().y;") else {
+            panic!("expected a field access");
+        };
+        assert!(matches!(call.inner, ExprInner::FnCall(ref name, _) if name == "foo"));
+        assert_eq!(field, "y");
+    }
+
+    #[test]
+    fn fields_chain_and_bind_before_operators() {
+        let ExprInner::Arithmetic(lhs, _, ArithmeticOp::Add, _) =
+            returned_expr("fn f() -> i64 => r.0 + -s.1;")
+        else {
+            panic!("expected an addition");
+        };
+        assert!(matches!(lhs.inner, ExprInner::Field(_, ref field, _) if field == "0"));
+    }
+}
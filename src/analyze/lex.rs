@@ -1,14 +1,17 @@
 use std::{ops::Range, path::PathBuf, rc::Rc};
 
 use crate::analyze::{
-    Error, ErrorContext,
+    ColorChoice, DEFAULT_TAB_WIDTH, Error, ErrorCode, ErrorContext, Messages,
     lex::token::{Keyword, Operator, Token},
+    semantics::SemanticType,
 };
 
 pub mod token;
 
-pub struct Lexer {
-    code: Vec<char>,
+/// Splits source code into tokens, one at a time. The lexer works directly on the source
+/// string, and `index` and all token ranges are byte offsets into it.
+pub struct Lexer<'src> {
+    code: &'src str,
     index: usize,
     last: Option<(Token, Range<usize>)>,
     current: Option<(Token, Range<usize>)>,
@@ -17,17 +20,35 @@ pub struct Lexer {
     src_path: Rc<PathBuf>,
 }
 
-impl Lexer {
-    pub fn new(src_path: Rc<PathBuf>, code: impl AsRef<str>) -> Result<Self, Error> {
-        let code: Vec<char> = code.as_ref().chars().collect();
+impl<'src> Lexer<'src> {
+    pub fn new(src_path: Rc<PathBuf>, code: &'src str) -> Result<Self, Error> {
+        Self::with_color(src_path, code, ColorChoice::default())
+    }
+
+    pub fn with_color(
+        src_path: Rc<PathBuf>,
+        code: &'src str,
+        color: ColorChoice,
+    ) -> Result<Self, Error> {
+        Self::with_messages(src_path, code, color, Rc::default(), DEFAULT_TAB_WIDTH)
+    }
 
+    /// Like [with_color](Self::with_color), with the wording of diagnostics replaced and tabs
+    /// expanded to `tab_width` columns. The parser built on this lexer reports the same way.
+    pub fn with_messages(
+        src_path: Rc<PathBuf>,
+        code: &'src str,
+        color: ColorChoice,
+        messages: Rc<Messages>,
+        tab_width: usize,
+    ) -> Result<Self, Error> {
         let mut lexer = Self {
             code,
             index: 0,
             last: None,
             current: None,
             next: None,
-            err_ctx: ErrorContext::new(),
+            err_ctx: ErrorContext::with_messages(color, messages).with_tab_width(tab_width),
             src_path,
         };
 
@@ -36,6 +57,18 @@ impl Lexer {
         Ok(lexer)
     }
 
+    pub fn color(&self) -> ColorChoice {
+        self.err_ctx.color()
+    }
+
+    pub fn messages(&self) -> &Rc<Messages> {
+        self.err_ctx.messages()
+    }
+
+    pub fn tab_width(&self) -> usize {
+        self.err_ctx.tab_width()
+    }
+
     pub fn cur_token_start(&self) -> usize {
         self.current
             .as_ref()
@@ -83,30 +116,64 @@ impl Lexer {
     }
 }
 
+/// Yields the remaining tokens, starting with the current one.
+///
+/// Iteration stops after the first error, since the lexer cannot recover
+/// its position past an invalid token.
+impl Iterator for Lexer<'_> {
+    type Item = Result<(Token, Range<usize>), Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.current.as_ref()?;
+
+        match self.take_current() {
+            Ok(token) => token.map(Ok),
+            Err(err) => {
+                self.current = None;
+                self.next = None;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
 /// Internals
-impl Lexer {
+impl Lexer<'_> {
     fn peek_char(&self) -> Option<char> {
-        self.code.get(self.index + 1).copied()
+        self.code[self.index..].chars().nth(1)
     }
 
     fn cur_char(&self) -> Option<char> {
-        self.code.get(self.index).copied()
+        self.code[self.index..].chars().next()
     }
 
-    fn find_next_lexable(&mut self) {
+    /// Moves past the current character.
+    fn advance(&mut self) {
+        if let Some(c) = self.cur_char() {
+            self.index += c.len_utf8();
+        }
+    }
+
+    /// Skips whitespace and comments up to the start of the next token, or the end of the
+    /// source.
+    fn find_next_lexable(&mut self) -> Result<(), Error> {
         while let Some(c) = self.cur_char() {
             if c == '/' && self.peek_char() == Some('/') {
                 self.lex_comment();
+            } else if c == '/' && self.peek_char() == Some('*') {
+                self.lex_block_comment()?;
             } else if c.is_whitespace() {
-                self.index += 1;
+                self.advance();
             } else {
                 break;
             }
         }
+
+        Ok(())
     }
 
     fn lex_next(&mut self) -> Result<Option<(Token, Range<usize>)>, Error> {
-        self.find_next_lexable();
+        self.find_next_lexable()?;
 
         let Some(c) = self.cur_char() else {
             return Ok(None);
@@ -116,6 +183,7 @@ impl Lexer {
         let op = Operator::parse(c, self.peek_char());
 
         match (token_atom, op) {
+            // atoms and operators are ascii, so each character is one byte
             (Some((token, true)), _) => {
                 self.index += 2;
                 return Ok(Some((token, (self.index - 2)..self.index)));
@@ -140,10 +208,15 @@ impl Lexer {
         }
 
         if c.is_ascii_digit() {
-            return Ok(Some(self.lex_number()));
+            return self.lex_number().map(Some);
+        }
+
+        if c == '@' {
+            return self.lex_attribute().map(Some);
         }
 
         if c == '\'' {
+            let start = self.index;
             self.index += 1;
             let Some(character) = self.cur_char() else {
                 return Err(self
@@ -154,22 +227,19 @@ impl Lexer {
 
             let character = self.lex_full_char(character)?;
 
-            self.index += 1;
+            self.advance();
             if !matches!(self.cur_char(), Some('\'')) {
                 return Err(self
                     .err_ctx
                     .unexpected_token(
-                        self.span(self.index..(self.index + 1)),
+                        self.span(self.index..self.next_char_boundary()),
                         format!("expected ' (quote), got '{:?}'", self.cur_char()),
                     )
                     .finish());
             }
 
             self.index += 1;
-            return Ok(Some((
-                Token::Character(character),
-                (self.index - 3)..self.index,
-            )));
+            return Ok(Some((Token::Character(character), start..self.index)));
         }
 
         if c == '"' {
@@ -179,9 +249,10 @@ impl Lexer {
         Err(self
             .err_ctx
             .unexpected_token(
-                self.span(self.index..(self.index + 1)),
+                self.span(self.index..self.next_char_boundary()),
                 "unexpected character",
             )
+            .with_code(ErrorCode::UnexpectedCharacter)
             .finish())
     }
 
@@ -200,7 +271,7 @@ impl Lexer {
             let c = self.lex_full_char(c)?;
             string.push(c);
 
-            self.index += 1;
+            self.advance();
         }
 
         Err(self
@@ -224,12 +295,14 @@ impl Lexer {
                 '"' => '"',
                 '\'' => '\'',
                 'n' => '\n',
+                't' => '\t',
                 '0' => '\0',
                 _ => {
                     let span = self.span((self.index - 1)..self.index);
                     return Err(self
                         .err_ctx
                         .error(span.clone())
+                        .with_code(ErrorCode::InvalidEscape)
                         .with_message("invalid escape character")
                         .with_label(span, "this is not a valid escape character")
                         .finish());
@@ -240,10 +313,11 @@ impl Lexer {
         } else if c.is_ascii() {
             Ok(c)
         } else {
-            let span = self.span((self.index - 1)..self.index);
+            let span = self.span(self.index..self.next_char_boundary());
             return Err(self
                 .err_ctx
                 .error(span.clone())
+                .with_code(ErrorCode::UnexpectedCharacter)
                 .with_message("invalid string")
                 .with_label(span, "not a valid character")
                 .finish());
@@ -252,49 +326,363 @@ impl Lexer {
 
     fn lex_ascii(&mut self) -> (Token, Range<usize>) {
         let start = self.index;
-        let mut string = String::new();
         while let Some(c) = self.cur_char()
             && (c.is_ascii_alphanumeric() || c == '_')
         {
-            string.push(c);
             self.index += 1;
         }
 
-        let token = if let Some(keyword) = Keyword::parse(&string) {
+        let string = &self.code[start..self.index];
+        let token = if let Some(keyword) = Keyword::parse(string) {
             Token::Keyword(keyword)
         } else if let Ok(b) = string.parse::<bool>() {
             Token::Bool(b)
         } else {
-            Token::Ident(string)
+            Token::Ident(string.to_owned())
         };
 
         (token, start..self.index)
     }
 
-    fn lex_number(&mut self) -> (Token, Range<usize>) {
+    fn lex_attribute(&mut self) -> Result<(Token, Range<usize>), Error> {
+        assert!(self.cur_char() == Some('@'));
+
+        let start = self.index;
+        self.index += 1;
+        if let Some(c) = self.cur_char()
+            && (c.is_ascii_alphabetic() || c == '_')
+            && let (Token::Ident(name), range) = self.lex_ascii()
+        {
+            return Ok((Token::Attribute(name), start..range.end));
+        }
+
+        Err(self
+            .err_ctx
+            .unexpected_token(
+                self.span(start..self.index),
+                "expected attribute name after @",
+            )
+            .finish())
+    }
+
+    fn lex_number(&mut self) -> Result<(Token, Range<usize>), Error> {
         let start = self.index;
-        let mut string = String::new();
         while let Some(c) = self.cur_char()
             && c.is_ascii_digit()
         {
-            string.push(c);
             self.index += 1;
         }
 
-        let num: i64 = string.parse().unwrap();
-        (Token::Number(num), start..self.index)
+        let is_float =
+            self.cur_char() == Some('.') && self.peek_char().is_some_and(|c| c.is_ascii_digit());
+        if is_float {
+            self.index += 1;
+            while let Some(c) = self.cur_char()
+                && c.is_ascii_digit()
+            {
+                self.index += 1;
+            }
+        }
+
+        let digits_end = self.index;
+        while let Some(c) = self.cur_char()
+            && (c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.index += 1;
+        }
+
+        let string = &self.code[start..digits_end];
+        let suffix = &self.code[digits_end..self.index];
+
+        if is_float {
+            if !suffix.is_empty() {
+                let span = self.span(digits_end..self.index);
+                return Err(self
+                    .err_ctx
+                    .error(span.clone())
+                    .with_code(ErrorCode::InvalidLiteral)
+                    .with_message(format!("invalid suffix '{}' for float literal", suffix))
+                    .with_label(span, "float literals are always f64")
+                    .finish());
+            }
+
+            let num = string
+                .parse()
+                .expect("float literals are digits around a point");
+            return Ok((Token::Float(num), start..self.index));
+        }
+
+        let typ = (!suffix.is_empty()).then(|| SemanticType::from(&suffix));
+        if let Some(typ) = &typ
+            && !typ.is_integer()
+        {
+            let span = self.span(digits_end..self.index);
+            return Err(self
+                .err_ctx
+                .error(span.clone())
+                .with_code(ErrorCode::InvalidLiteral)
+                .with_message(format!("invalid suffix '{}' for integer literal", suffix))
+                .with_label(span, "expected an integer type like i64 or u64")
+                .finish());
+        }
+
+        // the analyzer checks that suffixed literals fit their type
+        let num = match typ {
+            Some(_) => string.parse::<u64>().ok().map(|n| n as i64),
+            None => string.parse::<i64>().ok(),
+        };
+
+        let Some(num) = num else {
+            let span = self.span(start..self.index);
+            let max = match typ {
+                Some(_) => format!("a u64 (max {})", u64::MAX),
+                None => format!("an i64 (max {})", i64::MAX),
+            };
+            return Err(self
+                .err_ctx
+                .error(span.clone())
+                .with_code(ErrorCode::InvalidLiteral)
+                .with_message("integer literal too large")
+                .with_label(span, format!("does not fit in {}", max))
+                .finish());
+        };
+
+        Ok((Token::Number(num, typ), start..self.index))
     }
 
     fn lex_comment(&mut self) {
-        while let Some(c) = self.peek_char() {
-            self.index += 1;
-            if c == '\n' {
-                break;
+        // stop on the newline, which is skipped as whitespace
+        match self.code[self.index..].find('\n') {
+            Some(len) => self.index += len,
+            None => self.index = self.code.len(),
+        }
+    }
+
+    /// Skips a `/* ... */` comment, up to and including the closing `*/`.
+    fn lex_block_comment(&mut self) -> Result<(), Error> {
+        let start = self.index;
+        match self.code[start + 2..].find("*/") {
+            Some(len) => self.index = start + 2 + len + 2,
+            None => {
+                return Err(self
+                    .err_ctx
+                    .unexpected_eof(self.span(start..start + 2))
+                    .finish());
             }
         }
+
+        Ok(())
+    }
+
+    /// End of the current character, for spans pointing at it.
+    fn next_char_boundary(&self) -> usize {
+        self.index + self.cur_char().map_or(1, char::len_utf8)
     }
 
     fn span(&self, range: Range<usize>) -> (Rc<PathBuf>, Range<usize>) {
         (self.src_path.clone(), range)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lexer(source: &str) -> Lexer<'_> {
+        Lexer::new(Rc::new(PathBuf::from("main")), source).unwrap()
+    }
+
+    #[test]
+    fn iterate_to_completion() {
+        let tokens: Vec<Token> = lexer("x := 42;").map(|token| token.unwrap().0).collect();
+
+        assert_eq!(
+            tokens,
+            [
+                Token::Ident("x".to_owned()),
+                Token::Declare,
+                Token::Number(42, None),
+                Token::Semicolon,
+            ]
+        );
+    }
+
+    #[test]
+    fn integer_literal_overflow() {
+        let Err(err) = Lexer::new(Rc::new(PathBuf::from("main")), "99999999999999999999999") else {
+            panic!("overflowing literal was accepted");
+        };
+
+        assert!(format!("{:?}", err).contains("integer literal too large"));
+    }
+
+    #[test]
+    fn largest_integer_literal() {
+        let tokens: Vec<Token> = lexer("9223372036854775807")
+            .map(|token| token.unwrap().0)
+            .collect();
+
+        assert_eq!(tokens, [Token::Number(i64::MAX, None)]);
+    }
+
+    #[test]
+    fn unexpected_character_code() {
+        let Err(err) = Lexer::new(Rc::new(PathBuf::from("main")), "x := $;") else {
+            panic!("unexpected character was accepted");
+        };
+
+        assert_eq!(err.code().unwrap().to_string(), "E02");
+    }
+
+    #[test]
+    fn suffixed_integer_literals() {
+        let tokens: Vec<Token> = lexer("5i64 18446744073709551615u64")
+            .map(|token| token.unwrap().0)
+            .collect();
+
+        assert_eq!(
+            tokens,
+            [
+                Token::Number(5, Some(SemanticType::I64)),
+                Token::Number(-1, Some(SemanticType::U64)),
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_integer_suffix() {
+        let Err(err) = Lexer::new(Rc::new(PathBuf::from("main")), "5char") else {
+            panic!("invalid suffix was accepted");
+        };
+
+        assert!(format!("{:?}", err).contains("invalid suffix 'char'"));
+    }
+
+    #[test]
+    fn float_literals() {
+        let tokens: Vec<Token> = lexer("1.5 2.25 3").map(|token| token.unwrap().0).collect();
+
+        assert_eq!(
+            tokens,
+            [
+                Token::Float(1.5),
+                Token::Float(2.25),
+                Token::Number(3, None)
+            ]
+        );
+    }
+
+    #[test]
+    fn peek_does_not_advance() {
+        let lexer = lexer("a b");
+
+        assert_eq!(
+            lexer.current().map(|(t, _)| t),
+            Some(&Token::Ident("a".to_owned()))
+        );
+        assert_eq!(
+            lexer.peek().map(|(t, _)| t),
+            Some(&Token::Ident("b".to_owned()))
+        );
+        assert_eq!(
+            lexer.current().map(|(t, _)| t),
+            Some(&Token::Ident("a".to_owned()))
+        );
+    }
+
+    #[test]
+    fn spans_are_byte_offsets() {
+        let tokens: Vec<(Token, Range<usize>)> = lexer("// héllo\nx := \"ab\";")
+            .map(|token| token.unwrap())
+            .collect();
+
+        // 'é' takes two bytes
+        assert_eq!(tokens[0], (Token::Ident("x".to_owned()), 10..11));
+        assert_eq!(tokens[2], (Token::String("ab".to_owned()), 15..19));
+    }
+
+    #[test]
+    fn escaped_character_literals() {
+        let tokens: Vec<Token> = lexer(r"'A' '\n' '\t' '\\' '\''")
+            .map(|token| token.unwrap().0)
+            .collect();
+
+        assert_eq!(tokens, ['A', '\n', '\t', '\\', '\''].map(Token::Character));
+    }
+
+    #[test]
+    fn comment_at_end_of_source() {
+        let tokens: Vec<Token> = lexer("x // done").map(|token| token.unwrap().0).collect();
+
+        assert_eq!(tokens, [Token::Ident("x".to_owned())]);
+    }
+
+    #[test]
+    fn block_comment_between_operands() {
+        let tokens: Vec<(Token, Range<usize>)> =
+            lexer("1 /* c */ + 2").map(|token| token.unwrap()).collect();
+
+        assert_eq!(
+            tokens,
+            [
+                (Token::Number(1, None), 0..1),
+                (Token::Operator(Operator::Plus), 10..11),
+                (Token::Number(2, None), 12..13),
+            ]
+        );
+    }
+
+    #[test]
+    fn line_comment_at_end_of_source_without_space() {
+        let tokens: Vec<(Token, Range<usize>)> =
+            lexer("1 //c").map(|token| token.unwrap()).collect();
+
+        assert_eq!(tokens, [(Token::Number(1, None), 0..1)]);
+    }
+
+    #[test]
+    fn comment_between_signature_and_body() {
+        for source in ["fn main() // c\n{}", "fn main() /* c */ {}"] {
+            let tokens: Vec<(Token, Range<usize>)> =
+                lexer(source).map(|token| token.unwrap()).collect();
+
+            let (token, range) = &tokens[4];
+            assert_eq!(token, &Token::LeftBrace, "{source}");
+            assert_eq!(&source[range.clone()], "{");
+            assert_eq!(tokens.len(), 6);
+        }
+    }
+
+    #[test]
+    fn unterminated_block_comment() {
+        let Err(err) = Lexer::new(Rc::new(PathBuf::from("main")), "1 /* c") else {
+            panic!("unterminated block comment was accepted");
+        };
+
+        assert!(format!("{:?}", err).contains("unexpected end of file"));
+    }
+
+    #[test]
+    fn non_ascii_character_in_string() {
+        let Err(err) = Lexer::new(Rc::new(PathBuf::from("main")), "\"é\"") else {
+            panic!("non-ascii character was accepted");
+        };
+
+        assert!(format!("{:?}", err).contains("invalid string"));
+    }
+
+    #[test]
+    fn large_generated_source() {
+        let source: String = (0..20_000)
+            .map(|i| format!("v{i} := {i} + 1; // ünïcode\n"))
+            .collect();
+
+        let tokens: Vec<(Token, Range<usize>)> =
+            lexer(&source).map(|token| token.unwrap()).collect();
+
+        assert_eq!(tokens.len(), 20_000 * 6);
+        let (token, range) = tokens.last().unwrap();
+        assert_eq!(token, &Token::Semicolon);
+        assert_eq!(&source[range.clone()], ";");
+    }
+}
@@ -1,17 +1,28 @@
+use crate::analyze::semantics::SemanticType;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     Keyword(Keyword),
 
-    Number(i64),
+    /// An integer literal, with the type given by its suffix as in `10u64`. Suffixed literals
+    /// may be larger than `i64::MAX`, and are stored as the same bits.
+    Number(i64, Option<SemanticType>),
+    /// A floating-point literal with digits on both sides of the point, as in `1.5`.
+    Float(f64),
     Character(char),
     String(String),
     Bool(bool),
 
     Ident(String),
+    /// `@name`, placed before a function.
+    Attribute(String),
 
     Semicolon,
     Colon,
+    Question,
     Comma,
+    /// `.`, before the field of a record.
+    Dot,
     LeftParenthesis,
     RightParenthesis,
     LeftCurlyBracket,
@@ -22,6 +33,8 @@ pub enum Token {
     Declare,
     Assign,
     Arrow,
+    /// `=>`
+    FatArrow,
     PathSeparator,
 
     Operator(Operator),
@@ -33,11 +46,14 @@ impl Token {
             (':', Some('=')) => (Self::Declare, true),
             (':', Some(':')) => (Self::PathSeparator, true),
             ('-', Some('>')) => (Self::Arrow, true),
+            ('=', Some('>')) => (Self::FatArrow, true),
 
             ('=', _) => (Self::Assign, false),
             (';', _) => (Self::Semicolon, false),
             (':', _) => (Self::Colon, false),
+            ('?', _) => (Self::Question, false),
             (',', _) => (Self::Comma, false),
+            ('.', _) => (Self::Dot, false),
             ('(', _) => (Self::LeftParenthesis, false),
             (')', _) => (Self::RightParenthesis, false),
             ('{', _) => (Self::LeftCurlyBracket, false),
@@ -65,6 +81,7 @@ pub enum Operator {
     Minus,
     Star,
     Slash,
+    Percent,
 }
 
 impl Operator {
@@ -81,6 +98,7 @@ impl Operator {
             ('-', _) => (Self::Minus, false),
             ('*', _) => (Self::Star, false),
             ('/', _) => (Self::Slash, false),
+            ('%', _) => (Self::Percent, false),
 
             _ => return None,
         };
@@ -94,7 +112,7 @@ impl Operator {
         match self {
             Equal | NotEqual | Less | LessOrEqual | Greater | GreaterOrEqual => 0,
             Plus | Minus => 1,
-            Star | Slash => 2,
+            Star | Slash | Percent => 2,
         }
     }
 }
@@ -104,12 +122,17 @@ pub enum Keyword {
     Function,
     Return,
     If,
+    Else,
     Use,
     Extern,
     As,
     While,
     For,
     In,
+    Break,
+    Const,
+    Switch,
+    Let,
 }
 
 impl Keyword {
@@ -118,12 +141,17 @@ impl Keyword {
             "fn" => Keyword::Function,
             "return" => Keyword::Return,
             "if" => Keyword::If,
+            "else" => Keyword::Else,
             "use" => Keyword::Use,
             "extern" => Keyword::Extern,
             "as" => Keyword::As,
             "while" => Keyword::While,
             "for" => Keyword::For,
             "in" => Keyword::In,
+            "break" => Keyword::Break,
+            "const" => Keyword::Const,
+            "switch" => Keyword::Switch,
+            "let" => Keyword::Let,
             _ => return None,
         };
 
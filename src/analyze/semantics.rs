@@ -1,46 +1,78 @@
 use std::{
     collections::{HashMap, HashSet},
     fmt,
+    rc::Rc,
 };
 
 use crate::{
     analyze::{
-        ErrorContext, ErrorVec, Span,
-        ast::{AST, Assignable, ExprInner, Expression, Item, Statement},
+        ColorChoice, DEFAULT_TAB_WIDTH, ErrorContext, ErrorVec, Messages, Span,
+        ast::{AST, ArithmeticOp, Assignable, Attribute, ExprInner, Expression, Item, Statement},
     },
     ir::VarSize,
 };
 
-pub struct ValidAST(pub AST);
+pub struct ValidAST {
+    pub ast: AST,
+    pub warnings: ErrorVec,
+}
 
 const MAIN_FN: &str = "main";
 
-pub fn analyze(mut ast: AST) -> Result<ValidAST, ErrorVec> {
-    let analyzer = Analyzer::new();
-    analyzer.analyze(&mut ast)?;
+pub fn analyze(ast: AST, color: ColorChoice) -> Result<ValidAST, ErrorVec> {
+    analyze_with_messages(ast, color, Rc::default(), DEFAULT_TAB_WIDTH)
+}
+
+/// Like [analyze], with the wording of diagnostics replaced and tabs expanded to `tab_width`
+/// columns.
+pub fn analyze_with_messages(
+    mut ast: AST,
+    color: ColorChoice,
+    messages: Rc<Messages>,
+    tab_width: usize,
+) -> Result<ValidAST, ErrorVec> {
+    let analyzer = Analyzer::new(color, messages, tab_width);
+    let warnings = analyzer.analyze(&mut ast)?;
 
-    Ok(ValidAST(ast))
+    Ok(ValidAST { ast, warnings })
 }
 
 struct Analyzer {
     err_ctx: ErrorContext,
 
     variables: HashMap<String, SemanticType>,
+    /// Where each parameter of the function being analyzed is declared.
+    params: HashMap<String, Span>,
+    /// Labels of the enclosing labeled blocks, innermost last.
+    labels: Vec<String>,
     functions: HashMap<String, (Span, SemanticType, Vec<(Span, SemanticType)>)>,
-    called_funcs: HashSet<String>,
+    const_fns: HashSet<String>,
+    /// Whether the function being analyzed is a const function, which may only do what can be
+    /// evaluated at compile time.
+    in_const_fn: bool,
+    /// Name of the function being analyzed.
+    current_fn: String,
+    /// The functions called by each function.
+    calls: HashMap<String, HashSet<String>>,
 }
 
 impl Analyzer {
-    pub fn new() -> Self {
+    pub fn new(color: ColorChoice, messages: Rc<Messages>, tab_width: usize) -> Self {
         Self {
-            err_ctx: ErrorContext::new(),
+            err_ctx: ErrorContext::with_messages(color, messages).with_tab_width(tab_width),
             variables: HashMap::new(),
+            params: HashMap::new(),
+            labels: Vec::new(),
             functions: HashMap::new(),
-            called_funcs: HashSet::from([String::from(MAIN_FN)]),
+            const_fns: HashSet::new(),
+            in_const_fn: false,
+            current_fn: String::new(),
+            calls: HashMap::new(),
         }
     }
 
-    pub fn analyze(mut self, ast: &mut AST) -> Result<(), ErrorVec> {
+    /// Returns the warnings if there are no errors.
+    pub fn analyze(mut self, ast: &mut AST) -> Result<ErrorVec, ErrorVec> {
         for item in &ast.items {
             if let Item::Function {
                 name,
@@ -56,6 +88,31 @@ impl Analyzer {
                 args,
             } = item
             {
+                if let SemanticType::Record(fields) = ret_type {
+                    self.check_record_fields(fields, decl_span);
+                }
+
+                for (_, typ, span) in args {
+                    if let SemanticType::Record(_) = typ {
+                        self.err_ctx
+                            .error(span.clone())
+                            .with_message("records cannot be passed to functions yet")
+                            .with_label(span.clone(), format!("this is of type {}", typ))
+                            .with_help("pass the fields as separate arguments")
+                            .report();
+                    }
+                }
+
+                // calls to intrinsics never reach a function of the same name
+                if intrinsic(name).is_some() {
+                    self.err_ctx
+                        .error(decl_span.clone())
+                        .with_message("redefinition of a built-in function")
+                        .with_label(decl_span.clone(), format!("{} is built in", name))
+                        .with_help("give the function another name")
+                        .report();
+                }
+
                 let args = args
                     .iter()
                     .map(|(_, typ, span)| (span.clone(), typ.clone()))
@@ -75,40 +132,70 @@ impl Analyzer {
             }
         }
 
+        for item in &ast.items {
+            if let Item::Function {
+                name,
+                is_const: true,
+                ..
+            } = item
+            {
+                self.const_fns.insert(name.clone());
+            }
+        }
+
         for item in &mut ast.items {
             self.item(item);
         }
 
+        let defined: HashSet<String> = ast
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Function { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let reachable = self.reachable_fns();
+        for item in &ast.items {
+            // library functions are mangled as `lib::name`, and are not the user's to trim
+            if let Item::Function {
+                name, decl_span, ..
+            } = item
+                && !reachable.contains(name)
+                && !name.contains("::")
+            {
+                self.err_ctx
+                    .warn(decl_span.clone())
+                    .with_message("unused function")
+                    .with_label(decl_span.clone(), "function is never called from main")
+                    .report();
+            }
+        }
+
+        let called: HashSet<&String> = self.calls.values().flatten().collect();
+        let called = |name: &String| name == MAIN_FN || called.contains(name);
+
+        // Forward declarations that are called but never defined are kept when libc is imported,
+        // so the backend can resolve them as external symbols. Without the import, calling them
+        // is still an error.
+        let links_libc = ast.imports().any(|lib| lib == "libc");
         ast.items.retain(|item| match item {
-            Item::Function { name, .. } => self.called_funcs.contains(name),
-            Item::ForwardDecl { .. } => false,
+            Item::Function { name, .. } => called(name),
+            Item::ForwardDecl { name, .. } => links_libc && called(name) && !defined.contains(name),
             Item::ExternLib(_) => false,
         });
 
-        // for item in &ast.items {
-        //     if let Item::Function {
-        //         name, decl_span, ..
-        //     } = item
-        //         && name != MAIN_FN
-        //         && !self.called_funcs.contains(name)
-        //     {
-        //         // self.err_ctx
-        //         //     .warn(decl_span.clone())
-        //         //     .with_message("unused function")
-        //         //     .with_label(decl_span.clone(), "function is never used")
-        //         //     .report();
-        //     }
-        // }
-
         if !self.err_ctx.is_empty() {
             return Err(self.err_ctx.take_errors());
         }
 
-        Ok(())
+        Ok(self.err_ctx.take_warnings())
     }
 
     fn item(&mut self, item: &mut Item) {
         self.variables.clear();
+        self.params.clear();
 
         match item {
             Item::Function {
@@ -117,11 +204,38 @@ impl Analyzer {
                 body,
                 decl_span,
                 ret_type,
+                is_const,
+                attributes,
             } => {
-                for (arg, typ, _) in args {
+                self.in_const_fn = *is_const;
+                self.current_fn = name.clone();
+
+                if attributes.contains(&Attribute::Inline)
+                    && attributes.contains(&Attribute::NoInline)
+                {
+                    self.err_ctx
+                        .error(decl_span.clone())
+                        .with_message("conflicting attributes")
+                        .with_label(
+                            decl_span.clone(),
+                            "a function cannot be both @inline and @noinline",
+                        )
+                        .report();
+                }
+
+                if name == MAIN_FN {
+                    self.main_args(args);
+                }
+
+                for (arg, typ, span) in args {
                     self.variables.insert(arg.to_owned(), typ.clone());
+                    self.params.insert(arg.to_owned(), span.clone());
                 }
 
+                // main returns the exit code, even without a declared return type
+                if name == MAIN_FN && *ret_type == SemanticType::Unit {
+                    *ret_type = SemanticType::I64;
+                }
                 let has_return = self.body(body, ret_type, decl_span);
 
                 if !has_return && name == MAIN_FN {
@@ -137,6 +251,28 @@ impl Analyzer {
         }
     }
 
+    /// `main` receives `argc` and `argv` from the bootstrap, so it may declare at most those two
+    /// parameters, both as `i64`.
+    fn main_args(&mut self, args: &[(String, SemanticType, Span)]) {
+        if let Some((_, _, span)) = args.get(2) {
+            self.err_ctx
+                .error(span.clone())
+                .with_message("too many parameters for function main")
+                .with_label(span.clone(), "main only takes argc and argv")
+                .report();
+        }
+
+        for (_, typ, span) in args.iter().take(2) {
+            if *typ != SemanticType::I64 {
+                self.err_ctx
+                    .error(span.clone())
+                    .with_message("mismatched types")
+                    .with_label(span.clone(), format!("expected i64, found {}", typ))
+                    .report();
+            }
+        }
+    }
+
     /// Returns whether this statement contains a return statement
     fn body(
         &mut self,
@@ -154,6 +290,22 @@ impl Analyzer {
         has_return
     }
 
+    /// Checks that the guard of an `if` or `while` is a bool.
+    fn guard(&mut self, guard: &mut Expression) {
+        if let Some(typ) = self.expression(guard)
+            && typ != SemanticType::Bool
+        {
+            self.err_ctx
+                .error(guard.span.clone())
+                .with_message("unexpected type")
+                .with_label(
+                    guard.span.clone(),
+                    format!("expected type 'bool', got '{}'", typ),
+                )
+                .report();
+        }
+    }
+
     /// Returns whether this statement contains a return statement
     fn statement(
         &mut self,
@@ -168,11 +320,22 @@ impl Analyzer {
                 var_span,
             } => {
                 let var_type = self.expression(expr);
-                if self
+                let declared = self
                     .variables
                     .insert(var.clone(), var_type.unwrap_or(SemanticType::Unit))
-                    .is_some()
-                {
+                    .is_some();
+
+                if let Some(param_span) = self.params.get(var) {
+                    self.err_ctx
+                        .error(var_span.clone())
+                        .with_message("local shadows parameter")
+                        .with_label(
+                            var_span.clone(),
+                            format!("'{}' is declared again here", var),
+                        )
+                        .with_label(param_span.clone(), "parameter declared here")
+                        .report();
+                } else if declared {
                     self.err_ctx
                         .error(var_span.clone())
                         .with_message("duplicate variable declaration")
@@ -185,10 +348,27 @@ impl Analyzer {
                 expr,
                 var_span,
             } => {
+                if let Assignable::Var(var) = var
+                    && let ExprInner::Variable(src) = &expr.inner
+                    && src == var
+                {
+                    self.err_ctx
+                        .warn(combine_span(var_span, &expr.span))
+                        .with_message("self-assignment")
+                        .with_label(
+                            var_span.clone(),
+                            format!("assigning '{}' to itself has no effect", var),
+                        )
+                        .report();
+                }
+
                 let assign_type = self.expression(expr);
                 let decl_type = match var {
                     Assignable::Var(var) => self.check_var(var, var_span),
-                    Assignable::Ptr(ptr) => self.check_ptr(ptr, var_span),
+                    Assignable::Ptr(ptr) => {
+                        self.check_const_allowed(var_span, "pointer writes");
+                        self.check_ptr(ptr, var_span)
+                    }
                 };
 
                 if let Some(assign_type) = assign_type
@@ -206,26 +386,107 @@ impl Analyzer {
                         .report();
                 }
             }
-            Statement::If { guard, body } | Statement::WhileLoop { guard, body } => {
-                if let Some(typ) = self.expression(guard)
-                    && typ != SemanticType::Bool
+            Statement::If {
+                guard,
+                body,
+                otherwise,
+            } => {
+                self.guard(guard);
+
+                let mut has_return = false;
+                for body in std::iter::once(body).chain(otherwise) {
+                    // variables declared in a branch go out of scope at its end
+                    let outer_variables = self.variables.clone();
+                    has_return |= self.body(body, fn_ret_type, fn_decl_span);
+                    self.variables = outer_variables;
+                }
+
+                return has_return;
+            }
+            Statement::WhileLoop { guard, body } => {
+                self.guard(guard);
+
+                // variables declared in the block go out of scope at its end
+                let outer_variables = self.variables.clone();
+                let has_return = self.body(body, fn_ret_type, fn_decl_span);
+                self.variables = outer_variables;
+
+                return has_return;
+            }
+            Statement::Labeled { label, body, .. } => {
+                self.labels.push(label.clone());
+
+                let outer_variables = self.variables.clone();
+                let has_return = self.body(body, fn_ret_type, fn_decl_span);
+                self.variables = outer_variables;
+
+                self.labels.pop();
+
+                return has_return;
+            }
+            Statement::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                if let Some(typ) = self.expression(value)
+                    && !typ.is_integer()
+                    && typ != SemanticType::Char
                 {
                     self.err_ctx
-                        .error(guard.span.clone())
+                        .error(value.span.clone())
                         .with_message("unexpected type")
                         .with_label(
-                            guard.span.clone(),
-                            format!("expected type 'bool', got '{}'", typ),
+                            value.span.clone(),
+                            format!("expected an integer or char, got '{}'", typ),
                         )
                         .report();
                 }
 
-                return self.body(body, fn_ret_type, fn_decl_span);
+                let mut seen: HashMap<i64, Span> = HashMap::new();
+                for (n, span, _) in cases.iter() {
+                    if let Some(first) = seen.insert(*n, span.clone()) {
+                        self.err_ctx
+                            .error(span.clone())
+                            .with_message("duplicate switch case")
+                            .with_label(span.clone(), format!("{} is already handled", n))
+                            .with_label(first, "first handled here")
+                            .report();
+                    }
+                }
+
+                let mut has_return = false;
+                let bodies = cases.iter_mut().map(|(_, _, body)| body);
+                for body in bodies.chain(default) {
+                    // variables declared in a case go out of scope at its end
+                    let outer_variables = self.variables.clone();
+                    has_return |= self.body(body, fn_ret_type, fn_decl_span);
+                    self.variables = outer_variables;
+                }
+
+                return has_return;
+            }
+            Statement::Break { label, span } => {
+                if !self.labels.contains(label) {
+                    self.err_ctx
+                        .error(span.clone())
+                        .with_message(format!("unknown label '{}'", label))
+                        .with_label(span.clone(), "not inside a block with this label")
+                        .report();
+                }
             }
             Statement::Expr(expr) => {
                 self.expression(expr);
+
+                if expr.is_pure() {
+                    self.err_ctx
+                        .warn(expr.span.clone())
+                        .with_message("statement has no effect")
+                        .with_label(expr.span.clone(), "this value is never used")
+                        .report();
+                }
             }
-            Statement::Return(expr) => {
+            Statement::Return(expr) | Statement::Tail(expr) => {
                 if let Some(typ) = self.expression(expr)
                     && &typ != fn_ret_type
                 {
@@ -249,27 +510,84 @@ impl Analyzer {
 
     fn expression(&mut self, expr: &mut Expression) -> Option<SemanticType> {
         match &mut expr.inner {
-            ExprInner::Const(_) => Some(SemanticType::I64),
+            ExprInner::Const(_, None) => Some(SemanticType::I64),
+            ExprInner::Const(num, Some(typ)) => {
+                if !typ.fits_literal(*num) {
+                    self.err_ctx
+                        .error(expr.span.clone())
+                        .with_message("literal out of range")
+                        .with_label(
+                            expr.span.clone(),
+                            format!("does not fit in {} (max {})", typ, typ.max_literal()),
+                        )
+                        .report();
+                }
+
+                Some(typ.clone())
+            }
+            ExprInner::Float(_) => Some(SemanticType::F64),
             ExprInner::Character(_) => Some(SemanticType::Char),
-            ExprInner::String(_) => Some(SemanticType::Pointer(Box::new(SemanticType::Char))),
+            ExprInner::String(_) => {
+                self.check_const_allowed(&expr.span, "string literals");
+                Some(SemanticType::Pointer(Box::new(SemanticType::Char)))
+            }
             ExprInner::Bool(_) => Some(SemanticType::Bool),
 
             ExprInner::Variable(var) => self.check_var(var, &expr.span),
-            ExprInner::Pointer(var) => self
-                .check_var(var, &expr.span)
-                .map(|t| SemanticType::Pointer(Box::new(t))),
+            ExprInner::Pointer(var) => {
+                self.check_const_allowed(&expr.span, "references");
+                let typ = self.check_var(var, &expr.span)?;
+                if let SemanticType::Record(_) = typ {
+                    self.err_ctx
+                        .error(expr.span.clone())
+                        .with_message("references to records are not supported yet")
+                        .with_label(expr.span.clone(), format!("{} is of type {}", var, typ))
+                        .report();
+                    return None;
+                }
+
+                Some(SemanticType::Pointer(Box::new(typ)))
+            }
             ExprInner::Deref(var, typ) => {
+                self.check_const_allowed(&expr.span, "pointer reads");
                 *typ = self.check_ptr(var, &expr.span);
                 typ.clone()
             }
 
-            ExprInner::Arithmetic(expr1, expr2, _op, expr_sign) => {
+            ExprInner::Negation(operand, operand_type) => {
+                let typ = self.expression(operand)?;
+                if matches!(typ, SemanticType::I64 | SemanticType::F64) {
+                    *operand_type = Some(typ.clone());
+                    return Some(typ);
+                }
+
+                self.err_ctx
+                    .error(expr.span.clone())
+                    .with_message("invalid negation")
+                    .with_label(
+                        operand.span.clone(),
+                        format!("this is of type {}, expected i64 or f64", typ),
+                    )
+                    .report();
+                None
+            }
+
+            ExprInner::Arithmetic(expr1, expr2, op, operand_type) => {
                 if let Some(type1) = self.expression(expr1)
                     && let Some(type2) = self.expression(expr2)
                 {
+                    if type1 == SemanticType::F64 && matches!(op, ArithmeticOp::Mod) {
+                        self.err_ctx
+                            .error(combine_span(&expr1.span, &expr2.span))
+                            .with_message("f64 values have no remainder yet")
+                            .with_label(expr1.span.clone(), "this is of type f64")
+                            .report();
+                        return None;
+                    }
+
                     if type1 == type2 {
-                        if let Some(type_sign) = type1.sign() {
-                            *expr_sign = Some(type_sign);
+                        if type1.is_arithmetic() {
+                            *operand_type = Some(type1.clone());
                             return Some(type1);
                         }
 
@@ -278,17 +596,31 @@ impl Analyzer {
                             .with_message("mismatched arithmetic types")
                             .with_label(
                                 expr1.span.clone(),
-                                "arithmetic only allowed on integer types",
+                                "arithmetic only allowed on integer and float types",
                             )
                             .report();
+                        return None;
                     }
 
-                    self.err_ctx
+                    let error = self
+                        .err_ctx
                         .error(combine_span(&expr1.span, &expr2.span))
                         .with_message("mismatched types")
                         .with_label(expr1.span.clone(), format!("this is of type {}", type1))
-                        .with_label(expr2.span.clone(), format!("this is of type {}", type2))
-                        .report();
+                        .with_label(expr2.span.clone(), format!("this is of type {}", type2));
+
+                    // operands are never converted implicitly, even between numeric types
+                    if type1.is_arithmetic() && type2.can_cast_to(&type1) {
+                        error
+                            .with_help(format!("convert the right operand with `as {}`", type1))
+                            .report();
+                    } else if type2.is_arithmetic() && type1.can_cast_to(&type2) {
+                        error
+                            .with_help(format!("convert the left operand with `as {}`", type2))
+                            .report();
+                    } else {
+                        error.report();
+                    }
                 }
 
                 None
@@ -298,6 +630,25 @@ impl Analyzer {
                 if let Some(type1) = self.expression(expr1)
                     && let Some(type2) = self.expression(expr2)
                 {
+                    if let SemanticType::Record(_) = type1 {
+                        self.err_ctx
+                            .error(combine_span(&expr1.span, &expr2.span))
+                            .with_message("records cannot be compared")
+                            .with_label(expr1.span.clone(), format!("this is of type {}", type1))
+                            .with_help("compare the fields one by one")
+                            .report();
+                        return None;
+                    }
+
+                    if type1 == SemanticType::F64 && type2 == SemanticType::F64 {
+                        self.err_ctx
+                            .error(combine_span(&expr1.span, &expr2.span))
+                            .with_message("f64 values cannot be compared yet")
+                            .with_label(expr1.span.clone(), "this is of type f64")
+                            .report();
+                        return None;
+                    }
+
                     if type1 == type2 {
                         let sign1 = type1.sign();
                         let sign2 = type2.sign();
@@ -339,9 +690,57 @@ impl Analyzer {
                 None
             }
 
-            ExprInner::Cast(expr, cast_to) => {
+            ExprInner::Ternary(cond, then, otherwise) => {
+                let cond_type = self.expression(cond);
+                let then_type = self.expression(then);
+                let otherwise_type = self.expression(otherwise);
+
+                if let Some(cond_type) = cond_type
+                    && cond_type != SemanticType::Bool
+                    && cond_type.sign().is_none()
+                {
+                    self.err_ctx
+                        .error(cond.span.clone())
+                        .with_message("invalid condition")
+                        .with_label(
+                            cond.span.clone(),
+                            format!("this is of type {}, expected bool or integer", cond_type),
+                        )
+                        .report();
+                }
+
+                let (then_type, otherwise_type) = (then_type?, otherwise_type?);
+                if let SemanticType::Record(_) = then_type {
+                    self.err_ctx
+                        .error(then.span.clone())
+                        .with_message("conditional expressions cannot produce records yet")
+                        .with_label(then.span.clone(), format!("this is of type {}", then_type))
+                        .with_help("use an if statement instead")
+                        .report();
+                    return None;
+                }
+
+                if then_type == otherwise_type {
+                    return Some(then_type);
+                }
+
+                self.err_ctx
+                    .error(combine_span(&then.span, &otherwise.span))
+                    .with_message("mismatched types")
+                    .with_label(then.span.clone(), format!("this is of type {}", then_type))
+                    .with_label(
+                        otherwise.span.clone(),
+                        format!("this is of type {}", otherwise_type),
+                    )
+                    .report();
+
+                None
+            }
+
+            ExprInner::Cast(expr, cast_to, cast_from) => {
                 if let Some(expr_type) = self.expression(expr) {
                     if expr_type.can_cast_to(cast_to) {
+                        *cast_from = Some(expr_type);
                         return Some(cast_to.clone());
                     }
 
@@ -364,6 +763,41 @@ impl Analyzer {
                     .filter_map(|e| self.expression(e).map(|t| (t, e.span.clone())))
                     .collect();
 
+                if let Some((decl_types, ret_type)) = intrinsic(function) {
+                    if decl_types.len() != call_args.len() {
+                        self.err_ctx
+                            .error(expr.span.clone())
+                            .with_message("invalid argument count")
+                            .with_label(
+                                expr.span.clone(),
+                                format!(
+                                    "expected {} arguments, got {}",
+                                    decl_types.len(),
+                                    call_args.len()
+                                ),
+                            )
+                            .report();
+                    }
+
+                    for ((call_type, call_span), decl_type) in call_types.iter().zip(&decl_types) {
+                        if call_type != decl_type {
+                            self.err_ctx
+                                .error(call_span.clone())
+                                .with_message("incompatible types")
+                                .with_label(
+                                    call_span.clone(),
+                                    format!(
+                                        "this is of type {}, expected {}",
+                                        call_type, decl_type
+                                    ),
+                                )
+                                .report();
+                        }
+                    }
+
+                    return Some(ret_type);
+                }
+
                 if let Some((fn_decl_span, ret_type, decl_args)) = self.functions.get(function) {
                     if decl_args.len() != call_args.len() {
                         self.err_ctx
@@ -400,10 +834,20 @@ impl Analyzer {
                         }
                     }
 
-                    if !self.called_funcs.contains(function) {
-                        self.called_funcs.insert(function.to_owned());
+                    if self.in_const_fn && !self.const_fns.contains(function) {
+                        self.err_ctx
+                            .error(expr.span.clone())
+                            .with_message("call to non-const function in const function")
+                            .with_label(expr.span.clone(), format!("{} is not const", function))
+                            .with_label(fn_decl_span.clone(), "function defined here")
+                            .report();
                     }
 
+                    self.calls
+                        .entry(self.current_fn.clone())
+                        .or_default()
+                        .insert(function.to_owned());
+
                     return Some(ret_type.clone());
                 } else {
                     self.err_ctx
@@ -415,6 +859,100 @@ impl Analyzer {
 
                 None
             }
+
+            ExprInner::Record(fields) => {
+                let fields: Vec<RecordField> = fields
+                    .iter_mut()
+                    .map(|field| self.expression(field).map(RecordField::unnamed))
+                    .collect::<Option<_>>()?;
+
+                self.check_record_fields(&fields, &expr.span)
+                    .then_some(SemanticType::Record(fields))
+            }
+            ExprInner::Field(record, field, position) => match self.expression(record)? {
+                SemanticType::Record(mut fields)
+                    if let Some(index) = field_position(&fields, field) =>
+                {
+                    *position = Some(index);
+                    Some(fields.swap_remove(index).typ)
+                }
+                typ @ SemanticType::Record(_) => {
+                    self.err_ctx
+                        .error(expr.span.clone())
+                        .with_message("no such field")
+                        .with_label(expr.span.clone(), format!("{} has no field {}", typ, field))
+                        .report();
+                    None
+                }
+                typ => {
+                    self.err_ctx
+                        .error(expr.span.clone())
+                        .with_message("field access on a non-record")
+                        .with_label(record.span.clone(), format!("this is of type {}", typ))
+                        .report();
+                    None
+                }
+            },
+        }
+    }
+
+    /// Reports records that can't be returned in two registers, and returns whether the
+    /// fields are valid.
+    fn check_record_fields(&mut self, fields: &[RecordField], span: &Span) -> bool {
+        if fields.len() != 2 {
+            self.err_ctx
+                .error(span.clone())
+                .with_message("unsupported record")
+                .with_label(
+                    span.clone(),
+                    format!("records have two fields, this has {}", fields.len()),
+                )
+                .report();
+            return false;
+        }
+
+        let mut valid = true;
+        for RecordField { typ, .. } in fields {
+            if matches!(
+                typ,
+                SemanticType::Unit | SemanticType::F64 | SemanticType::Record(_)
+            ) {
+                self.err_ctx
+                    .error(span.clone())
+                    .with_message("unsupported record field")
+                    .with_label(span.clone(), format!("fields cannot be of type {}", typ))
+                    .report();
+                valid = false;
+            }
+        }
+
+        valid
+    }
+
+    /// Functions that may run, by following the calls made from `main`.
+    fn reachable_fns(&self) -> HashSet<String> {
+        let mut reachable = HashSet::from([String::from(MAIN_FN)]);
+        let mut pending = vec![MAIN_FN];
+
+        while let Some(function) = pending.pop() {
+            for callee in self.calls.get(function).into_iter().flatten() {
+                if reachable.insert(callee.clone()) {
+                    pending.push(callee);
+                }
+            }
+        }
+
+        reachable
+    }
+
+    /// Reports `what` as not allowed if the current function is const.
+    fn check_const_allowed(&mut self, span: &Span, what: &str) {
+        if self.in_const_fn {
+            self.err_ctx
+                .error(span.clone())
+                .with_message(format!("{} are not allowed in const functions", what))
+                .with_label(span.clone(), "cannot be evaluated at compile time")
+                .report();
         }
     }
 
@@ -423,10 +961,11 @@ impl Analyzer {
             return Some(typ.clone());
         }
 
+        let messages = self.err_ctx.messages().clone();
         self.err_ctx
             .error(span.clone())
-            .with_message("undeclared variable")
-            .with_label(span.clone(), "this guy doesn't exist")
+            .with_message(&messages.undeclared_variable)
+            .with_label(span.clone(), &messages.undeclared_variable_label)
             .report();
 
         None
@@ -435,6 +974,13 @@ impl Analyzer {
     fn check_ptr(&mut self, symbol: &str, span: &Span) -> Option<SemanticType> {
         if let Some(typ) = self.check_var(symbol, &span) {
             match typ {
+                SemanticType::Pointer(typ) if matches!(*typ, SemanticType::Record(_)) => {
+                    self.err_ctx
+                        .error(span.clone())
+                        .with_message("pointers to records are not supported yet")
+                        .with_label(span.clone(), format!("{} points to {}", symbol, typ))
+                        .report();
+                }
                 SemanticType::Pointer(typ) => return Some(*typ),
                 typ => {
                     self.err_ctx
@@ -450,6 +996,35 @@ impl Analyzer {
     }
 }
 
+/// Returns the argument types and return type of a built-in function that the backend provides
+/// itself, or `None` if `name` is not an intrinsic.
+fn intrinsic(name: &str) -> Option<(Vec<SemanticType>, SemanticType)> {
+    let signature = match name {
+        "assert" => (vec![SemanticType::Bool], SemanticType::Unit),
+        "abs" => (vec![SemanticType::I64], SemanticType::I64),
+        "print" | "println" => (vec![SemanticType::I64], SemanticType::Unit),
+        "strlen" => (
+            vec![SemanticType::Pointer(Box::new(SemanticType::Char))],
+            SemanticType::I64,
+        ),
+        "min" | "max" => (
+            vec![SemanticType::I64, SemanticType::I64],
+            SemanticType::I64,
+        ),
+        _ => return None,
+    };
+
+    Some(signature)
+}
+
+/// The position of the field called `name`, which may also be the position itself, as in `r.0`.
+fn field_position(fields: &[RecordField], name: &str) -> Option<usize> {
+    fields
+        .iter()
+        .position(|field| field.name.as_deref() == Some(name))
+        .or_else(|| name.parse().ok().filter(|&index| index < fields.len()))
+}
+
 fn combine_span(span: &Span, span_2: &Span) -> Span {
     (span.0.clone(), span.1.start..span_2.1.end)
 }
@@ -465,41 +1040,99 @@ pub enum SemanticType {
     Unit,
     I64,
     U64,
+    F64,
     Char,
     Bool,
     Pointer(Box<SemanticType>),
+    /// A record of fields, passed by value. Only two-field records of integers, chars, bools
+    /// and pointers are supported, which are returned in two registers.
+    Record(Vec<RecordField>),
     UserType(String),
 }
 
+/// A field of a record type. Fields may be named, as in `(x: i64, y: i64)`, and can always be
+/// reached by their position. Names are only labels, so records with the same field types are the
+/// same type, and `(1, 2)` can be returned as `(x: i64, y: i64)`.
+#[derive(Debug, Clone)]
+pub struct RecordField {
+    pub name: Option<String>,
+    pub typ: SemanticType,
+}
+
+impl RecordField {
+    pub fn unnamed(typ: SemanticType) -> Self {
+        Self { name: None, typ }
+    }
+}
+
+impl PartialEq for RecordField {
+    fn eq(&self, other: &Self) -> bool {
+        self.typ == other.typ
+    }
+}
+
 impl SemanticType {
     pub fn sign(&self) -> Option<Sign> {
         match self {
             SemanticType::Unit => None,
             SemanticType::I64 => Some(Sign::Signed),
             SemanticType::U64 => Some(Sign::Unsigned),
+            SemanticType::F64 => None,
             SemanticType::Char => Some(Sign::Unsigned),
             SemanticType::Bool => None,
             SemanticType::Pointer(typ) => typ.sign(),
+            SemanticType::Record(_) => None,
             SemanticType::UserType(_) => None,
         }
     }
 
-    pub fn size(&self) -> VarSize {
+    /// Size of a value of this type when it is held in one register, or `None` for records,
+    /// which take one register per field, and for user types.
+    pub fn size(&self) -> Option<VarSize> {
+        match self {
+            SemanticType::Unit => Some(VarSize::Zero),
+            SemanticType::I64 | SemanticType::U64 | SemanticType::F64 => Some(VarSize::B64),
+            SemanticType::Bool | SemanticType::Char => Some(VarSize::B8),
+            SemanticType::Pointer(_) => Some(VarSize::B64),
+            SemanticType::Record(_) | SemanticType::UserType(_) => None,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, SemanticType::I64 | SemanticType::U64)
+    }
+
+    /// Whether values of this type can be added, subtracted, multiplied and divided.
+    pub fn is_arithmetic(&self) -> bool {
+        self.sign().is_some() || *self == SemanticType::F64
+    }
+
+    /// The largest literal of this integer type.
+    pub fn max_literal(&self) -> u64 {
         match self {
-            SemanticType::Unit => VarSize::Zero,
-            SemanticType::I64 | SemanticType::U64 => VarSize::B64,
-            SemanticType::Bool | SemanticType::Char => VarSize::B8,
-            SemanticType::Pointer(_) => VarSize::B64,
-            SemanticType::UserType(_) => todo!(),
+            SemanticType::I64 => i64::MAX as u64,
+            _ => u64::MAX,
         }
     }
 
+    /// Whether a literal, stored as the bits of `num`, fits in this type. Literals are never
+    /// negative, so bits above `i64::MAX` stand for large unsigned values.
+    pub fn fits_literal(&self, num: i64) -> bool {
+        num as u64 <= self.max_literal()
+    }
+
     pub fn can_cast_to(&self, other: &SemanticType) -> bool {
         use SemanticType::*;
 
         matches!(
             (self, other),
-            (Char, I64) | (I64, Char) | (Pointer(_), I64) | (I64, Pointer(_))
+            (Char, I64)
+                | (I64, Char)
+                | (Bool, I64)
+                | (F64, I64)
+                | (I64, F64)
+                | (Pointer(_), I64)
+                | (I64, Pointer(_))
         )
     }
 }
@@ -516,6 +1149,7 @@ impl<S: AsRef<str>> From<S> for SemanticType {
         match string {
             "i64" => Self::I64,
             "u64" => Self::U64,
+            "f64" => Self::F64,
             "char" => Self::Char,
             "bool" => Self::Bool,
             name => Self::UserType(name.to_owned()),
@@ -529,10 +1163,260 @@ impl fmt::Display for SemanticType {
             SemanticType::Unit => write!(f, "()"),
             SemanticType::I64 => write!(f, "i64"),
             SemanticType::U64 => write!(f, "u64"),
+            SemanticType::F64 => write!(f, "f64"),
             SemanticType::Char => write!(f, "char"),
             SemanticType::Bool => write!(f, "bool"),
             SemanticType::Pointer(typ) => write!(f, "&{}", typ),
+            SemanticType::Record(fields) => {
+                write!(f, "(")?;
+                for (i, field) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    if let Some(name) = &field.name {
+                        write!(f, "{}: ", name)?;
+                    }
+                    write!(f, "{}", field.typ)?;
+                }
+                write!(f, ")")
+            }
             SemanticType::UserType(typ) => write!(f, "{}", typ),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, rc::Rc};
+
+    use super::*;
+    use crate::analyze::{ast::parse::Parser, lex::Lexer};
+
+    fn try_analyze(source: &str) -> Result<ValidAST, ErrorVec> {
+        let path = Rc::new(PathBuf::from("main"));
+        let lexer = Lexer::new(path.clone(), source).unwrap();
+        let ast = Parser::new(path, lexer).into_ast().unwrap();
+        analyze(ast, ColorChoice::Never)
+    }
+
+    fn analyze_source(source: &str) -> ValidAST {
+        try_analyze(source).unwrap()
+    }
+
+    #[test]
+    fn warns_on_self_assignment() {
+        let valid = analyze_source("fn main() -> i64 { x := 1; x = x; return x; }");
+        assert_eq!(valid.warnings.len(), 1);
+    }
+
+    #[test]
+    fn warns_on_statement_without_effect() {
+        let valid = analyze_source("fn main() -> i64 { x := 1; x + 2; return x; }");
+        assert_eq!(valid.warnings.len(), 1);
+    }
+
+    #[test]
+    fn suffixed_literal_types() {
+        let valid =
+            analyze_source("fn main() -> i64 { x := 18446744073709551615u64; return 1i64; }");
+        assert!(valid.warnings.is_empty());
+    }
+
+    #[test]
+    fn suffixed_literal_out_of_range() {
+        let Err(errors) = try_analyze("fn main() -> i64 { return 9223372036854775808i64; }") else {
+            panic!("out of range literal was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn suffixed_literal_mismatched_type() {
+        assert!(try_analyze("fn main() -> i64 { return 1u64; }").is_err());
+    }
+
+    #[test]
+    fn warns_on_unreachable_function() {
+        let valid = analyze_source(
+            "
+            fn used() -> i64 { return 1; }
+            fn unused() -> i64 { return dead(); }
+            fn dead() -> i64 { return 2; }
+            fn main() -> i64 { return used(); }
+            ",
+        );
+
+        // `dead` is called, but only from `unused`
+        assert_eq!(valid.warnings.len(), 2);
+    }
+
+    #[test]
+    fn duplicate_switch_case() {
+        let Err(errors) = try_analyze(
+            "fn main(x: i64) -> i64 { switch x { 1 => { x = 2; }, 1 => { x = 3; } } return x; }",
+        ) else {
+            panic!("duplicate switch case was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn local_shadows_parameter() {
+        let Err(errors) = try_analyze(
+            "fn f(a: i64) -> i64 { a := 2; return a; } fn main() -> i64 { return f(1); }",
+        ) else {
+            panic!("shadowed parameter was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+
+        let error = format!("{:?}", errors.iter().next().unwrap());
+        assert!(error.contains("local shadows parameter"), "{}", error);
+        assert!(error.contains("parameter declared here"), "{}", error);
+    }
+
+    #[test]
+    fn duplicate_let_declaration() {
+        let Err(errors) = try_analyze("fn main() -> i64 { let a = 5; let a = 6; return a; }")
+        else {
+            panic!("duplicate declaration was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+
+        let error = format!("{:?}", errors.iter().next().unwrap());
+        assert!(
+            error.contains("duplicate variable declaration"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn switch_on_non_integer() {
+        assert!(try_analyze("fn main() -> i64 { switch \"a\" { 1 => {} } return 0; }").is_err());
+    }
+
+    #[test]
+    fn conflicting_inline_attributes() {
+        let Err(errors) = try_analyze(
+            "@inline @noinline fn f() -> i64 { return 1; } fn main() -> i64 { return f(); }",
+        ) else {
+            panic!("conflicting attributes were accepted");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn no_warnings_for_useful_statements() {
+        let valid = analyze_source(
+            "
+            fn main() -> i64 { x := 1; y := 2; x = y; assert(x == 2); return x; }
+            ",
+        );
+        assert!(valid.warnings.is_empty());
+    }
+
+    #[test]
+    fn mixed_integer_arithmetic() {
+        let Err(errors) = try_analyze("fn main() -> i64 { x := 1 + 2u64; return 0; }") else {
+            panic!("mixed integer types were accepted");
+        };
+        assert_eq!(errors.len(), 1);
+
+        assert!(try_analyze("fn main() -> i64 { return 1 + 'a'; }").is_err());
+        analyze_source("fn main() -> i64 { return 1 + 'a' as i64; }");
+    }
+
+    #[test]
+    fn arithmetic_on_non_integers() {
+        let Err(errors) = try_analyze("fn main() -> i64 { x := true + false; return 0; }") else {
+            panic!("arithmetic on bools was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn mixed_int_and_float_arithmetic() {
+        let Err(errors) = try_analyze("fn main() -> i64 { x := 1 + 2.0; return 0; }") else {
+            panic!("mixed integer and float operands were accepted");
+        };
+        assert_eq!(errors.len(), 1);
+
+        analyze_source("fn main() -> i64 { return (1 as f64 + 2.0) as i64; }");
+    }
+
+    #[test]
+    fn remainder_of_floats() {
+        analyze_source("fn main() -> i64 { x := 7u64 % 2u64; return 7 % 2; }");
+
+        let Err(errors) = try_analyze("fn main() -> i64 { x := 7.0 % 2.0; return 0; }") else {
+            panic!("a remainder of floats was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn named_record_fields() {
+        analyze_source(
+            "
+            fn point() -> (x: i64, y: i64) => (3, 4);
+            fn main() -> i64 { p := point(); q := p; return p.x * q.y + p.1; }
+            ",
+        );
+
+        let Err(errors) = try_analyze(
+            "fn point() -> (x: i64, y: i64) => (3, 4); fn main() -> i64 { return point().z; }",
+        ) else {
+            panic!("an unknown field was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn records() {
+        analyze_source(
+            "fn pair() -> (i64, char) => (1, 'a'); fn main() -> i64 { p := pair(); return p.0; }",
+        );
+
+        for source in [
+            "fn main() -> i64 { p := (1, 2); return p.2; }",
+            "fn main() -> i64 { p := (1, 2.0); return 0; }",
+            "fn main() -> i64 { p := (1, 2); return p == p ? 1 : 0; }",
+            "fn main() -> i64 { p := (1, 2); q := &p; return 0; }",
+            "fn f(p: (i64, i64)) -> i64 => p.0; fn main() -> i64 { return f((1, 2)); }",
+            "fn main() -> i64 { x := 1; return x.0; }",
+            "fn main() -> i64 { p := (1, 2); return p.x; }",
+        ] {
+            let Err(errors) = try_analyze(source) else {
+                panic!("invalid record use was accepted: {}", source);
+            };
+            assert_eq!(errors.len(), 1, "{}", source);
+        }
+    }
+
+    #[test]
+    fn intrinsics_cannot_be_redefined() {
+        let Err(errors) = try_analyze(
+            "fn print(n: i64) -> i64 { return n; } fn main() -> i64 { print(1); return 0; }",
+        ) else {
+            panic!("a redefinition of print was accepted");
+        };
+        assert_eq!(errors.len(), 1);
+
+        analyze_source(
+            "fn printer(n: i64) { print(n); } fn main() -> i64 { printer(1); return 0; }",
+        );
+    }
+
+    #[test]
+    fn records_are_not_held_in_one_register() {
+        let pair = SemanticType::Record(vec![
+            RecordField::unnamed(SemanticType::I64),
+            RecordField::unnamed(SemanticType::Bool),
+        ]);
+        assert_eq!(pair.size(), None);
+        assert_eq!(
+            SemanticType::Pointer(Box::new(pair)).size(),
+            Some(VarSize::B64)
+        );
+    }
+}
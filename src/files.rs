@@ -6,6 +6,8 @@ use std::{
 pub const BUILD_DIR: &str = "build";
 pub const STDLIB_DIR: &str = "stdlib";
 pub const STDLIB_FILE: &str = "lib.bl";
+/// Name that diagnostics give a program read from stdin.
+pub const STDIN_NAME: &str = "<stdin>";
 
 pub fn target_mod(module: impl AsRef<Path>) -> Result<PathBuf, io::Error> {
     let target_dir = Path::new(BUILD_DIR);
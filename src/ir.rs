@@ -1,47 +1,164 @@
 use std::{
-    collections::{HashMap, HashSet},
-    fmt,
+    collections::{BTreeSet, HashMap, HashSet},
+    fmt, fs,
+    path::PathBuf,
+    rc::Rc,
 };
 
 use crate::{
-    analyze::ast::CompareOp,
+    analyze::{
+        Span,
+        ast::{Attribute, CompareOp},
+        semantics::SemanticType,
+    },
     ir::lifetime::{Interval, Lifetime},
 };
 
+pub mod cfg;
 pub mod codegen;
+pub mod cse;
+pub mod dead;
+pub mod dot;
+pub mod eval;
+pub mod inline;
 pub mod lifetime;
+pub mod optimize;
+pub mod propagate;
+pub mod unroll;
 
 #[derive(Default)]
 pub struct IR {
     pub items: Vec<Item>,
     pub strings: HashMap<String, StrId>,
+    /// Functions that are declared but not defined, and must be resolved elsewhere.
+    pub externs: Vec<String>,
+    /// Whether signed addition, subtraction and multiplication trap when they overflow at
+    /// runtime. Passes then keep the arithmetic that may trap, instead of folding or removing it.
+    pub overflow_checks: bool,
 }
 
 impl IR {
+    /// Displays the IR with a trailing `// line:col` comment on each operation, pointing to
+    /// the statement it was generated from. Tabs count as `tab_width` columns.
+    pub fn display_annotated(&self, tab_width: usize) -> Annotated<'_> {
+        Annotated(self, tab_width)
+    }
+
     pub fn alloc_str(&mut self, string: String) -> StrId {
         let len = self.strings.len();
         *self.strings.entry(string).or_insert(len)
     }
+
+    /// Names of the functions that may run when starting at `entry`, found by following calls.
+    /// Calls to functions that are not items, such as externs, are included.
+    pub fn reachable_functions<'a>(&'a self, entry: &'a str) -> HashSet<&'a str> {
+        let functions: HashMap<&str, &BasicBlock> = self
+            .items
+            .iter()
+            .map(|Item::Function { name, bb, .. }| (name.as_str(), bb))
+            .collect();
+
+        let mut reachable = HashSet::from([entry]);
+        let mut pending = vec![entry];
+        while let Some(function) = pending.pop() {
+            let Some(bb) = functions.get(function) else {
+                continue;
+            };
+
+            for op in &bb.ops {
+                if let Operation::Call { function, .. } = op
+                    && reachable.insert(function.as_str())
+                {
+                    pending.push(function);
+                }
+            }
+        }
+
+        reachable
+    }
 }
 
 pub type StrId = usize;
 
+#[derive(Debug, PartialEq)]
 pub enum Item {
     Function {
         name: String,
         args: Vec<VirtualReg>,
         bb: BasicBlock,
+        /// The declaration of the function in the source.
+        span: Span,
+        /// Calls with constant arguments are evaluated at compile time.
+        is_const: bool,
+        attributes: Vec<Attribute>,
     },
 }
 
 pub type OpIndex = usize;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct BasicBlock {
     pub labels: HashMap<OpIndex, Vec<Label>>,
     pub ops: Vec<Operation>,
+    /// Source of the expression, or else the statement, each operation was generated from,
+    /// indexed like `ops`. Blocks built by hand may leave it empty.
+    pub spans: Vec<Option<Span>>,
+    /// Classes of the virtual registers that are not [RegClass::General].
+    pub classes: HashMap<VirtualReg, RegClass>,
 }
 
 impl BasicBlock {
+    /// A block of `ops` without any labels, spans or register classes.
+    pub fn from_ops(ops: Vec<Operation>) -> Self {
+        Self {
+            labels: HashMap::new(),
+            ops,
+            spans: Vec::new(),
+            classes: HashMap::new(),
+        }
+    }
+
+    /// The class of register `vreg` holds its value in, as derived from its type.
+    pub fn class_of(&self, vreg: VirtualReg) -> RegClass {
+        self.classes.get(&vreg).copied().unwrap_or_default()
+    }
+
+    /// Removes the operations at the indices in `dead`. Labels on a removed operation move to
+    /// the next one that is kept.
+    pub fn remove_ops(&mut self, dead: &HashSet<OpIndex>) {
+        let has_spans = self.spans.len() == self.ops.len();
+
+        // new index of every old one, and of the end
+        let mut new_index = Vec::with_capacity(self.ops.len() + 1);
+        let mut kept = 0;
+        for idx in 0..=self.ops.len() {
+            new_index.push(kept);
+            if !dead.contains(&idx) {
+                kept += 1;
+            }
+        }
+
+        let mut idx = 0;
+        self.ops.retain(|_| {
+            idx += 1;
+            !dead.contains(&(idx - 1))
+        });
+
+        if has_spans {
+            let mut idx = 0;
+            self.spans.retain(|_| {
+                idx += 1;
+                !dead.contains(&(idx - 1))
+            });
+        }
+
+        let mut labels: HashMap<OpIndex, Vec<Label>> = HashMap::new();
+        for (idx, moved) in std::mem::take(&mut self.labels) {
+            labels.entry(new_index[idx]).or_default().extend(moved);
+        }
+        self.labels = labels;
+    }
+
     /// Generates a registry mapping virtual registers to a lifetime.
     pub fn lifetimes(&self) -> HashMap<VirtualReg, Lifetime> {
         let mut lifetimes: HashMap<VirtualReg, Lifetime> = HashMap::new();
@@ -50,7 +167,7 @@ impl BasicBlock {
 
         for (i, op) in self.ops.iter().enumerate() {
             uses.clear();
-            op._vregs_used(&mut uses);
+            op.all_vregs(&mut uses);
 
             active.retain_mut(|(vreg, interval)| {
                 if let Some(u) = uses.iter().position(|r| r == vreg) {
@@ -103,7 +220,7 @@ impl fmt::Display for Label {
 
 pub type Op = Operation;
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum VarSize {
     Zero,
     B8,
@@ -112,7 +229,7 @@ pub enum VarSize {
     B64,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Operation {
     Assign {
         src: SourceVal,
@@ -131,14 +248,21 @@ pub enum Operation {
         src: VirtualReg,
         ptr: VirtualReg,
     },
+    /// `b` is never a string.
     Add {
         a: VirtualReg,
-        b: VirtualReg,
+        b: SourceVal,
         dest: VirtualReg,
     },
+    /// `b` is never a string.
     Subtract {
         a: VirtualReg,
-        b: VirtualReg,
+        b: SourceVal,
+        dest: VirtualReg,
+    },
+    /// Integer negation, wrapping around for `i64::MIN`.
+    Negate {
+        src: VirtualReg,
         dest: VirtualReg,
     },
     Multiply {
@@ -146,10 +270,39 @@ pub enum Operation {
         b: VirtualReg,
         dest: VirtualReg,
     },
+    /// Integer division. The quotient is rounded toward zero, so `-7 / 2` is `-3` and the
+    /// remainder `a - (a / b) * b` takes the sign of the dividend. Dividing by zero gives zero.
     Divide {
         a: VirtualReg,
         b: VirtualReg,
         dest: VirtualReg,
+        signed: bool,
+    },
+    /// Integer remainder, `a - (a / b) * b` with the quotient of [`Divide`](Self::Divide). It
+    /// takes the sign of the dividend, and since dividing by zero gives zero, `a % 0` is `a`.
+    Modulo {
+        a: VirtualReg,
+        b: VirtualReg,
+        dest: VirtualReg,
+        signed: bool,
+    },
+    /// Arithmetic on `f64` values, which are held in virtual registers as their bits.
+    FloatArithmetic {
+        op: FloatOp,
+        a: VirtualReg,
+        b: VirtualReg,
+        dest: VirtualReg,
+    },
+    /// Converts an `f64` to an `i64`, rounding toward zero. Values out of range saturate, and
+    /// NaN becomes zero.
+    FloatToInt {
+        src: VirtualReg,
+        dest: VirtualReg,
+    },
+    /// Converts an `i64` to the nearest `f64`.
+    IntToFloat {
+        src: VirtualReg,
+        dest: VirtualReg,
     },
     Compare {
         a: VirtualReg,
@@ -157,6 +310,13 @@ pub enum Operation {
         cond: Condition,
         dest: VirtualReg,
     },
+    /// `dest = cond != 0 ? a : b`
+    Select {
+        cond: VirtualReg,
+        a: VirtualReg,
+        b: VirtualReg,
+        dest: VirtualReg,
+    },
     Branch {
         label: Label,
     },
@@ -168,22 +328,82 @@ pub enum Operation {
         cond: VirtualReg,
         label: Label,
     },
+    /// Jumps to the label of the case equal to `value`, or to `default` if there is none.
+    Switch {
+        value: VirtualReg,
+        cases: Vec<(i64, Label)>,
+        default: Label,
+    },
     Return {
         value: SourceVal,
     },
+    /// Returns a record of two fields, which are passed back like two return values.
+    ReturnPair {
+        first: VirtualReg,
+        second: VirtualReg,
+    },
+    /// Calls may have side effects, so they are barriers: optimizations never remove a call,
+    /// even if its result is unused, nor move other operations across one. Only calls to const
+    /// functions are exempt, since those are folded into their result.
     Call {
         function: String,
-        args: Vec<VirtualReg>,
+        args: Vec<SourceVal>,
         dest: Option<VirtualReg>,
     },
+    /// Takes the second field of the record returned by the call right before it, whose `dest`
+    /// holds the first one. Nothing may come in between.
+    SecondResult {
+        dest: VirtualReg,
+    },
 }
 
 impl Operation {
-    pub fn _vregs_used(&self, _out: &mut Vec<VirtualReg>) {}
+    /// `dest = n`
+    pub fn assign_imm(dest: VirtualReg, n: i64) -> Self {
+        Self::Assign {
+            src: SourceVal::Immediate(n),
+            dest,
+        }
+    }
+
+    /// `dest = a + b`
+    pub fn add(dest: VirtualReg, a: VirtualReg, b: impl Into<SourceVal>) -> Self {
+        Self::Add {
+            a,
+            b: b.into(),
+            dest,
+        }
+    }
+
+    /// `return value`
+    pub fn ret_reg(value: VirtualReg) -> Self {
+        Self::Return {
+            value: SourceVal::VReg(value),
+        }
+    }
+
+    /// Pushes every virtual register this operation touches to `out`, each once.
+    pub fn all_vregs(&self, out: &mut Vec<VirtualReg>) {
+        let (used, assigned) = self.vregs_used();
+        out.extend(assigned.filter(|vreg| !used.contains(vreg)));
+        out.extend(used);
+    }
+
+    /// The class of register this operation reads or writes `vreg` in. Float operations work on
+    /// floating-point registers, while every other operation moves the bits of its values
+    /// through general-purpose registers, whatever their type.
+    pub fn operand_class(&self, vreg: VirtualReg) -> RegClass {
+        match *self {
+            Operation::FloatArithmetic { .. } => RegClass::Float,
+            Operation::FloatToInt { src, .. } if src == vreg => RegClass::Float,
+            Operation::IntToFloat { dest, .. } if dest == vreg => RegClass::Float,
+            _ => RegClass::General,
+        }
+    }
 
     /// Gets the virtual registers used in this operation. Both source and destination registers.
-    pub fn vregs_used(&self) -> (HashSet<VirtualReg>, Option<VirtualReg>) {
-        let mut used = HashSet::new();
+    pub fn vregs_used(&self) -> (BTreeSet<VirtualReg>, Option<VirtualReg>) {
+        let mut used = BTreeSet::new();
         let mut assigned = None;
 
         let mut push = |vreg: Option<VirtualReg>| {
@@ -211,14 +431,23 @@ impl Operation {
 
             Operation::Add { a, b, dest } | Operation::Subtract { a, b, dest } => {
                 push(Some(*a));
-                push(Some(*b));
+                push(b.reg());
                 assigned = Some(*dest);
             }
-            Operation::Multiply { a, b, dest } | Operation::Divide { a, b, dest } => {
+            Operation::Multiply { a, b, dest }
+            | Operation::Divide { a, b, dest, .. }
+            | Operation::Modulo { a, b, dest, .. }
+            | Operation::FloatArithmetic { a, b, dest, .. } => {
                 push(Some(*a));
                 push(Some(*b));
                 assigned = Some(*dest);
             }
+            Operation::Negate { src, dest }
+            | Operation::FloatToInt { src, dest }
+            | Operation::IntToFloat { src, dest } => {
+                push(Some(*src));
+                assigned = Some(*dest);
+            }
 
             Operation::Compare {
                 a,
@@ -230,29 +459,196 @@ impl Operation {
                 push(Some(*b));
                 assigned = Some(*dest);
             }
+            Operation::Select { cond, a, b, dest } => {
+                push(Some(*cond));
+                push(Some(*a));
+                push(Some(*b));
+                assigned = Some(*dest);
+            }
             Operation::BranchIfNot { cond, label: _ } | Operation::BranchIf { cond, label: _ } => {
                 push(Some(*cond));
             }
 
             Operation::Return { value } => push(value.reg()),
+            Operation::ReturnPair { first, second } => {
+                push(Some(*first));
+                push(Some(*second));
+            }
+            Operation::SecondResult { dest } => assigned = Some(*dest),
             Operation::Call {
                 dest,
                 args,
                 function: _,
             } => {
                 assigned = *dest;
-                for vreg in args {
-                    push(Some(*vreg));
+                for arg in args {
+                    push(arg.reg());
                 }
             }
+            Operation::Switch { value, .. } => push(Some(*value)),
             Operation::Branch { label: _ } => {}
         }
 
         (used, assigned)
     }
+
+    /// Replaces every virtual register this operation touches with `f` of it.
+    pub fn map_vregs(&mut self, mut f: impl FnMut(VirtualReg) -> VirtualReg) {
+        let mut map = |vreg: &mut VirtualReg| *vreg = f(*vreg);
+
+        match self {
+            Operation::Assign { src, dest } => {
+                if let SourceVal::VReg(vreg) = src {
+                    map(vreg);
+                }
+                map(dest);
+            }
+            Operation::AddressOf { val, dest } => {
+                map(val);
+                map(dest);
+            }
+            Operation::LoadPointer { ptr, dest, .. } => {
+                map(ptr);
+                map(dest);
+            }
+            Operation::StorePointer { src, ptr } => {
+                map(src);
+                map(ptr);
+            }
+            Operation::Add { a, b, dest } | Operation::Subtract { a, b, dest } => {
+                map(a);
+                if let SourceVal::VReg(vreg) = b {
+                    map(vreg);
+                }
+                map(dest);
+            }
+            Operation::Multiply { a, b, dest }
+            | Operation::Divide { a, b, dest, .. }
+            | Operation::Modulo { a, b, dest, .. }
+            | Operation::FloatArithmetic { a, b, dest, .. }
+            | Operation::Compare { a, b, dest, .. } => {
+                map(a);
+                map(b);
+                map(dest);
+            }
+            Operation::Negate { src, dest }
+            | Operation::FloatToInt { src, dest }
+            | Operation::IntToFloat { src, dest } => {
+                map(src);
+                map(dest);
+            }
+            Operation::Select { cond, a, b, dest } => {
+                map(cond);
+                map(a);
+                map(b);
+                map(dest);
+            }
+            Operation::BranchIf { cond, .. } | Operation::BranchIfNot { cond, .. } => map(cond),
+            Operation::Return { value } => {
+                if let SourceVal::VReg(vreg) = value {
+                    map(vreg);
+                }
+            }
+            Operation::ReturnPair { first, second } => {
+                map(first);
+                map(second);
+            }
+            Operation::Call { args, dest, .. } => {
+                for arg in args {
+                    if let SourceVal::VReg(vreg) = arg {
+                        map(vreg);
+                    }
+                }
+                if let Some(dest) = dest {
+                    map(dest);
+                }
+            }
+            Operation::SecondResult { dest } => map(dest),
+            Operation::Switch { value, .. } => map(value),
+            Operation::Branch { .. } => {}
+        }
+    }
+
+    /// Replaces every label this operation may jump to with `f` of it.
+    pub fn map_labels(&mut self, mut f: impl FnMut(Label) -> Label) {
+        match self {
+            Operation::Branch { label }
+            | Operation::BranchIf { label, .. }
+            | Operation::BranchIfNot { label, .. } => *label = f(*label),
+            Operation::Switch { cases, default, .. } => {
+                for (_, label) in cases {
+                    *label = f(*label);
+                }
+                *default = f(*default);
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether this operation does more than assign its destination, like calling a function,
+    /// writing through a pointer or jumping. Passes must keep such operations, in their order,
+    /// and not move other operations across them.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(
+            self,
+            Operation::StorePointer { .. }
+                | Operation::Branch { .. }
+                | Operation::BranchIf { .. }
+                | Operation::BranchIfNot { .. }
+                | Operation::Switch { .. }
+                | Operation::Return { .. }
+                | Operation::ReturnPair { .. }
+                | Operation::Call { .. }
+        )
+    }
+
+    /// The labels this operation may jump to.
+    pub fn branch_targets(&self) -> Vec<Label> {
+        match self {
+            Operation::Branch { label }
+            | Operation::BranchIf { label, .. }
+            | Operation::BranchIfNot { label, .. } => vec![*label],
+            Operation::Switch { cases, default, .. } => cases
+                .iter()
+                .map(|&(_, label)| label)
+                .chain([*default])
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FloatOp {
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+}
+
+impl FloatOp {
+    pub fn apply(&self, a: f64, b: f64) -> f64 {
+        match self {
+            FloatOp::Add => a + b,
+            FloatOp::Subtract => a - b,
+            FloatOp::Multiply => a * b,
+            FloatOp::Divide => a / b,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy)]
+impl fmt::Display for FloatOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FloatOp::Add => write!(f, "+"),
+            FloatOp::Subtract => write!(f, "-"),
+            FloatOp::Multiply => write!(f, "*"),
+            FloatOp::Divide => write!(f, "/"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Condition {
     Equal,
     NotEqual,
@@ -314,7 +710,7 @@ impl Condition {
 
 /// A value that can be used in an operation as a source, either an immediate operand or a
 /// register.
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum SourceVal {
     Immediate(i64),
     VReg(VirtualReg),
@@ -331,6 +727,18 @@ impl SourceVal {
     }
 }
 
+impl From<VirtualReg> for SourceVal {
+    fn from(vreg: VirtualReg) -> Self {
+        Self::VReg(vreg)
+    }
+}
+
+impl From<i64> for SourceVal {
+    fn from(n: i64) -> Self {
+        Self::Immediate(n)
+    }
+}
+
 impl fmt::Display for SourceVal {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -350,8 +758,47 @@ impl fmt::Display for VirtualReg {
     }
 }
 
+/// The kind of physical register a value is kept in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegClass {
+    #[default]
+    General,
+    Float,
+}
+
+impl RegClass {
+    /// The class of register that holds values of type `ty`.
+    pub fn of(ty: &SemanticType) -> RegClass {
+        match ty {
+            SemanticType::F64 => RegClass::Float,
+            _ => RegClass::General,
+        }
+    }
+}
+
 impl fmt::Display for IR {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.write(f, None)
+    }
+}
+
+pub struct Annotated<'ir>(&'ir IR, usize);
+
+impl fmt::Display for Annotated<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.write(f, Some((&mut HashMap::new(), self.1)))
+    }
+}
+
+impl IR {
+    /// Writes the IR, annotating each operation with its source location if `sources` is
+    /// given, along with the width of a tab. Source files are read on demand and cached in
+    /// `sources`.
+    fn write(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        mut sources: Option<(&mut HashMap<Rc<PathBuf>, Option<String>>, usize)>,
+    ) -> fmt::Result {
         for (string, id) in self.strings.iter() {
             writeln!(f, "#{} => \"{}\"", id, string)?;
         }
@@ -360,8 +807,16 @@ impl fmt::Display for IR {
             writeln!(f)?;
         }
 
+        for name in self.externs.iter() {
+            writeln!(f, "extern fn {}", name)?;
+        }
+
+        if !self.externs.is_empty() {
+            writeln!(f)?;
+        }
+
         for item in self.items.iter() {
-            let Item::Function { name, args, bb } = item;
+            let Item::Function { name, args, bb, .. } = item;
             write!(f, "fn {}(", name)?;
             for reg in args.iter().take(1) {
                 write!(f, "{}", reg)?;
@@ -381,63 +836,19 @@ impl fmt::Display for IR {
                     writeln!(f)?;
                 }
 
-                match op {
-                    Operation::Assign { src, dest } => writeln!(f, "    {} = {}", dest, src)?,
-                    Operation::AddressOf { val, dest } => {
-                        writeln!(f, "    {} = ref {}", dest, val)?
-                    }
-                    Operation::LoadPointer { ptr, size, dest } => {
-                        writeln!(f, "    {} = deref {:?} {}", dest, size, ptr)?
-                    }
-                    Operation::StorePointer { src, ptr } => {
-                        writeln!(f, "    deref {} = {}", ptr, src)?
-                    }
+                write!(f, "    {}", op)?;
 
-                    Operation::Add { a, b, dest } => writeln!(f, "    {} = {} + {}", dest, a, b)?,
-                    Operation::Subtract { a, b, dest } => {
-                        writeln!(f, "    {} = {} - {}", dest, a, b)?
-                    }
-                    Operation::Multiply { a, b, dest } => {
-                        writeln!(f, "    {} = {} * {}", dest, a, b)?
-                    }
-                    Operation::Divide { a, b, dest } => {
-                        writeln!(f, "    {} = {} / {}", dest, a, b)?
-                    }
-                    Operation::Compare { a, b, cond, dest } => {
-                        writeln!(f, "    {} = cmp {} {:?} {}", dest, a, cond, b)?
-                    }
-                    Operation::Branch { label } => {
-                        writeln!(f, "    goto {}", label)?;
-                    }
-                    Operation::BranchIf { cond, label } => {
-                        writeln!(f, "    if {} goto {}", cond, label)?;
-                    }
-                    Operation::BranchIfNot { cond, label } => {
-                        writeln!(f, "    if not {} goto {}", cond, label)?;
-                    }
-                    Operation::Return { value } => writeln!(f, "    ret {}", value)?,
-                    Operation::Call {
-                        function,
-                        args,
-                        dest,
-                    } => {
-                        if let Some(dest) = dest {
-                            write!(f, "    {} = call {}(", dest, function)?
-                        } else {
-                            write!(f, "    call {}(", function)?
-                        }
-
-                        for arg in args.iter().take(1) {
-                            write!(f, "{}", arg)?;
-                        }
-
-                        for arg in args.iter().skip(1) {
-                            write!(f, ", {}", arg)?;
-                        }
-
-                        writeln!(f, ")")?
-                    }
+                if let Some((sources, tab_width)) = sources.as_mut()
+                    && let Some(Some((path, range))) = bb.spans.get(i)
+                    && let Some(source) = sources
+                        .entry(path.clone())
+                        .or_insert_with(|| fs::read_to_string(path.as_ref()).ok())
+                {
+                    let (line, col) = line_col(source, range.start, *tab_width);
+                    write!(f, " // {}:{}", line, col)?;
                 }
+
+                writeln!(f)?;
             }
 
             writeln!(f, "}}\n")?;
@@ -446,3 +857,160 @@ impl fmt::Display for IR {
         Ok(())
     }
 }
+
+/// Converts a byte offset into a 1-based line and column, counting columns in characters. A
+/// tab moves to the next multiple of `tab_width` columns, like in an editor.
+fn line_col(source: &str, offset: usize, tab_width: usize) -> (usize, usize) {
+    let mut line = 1;
+    let mut col = 1;
+    for c in source.get(..offset).unwrap_or(source).chars() {
+        match c {
+            '\n' => {
+                line += 1;
+                col = 1;
+            }
+            '\t' => col += tab_width - (col - 1) % tab_width,
+            _ => col += 1,
+        }
+    }
+
+    (line, col)
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Operation::Assign { src, dest } => write!(f, "{} = {}", dest, src),
+            Operation::AddressOf { val, dest } => write!(f, "{} = ref {}", dest, val),
+            Operation::LoadPointer { ptr, size, dest } => {
+                write!(f, "{} = deref {:?} {}", dest, size, ptr)
+            }
+            Operation::StorePointer { src, ptr } => write!(f, "deref {} = {}", ptr, src),
+
+            Operation::Add { a, b, dest } => write!(f, "{} = {} + {}", dest, a, b),
+            Operation::Subtract { a, b, dest } => write!(f, "{} = {} - {}", dest, a, b),
+            Operation::Negate { src, dest } => write!(f, "{} = -{}", dest, src),
+            Operation::Multiply { a, b, dest } => write!(f, "{} = {} * {}", dest, a, b),
+            Operation::Divide { a, b, dest, signed } => {
+                let op = if *signed { "/" } else { "/u" };
+                write!(f, "{} = {} {} {}", dest, a, op, b)
+            }
+            Operation::Modulo { a, b, dest, signed } => {
+                let op = if *signed { "%" } else { "%u" };
+                write!(f, "{} = {} {} {}", dest, a, op, b)
+            }
+            Operation::FloatArithmetic { op, a, b, dest } => {
+                write!(f, "{} = {} {}. {}", dest, a, op, b)
+            }
+            Operation::FloatToInt { src, dest } => write!(f, "{} = f64 to i64 {}", dest, src),
+            Operation::IntToFloat { src, dest } => write!(f, "{} = i64 to f64 {}", dest, src),
+            Operation::Compare { a, b, cond, dest } => {
+                write!(f, "{} = cmp {} {:?} {}", dest, a, cond, b)
+            }
+            Operation::Select { cond, a, b, dest } => {
+                write!(f, "{} = {} ? {} : {}", dest, cond, a, b)
+            }
+            Operation::Branch { label } => write!(f, "goto {}", label),
+            Operation::BranchIf { cond, label } => write!(f, "if {} goto {}", cond, label),
+            Operation::BranchIfNot { cond, label } => {
+                write!(f, "if not {} goto {}", cond, label)
+            }
+            Operation::Switch {
+                value,
+                cases,
+                default,
+            } => {
+                write!(f, "switch {} [", value)?;
+                for (i, (n, label)) in cases.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{} => {}", n, label)?;
+                }
+                write!(f, "] else goto {}", default)
+            }
+            Operation::Return { value } => write!(f, "ret {}", value),
+            Operation::ReturnPair { first, second } => write!(f, "ret ({}, {})", first, second),
+            Operation::SecondResult { dest } => write!(f, "{} = second result", dest),
+            Operation::Call {
+                function,
+                args,
+                dest,
+            } => {
+                if let Some(dest) = dest {
+                    write!(f, "{} = call {}(", dest, function)?
+                } else {
+                    write!(f, "call {}(", function)?
+                }
+
+                for arg in args.iter().take(1) {
+                    write!(f, "{}", arg)?;
+                }
+
+                for arg in args.iter().skip(1) {
+                    write!(f, ", {}", arg)?;
+                }
+
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+/// Compiles `source` down to IR, for the tests of the passes.
+#[cfg(test)]
+pub(crate) fn generate(source: &str) -> IR {
+    use crate::analyze::{ColorChoice, ast::parse::Parser, lex::Lexer, semantics};
+
+    let path = Rc::new(PathBuf::from("main"));
+    let lexer = Lexer::new(path.clone(), source).unwrap();
+    let ast = Parser::new(path, lexer).into_ast().unwrap();
+    IR::generate(semantics::analyze(ast, ColorChoice::Never).unwrap())
+}
+
+/// The operations of `function` in `ir`, for the tests of the passes.
+#[cfg(test)]
+pub(crate) fn ops<'a>(ir: &'a IR, function: &str) -> &'a [Operation] {
+    let Item::Function { bb, .. } = ir
+        .items
+        .iter()
+        .find(|Item::Function { name, .. }| name == function)
+        .unwrap();
+    &bb.ops
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, rc::Rc};
+
+    use crate::{
+        analyze::{ColorChoice, DEFAULT_TAB_WIDTH, ast::parse::Parser, lex::Lexer, semantics},
+        ir::{IR, line_col},
+    };
+
+    #[test]
+    fn annotated_display_has_line_references() {
+        let source = "fn main() -> i64 {\n    x := 1;\n    return x;\n}\n";
+        let path = Rc::new(env::temp_dir().join("istind_annotated_ir.is"));
+        fs::write(path.as_ref(), source).unwrap();
+
+        let lexer = Lexer::new(path.clone(), source).unwrap();
+        let ast = Parser::new(path, lexer).into_ast().unwrap();
+        let ir = IR::generate(semantics::analyze(ast, ColorChoice::Never).unwrap());
+
+        let dump = ir.display_annotated(DEFAULT_TAB_WIDTH).to_string();
+        assert!(dump.contains("= 1 // 2:5"), "{}", dump);
+        assert!(dump.contains("ret %0 // 3:12"), "{}", dump);
+        assert!(!ir.to_string().contains("//"));
+    }
+
+    #[test]
+    fn tabs_expand_to_the_next_tab_stop() {
+        let source = "fn main() -> i64 {\n\treturn 1;\n  \tx;\n}\n";
+
+        assert_eq!(line_col(source, 20, 4), (2, 5));
+        assert_eq!(line_col(source, 20, 8), (2, 9));
+        // the tab after two spaces only fills up to the tab stop
+        assert_eq!(line_col(source, 33, 4), (3, 5));
+    }
+}
@@ -0,0 +1,150 @@
+use std::ops::Range;
+
+use crate::ir::{BasicBlock, Item, Label, OpIndex, Operation};
+
+/// A straight-line run of operations in a [BasicBlock], which control only enters at its first
+/// operation and only leaves after its last.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// Indices of the operations in the block.
+    pub ops: Range<OpIndex>,
+    successors: Vec<usize>,
+}
+
+impl Block {
+    /// Indices of the blocks that control may continue to after this one, found from the
+    /// branch that ends it and whether it falls through to the next block.
+    pub fn successors(&self) -> &[usize] {
+        &self.successors
+    }
+}
+
+impl Item {
+    /// The control-flow graph of the function, as its blocks in order.
+    pub fn blocks(&self) -> Vec<Block> {
+        let Item::Function { bb, .. } = self;
+        bb.blocks()
+    }
+}
+
+impl BasicBlock {
+    /// Splits the operations into blocks, at every label and after every branch or return, and
+    /// links each block to the ones that may run after it.
+    ///
+    /// ```
+    /// use istind::ir::{BasicBlock, Label, Operation, SourceVal};
+    ///
+    /// let mut bb = BasicBlock::from_ops(vec![
+    ///     Operation::Branch { label: Label::N(0) },
+    ///     Operation::Return {
+    ///         value: SourceVal::Immediate(0),
+    ///     },
+    /// ]);
+    /// bb.labels.insert(1, vec![Label::N(0)]);
+    ///
+    /// let blocks = bb.blocks();
+    /// assert_eq!(blocks.len(), 2);
+    /// assert_eq!(blocks[0].ops, 0..1);
+    /// assert_eq!(blocks[0].successors(), [1]);
+    /// assert!(blocks[1].successors().is_empty());
+    /// ```
+    pub fn blocks(&self) -> Vec<Block> {
+        let leaders = leaders(self);
+        let block_of = |index: OpIndex| leaders.partition_point(|&leader| leader <= index) - 1;
+
+        let mut blocks = Vec::with_capacity(leaders.len());
+        for (block, &start) in leaders.iter().enumerate() {
+            let end = leaders.get(block + 1).copied().unwrap_or(self.ops.len());
+
+            let mut successors = Vec::new();
+            let last = self.ops.get(start..end).and_then(<[Operation]>::last);
+            if let Some(last) = last {
+                for target in last
+                    .branch_targets()
+                    .into_iter()
+                    .filter_map(|label| self.label_index(label))
+                    .map(block_of)
+                {
+                    if !successors.contains(&target) {
+                        successors.push(target);
+                    }
+                }
+            }
+
+            let falls_through = !matches!(
+                last,
+                Some(
+                    Operation::Branch { .. }
+                        | Operation::Switch { .. }
+                        | Operation::Return { .. }
+                        | Operation::ReturnPair { .. }
+                )
+            );
+            if falls_through && block + 1 < leaders.len() && !successors.contains(&(block + 1)) {
+                successors.push(block + 1);
+            }
+
+            blocks.push(Block {
+                ops: start..end,
+                successors,
+            });
+        }
+
+        blocks
+    }
+
+    /// Index of the operation `label` is placed on, if it is on one.
+    fn label_index(&self, label: Label) -> Option<OpIndex> {
+        self.labels
+            .iter()
+            .find(|(_, labels)| labels.contains(&label))
+            .map(|(&index, _)| index)
+            .filter(|&index| index < self.ops.len())
+    }
+}
+
+/// Indices of the first operation of every block, in ascending order.
+fn leaders(bb: &BasicBlock) -> Vec<OpIndex> {
+    let mut leaders = vec![0];
+    leaders.extend(bb.labels.keys().copied());
+
+    for (i, op) in bb.ops.iter().enumerate() {
+        if !op.branch_targets().is_empty()
+            || matches!(op, Operation::Return { .. } | Operation::ReturnPair { .. })
+        {
+            leaders.push(i + 1);
+        }
+    }
+
+    leaders.retain(|&leader| leader == 0 || leader < bb.ops.len());
+    leaders.sort_unstable();
+    leaders.dedup();
+    leaders
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::generate;
+
+    #[test]
+    fn while_loop_blocks() {
+        let ir = generate(
+            "
+            fn main() -> i64 {
+                x := 3;
+                while x != 0 { x = x - 1; }
+                return x;
+            }
+            ",
+        );
+
+        let blocks = ir.items[0].blocks();
+        let successors: Vec<&[usize]> = blocks.iter().map(|block| block.successors()).collect();
+
+        // entry jumps to the condition, which loops back to the body or leaves the loop
+        let cond = successors[0][0];
+        assert_eq!(successors[1], [cond], "{}", ir);
+        assert!(successors[cond].contains(&1), "{}", ir);
+        assert!(successors[cond].contains(&(cond + 1)), "{}", ir);
+    }
+}
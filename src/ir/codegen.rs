@@ -1,56 +1,111 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use crate::{
     analyze::{
-        ast::{ArithmeticOp, Assignable, ExprInner, Expression, Item as AstItem, Statement},
-        semantics::{Sign, ValidAST},
+        Span,
+        ast::{
+            ArithmeticOp, Assignable, CompareOp, ExprInner, Expression, Item as AstItem, Statement,
+        },
+        semantics::{SemanticType, Sign, ValidAST},
+    },
+    ir::{
+        BasicBlock, Condition, FloatOp, IR, Item, Label, Op, OpIndex, RegClass, SourceVal,
+        VirtualReg,
     },
-    ir::{BasicBlock, Condition, IR, Item, Label, Op, OpIndex, SourceVal, VirtualReg},
 };
 
 impl IR {
     pub fn generate(ast: ValidAST) -> IR {
-        let ast = ast.0;
+        let ast = ast.ast;
 
         let mut ir = IR::default();
 
-        for item in ast.items {
-            if let AstItem::Function {
-                name, body, args, ..
-            } = item
-            {
-                let mut block_builder = BlockBuilder::new(&mut ir);
-                let args = args
-                    .iter()
-                    .map(|(arg, _, _)| block_builder.get_or_insert_vreg(arg))
-                    .collect();
+        let record_fns: HashSet<String> = ast
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                AstItem::Function { name, ret_type, .. }
+                | AstItem::ForwardDecl { name, ret_type, .. } => {
+                    matches!(ret_type, SemanticType::Record(_)).then(|| name.clone())
+                }
+                AstItem::ExternLib(_) => None,
+            })
+            .collect();
 
-                let bb = block_builder.build(body);
-                ir.items.push(Item::Function { name, args, bb });
-            };
+        for item in ast.items {
+            match item {
+                AstItem::Function {
+                    name,
+                    body,
+                    args,
+                    decl_span,
+                    is_const,
+                    attributes,
+                    ..
+                } => {
+                    let mut block_builder = BlockBuilder::new(&mut ir, &record_fns);
+                    let args = args
+                        .iter()
+                        .map(|(arg, ty, _)| {
+                            let vreg = block_builder.get_or_insert_vreg(arg);
+                            block_builder.set_type(vreg, ty);
+                            vreg
+                        })
+                        .collect();
+
+                    let bb = block_builder.build(body);
+                    ir.items.push(Item::Function {
+                        name,
+                        args,
+                        bb,
+                        span: decl_span,
+                        is_const,
+                        attributes,
+                    });
+                }
+                AstItem::ForwardDecl { name, .. } => ir.externs.push(name),
+                AstItem::ExternLib(_) => (),
+            }
         }
 
+        ir.fold_const_calls();
         ir
     }
 }
 
 struct BlockBuilder<'ir> {
     vregs: HashMap<String, VirtualReg>,
+    /// Variables holding records, by the first of the consecutive registers of their fields.
+    records: HashSet<VirtualReg>,
+    /// Functions that return a record.
+    record_fns: &'ir HashSet<String>,
     vreg_counter: u32,
+    classes: HashMap<VirtualReg, RegClass>,
     labels: HashMap<OpIndex, Vec<Label>>,
     label_counter: u32,
+    /// Labels of the enclosing labeled blocks, with the IR label after their end.
+    break_labels: Vec<(String, Label)>,
     ops: Vec<Op>,
+    spans: Vec<Option<Span>>,
+    /// Source of the innermost expression, or else the statement, currently being lowered.
+    span: Option<Span>,
     ir: &'ir mut IR,
 }
 
 impl<'ir> BlockBuilder<'ir> {
-    pub fn new(ir: &'ir mut IR) -> Self {
+    pub fn new(ir: &'ir mut IR, record_fns: &'ir HashSet<String>) -> Self {
         Self {
             vregs: HashMap::new(),
+            records: HashSet::new(),
+            record_fns,
             vreg_counter: 0,
+            classes: HashMap::new(),
             labels: HashMap::new(),
             label_counter: 0,
+            break_labels: Vec::new(),
             ops: Vec::new(),
+            spans: Vec::new(),
+            span: None,
             ir,
         }
     }
@@ -59,23 +114,80 @@ impl<'ir> BlockBuilder<'ir> {
         self.consume_block(block);
         BasicBlock {
             ops: self.ops,
+            spans: self.spans,
             labels: self.labels,
+            classes: self.classes,
         }
     }
 
     fn consume_block(&mut self, block: Vec<Statement>) {
         self.ops.reserve(block.len());
+        self.spans.reserve(block.len());
 
         for stmt in block {
+            self.span = Some(match &stmt {
+                Statement::Declare { var_span, .. } | Statement::Assign { var_span, .. } => {
+                    var_span.clone()
+                }
+                Statement::Return(expr) | Statement::Tail(expr) | Statement::Expr(expr) => {
+                    expr.span.clone()
+                }
+                Statement::If { guard, .. } | Statement::WhileLoop { guard, .. } => {
+                    guard.span.clone()
+                }
+                Statement::Labeled { label_span, .. } => label_span.clone(),
+                Statement::Break { span, .. } => span.clone(),
+                Statement::Switch { value, .. } => value.span.clone(),
+            });
+
             match stmt {
+                Statement::Declare { var, expr, .. } if self.is_record(&expr) => {
+                    assert!(!self.vregs.contains_key(&var), "variable declared twice");
+
+                    // a copy of another variable gets registers of its own
+                    let first = match expr.inner {
+                        ExprInner::Variable(_) => {
+                            let src = self.unroll_record(expr);
+                            let dest = self.get_record_vregs();
+                            self.copy_record(src, dest);
+                            dest
+                        }
+                        _ => self.unroll_record(expr),
+                    };
+
+                    self.vregs.insert(var, first);
+                    self.records.insert(first);
+                }
+                Statement::Assign {
+                    var: Assignable::Var(var),
+                    expr,
+                    ..
+                } if self.is_record(&expr) => {
+                    let dest = self.expect_vreg(&var);
+                    let src = self.unroll_record(expr);
+                    if src != dest {
+                        self.copy_record(src, dest);
+                    }
+                }
+                Statement::Return(expr) | Statement::Tail(expr) if self.is_record(&expr) => {
+                    let first = self.unroll_record(expr);
+                    self.emit(Op::ReturnPair {
+                        first,
+                        second: VirtualReg(first.0 + 1),
+                    });
+                }
                 Statement::Declare { var, expr, .. } => {
                     assert!(!self.vregs.contains_key(&var), "variable declared twice");
 
                     let dest = self.get_or_insert_vreg(var);
+                    if self.is_float(&expr) {
+                        self.set_type(dest, &SemanticType::F64);
+                    }
+
                     let src = self.unroll_expr(expr, Some(dest));
 
                     if src != SourceVal::VReg(dest) {
-                        self.ops.push(Op::Assign { src, dest });
+                        self.emit(Op::Assign { src, dest });
                     }
                 }
                 Statement::Assign { var, expr, .. } => {
@@ -85,82 +197,159 @@ impl<'ir> BlockBuilder<'ir> {
                     match var {
                         Assignable::Var(_) => {
                             if src.reg() != Some(dest) {
-                                self.ops.push(Op::Assign { src, dest })
+                                self.emit(Op::Assign { src, dest })
                             }
                         }
                         Assignable::Ptr(_) => {
                             let src = self.src_to_vreg(src);
-                            self.ops.push(Op::StorePointer { src, ptr: dest });
+                            self.emit(Op::StorePointer { src, ptr: dest });
                         }
                     }
                 }
-                Statement::Return(expr) => {
+                Statement::Return(expr) | Statement::Tail(expr) => {
                     let value = self.unroll_expr(expr, None);
-                    self.ops.push(Op::Return { value });
+                    self.emit(Op::Return { value });
                 }
 
-                Statement::If { guard, body } => {
-                    let cond = self.unroll_expr(guard, None);
-                    let cond = self.src_to_vreg(cond);
+                Statement::If {
+                    guard,
+                    body,
+                    otherwise,
+                } => {
+                    let (cond, negated) = self.unroll_branch_cond(guard);
                     let label = self.reserve_label();
-                    self.ops.push(Op::BranchIfNot { cond, label });
+                    if negated {
+                        self.emit(Op::BranchIf { cond, label });
+                    } else {
+                        self.emit(Op::BranchIfNot { cond, label });
+                    }
 
                     let outer_vregs = self.vregs.clone();
-                    let outer_vreg_counter = self.vreg_counter;
+                    let span = self.span.clone();
 
                     self.consume_block(body);
+                    self.span = span;
 
-                    for (name, inner) in self.vregs.iter() {
-                        if let Some(outer) = outer_vregs.get(name)
-                            && inner != outer
-                        {
-                            self.ops.push(Op::Assign {
-                                src: SourceVal::VReg(*inner),
-                                dest: *outer,
-                            });
-                        }
-                    }
+                    self.leave_scope(outer_vregs);
+
+                    // the body skips over the else branch, which starts at the label
+                    if let Some(otherwise) = otherwise {
+                        let end_label = self.reserve_label();
+                        self.emit(Op::Branch { label: end_label });
+                        self.set_label_here(label);
+
+                        let outer_vregs = self.vregs.clone();
+                        let span = self.span.clone();
 
-                    self.set_label_here(label);
+                        self.consume_block(otherwise);
+                        self.span = span;
 
-                    self.vregs = outer_vregs;
-                    self.vreg_counter = outer_vreg_counter;
+                        self.leave_scope(outer_vregs);
+                        self.set_label_here(end_label);
+                    } else {
+                        self.set_label_here(label);
+                    }
                 }
 
                 Statement::WhileLoop { guard, body } => {
                     let cond_label = self.reserve_label();
 
                     let outer_vregs = self.vregs.clone();
-                    let outer_vreg_counter = self.vreg_counter;
 
-                    self.ops.push(Op::Branch { label: cond_label });
+                    self.emit(Op::Branch { label: cond_label });
 
+                    let span = self.span.clone();
                     let body_label = self.insert_label();
                     self.consume_block(body);
+                    self.span = span;
 
                     self.set_label_here(cond_label);
-                    let cond = self.unroll_expr(guard, None);
-                    let cond = self.src_to_vreg(cond);
-                    self.ops.push(Op::BranchIf {
-                        cond,
-                        label: body_label,
+                    let (cond, negated) = self.unroll_branch_cond(guard);
+                    let label = body_label;
+                    if negated {
+                        self.emit(Op::BranchIfNot { cond, label });
+                    } else {
+                        self.emit(Op::BranchIf { cond, label });
+                    }
+
+                    self.leave_scope(outer_vregs);
+                }
+
+                Statement::Labeled { label, body, .. } => {
+                    let end_label = self.reserve_label();
+                    self.break_labels.push((label, end_label));
+
+                    let outer_vregs = self.vregs.clone();
+                    let span = self.span.clone();
+
+                    self.consume_block(body);
+                    self.span = span;
+
+                    // breaks land before the moves, so they apply on every way out of the block
+                    self.set_label_here(end_label);
+                    self.leave_scope(outer_vregs);
+
+                    self.break_labels.pop();
+                }
+                Statement::Break { label, .. } => {
+                    let (_, target) = self
+                        .break_labels
+                        .iter()
+                        .rev()
+                        .find(|(name, _)| *name == label)
+                        .unwrap_or_else(|| panic!("break to unknown label '{}'", label));
+
+                    self.emit(Op::Branch { label: *target });
+                }
+
+                Statement::Switch {
+                    value,
+                    cases,
+                    default,
+                } => {
+                    let value = self.unroll_expr(value, None);
+                    let value = self.src_to_vreg(value);
+
+                    let end_label = self.reserve_label();
+                    let case_labels: Vec<(i64, Label)> = cases
+                        .iter()
+                        .map(|&(n, _, _)| (n, self.reserve_label()))
+                        .collect();
+                    let default_label = match default {
+                        Some(_) => self.reserve_label(),
+                        None => end_label,
+                    };
+
+                    self.emit(Op::Switch {
+                        value,
+                        cases: case_labels.clone(),
+                        default: default_label,
                     });
 
-                    for (name, inner) in self.vregs.iter() {
-                        if let Some(outer) = outer_vregs.get(name)
-                            && inner != outer
-                        {
-                            self.ops.push(Op::Assign {
-                                src: SourceVal::VReg(*inner),
-                                dest: *outer,
-                            });
-                        }
+                    let bodies = case_labels
+                        .into_iter()
+                        .map(|(_, label)| label)
+                        .zip(cases.into_iter().map(|(_, _, body)| body));
+                    let default = default.map(|body| (default_label, body));
+
+                    for (label, body) in bodies.chain(default) {
+                        self.set_label_here(label);
+
+                        let outer_vregs = self.vregs.clone();
+                        let span = self.span.clone();
+
+                        self.consume_block(body);
+                        self.span = span;
+
+                        self.leave_scope(outer_vregs);
+                        self.emit(Op::Branch { label: end_label });
                     }
 
-                    self.vregs = outer_vregs;
-                    self.vreg_counter = outer_vreg_counter;
+                    self.set_label_here(end_label);
                 }
 
+                // the analyzer warns about these, there is nothing to evaluate
+                Statement::Expr(expr) if expr.is_pure() => (),
                 Statement::Expr(expr) => {
                     self.unroll_expr(expr, None);
                 }
@@ -168,9 +357,43 @@ impl<'ir> BlockBuilder<'ir> {
         }
     }
 
+    /// Unrolls the guard of a branch. Returns a register that is non-zero when the guard holds,
+    /// or zero when it holds if the returned flag is set.
+    ///
+    /// Comparing against a literal zero branches on the other operand directly instead of
+    /// materializing the comparison, so the backend can use a single compare-and-branch.
+    fn unroll_branch_cond(&mut self, guard: Expression) -> (VirtualReg, bool) {
+        if let ExprInner::Comparison(lhs, rhs, op @ (CompareOp::Equal | CompareOp::NotEqual), _) =
+            &guard.inner
+            && (is_zero(lhs) || is_zero(rhs))
+        {
+            let negated = matches!(op, CompareOp::Equal);
+            let ExprInner::Comparison(lhs, rhs, ..) = guard.inner else {
+                unreachable!()
+            };
+
+            let value = if is_zero(&rhs) { *lhs } else { *rhs };
+            let value = self.unroll_expr(value, None);
+            return (self.src_to_vreg(value), negated);
+        }
+
+        let cond = self.unroll_expr(guard, None);
+        (self.src_to_vreg(cond), false)
+    }
+
+    /// Emits the operations that compute `expr`. They are attributed to the span of the
+    /// expression itself, while its operands keep their own.
     fn unroll_expr(&mut self, expr: Expression, dest: Option<VirtualReg>) -> SourceVal {
+        let outer = self.span.replace(expr.span.clone());
+        let src = self.unroll_expr_inner(expr, dest);
+        self.span = outer;
+        src
+    }
+
+    fn unroll_expr_inner(&mut self, expr: Expression, dest: Option<VirtualReg>) -> SourceVal {
         match expr.inner {
-            ExprInner::Const(num) => SourceVal::Immediate(num),
+            ExprInner::Const(num, _) => SourceVal::Immediate(num),
+            ExprInner::Float(num) => SourceVal::Immediate(num.to_bits() as i64),
             ExprInner::Character(c) => SourceVal::Immediate(c as i64),
             ExprInner::String(string) => {
                 let str_id = self.ir.alloc_str(string);
@@ -183,36 +406,118 @@ impl<'ir> BlockBuilder<'ir> {
                 let val = self.expect_vreg(&var);
                 let dest = dest.unwrap_or_else(|| self.get_vreg());
 
-                self.ops.push(Op::AddressOf { val, dest });
+                self.emit(Op::AddressOf { val, dest });
                 SourceVal::VReg(dest)
             }
             ExprInner::Deref(var, typ) => {
                 let ptr = self.expect_vreg(&var);
                 let dest = dest.unwrap_or_else(|| self.get_vreg());
 
-                self.ops.push(Op::LoadPointer {
+                self.emit(Op::LoadPointer {
                     ptr,
-                    size: typ.unwrap().size(),
+                    size: typ
+                        .unwrap()
+                        .size()
+                        .expect("pointers to records are rejected by semantic analysis"),
                     dest,
                 });
                 SourceVal::VReg(dest)
             }
 
-            ExprInner::Arithmetic(expr1, expr2, op, _sign) => {
-                // TODO: sign
+            ExprInner::Negation(operand, Some(SemanticType::F64)) => {
+                let src = self.unroll_expr(*operand, None);
+                // constants have their sign bit flipped right away
+                if let SourceVal::Immediate(bits) = src {
+                    return SourceVal::Immediate(bits ^ i64::MIN);
+                }
+
+                let a = self.src_to_vreg(src);
+                let b = self.src_to_vreg(SourceVal::Immediate((-1.0f64).to_bits() as i64));
+                let dest = dest.unwrap_or_else(|| self.get_vreg());
+
+                for vreg in [a, b, dest] {
+                    self.set_type(vreg, &SemanticType::F64);
+                }
+                self.emit(Op::FloatArithmetic {
+                    op: FloatOp::Multiply,
+                    a,
+                    b,
+                    dest,
+                });
+
+                SourceVal::VReg(dest)
+            }
+            ExprInner::Negation(operand, _) => {
+                let src = match self.unroll_expr(*operand, None) {
+                    SourceVal::Immediate(n) => return SourceVal::Immediate(n.wrapping_neg()),
+                    src => self.src_to_vreg(src),
+                };
+                let dest = dest.unwrap_or_else(|| self.get_vreg());
+
+                self.emit(Op::Negate { src, dest });
+                SourceVal::VReg(dest)
+            }
+            ExprInner::Arithmetic(expr1, expr2, op, Some(SemanticType::F64)) => {
                 let a = self.unroll_expr(*expr1, None);
                 let b = self.unroll_expr(*expr2, None);
 
                 let a = self.src_to_vreg(a);
                 let b = self.src_to_vreg(b);
+                let dest = dest.unwrap_or_else(|| self.get_vreg());
+
+                let op = match op {
+                    ArithmeticOp::Add => FloatOp::Add,
+                    ArithmeticOp::Sub => FloatOp::Subtract,
+                    ArithmeticOp::Mult => FloatOp::Multiply,
+                    ArithmeticOp::Div => FloatOp::Divide,
+                    ArithmeticOp::Mod => unreachable!("the analyzer rejects f64 remainders"),
+                };
+                for vreg in [a, b, dest] {
+                    self.set_type(vreg, &SemanticType::F64);
+                }
+                self.emit(Op::FloatArithmetic { op, a, b, dest });
+
+                SourceVal::VReg(dest)
+            }
+            ExprInner::Arithmetic(expr1, expr2, op, operand_type) => {
+                let a = self.unroll_expr(*expr1, None);
+                let b = self.unroll_expr(*expr2, None);
+
+                let a = self.src_to_vreg(a);
+                // additions and subtractions take constants directly, which the backend can
+                // often encode into the instruction
+                let b = match (op, b) {
+                    (ArithmeticOp::Add | ArithmeticOp::Sub, SourceVal::Immediate(_)) => b,
+                    _ => SourceVal::VReg(self.src_to_vreg(b)),
+                };
 
                 let dest = dest.unwrap_or_else(|| self.get_vreg());
 
                 match op {
-                    ArithmeticOp::Add => self.ops.push(Op::Add { a, b, dest }),
-                    ArithmeticOp::Sub => self.ops.push(Op::Subtract { a, b, dest }),
-                    ArithmeticOp::Mult => self.ops.push(Op::Multiply { a, b, dest }),
-                    ArithmeticOp::Div => self.ops.push(Op::Divide { a, b, dest }),
+                    ArithmeticOp::Add => self.emit(Op::Add { a, b, dest }),
+                    ArithmeticOp::Sub => self.emit(Op::Subtract { a, b, dest }),
+                    ArithmeticOp::Mult => {
+                        let b = self.src_to_vreg(b);
+                        self.emit(Op::Multiply { a, b, dest })
+                    }
+                    ArithmeticOp::Div => {
+                        let b = self.src_to_vreg(b);
+                        self.emit(Op::Divide {
+                            a,
+                            b,
+                            dest,
+                            signed: operand_type.and_then(|typ| typ.sign()) == Some(Sign::Signed),
+                        })
+                    }
+                    ArithmeticOp::Mod => {
+                        let b = self.src_to_vreg(b);
+                        self.emit(Op::Modulo {
+                            a,
+                            b,
+                            dest,
+                            signed: operand_type.and_then(|typ| typ.sign()) == Some(Sign::Signed),
+                        })
+                    }
                 }
 
                 SourceVal::VReg(dest)
@@ -226,7 +531,7 @@ impl<'ir> BlockBuilder<'ir> {
 
                 let dest = dest.unwrap_or_else(|| self.get_vreg());
 
-                self.ops.push(Op::Compare {
+                self.emit(Op::Compare {
                     a: expr1,
                     b: expr2,
                     cond: Condition::from_ast_op(op, matches!(sign, Some(Sign::Signed))),
@@ -236,19 +541,64 @@ impl<'ir> BlockBuilder<'ir> {
                 SourceVal::VReg(dest)
             }
 
+            ExprInner::Ternary(cond, then, otherwise) => {
+                let dest = dest.unwrap_or_else(|| self.get_vreg());
+
+                // arms without side effects are both evaluated and the result picked without
+                // branching
+                if then.is_pure() && otherwise.is_pure() {
+                    let cond = self.unroll_expr(*cond, None);
+                    let cond = self.src_to_vreg(cond);
+                    let a = self.unroll_expr(*then, None);
+                    let a = self.src_to_vreg(a);
+                    let b = self.unroll_expr(*otherwise, None);
+                    let b = self.src_to_vreg(b);
+
+                    self.emit(Op::Select { cond, a, b, dest });
+                    return SourceVal::VReg(dest);
+                }
+
+                let (cond, negated) = self.unroll_branch_cond(*cond);
+                let otherwise_label = self.reserve_label();
+                let end_label = self.reserve_label();
+                if negated {
+                    self.emit(Op::BranchIf {
+                        cond,
+                        label: otherwise_label,
+                    });
+                } else {
+                    self.emit(Op::BranchIfNot {
+                        cond,
+                        label: otherwise_label,
+                    });
+                }
+
+                let src = self.unroll_expr(*then, Some(dest));
+                if src != SourceVal::VReg(dest) {
+                    self.emit(Op::Assign { src, dest });
+                }
+                self.emit(Op::Branch { label: end_label });
+
+                self.set_label_here(otherwise_label);
+                let src = self.unroll_expr(*otherwise, Some(dest));
+                if src != SourceVal::VReg(dest) {
+                    self.emit(Op::Assign { src, dest });
+                }
+                self.set_label_here(end_label);
+
+                SourceVal::VReg(dest)
+            }
+
             ExprInner::FnCall(function, args) => {
                 let args = args
                     .into_iter()
-                    .map(|e| {
-                        let src = self.unroll_expr(e, None);
-                        self.src_to_vreg(src)
-                    })
+                    .map(|e| self.unroll_expr(e, None))
                     .collect();
 
                 let dest = dest.unwrap_or_else(|| self.get_vreg());
 
                 println!("call to {} ret {:?}", function, dest);
-                self.ops.push(Op::Call {
+                self.emit(Op::Call {
                     function: function.clone(),
                     args,
                     dest: Some(dest),
@@ -257,10 +607,124 @@ impl<'ir> BlockBuilder<'ir> {
                 SourceVal::VReg(dest)
             }
 
-            ExprInner::Cast(expr, _typ) => self.unroll_expr(*expr, dest),
+            ExprInner::Cast(expr, SemanticType::I64, Some(SemanticType::F64)) => {
+                let src = self.unroll_expr(*expr, None);
+                let src = self.src_to_vreg(src);
+                let dest = dest.unwrap_or_else(|| self.get_vreg());
+
+                self.set_type(src, &SemanticType::F64);
+                self.emit(Op::FloatToInt { src, dest });
+                SourceVal::VReg(dest)
+            }
+            ExprInner::Cast(expr, SemanticType::F64, Some(SemanticType::I64)) => {
+                let src = self.unroll_expr(*expr, None);
+                let src = self.src_to_vreg(src);
+                let dest = dest.unwrap_or_else(|| self.get_vreg());
+
+                self.set_type(dest, &SemanticType::F64);
+                self.emit(Op::IntToFloat { src, dest });
+                SourceVal::VReg(dest)
+            }
+            // other casts keep the bits as they are
+            ExprInner::Cast(expr, ..) => self.unroll_expr(*expr, dest),
+
+            ExprInner::Field(record, _, position) => {
+                let first = self.unroll_record(*record);
+                SourceVal::VReg(VirtualReg(first.0 + position.unwrap() as u32))
+            }
+            ExprInner::Record(_) => unreachable!("records are unrolled with unroll_record"),
+        }
+    }
+
+    /// Whether `expr` produces a record, which lives in two consecutive registers.
+    fn is_record(&self, expr: &Expression) -> bool {
+        match &expr.inner {
+            ExprInner::Record(_) => true,
+            ExprInner::Variable(var) => self.records.contains(&self.expect_vreg(var)),
+            ExprInner::FnCall(function, _) => self.record_fns.contains(function),
+            _ => false,
+        }
+    }
+
+    /// Emits the operations that compute the record `expr`, and returns the first of the two
+    /// consecutive registers holding its fields.
+    fn unroll_record(&mut self, expr: Expression) -> VirtualReg {
+        let outer = self.span.replace(expr.span.clone());
+
+        let first = match expr.inner {
+            ExprInner::Variable(var) => self.expect_vreg(&var),
+            ExprInner::Record(fields) => {
+                let first = self.get_record_vregs();
+                for (i, field) in fields.into_iter().enumerate() {
+                    let dest = VirtualReg(first.0 + i as u32);
+                    let src = self.unroll_expr(field, Some(dest));
+                    if src != SourceVal::VReg(dest) {
+                        self.emit(Op::Assign { src, dest });
+                    }
+                }
+                first
+            }
+            ExprInner::FnCall(function, args) => {
+                let args = args
+                    .into_iter()
+                    .map(|e| self.unroll_expr(e, None))
+                    .collect();
+
+                let first = self.get_record_vregs();
+                self.emit(Op::Call {
+                    function,
+                    args,
+                    dest: Some(first),
+                });
+                self.emit(Op::SecondResult {
+                    dest: VirtualReg(first.0 + 1),
+                });
+                first
+            }
+            _ => unreachable!("expression is not a record"),
+        };
+
+        self.span = outer;
+        first
+    }
+
+    /// Reserves two consecutive registers for the fields of a record, and returns the first.
+    fn get_record_vregs(&mut self) -> VirtualReg {
+        let first = self.get_vreg();
+        self.get_vreg();
+        first
+    }
+
+    fn copy_record(&mut self, src: VirtualReg, dest: VirtualReg) {
+        for i in 0..2 {
+            self.emit(Op::Assign {
+                src: SourceVal::VReg(VirtualReg(src.0 + i)),
+                dest: VirtualReg(dest.0 + i),
+            });
         }
     }
 
+    /// Moves variables reassigned in an inner scope back into their outer registers, and
+    /// restores the outer scope.
+    fn leave_scope(&mut self, outer_vregs: HashMap<String, VirtualReg>) {
+        let inner_vregs = std::mem::replace(&mut self.vregs, outer_vregs);
+        for (name, inner) in inner_vregs {
+            if let Some(&outer) = self.vregs.get(&name)
+                && inner != outer
+            {
+                self.emit(Op::Assign {
+                    src: SourceVal::VReg(inner),
+                    dest: outer,
+                });
+            }
+        }
+    }
+
+    fn emit(&mut self, op: Op) {
+        self.ops.push(op);
+        self.spans.push(self.span.clone());
+    }
+
     fn get_or_insert_vreg<S: Into<String> + AsRef<str>>(&mut self, var: S) -> VirtualReg {
         if let Some(&vreg) = self.vregs.get(var.as_ref()) {
             vreg
@@ -284,11 +748,36 @@ impl<'ir> BlockBuilder<'ir> {
         vreg
     }
 
+    /// Records that `vreg` holds a value of type `ty`, for picking the registers it is kept in.
+    fn set_type(&mut self, vreg: VirtualReg, ty: &SemanticType) {
+        match RegClass::of(ty) {
+            RegClass::General => (),
+            class => {
+                self.classes.insert(vreg, class);
+            }
+        }
+    }
+
+    /// Whether `expr` is known to evaluate to an `f64`.
+    fn is_float(&self, expr: &Expression) -> bool {
+        match &expr.inner {
+            ExprInner::Float(_)
+            | ExprInner::Negation(_, Some(SemanticType::F64))
+            | ExprInner::Arithmetic(.., Some(SemanticType::F64))
+            | ExprInner::Cast(_, SemanticType::F64, _)
+            | ExprInner::Deref(_, Some(SemanticType::F64)) => true,
+            ExprInner::Variable(var) => {
+                self.classes.get(&self.expect_vreg(var)) == Some(&RegClass::Float)
+            }
+            _ => false,
+        }
+    }
+
     fn src_to_vreg(&mut self, src: SourceVal) -> VirtualReg {
         match src {
             SourceVal::Immediate(_) | SourceVal::String(_) => {
                 let dest = self.get_vreg();
-                self.ops.push(Op::Assign { src, dest });
+                self.emit(Op::Assign { src, dest });
                 dest
             }
             SourceVal::VReg(vreg) => vreg,
@@ -310,3 +799,146 @@ impl<'ir> BlockBuilder<'ir> {
         label
     }
 }
+
+fn is_zero(expr: &Expression) -> bool {
+    matches!(expr.inner, ExprInner::Const(0, _))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{Item, Op, SourceVal, VirtualReg, generate};
+
+    #[test]
+    fn constant_addend_is_folded_into_add() {
+        let ir = generate("fn main() -> i64 { a := 1; return a + 5; }");
+        let Item::Function { bb, .. } = &ir.items[0];
+
+        assert_eq!(
+            bb.ops,
+            [
+                Op::Assign {
+                    src: SourceVal::Immediate(1),
+                    dest: VirtualReg(0),
+                },
+                Op::Add {
+                    a: VirtualReg(0),
+                    b: SourceVal::Immediate(5),
+                    dest: VirtualReg(1),
+                },
+                Op::Return {
+                    value: SourceVal::VReg(VirtualReg(1)),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn ternary_lowering() {
+        let is_branch = |op: &Op| {
+            matches!(
+                op,
+                Op::Branch { .. } | Op::BranchIf { .. } | Op::BranchIfNot { .. }
+            )
+        };
+
+        // pure arms are selected without branching
+        let ir = generate("fn main() -> i64 { a := 1; return a ? a + 1 : 2; }");
+        let Item::Function { bb, .. } = &ir.items[0];
+        assert!(bb.ops.iter().any(|op| matches!(op, Op::Select { .. })));
+        assert!(!bb.ops.iter().any(is_branch));
+
+        // a call is only made when its arm is taken
+        let ir = generate(
+            "fn main() -> i64 { a := 1; return a ? one() : 2; } fn one() -> i64 { return 1; }",
+        );
+        let Item::Function { bb, .. } = &ir.items[0];
+        assert!(!bb.ops.iter().any(|op| matches!(op, Op::Select { .. })));
+        assert_eq!(bb.ops.iter().filter(|op| is_branch(op)).count(), 2);
+    }
+
+    #[test]
+    fn operations_carry_the_span_of_their_expression() {
+        let source = "fn main() -> i64 { a := 1; return a + 5 * 2; }";
+        let ir = generate(source);
+        let Item::Function { bb, .. } = &ir.items[0];
+
+        let add = bb
+            .ops
+            .iter()
+            .position(|op| matches!(op, Op::Add { .. }))
+            .unwrap();
+        let (_, range) = bb.spans[add].clone().unwrap();
+        assert_eq!(&source[range], "a + 5 * 2");
+
+        let mul = bb
+            .ops
+            .iter()
+            .position(|op| matches!(op, Op::Multiply { .. }))
+            .unwrap();
+        let (_, range) = bb.spans[mul].clone().unwrap();
+        assert_eq!(&source[range], "5 * 2");
+    }
+
+    #[test]
+    fn call_arguments_are_lowered_in_order() {
+        let ir = generate(
+            "fn add(a: i64, b: i64) -> i64 => a + b; fn main() -> i64 { x := 4; return add(x, 3); }",
+        );
+        let Item::Function { bb, .. } = &ir.items[1];
+
+        assert!(bb.ops.contains(&Op::Call {
+            function: String::from("add"),
+            args: vec![SourceVal::VReg(VirtualReg(0)), SourceVal::Immediate(3)],
+            dest: Some(VirtualReg(1)),
+        }));
+    }
+
+    #[test]
+    fn parameters_are_the_first_registers() {
+        let ir = generate(
+            "fn second(a: i64, b: i64) -> i64 { return b; } fn main() -> i64 { return second(1, 2); }",
+        );
+        let Item::Function { args, bb, .. } = &ir.items[0];
+
+        assert_eq!(args, &[VirtualReg(0), VirtualReg(1)]);
+        assert_eq!(
+            bb.ops,
+            [Op::Return {
+                value: SourceVal::VReg(VirtualReg(1)),
+            }]
+        );
+    }
+
+    #[test]
+    fn record_fields_are_consecutive_registers() {
+        let ir = generate(
+            "fn pair() -> (i64, i64) => (1, 2); fn main() -> i64 { p := pair(); return p.1; }",
+        );
+
+        let [
+            Item::Function { bb: pair, .. },
+            Item::Function { bb: main, .. },
+        ] = &ir.items[..]
+        else {
+            panic!("expected two functions");
+        };
+        assert_eq!(
+            pair.ops.last(),
+            Some(&Op::ReturnPair {
+                first: VirtualReg(0),
+                second: VirtualReg(1),
+            })
+        );
+        assert_eq!(
+            main.ops[1..],
+            [
+                Op::SecondResult {
+                    dest: VirtualReg(1),
+                },
+                Op::Return {
+                    value: SourceVal::VReg(VirtualReg(1)),
+                },
+            ]
+        );
+    }
+}
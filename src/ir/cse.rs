@@ -0,0 +1,174 @@
+use std::collections::HashMap;
+
+use crate::ir::{BasicBlock, Condition, FloatOp, IR, Item, Operation, SourceVal, VirtualReg};
+
+/// A pure computation, identified by what it computes from which operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Computation {
+    Add(VirtualReg, SourceVal),
+    Subtract(VirtualReg, SourceVal),
+    Negate(VirtualReg),
+    Multiply(VirtualReg, VirtualReg),
+    Divide(VirtualReg, VirtualReg, bool),
+    Modulo(VirtualReg, VirtualReg, bool),
+    Float(FloatOp, VirtualReg, VirtualReg),
+    FloatToInt(VirtualReg),
+    IntToFloat(VirtualReg),
+    Compare(VirtualReg, VirtualReg, Condition),
+    Select(VirtualReg, VirtualReg, VirtualReg),
+}
+
+impl Computation {
+    /// What `op` computes and where it is stored, if it is a pure computation. Operands of
+    /// commutative operations are ordered, so `a + b` and `b + a` are the same.
+    fn of(op: &Operation) -> Option<(Self, VirtualReg)> {
+        let computation = match *op {
+            Operation::Add {
+                a,
+                b: SourceVal::VReg(b),
+                ..
+            } => Self::Add(a.min(b), SourceVal::VReg(a.max(b))),
+            Operation::Add { a, b, .. } => Self::Add(a, b),
+            Operation::Subtract { a, b, .. } => Self::Subtract(a, b),
+            Operation::Negate { src, .. } => Self::Negate(src),
+            Operation::Multiply { a, b, .. } => Self::Multiply(a.min(b), a.max(b)),
+            Operation::Divide { a, b, signed, .. } => Self::Divide(a, b, signed),
+            Operation::Modulo { a, b, signed, .. } => Self::Modulo(a, b, signed),
+            Operation::FloatArithmetic { op, a, b, .. } => Self::Float(op, a, b),
+            Operation::FloatToInt { src, .. } => Self::FloatToInt(src),
+            Operation::IntToFloat { src, .. } => Self::IntToFloat(src),
+            Operation::Compare { a, b, cond, .. } => Self::Compare(a, b, cond),
+            Operation::Select { cond, a, b, .. } => Self::Select(cond, a, b),
+            _ => return None,
+        };
+
+        let (_, Some(dest)) = op.vregs_used() else {
+            unreachable!("computation without a destination")
+        };
+        Some((computation, dest))
+    }
+
+    fn reads(&self, vreg: VirtualReg) -> bool {
+        let operands: &[VirtualReg] = match *self {
+            Self::Add(a, b) | Self::Subtract(a, b) => match b {
+                SourceVal::VReg(b) => &[a, b],
+                _ => &[a],
+            },
+            Self::Negate(src) | Self::FloatToInt(src) | Self::IntToFloat(src) => &[src],
+            Self::Multiply(a, b)
+            | Self::Divide(a, b, _)
+            | Self::Modulo(a, b, _)
+            | Self::Float(_, a, b)
+            | Self::Compare(a, b, _) => &[a, b],
+            Self::Select(cond, a, b) => &[cond, a, b],
+        };
+
+        operands.contains(&vreg)
+    }
+}
+
+impl IR {
+    /// Replaces computations that were already made with a copy of the earlier result. A
+    /// result is reused only while neither it nor any of its operands has been assigned again,
+    /// and only within straight-line code: everything is forgotten at labels, since control may
+    /// arrive there from elsewhere, and at [side effects](Operation::has_side_effects), since
+    /// they may write to variables through pointers.
+    pub fn eliminate_common_subexpressions(&mut self) {
+        for Item::Function { bb, .. } in &mut self.items {
+            eliminate_in_block(bb);
+        }
+    }
+}
+
+fn eliminate_in_block(bb: &mut BasicBlock) {
+    let mut available: HashMap<Computation, VirtualReg> = HashMap::new();
+
+    for idx in 0..bb.ops.len() {
+        if bb.labels.contains_key(&idx) {
+            available.clear();
+        }
+
+        let op = &mut bb.ops[idx];
+        if op.has_side_effects() {
+            available.clear();
+            continue;
+        }
+
+        let computation = Computation::of(op);
+        let mut reused = false;
+        if let Some((computation, dest)) = computation
+            && let Some(&earlier) = available.get(&computation)
+        {
+            *op = Operation::Assign {
+                src: SourceVal::VReg(earlier),
+                dest,
+            };
+            reused = true;
+        }
+
+        let written = match *op {
+            // the register is counted as used, but it is assigned the address
+            Operation::AddressOf { dest, .. } => Some(dest),
+            _ => op.vregs_used().1,
+        };
+        if let Some(written) = written {
+            available.retain(|computation, &mut result| {
+                result != written && !computation.reads(written)
+            });
+        }
+
+        if !reused
+            && let Some((computation, dest)) = computation
+            && !computation.reads(dest)
+        {
+            available.insert(computation, dest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{IR, Operation, eval::Interpreter, generate, ops};
+
+    /// Operations in `f` that compute something, rather than copy or jump.
+    fn computations(ir: &IR) -> usize {
+        ops(ir, "f")
+            .iter()
+            .filter(|op| !matches!(op, Operation::Assign { .. }) && !op.has_side_effects())
+            .count()
+    }
+
+    #[test]
+    fn repeated_subexpression_is_computed_once() {
+        let mut ir = generate(
+            "
+            const fn f(a: i64, b: i64) -> i64 { return (a + b) * (b + a); }
+            fn main(argc: i64) -> i64 { return f(argc, 1); }
+            ",
+        );
+        assert_eq!(computations(&ir), 3);
+
+        ir.eliminate_common_subexpressions();
+        assert_eq!(computations(&ir), 2, "{}", ir);
+        assert_eq!(Interpreter::new(&ir).call("f", &[3, 4]), Some(49));
+    }
+
+    #[test]
+    fn reassigned_operand_is_computed_again() {
+        let mut ir = generate(
+            "
+            const fn f(a: i64) -> i64 {
+                b := a;
+                x := b * 3;
+                b = 5;
+                return x + b * 3;
+            }
+            fn main(argc: i64) -> i64 { return f(argc); }
+            ",
+        );
+
+        ir.eliminate_common_subexpressions();
+        assert_eq!(computations(&ir), 3, "{}", ir);
+        assert_eq!(Interpreter::new(&ir).call("f", &[2]), Some(21));
+    }
+}
@@ -0,0 +1,97 @@
+use std::collections::HashSet;
+
+use crate::ir::{BasicBlock, IR, Item, OpIndex, Operation, VirtualReg};
+
+impl IR {
+    /// Removes operations whose result is never read, in every function.
+    pub fn eliminate_dead_code(&mut self) {
+        let overflow_checks = self.overflow_checks;
+        for Item::Function { bb, .. } in &mut self.items {
+            eliminate_dead_code(bb, overflow_checks);
+        }
+    }
+}
+
+/// Removes operations without side effects whose result is never read, until there are none
+/// left, since removing one may leave the operations it read from unread as well.
+///
+/// A register whose lifetime only covers the operation assigning it is never read. Registers
+/// whose address is taken are kept, since they may be read through a pointer. With
+/// `overflow_checks`, arithmetic is kept as well, since it may trap.
+pub fn eliminate_dead_code(bb: &mut BasicBlock, overflow_checks: bool) {
+    let addressed: HashSet<VirtualReg> = bb
+        .ops
+        .iter()
+        .filter_map(|op| match *op {
+            Operation::AddressOf { val, .. } => Some(val),
+            _ => None,
+        })
+        .collect();
+
+    loop {
+        let lifetimes = bb.lifetimes();
+        let dead: HashSet<OpIndex> = bb
+            .ops
+            .iter()
+            .enumerate()
+            .filter(|(idx, op)| {
+                let Some(dest) = op.vregs_used().1 else {
+                    return false;
+                };
+
+                !op.has_side_effects()
+                    && !(overflow_checks && may_overflow(op))
+                    && !addressed.contains(&dest)
+                    && lifetimes.get(&dest).is_some_and(|lifetime| {
+                        lifetime.start() == Some(*idx) && lifetime.end() == Some(idx + 1)
+                    })
+            })
+            .map(|(idx, _)| idx)
+            .collect();
+
+        if dead.is_empty() {
+            return;
+        }
+        bb.remove_ops(&dead);
+    }
+}
+
+/// Whether `op` traps when it overflows, if overflow checks are on.
+fn may_overflow(op: &Operation) -> bool {
+    matches!(
+        op,
+        Operation::Add { .. } | Operation::Subtract { .. } | Operation::Multiply { .. }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{Operation, SourceVal, generate, ops};
+
+    #[test]
+    fn dead_declarations_leave_only_the_return() {
+        let mut ir = generate("fn main() -> i64 { a := 5; b := a + 1; c := b - 2; return 0; }");
+
+        ir.eliminate_dead_code();
+        assert_eq!(
+            ops(&ir, "main"),
+            [Operation::Return {
+                value: SourceVal::Immediate(0),
+            }]
+        );
+    }
+
+    #[test]
+    fn calls_and_addressed_registers_are_kept() {
+        let mut ir = generate(
+            "
+            fn f() -> i64 { return 1; }
+            fn main() -> i64 { a := 5; p := &a; unused := f(); return *p; }
+            ",
+        );
+        let before = ops(&ir, "main").len();
+
+        ir.eliminate_dead_code();
+        assert_eq!(ops(&ir, "main").len(), before, "{}", ir);
+    }
+}
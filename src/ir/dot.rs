@@ -0,0 +1,76 @@
+use std::fmt::Write;
+
+use crate::ir::{BasicBlock, IR, Item, cfg::Block};
+
+impl IR {
+    /// Renders the control-flow graph of every function in Graphviz DOT format.
+    ///
+    /// Each function becomes a cluster, with one node per straight-line block
+    /// of operations and an edge for every possible successor.
+    pub fn to_dot(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "digraph cfg {{").unwrap();
+        writeln!(out, "    node [shape=box, fontname=monospace];").unwrap();
+
+        for item in &self.items {
+            let Item::Function { name, bb, .. } = item;
+            write_function(&mut out, name, bb);
+        }
+
+        writeln!(out, "}}").unwrap();
+        out
+    }
+}
+
+fn write_function(out: &mut String, name: &str, bb: &BasicBlock) {
+    let blocks = bb.blocks();
+    let node = |block: usize| format!("\"{}.B{}\"", name, block);
+
+    writeln!(out, "    subgraph \"cluster_{}\" {{", name).unwrap();
+    writeln!(out, "        label=\"{}\";", escape(name)).unwrap();
+
+    for (block, Block { ops, .. }) in blocks.iter().enumerate() {
+        let mut text = String::new();
+        for i in ops.clone() {
+            if let Some(labels) = bb.labels.get(&i) {
+                for label in labels {
+                    write!(text, "{}:\\l", label).unwrap();
+                }
+            }
+            write!(text, "    {}\\l", escape(&bb.ops[i].to_string())).unwrap();
+        }
+
+        writeln!(out, "        {} [label=\"{}\"];", node(block), text).unwrap();
+    }
+
+    for (block, successors) in blocks.iter().map(Block::successors).enumerate() {
+        for &successor in successors {
+            writeln!(out, "        {} -> {};", node(block), node(successor)).unwrap();
+        }
+    }
+
+    writeln!(out, "    }}").unwrap();
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::generate;
+
+    #[test]
+    fn if_statement_graph() {
+        let dot = generate("fn main() -> i64 { x := 1; if x == 2 { x = 3; } return x; }").to_dot();
+
+        let nodes = dot.lines().filter(|l| l.contains("[label=")).count();
+        let edges = dot.lines().filter(|l| l.contains("->")).count();
+
+        assert_eq!(nodes, 3, "{}", dot);
+        assert_eq!(edges, 3, "{}", dot);
+        assert!(dot.contains("\"main.B0\" -> \"main.B1\""));
+        assert!(dot.contains("\"main.B0\" -> \"main.B2\""));
+        assert!(dot.contains("\"main.B1\" -> \"main.B2\""));
+    }
+}
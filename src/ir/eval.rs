@@ -0,0 +1,512 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    BasicBlock, Condition, IR, Item, Label, OpIndex, Operation, SourceVal, VirtualReg,
+};
+
+/// Operations a single compile-time call may execute. Calls that take longer, such as ones
+/// that never return, are left to run at runtime.
+const STEP_LIMIT: usize = 100_000;
+
+/// How deeply const functions may call each other during one compile-time call.
+const DEPTH_LIMIT: usize = 64;
+
+impl IR {
+    /// Replaces calls to const functions whose arguments are all constants with the value they
+    /// return. Calls that cannot be evaluated at compile time are kept.
+    ///
+    /// Calls [have side effects](Operation::has_side_effects) in general, but a const function
+    /// can only compute its result, so replacing the call in place is safe. Nothing is moved.
+    pub fn fold_const_calls(&mut self) {
+        let mut folded = Vec::new();
+
+        let mut interpreter = Interpreter::new(self);
+        for (item_idx, Item::Function { args, bb, .. }) in self.items.iter().enumerate() {
+            let constants = constant_vregs(bb, args);
+
+            for (idx, op) in bb.ops.iter().enumerate() {
+                let Operation::Call {
+                    function,
+                    args,
+                    dest: Some(dest),
+                } = op
+                else {
+                    continue;
+                };
+
+                if !interpreter.is_const(function) {
+                    continue;
+                }
+
+                let args: Option<Vec<i64>> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        SourceVal::Immediate(n) => Some(*n),
+                        SourceVal::VReg(vreg) => match constants.get(vreg) {
+                            Some(&(def, n)) if def < idx => Some(n),
+                            _ => None,
+                        },
+                        SourceVal::String(_) => None,
+                    })
+                    .collect();
+
+                if let Some(args) = args
+                    && let Some(value) = interpreter.call(function, &args)
+                {
+                    let op = Operation::Assign {
+                        src: SourceVal::Immediate(value),
+                        dest: *dest,
+                    };
+                    folded.push((item_idx, idx, op));
+                }
+            }
+        }
+
+        for (item_idx, idx, op) in folded {
+            let Item::Function { bb, .. } = &mut self.items[item_idx];
+            bb.ops[idx] = op;
+        }
+    }
+}
+
+/// Finds the virtual registers that are assigned a constant once and never changed, with the
+/// index of the assignment. Registers whose address is taken may be written through a pointer,
+/// so they are never constant.
+fn constant_vregs(bb: &BasicBlock, args: &[VirtualReg]) -> HashMap<VirtualReg, (OpIndex, i64)> {
+    let addressed: HashSet<VirtualReg> = bb
+        .ops
+        .iter()
+        .filter_map(|op| match *op {
+            Operation::AddressOf { val, .. } => Some(val),
+            _ => None,
+        })
+        .collect();
+
+    let mut assignments: HashMap<VirtualReg, (usize, OpIndex, Option<i64>)> = HashMap::new();
+
+    for (idx, op) in bb.ops.iter().enumerate() {
+        let (_, Some(dest)) = op.vregs_used() else {
+            continue;
+        };
+
+        let value = match op {
+            Operation::Assign {
+                src: SourceVal::Immediate(n),
+                ..
+            } => Some(*n),
+            _ => None,
+        };
+
+        let entry = assignments.entry(dest).or_insert((0, idx, value));
+        entry.0 += 1;
+    }
+
+    assignments
+        .into_iter()
+        .filter(|(vreg, _)| !args.contains(vreg) && !addressed.contains(vreg))
+        .filter_map(|(vreg, (count, idx, value))| match (count, value) {
+            (1, Some(n)) => Some((vreg, (idx, n))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Evaluates calls to const functions at compile time.
+pub struct Interpreter<'ir> {
+    functions: HashMap<&'ir str, (&'ir [VirtualReg], &'ir BasicBlock)>,
+    steps: usize,
+}
+
+impl<'ir> Interpreter<'ir> {
+    pub fn new(ir: &'ir IR) -> Self {
+        let functions = ir
+            .items
+            .iter()
+            .filter_map(|item| match item {
+                Item::Function {
+                    name,
+                    args,
+                    bb,
+                    is_const: true,
+                    ..
+                } => Some((name.as_str(), (args.as_slice(), bb))),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            functions,
+            steps: 0,
+        }
+    }
+
+    pub fn is_const(&self, function: &str) -> bool {
+        self.functions.contains_key(function)
+    }
+
+    /// Evaluates a call to the const function `function`. Returns `None` if the call does
+    /// anything that can only happen at runtime, or runs for too long.
+    pub fn call(&mut self, function: &str, args: &[i64]) -> Option<i64> {
+        self.steps = 0;
+        self.eval(function, args, 0)
+    }
+
+    fn eval(&mut self, function: &str, args: &[i64], depth: usize) -> Option<i64> {
+        let &(params, bb) = self.functions.get(function)?;
+        if depth > DEPTH_LIMIT || params.len() != args.len() {
+            return None;
+        }
+
+        let labels: HashMap<Label, OpIndex> = bb
+            .labels
+            .iter()
+            .flat_map(|(&idx, labels)| labels.iter().map(move |&label| (label, idx)))
+            .collect();
+
+        let mut regs: HashMap<VirtualReg, i64> =
+            params.iter().copied().zip(args.iter().copied()).collect();
+
+        let mut pc = 0;
+        while let Some(op) = bb.ops.get(pc) {
+            self.steps += 1;
+            if self.steps > STEP_LIMIT {
+                return None;
+            }
+
+            pc += 1;
+            let reg = |vreg: &VirtualReg| regs.get(vreg).copied();
+
+            match op {
+                Operation::Assign { src, dest } => {
+                    let value = value(&regs, src)?;
+                    regs.insert(*dest, value);
+                }
+                Operation::Add { a, b, dest } => {
+                    let value = reg(a)?.wrapping_add(value(&regs, b)?);
+                    regs.insert(*dest, value);
+                }
+                Operation::Subtract { a, b, dest } => {
+                    let value = reg(a)?.wrapping_sub(value(&regs, b)?);
+                    regs.insert(*dest, value);
+                }
+                Operation::Negate { src, dest } => {
+                    let value = reg(src)?.wrapping_neg();
+                    regs.insert(*dest, value);
+                }
+                Operation::Multiply { a, b, dest } => {
+                    let value = reg(a)?.wrapping_mul(reg(b)?);
+                    regs.insert(*dest, value);
+                }
+                Operation::Divide { a, b, dest, signed } => {
+                    let (a, b) = (reg(a)?, reg(b)?);
+                    let value = match (b, signed) {
+                        (0, _) => 0,
+                        (_, true) => a.wrapping_div(b),
+                        (_, false) => ((a as u64) / (b as u64)) as i64,
+                    };
+                    regs.insert(*dest, value);
+                }
+                Operation::Modulo { a, b, dest, signed } => {
+                    let (a, b) = (reg(a)?, reg(b)?);
+                    let value = match (b, signed) {
+                        (0, _) => a,
+                        (_, true) => a.wrapping_rem(b),
+                        (_, false) => ((a as u64) % (b as u64)) as i64,
+                    };
+                    regs.insert(*dest, value);
+                }
+                Operation::FloatArithmetic { op, a, b, dest } => {
+                    let (a, b) = (
+                        f64::from_bits(reg(a)? as u64),
+                        f64::from_bits(reg(b)? as u64),
+                    );
+                    regs.insert(*dest, op.apply(a, b).to_bits() as i64);
+                }
+                Operation::FloatToInt { src, dest } => {
+                    // saturates like FCVTZS
+                    let value = f64::from_bits(reg(src)? as u64) as i64;
+                    regs.insert(*dest, value);
+                }
+                Operation::IntToFloat { src, dest } => {
+                    let value = reg(src)? as f64;
+                    regs.insert(*dest, value.to_bits() as i64);
+                }
+                Operation::Compare { a, b, cond, dest } => {
+                    let value = compare(reg(a)?, reg(b)?, *cond)?;
+                    regs.insert(*dest, value as i64);
+                }
+                Operation::Select { cond, a, b, dest } => {
+                    let value = if reg(cond)? != 0 { reg(a)? } else { reg(b)? };
+                    regs.insert(*dest, value);
+                }
+
+                Operation::Branch { label } => pc = *labels.get(label)?,
+                Operation::BranchIf { cond, label } => {
+                    if reg(cond)? != 0 {
+                        pc = *labels.get(label)?;
+                    }
+                }
+                Operation::BranchIfNot { cond, label } => {
+                    if reg(cond)? == 0 {
+                        pc = *labels.get(label)?;
+                    }
+                }
+                Operation::Switch {
+                    value,
+                    cases,
+                    default,
+                } => {
+                    let value = reg(value)?;
+                    let label = cases
+                        .iter()
+                        .find(|&&(n, _)| n == value)
+                        .map_or(default, |(_, label)| label);
+                    pc = *labels.get(label)?;
+                }
+
+                Operation::Return { value: src } => return value(&regs, src),
+                Operation::Call {
+                    function,
+                    args,
+                    dest,
+                } => {
+                    let args: Vec<i64> = args
+                        .iter()
+                        .map(|arg| value(&regs, arg))
+                        .collect::<Option<_>>()?;
+                    let value = match (function.as_str(), args.as_slice()) {
+                        ("abs", &[n]) => n.wrapping_abs(),
+                        ("min", &[a, b]) => a.min(b),
+                        ("max", &[a, b]) => a.max(b),
+                        // a failing assertion traps at runtime
+                        ("assert", &[cond]) => (cond != 0).then_some(0)?,
+                        _ => self.eval(function, &args, depth + 1)?,
+                    };
+
+                    if let Some(dest) = dest {
+                        regs.insert(*dest, value);
+                    }
+                }
+
+                Operation::AddressOf { .. }
+                | Operation::LoadPointer { .. }
+                | Operation::StorePointer { .. }
+                | Operation::ReturnPair { .. }
+                | Operation::SecondResult { .. } => return None,
+            }
+        }
+
+        None
+    }
+}
+
+fn value(regs: &HashMap<VirtualReg, i64>, src: &SourceVal) -> Option<i64> {
+    match src {
+        SourceVal::Immediate(n) => Some(*n),
+        SourceVal::VReg(vreg) => regs.get(vreg).copied(),
+        SourceVal::String(_) => None,
+    }
+}
+
+/// Whether comparing `a` with `b` satisfies `cond`, or `None` for conditions that depend on
+/// flags other than the comparison itself.
+pub(super) fn compare(a: i64, b: i64, cond: Condition) -> Option<bool> {
+    use Condition::*;
+
+    let (ua, ub) = (a as u64, b as u64);
+    let holds = match cond {
+        Equal => a == b,
+        NotEqual => a != b,
+        UnsignedGreaterOrEqual => ua >= ub,
+        UnsignedLess => ua < ub,
+        UnsignedGreater => ua > ub,
+        UnsignedLessOrEqual => ua <= ub,
+        SignedGreaterOrEqual => a >= b,
+        SignedLess => a < b,
+        SignedGreater => a > b,
+        SignedLessOrEqual => a <= b,
+        Always => true,
+        Never => false,
+        Negative | PositiveOrZero | Overflow | NoOverflow => return None,
+    };
+
+    Some(holds)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{Operation, SourceVal, generate, ops};
+
+    fn calls(ops: &[Operation]) -> usize {
+        ops.iter()
+            .filter(|op| matches!(op, Operation::Call { .. }))
+            .count()
+    }
+
+    #[test]
+    fn const_call_is_folded() {
+        let ir = generate(
+            "
+            const fn square(x: i64) -> i64 { return x * x; }
+            fn main() -> i64 { return square(7); }
+            ",
+        );
+
+        let ops = ops(&ir, "main");
+        assert_eq!(calls(ops), 0);
+        assert!(ops.contains(&Operation::Assign {
+            src: SourceVal::Immediate(49),
+            dest: match ops.last() {
+                Some(Operation::Return {
+                    value: SourceVal::VReg(vreg),
+                }) => *vreg,
+                op => panic!("expected return of a register, got {:?}", op),
+            },
+        }));
+    }
+
+    #[test]
+    fn const_remainder() {
+        let ir = generate(
+            "
+            const fn rem(a: i64, b: i64) -> i64 { return a % b; }
+            fn main() -> i64 { return rem(-17, 5) + rem(7, 0); }
+            ",
+        );
+
+        // the remainder takes the sign of the dividend, and dividing by zero leaves it whole
+        let ops = ops(&ir, "main");
+        assert_eq!(calls(ops), 0);
+        for n in [-2, 7] {
+            assert!(ops.iter().any(|op| matches!(
+                op,
+                Operation::Assign { src: SourceVal::Immediate(m), .. } if *m == n
+            )));
+        }
+    }
+
+    #[test]
+    fn const_call_with_loop_and_nested_calls() {
+        let ir = generate(
+            "
+            const fn twice(x: i64) -> i64 { return x + x; }
+            const fn pow2(n: i64) -> i64 {
+                x := 1;
+                while n > 0 {
+                    x = twice(x);
+                    n = n - 1;
+                }
+                return x;
+            }
+            fn main() -> i64 { return pow2(10); }
+            ",
+        );
+
+        let ops = ops(&ir, "main");
+        assert_eq!(calls(ops), 0);
+        assert!(ops.iter().any(|op| matches!(
+            op,
+            Operation::Assign {
+                src: SourceVal::Immediate(1024),
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn call_with_runtime_argument_is_kept() {
+        let ir = generate(
+            "
+            const fn square(x: i64) -> i64 { return x * x; }
+            fn main(argc: i64) -> i64 { return square(argc); }
+            ",
+        );
+
+        assert_eq!(calls(ops(&ir, "main")), 1);
+    }
+
+    #[test]
+    fn endless_const_call_is_kept() {
+        let ir = generate(
+            "
+            const fn spin(x: i64) -> i64 { while x == x { x = x + 1; } return x; }
+            fn main() -> i64 { return spin(1); }
+            ",
+        );
+
+        assert_eq!(calls(ops(&ir, "main")), 1);
+    }
+
+    #[test]
+    fn argument_written_through_pointer_is_kept() {
+        let ir = generate(
+            "
+            const fn square(x: i64) -> i64 { return x * x; }
+            fn main() -> i64 { x := 3; p := &x; *p = 5; return square(x); }
+            ",
+        );
+
+        let ops = ops(&ir, "main");
+        assert_eq!(calls(ops), 1);
+        assert!(!ops.iter().any(|op| matches!(
+            op,
+            Operation::Assign {
+                src: SourceVal::Immediate(9),
+                ..
+            }
+        )));
+    }
+
+    #[test]
+    fn calls_with_side_effects_stay_in_place() {
+        let source = |qualifier: &str| {
+            format!(
+                "
+                {}fn square(x: i64) -> i64 {{ return x * x; }}
+                fn effect(x: i64) -> i64 {{ return x; }}
+                fn main() -> i64 {{ a := square(3); b := a * 2; effect(b); return 0; }}
+                ",
+                qualifier
+            )
+        };
+        let call = |ops: &[Operation], name: &str| {
+            ops.iter()
+                .position(|op| matches!(op, Operation::Call { function, .. } if function == name))
+        };
+
+        // square is only folded when it is const, so the other program shows where every
+        // operation was before the pass
+        let before = generate(&source(""));
+        let after = generate(&source("const "));
+        let (before, after) = (ops(&before, "main"), ops(&after, "main"));
+
+        let square = call(before, "square").unwrap();
+        let effect = call(before, "effect").unwrap();
+        let multiply = before
+            .iter()
+            .position(|op| matches!(op, Operation::Multiply { .. }))
+            .unwrap();
+        assert!(square < multiply && multiply < effect);
+
+        assert_eq!(before.len(), after.len());
+        assert!(matches!(
+            after[square],
+            Operation::Assign {
+                src: SourceVal::Immediate(9),
+                ..
+            }
+        ));
+        assert_eq!(
+            call(after, "effect"),
+            Some(effect),
+            "call with an unused result was moved or removed"
+        );
+        assert!(after[effect].has_side_effects());
+        for (i, (before, after)) in before.iter().zip(after).enumerate() {
+            if i != square {
+                assert_eq!(before, after, "operation {} was changed", i);
+            }
+        }
+        assert_eq!(calls(after), 1);
+    }
+}
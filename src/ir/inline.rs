@@ -0,0 +1,341 @@
+use std::collections::HashMap;
+
+use crate::{
+    analyze::{Span, ast::Attribute},
+    ir::{BasicBlock, IR, Item, Label, Operation, SourceVal, VirtualReg},
+};
+
+/// Functions with at most this many operations are inlined without being marked `@inline`.
+const INLINE_OP_LIMIT: usize = 8;
+
+impl IR {
+    /// Replaces calls with the body of the function they call. Functions marked `@inline` are
+    /// always inlined, and small functions without calls or branches are inlined unless marked
+    /// `@noinline`.
+    ///
+    /// A function is never inlined into itself, and calls in an inlined body are kept as they
+    /// are.
+    pub fn inline_calls(&mut self) {
+        let inlinable: HashMap<String, (Vec<VirtualReg>, BasicBlock)> = self
+            .items
+            .iter()
+            .filter(|item| should_inline(item))
+            .map(|Item::Function { name, args, bb, .. }| (name.clone(), (args.clone(), bb.clone())))
+            .collect();
+
+        for Item::Function { name, args, bb, .. } in &mut self.items {
+            let calls_inlinable = bb.ops.iter().any(|op| {
+                matches!(op, Operation::Call { function, .. }
+                    if function != name && inlinable.contains_key(function))
+            });
+
+            if calls_inlinable {
+                let caller = std::mem::replace(bb, empty_block());
+                *bb = Inliner::new(&caller, args).inline(caller, name, &inlinable);
+            }
+        }
+    }
+}
+
+fn should_inline(item: &Item) -> bool {
+    let Item::Function { bb, attributes, .. } = item;
+
+    // records are only passed back through a call
+    if attributes.contains(&Attribute::NoInline)
+        || bb
+            .ops
+            .iter()
+            .any(|op| matches!(op, Operation::ReturnPair { .. }))
+    {
+        return false;
+    }
+
+    if attributes.contains(&Attribute::Inline) {
+        return true;
+    }
+
+    bb.ops.len() <= INLINE_OP_LIMIT
+        && bb.labels.is_empty()
+        && bb
+            .ops
+            .iter()
+            .all(|op| op.branch_targets().is_empty() && !matches!(op, Operation::Call { .. }))
+}
+
+fn empty_block() -> BasicBlock {
+    BasicBlock {
+        labels: HashMap::new(),
+        ops: Vec::new(),
+        spans: Vec::new(),
+        classes: HashMap::new(),
+    }
+}
+
+/// Builds a new block for a caller, with the bodies of inlined functions spliced in.
+struct Inliner {
+    out: BasicBlock,
+    next_vreg: u32,
+    next_label: u32,
+}
+
+impl Inliner {
+    fn new(caller: &BasicBlock, args: &[VirtualReg]) -> Self {
+        Self {
+            out: empty_block(),
+            next_vreg: next_vreg(caller, args),
+            next_label: next_label(caller),
+        }
+    }
+
+    fn inline(
+        mut self,
+        caller: BasicBlock,
+        name: &str,
+        inlinable: &HashMap<String, (Vec<VirtualReg>, BasicBlock)>,
+    ) -> BasicBlock {
+        let BasicBlock {
+            mut labels,
+            ops,
+            spans,
+            classes,
+        } = caller;
+        self.out.classes = classes;
+
+        for (idx, (op, span)) in ops.into_iter().zip(spans).enumerate() {
+            if let Some(labels) = labels.remove(&idx) {
+                self.set_labels_here(labels);
+            }
+
+            match op {
+                Operation::Call {
+                    function,
+                    args,
+                    dest,
+                } if function != name
+                    && let Some((params, callee)) = inlinable.get(&function) =>
+                {
+                    self.inline_call(params, callee, &args, dest, span);
+                }
+                op => self.push(op, span),
+            }
+        }
+
+        // labels placed after the last operation
+        for (_, labels) in labels {
+            self.set_labels_here(labels);
+        }
+
+        self.out
+    }
+
+    fn inline_call(
+        &mut self,
+        params: &[VirtualReg],
+        callee: &BasicBlock,
+        args: &[SourceVal],
+        dest: Option<VirtualReg>,
+        span: Option<Span>,
+    ) {
+        let label_base = self.next_label;
+        self.next_label += next_label(callee);
+        let label = move |old: Label| match old {
+            Label::N(n) => Label::N(label_base + n),
+            Label::FnRet => Label::FnRet,
+        };
+
+        let end = Label::N(self.next_label);
+        self.next_label += 1;
+
+        let mut vregs: HashMap<VirtualReg, VirtualReg> = HashMap::new();
+        let mut vreg = |old: VirtualReg| {
+            *vregs.entry(old).or_insert_with(|| {
+                self.next_vreg += 1;
+                VirtualReg(self.next_vreg - 1)
+            })
+        };
+
+        let mut ops = Vec::new();
+        for (param, arg) in params.iter().zip(args) {
+            ops.push(Operation::Assign {
+                src: *arg,
+                dest: vreg(*param),
+            });
+        }
+
+        let mut inlined_labels = Vec::new();
+        for (idx, op) in callee.ops.iter().enumerate() {
+            if let Some(labels) = callee.labels.get(&idx) {
+                let labels: Vec<Label> = labels.iter().map(|&l| label(l)).collect();
+                inlined_labels.push((ops.len(), labels));
+            }
+
+            let mut op = op.clone();
+            op.map_vregs(&mut vreg);
+            op.map_labels(label);
+
+            let Operation::Return { value } = op else {
+                ops.push(op);
+                continue;
+            };
+
+            if let Some(dest) = dest {
+                ops.push(Operation::Assign { src: value, dest });
+            }
+
+            // the last return falls through to the end of the call
+            if idx + 1 < callee.ops.len() {
+                ops.push(Operation::Branch { label: end });
+            }
+        }
+
+        for (old, &class) in &callee.classes {
+            if let Some(&new) = vregs.get(old) {
+                self.out.classes.insert(new, class);
+            }
+        }
+
+        let after_body: Vec<Label> = callee
+            .labels
+            .iter()
+            .filter(|&(&idx, _)| idx >= callee.ops.len())
+            .flat_map(|(_, labels)| labels.iter().map(|&l| label(l)))
+            .chain([end])
+            .collect();
+
+        let start = self.out.ops.len();
+        for (idx, labels) in inlined_labels {
+            self.out
+                .labels
+                .entry(start + idx)
+                .or_default()
+                .extend(labels);
+        }
+
+        for op in ops {
+            self.push(op, span.clone());
+        }
+        self.set_labels_here(after_body);
+    }
+
+    fn push(&mut self, op: Operation, span: Option<Span>) {
+        self.out.ops.push(op);
+        self.out.spans.push(span);
+    }
+
+    fn set_labels_here(&mut self, labels: Vec<Label>) {
+        self.out
+            .labels
+            .entry(self.out.ops.len())
+            .or_default()
+            .extend(labels);
+    }
+}
+
+/// The first virtual register not used in `bb` or its arguments.
+fn next_vreg(bb: &BasicBlock, args: &[VirtualReg]) -> u32 {
+    let mut vregs = args.to_vec();
+    for op in &bb.ops {
+        op.all_vregs(&mut vregs);
+    }
+
+    vregs.iter().map(|vreg| vreg.0 + 1).max().unwrap_or(0)
+}
+
+/// The first label number not used in `bb`.
+fn next_label(bb: &BasicBlock) -> u32 {
+    bb.labels
+        .values()
+        .flatten()
+        .copied()
+        .chain(bb.ops.iter().flat_map(|op| op.branch_targets()))
+        .filter_map(|label| match label {
+            Label::N(n) => Some(n + 1),
+            Label::FnRet => None,
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{IR, Operation, eval::Interpreter, generate, ops};
+
+    fn calls<'a>(ir: &'a IR, caller: &str) -> Vec<&'a str> {
+        ops(ir, caller)
+            .iter()
+            .filter_map(|op| match op {
+                Operation::Call { function, .. } => Some(function.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn small_function_is_inlined() {
+        let mut ir = generate(
+            "
+            fn add(a: i64, b: i64) -> i64 { return a + b; }
+            fn main(argc: i64) -> i64 { return add(argc, 2); }
+            ",
+        );
+        ir.inline_calls();
+
+        assert!(calls(&ir, "main").is_empty(), "{}", ir);
+    }
+
+    #[test]
+    fn noinline_prevents_inlining() {
+        let mut ir = generate(
+            "
+            @noinline
+            fn add(a: i64, b: i64) -> i64 { return a + b; }
+            fn main(argc: i64) -> i64 { return add(argc, 2); }
+            ",
+        );
+        ir.inline_calls();
+
+        assert_eq!(calls(&ir, "main"), ["add"]);
+    }
+
+    #[test]
+    fn inline_attribute_inlines_branches() {
+        let mut ir = generate(
+            "
+            @inline
+            const fn clamp(x: i64) -> i64 {
+                if x > 10 {
+                    return 10;
+                }
+                return x;
+            }
+            const fn both(x: i64) -> i64 { return clamp(x + 15) * 100 + clamp(x); }
+            fn main(argc: i64) -> i64 { return both(argc); }
+            ",
+        );
+        ir.inline_calls();
+
+        assert!(calls(&ir, "both").is_empty(), "{}", ir);
+        assert_eq!(Interpreter::new(&ir).call("both", &[3]), Some(1003));
+    }
+
+    #[test]
+    fn recursive_function_is_not_inlined_into_itself() {
+        let mut ir = generate(
+            "
+            @inline
+            fn down(n: i64) -> i64 {
+                if n == 0 {
+                    return 0;
+                }
+                return down(n - 1);
+            }
+            fn main(argc: i64) -> i64 { return down(argc); }
+            ",
+        );
+        ir.inline_calls();
+
+        assert_eq!(calls(&ir, "down"), ["down"]);
+        // the inlined body still calls the original
+        assert_eq!(calls(&ir, "main"), ["down"]);
+    }
+}
@@ -1,8 +1,12 @@
-use std::{collections::HashMap, ops::Range};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    ops::Range,
+};
 
 use colored::{Color, Colorize};
 
-use crate::ir::VirtualReg;
+use crate::{analyze::ColorChoice, ir::VirtualReg};
 
 /// An interval where a variable is used continuously.
 #[derive(Debug, Clone)]
@@ -87,7 +91,25 @@ impl Lifetime {
 }
 
 /// Prints a very simple debug version of a lifetime registry with limited information.
-pub fn print_lifetimes(lifetimes: &HashMap<VirtualReg, Lifetime>) {
+///
+/// `registers` are the physical registers the intervals may be assigned to, and make up the
+/// legend. Intervals without a register are drawn as living on the stack.
+pub fn print_lifetimes(
+    lifetimes: &HashMap<VirtualReg, Lifetime>,
+    registers: &[u32],
+    color: ColorChoice,
+) {
+    write_lifetimes(&mut io::stdout(), lifetimes, registers, color)
+        .expect("couldn't print lifetimes to stdout");
+}
+
+/// Writes the output of [print_lifetimes] to `out`.
+pub fn write_lifetimes(
+    out: &mut impl Write,
+    lifetimes: &HashMap<VirtualReg, Lifetime>,
+    registers: &[u32],
+    color: ColorChoice,
+) -> io::Result<()> {
     let end = lifetimes
         .values()
         .map(|l| l.end())
@@ -95,7 +117,7 @@ pub fn print_lifetimes(lifetimes: &HashMap<VirtualReg, Lifetime>) {
         .unwrap_or_default();
 
     let Some(end) = end else {
-        return;
+        return Ok(());
     };
 
     let nlen = 4;
@@ -108,61 +130,74 @@ pub fn print_lifetimes(lifetimes: &HashMap<VirtualReg, Lifetime>) {
         Color::Magenta,
         Color::Cyan,
     ];
-    print!("{}", "Stack".cyan());
-    for (i, c) in reg_colors.iter().enumerate() {
-        print!("{}", format!(" X{}", i).color(*c));
-    }
+    let reg_color = |reg: u32| {
+        let i = registers
+            .iter()
+            .position(|&r| r == reg)
+            .unwrap_or(reg as usize);
+        reg_colors[i % reg_colors.len()]
+    };
+    let enabled = color.enabled();
+    let paint = |text: String, color: Color| {
+        if enabled {
+            text.color(color).to_string()
+        } else {
+            text
+        }
+    };
 
-    print!("   {}", "No location".bright_black());
-    println!();
+    write!(
+        out,
+        "{}",
+        paint(format!("{} Stack", STACK_MARKER), Color::BrightBlack)
+    )?;
+    for &reg in registers {
+        write!(out, "{}", paint(format!(" X{}", reg), reg_color(reg)))?;
+    }
+    writeln!(out)?;
 
-    print!("{:width$}  ", "", width = nlen);
+    write!(out, "{:width$}  ", "", width = nlen)?;
     for _ in 0..end {
-        print!("| ");
+        write!(out, "| ")?;
     }
 
-    println!();
+    writeln!(out)?;
 
     let mut lifetimes: Vec<(VirtualReg, Lifetime)> =
         lifetimes.iter().map(|(v, l)| (*v, l.to_owned())).collect();
-    lifetimes.sort_by_key(|(_, l)| l.start());
+    lifetimes.sort_by_key(|(vreg, l)| (l.start(), *vreg));
 
     for (vreg, l) in lifetimes {
-        print!("{:width$}: ", format!("{}", vreg), width = nlen);
+        write!(out, "{:width$}: ", format!("{}", vreg), width = nlen)?;
 
         let mut prev_end = 0;
         for interval in &l.intervals {
             let fill = interval.range.start - prev_end;
             if fill > 0 {
                 if prev_end == 0 {
-                    print!("{:width$}", "", width = fill * 2);
+                    write!(out, "{:width$}", "", width = fill * 2)?;
                 } else {
-                    print!("{:width$}", "", width = fill * 2 + 1);
+                    write!(out, "{:width$}", "", width = fill * 2 + 1)?;
                 }
             }
 
-            let color = if let Some(r) = interval.register {
-                reg_colors[r as usize % reg_colors.len()]
-            } else {
-                Color::BrightBlack
+            let (marker, color) = match interval.register {
+                Some(r) => (REGISTER_MARKER, reg_color(r)),
+                None => (STACK_MARKER, Color::BrightBlack),
             };
 
             let less = if interval.range.len() == 1 { 1 } else { 2 };
-            print!(
-                "{}",
-                format!(
-                    "{:\u{2588}<width$}",
-                    "",
-                    width = interval.range.len() * 2 - less
-                )
-                .color(color)
-            );
+            let width = interval.range.len() * 2 - less;
+            write!(out, "{}", paint(marker.repeat(width), color))?;
 
             prev_end = interval.range.end;
         }
 
-        println!();
+        writeln!(out)?;
     }
 
-    println!();
+    writeln!(out)
 }
+
+const REGISTER_MARKER: &str = "\u{2588}";
+const STACK_MARKER: &str = "\u{2591}";
@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+
+use crate::ir::{IR, Item};
+
+/// Function that dead function elimination starts from.
+const ENTRY: &str = "main";
+
+/// Which passes [IR::optimize] runs. Every pass is off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Optimizations {
+    /// Leaves out functions that can never be called from `main`.
+    pub eliminate_dead_functions: bool,
+    /// Replaces calls to small functions, and to functions marked `@inline`, with their body.
+    /// Functions marked `@noinline` are always called.
+    pub inline_functions: bool,
+    /// Repeats the body of loops that run a small number of times known at compile time, in
+    /// place of the loop.
+    pub unroll_loops: bool,
+    /// Reuses the result of a computation instead of repeating it, within straight-line code.
+    pub eliminate_common_subexpressions: bool,
+    /// Uses constants in place of the variables that hold them, and drops the assignments that
+    /// are left unread.
+    pub propagate_constants: bool,
+}
+
+impl IR {
+    /// Runs the passes that are turned on in `optimizations`, in an order where each one can
+    /// make use of what the previous ones did: inlining exposes loops and constants to the
+    /// others, and functions are only found to be dead once their calls have been inlined.
+    pub fn optimize(&mut self, optimizations: Optimizations) {
+        if optimizations.inline_functions {
+            self.inline_calls();
+        }
+
+        if optimizations.unroll_loops {
+            self.unroll_loops();
+        }
+
+        if optimizations.eliminate_common_subexpressions {
+            self.eliminate_common_subexpressions();
+        }
+
+        if optimizations.propagate_constants {
+            self.propagate_constants();
+        }
+
+        if optimizations.eliminate_dead_functions {
+            let reachable: HashSet<String> = self
+                .reachable_functions(ENTRY)
+                .into_iter()
+                .map(String::from)
+                .collect();
+            self.items
+                .retain(|Item::Function { name, .. }| reachable.contains(name));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::generate;
+
+    fn names(ir: &IR) -> Vec<&str> {
+        ir.items
+            .iter()
+            .map(|Item::Function { name, .. }| name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn inlined_functions_become_dead() {
+        let source = "
+            @inline fn one() -> i64 { return 1; }
+            fn unused() -> i64 { return 2; }
+            fn main() -> i64 { return one(); }
+            ";
+
+        let mut ir = generate(source);
+        ir.optimize(Optimizations {
+            eliminate_dead_functions: true,
+            ..Default::default()
+        });
+        assert_eq!(names(&ir), ["one", "main"]);
+
+        let mut ir = generate(source);
+        ir.optimize(Optimizations {
+            eliminate_dead_functions: true,
+            inline_functions: true,
+            ..Default::default()
+        });
+        assert_eq!(names(&ir), ["main"]);
+    }
+
+    #[test]
+    fn nothing_runs_by_default() {
+        let source = "@inline fn one() -> i64 { return 1; } fn main() -> i64 { return one(); }";
+        let mut ir = generate(source);
+        ir.optimize(Optimizations::default());
+        assert_eq!(ir.to_string(), generate(source).to_string());
+    }
+}
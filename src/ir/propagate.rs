@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    BasicBlock, IR, Item, Operation, SourceVal, VirtualReg, dead::eliminate_dead_code,
+    eval::compare,
+};
+
+impl IR {
+    /// Replaces reads of registers that hold a constant with the constant itself, where the
+    /// operand may be one, and then removes the operations that are no longer read.
+    ///
+    /// Like [common subexpression elimination](IR::eliminate_common_subexpressions), constants
+    /// are only followed through straight-line code and forgotten at labels. Registers whose
+    /// address is taken are left alone, since they may be written through a pointer.
+    pub fn propagate_constants(&mut self) {
+        let overflow_checks = self.overflow_checks;
+        for Item::Function { bb, .. } in &mut self.items {
+            propagate_in_block(bb);
+            eliminate_dead_code(bb, overflow_checks);
+        }
+    }
+
+    /// Replaces arithmetic on registers that hold a constant with an assignment of the result,
+    /// until nothing more folds, and then removes the operations that are no longer read.
+    ///
+    /// Results wrap on overflow and follow the interpreter on division by zero, so folding never
+    /// changes what a program computes. With [overflow checks](IR::overflow_checks), arithmetic
+    /// that overflows is left to trap at runtime.
+    pub fn fold_constants(&mut self) {
+        let overflow_checks = self.overflow_checks;
+        for Item::Function { bb, .. } in &mut self.items {
+            while fold_in_block(bb, overflow_checks) {}
+            eliminate_dead_code(bb, overflow_checks);
+        }
+    }
+}
+
+/// Registers known to hold a constant at some point in a basic block.
+#[derive(Clone)]
+pub(super) struct Constants {
+    addressed: HashSet<VirtualReg>,
+    known: HashMap<VirtualReg, i64>,
+}
+
+impl Constants {
+    pub(super) fn new(bb: &BasicBlock) -> Self {
+        let addressed = bb
+            .ops
+            .iter()
+            .filter_map(|op| match *op {
+                Operation::AddressOf { val, .. } => Some(val),
+                _ => None,
+            })
+            .collect();
+
+        Self {
+            addressed,
+            known: HashMap::new(),
+        }
+    }
+
+    pub(super) fn reg(&self, vreg: VirtualReg) -> Option<i64> {
+        self.known.get(&vreg).copied()
+    }
+
+    pub(super) fn value(&self, val: SourceVal) -> Option<i64> {
+        match val {
+            SourceVal::Immediate(n) => Some(n),
+            SourceVal::VReg(vreg) => self.reg(vreg),
+            _ => None,
+        }
+    }
+
+    /// Forgets everything, for when control may arrive from elsewhere.
+    pub(super) fn clear(&mut self) {
+        self.known.clear();
+    }
+
+    /// Knows `vreg` to hold `value`, unless its address is taken.
+    pub(super) fn set(&mut self, vreg: VirtualReg, value: i64) {
+        if !self.addressed.contains(&vreg) {
+            self.known.insert(vreg, value);
+        }
+    }
+
+    /// Updates what is known after `op` has run.
+    pub(super) fn record(&mut self, op: &Operation) {
+        let written = match *op {
+            Operation::AddressOf { dest, .. } => Some(dest),
+            _ => op.vregs_used().1,
+        };
+        if let Some(written) = written {
+            self.known.remove(&written);
+        }
+
+        if let Operation::Assign {
+            src: SourceVal::Immediate(n),
+            dest,
+        } = *op
+            && !self.addressed.contains(&dest)
+        {
+            self.known.insert(dest, n);
+        }
+    }
+}
+
+fn propagate_in_block(bb: &mut BasicBlock) {
+    let mut constants = Constants::new(bb);
+    for idx in 0..bb.ops.len() {
+        if bb.labels.contains_key(&idx) {
+            constants.known.clear();
+        }
+
+        let op = &mut bb.ops[idx];
+        for src in source_operands(op) {
+            if let SourceVal::VReg(vreg) = *src
+                && let Some(n) = constants.reg(vreg)
+            {
+                *src = SourceVal::Immediate(n);
+            }
+        }
+        constants.record(op);
+    }
+}
+
+/// Folds every operation whose operands are all constant, returning whether any was.
+fn fold_in_block(bb: &mut BasicBlock, overflow_checks: bool) -> bool {
+    let mut folded = false;
+    let mut constants = Constants::new(bb);
+    for idx in 0..bb.ops.len() {
+        if bb.labels.contains_key(&idx) {
+            constants.known.clear();
+        }
+
+        let op = &mut bb.ops[idx];
+        if let Some(result) = fold(op, &constants, overflow_checks) {
+            let dest = op.vregs_used().1.expect("folded operation has a result");
+            *op = Operation::Assign {
+                src: SourceVal::Immediate(result),
+                dest,
+            };
+            folded = true;
+        }
+        constants.record(op);
+    }
+    folded
+}
+
+/// The result of `op` if its operands are all known, with the same semantics as the
+/// [interpreter](crate::ir::eval::Interpreter). Arithmetic that overflows has no result when
+/// `overflow_checks` is set, since it traps instead.
+pub(super) fn fold(op: &Operation, constants: &Constants, overflow_checks: bool) -> Option<i64> {
+    let checked =
+        |(result, overflowed): (i64, bool)| (!overflowed || !overflow_checks).then_some(result);
+
+    match *op {
+        Operation::Add { a, b, .. } => {
+            checked(constants.reg(a)?.overflowing_add(constants.value(b)?))
+        }
+        Operation::Subtract { a, b, .. } => {
+            checked(constants.reg(a)?.overflowing_sub(constants.value(b)?))
+        }
+        Operation::Multiply { a, b, .. } => {
+            checked(constants.reg(a)?.overflowing_mul(constants.reg(b)?))
+        }
+        Operation::Compare { a, b, cond, .. } => {
+            compare(constants.reg(a)?, constants.reg(b)?, cond).map(i64::from)
+        }
+        Operation::Divide { a, b, signed, .. } => {
+            let (a, b) = (constants.reg(a)?, constants.reg(b)?);
+            Some(match (b, signed) {
+                (0, _) => 0,
+                (_, true) => a.wrapping_div(b),
+                (_, false) => ((a as u64) / (b as u64)) as i64,
+            })
+        }
+        Operation::Modulo { a, b, signed, .. } => {
+            let (a, b) = (constants.reg(a)?, constants.reg(b)?);
+            Some(match (b, signed) {
+                (0, _) => a,
+                (_, true) => a.wrapping_rem(b),
+                (_, false) => ((a as u64) % (b as u64)) as i64,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// The operands of `op` that may be constants.
+fn source_operands(op: &mut Operation) -> Vec<&mut SourceVal> {
+    match op {
+        Operation::Assign { src, .. } => vec![src],
+        Operation::Add { b, .. } | Operation::Subtract { b, .. } => vec![b],
+        Operation::Return { value } => vec![value],
+        Operation::Call { args, .. } => args.iter_mut().collect(),
+        _ => Vec::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{Operation, SourceVal, eval::Interpreter, generate, ops};
+
+    #[test]
+    fn copied_constant_is_returned_directly() {
+        let mut ir = generate("fn main() -> i64 { a := 5; b := a; return b; }");
+
+        ir.propagate_constants();
+        assert_eq!(
+            ops(&ir, "main"),
+            [Operation::Return {
+                value: SourceVal::Immediate(5),
+            }]
+        );
+    }
+
+    #[test]
+    fn constants_are_forgotten_at_labels() {
+        let mut ir = generate(
+            "
+            const fn f(n: i64) -> i64 {
+                a := 1;
+                while n != 0 { a = a + a; n = n - 1; }
+                return a;
+            }
+            fn main() -> i64 { return f(3); }
+            ",
+        );
+
+        ir.propagate_constants();
+        assert!(
+            !ops(&ir, "f").contains(&Operation::Return {
+                value: SourceVal::Immediate(1),
+            }),
+            "{}",
+            ir
+        );
+        assert_eq!(Interpreter::new(&ir).call("f", &[3]), Some(8));
+    }
+
+    #[test]
+    fn chained_additions_fold_fully() {
+        let mut ir = generate("fn main() -> i64 { return 1 + 2 + 3; }");
+        let before = ops(&ir, "main").len();
+
+        ir.fold_constants();
+        assert!(ops(&ir, "main").len() < before, "{}", ir);
+        assert!(
+            ops(&ir, "main")
+                .iter()
+                .all(|op| !matches!(op, Operation::Add { .. })),
+            "{}",
+            ir
+        );
+        assert_eq!(Interpreter::new(&ir).call("main", &[]), Some(6));
+    }
+
+    #[test]
+    fn folding_wraps_on_overflow() {
+        let mut ir = generate("fn main() -> i64 { a := 9223372036854775807; return a + 1; }");
+
+        ir.fold_constants();
+        assert!(
+            ops(&ir, "main").iter().any(|op| matches!(
+                op,
+                Operation::Assign {
+                    src: SourceVal::Immediate(i64::MIN),
+                    ..
+                }
+            )),
+            "{}",
+            ir
+        );
+        assert_eq!(Interpreter::new(&ir).call("main", &[]), Some(i64::MIN));
+    }
+
+    #[test]
+    fn overflow_is_left_to_trap_with_overflow_checks() {
+        let mut ir = generate(
+            "
+            fn main() -> i64 {
+                a := 9223372036854775807;
+                unread := a * 2;
+                b := 1 + 2;
+                return a + b;
+            }
+            ",
+        );
+        ir.overflow_checks = true;
+
+        ir.fold_constants();
+        let ops = ops(&ir, "main");
+        assert!(
+            ops.iter().any(|op| matches!(op, Operation::Add { .. })),
+            "{}",
+            ir
+        );
+        assert!(
+            ops.iter()
+                .any(|op| matches!(op, Operation::Multiply { .. })),
+            "{}",
+            ir
+        );
+        // arithmetic that doesn't overflow still folds
+        assert!(
+            ops.iter().any(|op| matches!(
+                op,
+                Operation::Assign {
+                    src: SourceVal::Immediate(3),
+                    ..
+                }
+            )),
+            "{}",
+            ir
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::ir::{
+    BasicBlock, IR, Item, Label, OpIndex, Operation,
+    propagate::{Constants, fold},
+};
+
+/// Loops are only unrolled if the unrolled operations are at most this many.
+const UNROLL_OP_LIMIT: usize = 48;
+
+impl IR {
+    /// Replaces loops that run a number of times known at compile time with their body repeated
+    /// that many times, and then folds what became constant.
+    ///
+    /// Only loops without branches in their body are unrolled, whose condition can be followed
+    /// from constants assigned before them in straight-line code, and whose unrolled body stays
+    /// within [UNROLL_OP_LIMIT] operations. A loop left without branches by unrolling the loops
+    /// in it may be unrolled in turn.
+    pub fn unroll_loops(&mut self) {
+        let mut unrolled = false;
+        let overflow_checks = self.overflow_checks;
+        for Item::Function { bb, .. } in &mut self.items {
+            while let Some(counted) = find_counted_loop(bb, overflow_checks) {
+                unroll(bb, counted);
+                unrolled = true;
+            }
+        }
+
+        if unrolled {
+            self.fold_constants();
+        }
+    }
+}
+
+/// A `while` loop, as generated: a jump to the condition, then the body, and then the
+/// condition, which ends in a branch back to the body.
+///
+/// ```text
+///     branch C
+/// B:  body
+/// C:  condition
+///     branch-if cond, B
+/// ```
+struct CountedLoop {
+    /// Index of the jump to the condition.
+    start: OpIndex,
+    /// Index of the first operation of the condition.
+    cond: OpIndex,
+    /// Index of the branch back to the body.
+    end: OpIndex,
+    trips: usize,
+}
+
+fn find_counted_loop(bb: &BasicBlock, overflow_checks: bool) -> Option<CountedLoop> {
+    let mut targeted: HashMap<Label, usize> = HashMap::new();
+    for op in &bb.ops {
+        for label in op.branch_targets() {
+            *targeted.entry(label).or_default() += 1;
+        }
+    }
+
+    let mut entry = Constants::new(bb);
+    for (start, op) in bb.ops.iter().enumerate() {
+        if bb.labels.contains_key(&start) {
+            entry.clear();
+        }
+
+        if let Operation::Branch { label: cond_label } = *op
+            && let Some(counted) =
+                counted_loop(bb, start, cond_label, &targeted, &entry, overflow_checks)
+        {
+            return Some(counted);
+        }
+
+        simulate(op, &mut entry, overflow_checks);
+    }
+
+    None
+}
+
+/// The loop starting with the jump at `start` to `cond_label`, if it has that shape and the
+/// number of times it runs is known from `entry`, the constants known when it is entered.
+fn counted_loop(
+    bb: &BasicBlock,
+    start: OpIndex,
+    cond_label: Label,
+    targeted: &HashMap<Label, usize>,
+    entry: &Constants,
+    overflow_checks: bool,
+) -> Option<CountedLoop> {
+    let &[body_label] = bb.labels.get(&(start + 1))?.as_slice() else {
+        return None;
+    };
+
+    // the labels are only jumped to by the loop itself
+    if targeted.get(&body_label) != Some(&1) || targeted.get(&cond_label) != Some(&1) {
+        return None;
+    }
+
+    let (end, cond, negated) =
+        bb.ops
+            .iter()
+            .enumerate()
+            .skip(start + 1)
+            .find_map(|(idx, op)| match *op {
+                Operation::BranchIf { cond, label } if label == body_label => {
+                    Some((idx, cond, false))
+                }
+                Operation::BranchIfNot { cond, label } if label == body_label => {
+                    Some((idx, cond, true))
+                }
+                _ => None,
+            })?;
+
+    // nothing else in the loop is a label or jumps
+    let mut cond_start = None;
+    for idx in start + 1..=end {
+        match bb.labels.get(&idx).map(Vec::as_slice) {
+            None => {}
+            Some(labels) if labels.iter().all(|&l| l == body_label || l == cond_label) => {
+                if labels.contains(&cond_label) {
+                    cond_start = Some(idx);
+                }
+            }
+            Some(_) => return None,
+        }
+    }
+    let cond_start = cond_start?;
+
+    let inner = &bb.ops[start + 1..end];
+    if inner.iter().any(|op| {
+        !op.branch_targets().is_empty()
+            || matches!(op, Operation::Return { .. } | Operation::ReturnPair { .. })
+    }) {
+        return None;
+    }
+
+    let body = &bb.ops[start + 1..cond_start];
+    let condition = &bb.ops[cond_start..end];
+
+    let mut constants = entry.clone();
+    let mut trips = 0;
+    loop {
+        for op in condition {
+            simulate(op, &mut constants, overflow_checks);
+        }
+
+        let holds = constants.reg(cond)? != 0;
+        if holds == negated {
+            break;
+        }
+
+        trips += 1;
+        if (trips + 1) * condition.len() + trips * body.len() > UNROLL_OP_LIMIT
+            || trips > UNROLL_OP_LIMIT
+        {
+            return None;
+        }
+
+        for op in body {
+            simulate(op, &mut constants, overflow_checks);
+        }
+    }
+
+    Some(CountedLoop {
+        start,
+        cond: cond_start,
+        end,
+        trips,
+    })
+}
+
+/// Runs `op` on what is known, remembering its result if it only depends on constants.
+fn simulate(op: &Operation, constants: &mut Constants, overflow_checks: bool) {
+    let value = match *op {
+        Operation::Assign { src, .. } => constants.value(src),
+        _ => fold(op, constants, overflow_checks),
+    };
+
+    constants.record(op);
+    if let Some(value) = value
+        && let Some(dest) = op.vregs_used().1
+    {
+        constants.set(dest, value);
+    }
+}
+
+/// Replaces the loop with what it runs: the condition, and then the body followed by the
+/// condition once for every trip.
+fn unroll(bb: &mut BasicBlock, counted: CountedLoop) {
+    let CountedLoop {
+        start,
+        cond,
+        end,
+        trips,
+    } = counted;
+    let has_spans = bb.spans.len() == bb.ops.len();
+
+    let mut order: Vec<OpIndex> = (cond..end).collect();
+    for _ in 0..trips {
+        order.extend(start + 1..cond);
+        order.extend(cond..end);
+    }
+
+    let ops: Vec<Operation> = order.iter().map(|&idx| bb.ops[idx].clone()).collect();
+    let unrolled = ops.len();
+    bb.ops.splice(start..=end, ops);
+    if has_spans {
+        let spans: Vec<_> = order.iter().map(|&idx| bb.spans[idx].clone()).collect();
+        bb.spans.splice(start..=end, spans);
+    }
+
+    // the labels of the loop itself are gone, and the ones after it move
+    let removed = end + 1 - start;
+    let loop_labels: HashSet<OpIndex> = (start + 1..=end).collect();
+    let mut labels: HashMap<OpIndex, Vec<Label>> = HashMap::new();
+    for (idx, moved) in std::mem::take(&mut bb.labels) {
+        if loop_labels.contains(&idx) {
+            continue;
+        }
+
+        let idx = if idx > end {
+            idx + unrolled - removed
+        } else {
+            idx
+        };
+        labels.entry(idx).or_default().extend(moved);
+    }
+    bb.labels = labels;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ir::{Operation, SourceVal, eval::Interpreter, generate, ops};
+
+    fn has_branches(ops: &[Operation]) -> bool {
+        ops.iter().any(|op| !op.branch_targets().is_empty())
+    }
+
+    #[test]
+    fn summing_loop_folds_to_constant() {
+        let mut ir = generate(
+            "
+            fn main() -> i64 {
+                sum := 0;
+                i := 1;
+                while i <= 4 {
+                    sum = sum + i;
+                    i = i + 1;
+                }
+                return sum;
+            }
+            ",
+        );
+
+        ir.unroll_loops();
+        let main = ops(&ir, "main");
+        assert!(!has_branches(main), "{}", ir);
+        assert!(
+            main.iter()
+                .all(|op| !matches!(op, Operation::Add { .. } | Operation::Compare { .. })),
+            "{}",
+            ir
+        );
+        assert!(
+            main.iter().any(|op| matches!(
+                op,
+                Operation::Assign {
+                    src: SourceVal::Immediate(10),
+                    ..
+                }
+            )),
+            "{}",
+            ir
+        );
+        assert_eq!(Interpreter::new(&ir).call("main", &[]), Some(10));
+    }
+
+    #[test]
+    fn loop_with_unknown_bound_is_kept() {
+        let mut ir = generate(
+            "
+            const fn f(n: i64) -> i64 {
+                a := 1;
+                while n != 0 { a = a + a; n = n - 1; }
+                return a;
+            }
+            fn main() -> i64 { return f(3); }
+            ",
+        );
+
+        ir.unroll_loops();
+        assert!(has_branches(ops(&ir, "f")), "{}", ir);
+        assert_eq!(Interpreter::new(&ir).call("f", &[3]), Some(8));
+    }
+
+    #[test]
+    fn long_loop_is_kept() {
+        let mut ir = generate(
+            "
+            fn main() -> i64 {
+                sum := 0;
+                i := 0;
+                while i < 1000 { sum = sum + i; i = i + 1; }
+                return sum;
+            }
+            ",
+        );
+
+        ir.unroll_loops();
+        assert!(has_branches(ops(&ir, "main")), "{}", ir);
+        assert_eq!(Interpreter::new(&ir).call("main", &[]), Some(499500));
+    }
+
+    #[test]
+    fn loop_that_never_runs_is_removed() {
+        let mut ir = generate(
+            "
+            fn main() -> i64 {
+                i := 5;
+                while i < 3 { i = i + 1; }
+                return i;
+            }
+            ",
+        );
+
+        ir.unroll_loops();
+        assert!(!has_branches(ops(&ir, "main")), "{}", ir);
+        assert_eq!(Interpreter::new(&ir).call("main", &[]), Some(5));
+    }
+}
@@ -1,24 +1,27 @@
 use std::{
     collections::HashMap,
-    fs,
+    env, fs, io,
     marker::PhantomData,
     path::{Path, PathBuf},
+    process,
     rc::Rc,
+    sync::atomic::{AtomicUsize, Ordering},
 };
 
 use ariadne::{Cache, FileCache, Source};
 
 use crate::{
     analyze::{
-        ErrorVec,
-        ast::{AST, parse::Parser},
+        ColorChoice, DEFAULT_TAB_WIDTH, ErrorVec, Messages,
+        ast::{AST, Item, Statement, parse::Parser},
         lex::Lexer,
-        semantics,
+        semantics::{self, SemanticType},
     },
-    ir::IR,
+    ir::{IR, eval::Interpreter, optimize::Optimizations},
     synthesize::{
-        arch::{Assembler, MachineCode, UnfinishedCode, arm::ArmAssembler},
-        exe::Executable,
+        arch::{AssembleOptions, Assembler, MachineCode, UnfinishedCode, arm::ArmAssembler},
+        exe::{Executable, ExecutableError},
+        target::{Os, Target},
     },
 };
 
@@ -27,83 +30,454 @@ pub mod files;
 pub mod ir;
 pub mod synthesize;
 
-#[derive(Default)]
 pub struct Compiler<E: Executable, A: Assembler> {
+    color: ColorChoice,
+    messages: Rc<Messages>,
+    tab_width: usize,
+    dump_ir: bool,
+    cfg_dump: Option<PathBuf>,
+    optimizations: Optimizations,
+    asm_options: AssembleOptions,
+    target: Option<Target>,
     _marker: PhantomData<(E, A)>,
 }
 
+impl<E: Executable, A: Assembler> Default for Compiler<E, A> {
+    fn default() -> Self {
+        Self {
+            color: ColorChoice::default(),
+            messages: Rc::default(),
+            tab_width: DEFAULT_TAB_WIDTH,
+            dump_ir: false,
+            cfg_dump: None,
+            optimizations: Optimizations::default(),
+            asm_options: AssembleOptions::default(),
+            target: None,
+            _marker: PhantomData,
+        }
+    }
+}
+
 impl<E: Executable, A: Assembler> Compiler<E, A> {
+    /// Sets whether diagnostics are printed with colors.
+    pub fn with_color(mut self, color: ColorChoice) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Replaces the wording of diagnostics.
+    pub fn with_messages(mut self, messages: Messages) -> Self {
+        self.messages = Rc::new(messages);
+        self
+    }
+
+    /// Sets how many columns a tab takes up in diagnostics and in source locations. Must be at
+    /// least 1.
+    pub fn with_tab_width(mut self, tab_width: usize) -> Self {
+        assert!(tab_width > 0, "tab width must be at least 1");
+
+        self.tab_width = tab_width;
+        self
+    }
+
+    /// Prints the generated IR, with the source location of each operation, before it is
+    /// assembled.
+    pub fn with_ir_dump(mut self, dump_ir: bool) -> Self {
+        self.dump_ir = dump_ir;
+        self
+    }
+
+    /// Writes the control-flow graph of the generated IR in Graphviz format to `path`.
+    pub fn with_cfg_dump(mut self, path: Option<PathBuf>) -> Self {
+        self.cfg_dump = path;
+        self
+    }
+
+    /// Sets which passes are run on the IR before it is assembled.
+    pub fn with_optimizations(mut self, optimizations: Optimizations) -> Self {
+        self.optimizations = optimizations;
+        self
+    }
+
+    /// Sets how the generated code is laid out.
+    pub fn with_assemble_options(mut self, options: AssembleOptions) -> Self {
+        self.asm_options = options;
+        self
+    }
+
+    /// Sets the target that executables are built for, instead of the host's operating system.
+    /// Must be for the architecture that `A` generates code for.
+    pub fn with_target(mut self, target: Target) -> Self {
+        assert_eq!(
+            target.arch,
+            A::ARCH,
+            "the assembler cannot generate code for {}",
+            target
+        );
+
+        self.target = Some(target);
+        self
+    }
+
+    /// The target that executables are built for. Unless it is set, it is the host's operating
+    /// system on the architecture that `A` generates code for, which may not be the host's.
+    pub fn target(&self) -> Target {
+        self.target
+            .unwrap_or_else(|| Target::new(A::ARCH, Os::host()))
+    }
+
+    /// Compiles the file at `path` into an executable at `out_path`. Errors in the source are
+    /// printed before they are returned.
     pub fn compile(
         self,
         path: impl Into<PathBuf>,
         out_path: impl AsRef<Path>,
-    ) -> Result<(), usize> {
-        let path: Rc<PathBuf> = Rc::from(path.into());
-        let source = fs::read_to_string(path.as_ref()).unwrap();
+    ) -> Result<(), CompileError> {
+        let path = path.into();
+        let source =
+            fs::read_to_string(&path).map_err(|err| CompileError::Read(path.clone(), err))?;
 
-        let code = match self.compile_source(path.clone(), &source) {
+        self.compile_str(path, &source, out_path)
+    }
+
+    /// Compiles `source` into an executable at `out_path`, without reading any file for it.
+    /// Diagnostics refer to the source as `name`, like [STDIN_NAME](files::STDIN_NAME) for a
+    /// program read from stdin.
+    pub fn compile_str(
+        self,
+        name: impl Into<PathBuf>,
+        source: &str,
+        out_path: impl AsRef<Path>,
+    ) -> Result<(), CompileError> {
+        let name = Rc::new(name.into());
+
+        let code = match self.compile_source(name.clone(), source) {
             Ok(code) => code,
             Err(errors) => {
-                errors.dump();
-                return Err(errors.len());
+                errors.dump_with_source(&name, source);
+                return Err(errors.into());
             }
         };
 
         E::default()
             .with_binary_identifier("dirthouse")
+            .with_target(self.target())
             .build(code, out_path);
 
         Ok(())
     }
 
+    /// Compiles `source`, runs it from a temporary file and returns its exit code.
+    pub fn compile_and_run(&self, source: &str) -> Result<i32, CompileError> {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+        let code = self.compile_source(Rc::new(PathBuf::from("main")), source)?;
+
+        let run = RUNS.fetch_add(1, Ordering::Relaxed);
+        let out_path = env::temp_dir().join(format!("istind-{}-{}", process::id(), run));
+
+        let mut exe = E::default()
+            .with_binary_identifier("dirthouse")
+            .with_target(self.target());
+        exe.build(code, &out_path);
+        let status = exe.run();
+        let _ = fs::remove_file(&out_path);
+
+        status?.code().ok_or(CompileError::Killed)
+    }
+
+    /// Evaluates a single expression, such as `2 * (3 + 4)`, as if it were returned from a
+    /// `const fn main() -> i64`. Spans in errors point into `expr`.
+    pub fn eval_expr(&self, expr: &str) -> Result<i64, CompileError> {
+        let name = Rc::new(PathBuf::from("expr"));
+        let lexer = Lexer::with_messages(
+            name.clone(),
+            expr,
+            self.color,
+            self.messages.clone(),
+            self.tab_width,
+        )
+        .map_err(ErrorVec::from)?;
+        let expr = Parser::new(name, lexer).into_expr()?;
+
+        // the wrapper is built around the parsed expression, so spans stay relative to it
+        let mut ast = AST::new();
+        ast.add_item(Item::Function {
+            name: String::from("main"),
+            args: Vec::new(),
+            decl_span: expr.span.clone(),
+            body: vec![Statement::Return(expr)],
+            ret_type: SemanticType::I64,
+            is_const: true,
+            attributes: Vec::new(),
+        });
+
+        let ast = semantics::analyze_with_messages(
+            ast,
+            self.color,
+            self.messages.clone(),
+            self.tab_width,
+        )?;
+        ast.warnings.dump();
+
+        let ir = IR::generate(ast);
+        Interpreter::new(&ir)
+            .call("main", &[])
+            .ok_or(CompileError::NotConstant)
+    }
+
     pub fn compile_source(
         &self,
         name: Rc<PathBuf>,
         source: &str,
     ) -> Result<UnfinishedCode<A>, ErrorVec> {
-        let mut ast = load_ast(name.clone(), source)?;
+        let mut ast = load_ast(
+            name.clone(),
+            source,
+            self.color,
+            &self.messages,
+            self.tab_width,
+        )?;
 
         let mut libmap = HashMap::new();
         for lib in ast.imports() {
-            load_lib_recursive(lib, &mut libmap)?;
+            load_lib_recursive(lib, &mut libmap, self.color, &self.messages, self.tab_width)?;
         }
 
         for lib_ast in libmap.into_values() {
             ast.items.extend(lib_ast.items);
         }
 
-        let ast = semantics::analyze(ast)?;
+        let ast = semantics::analyze_with_messages(
+            ast,
+            self.color,
+            self.messages.clone(),
+            self.tab_width,
+        )?;
+        ast.warnings.dump();
 
-        let ir = IR::generate(ast);
-        println!("{}", ir);
+        let mut ir = IR::generate(ast);
+        ir.overflow_checks = self.asm_options.overflow_checks;
+        ir.fold_constants();
+        ir.optimize(self.optimizations);
+        if self.dump_ir {
+            println!("{}", ir.display_annotated(self.tab_width));
+        }
 
-        let code = A::assemble(ir);
+        // the graph only helps with debugging, so failing to write it doesn't stop compilation
+        if let Some(path) = &self.cfg_dump
+            && let Err(err) = fs::write(path, ir.to_dot())
+        {
+            eprintln!(
+                "warning: cannot write control-flow graph to {}: {}",
+                path.display(),
+                err
+            );
+        }
+
+        let options = AssembleOptions {
+            color: self.color,
+            ..self.asm_options
+        };
+        let code = A::assemble_with_options(ir, options)?;
 
         Ok(code)
     }
 }
 
-fn load_ast(name: Rc<PathBuf>, source: &str) -> Result<AST, ErrorVec> {
-    let lexer = Lexer::new(name.clone(), source)?;
+#[derive(Debug, thiserror::Error)]
+pub enum CompileError {
+    #[error("{0:?}")]
+    Compile(ErrorVec),
+    #[error("cannot read {}: {1}", .0.display())]
+    Read(PathBuf, io::Error),
+    #[error(transparent)]
+    Run(#[from] ExecutableError),
+    #[error("executable was killed by a signal")]
+    Killed,
+    #[error("expression cannot be evaluated at compile time")]
+    NotConstant,
+}
+
+impl From<ErrorVec> for CompileError {
+    fn from(errors: ErrorVec) -> Self {
+        Self::Compile(errors)
+    }
+}
+
+fn load_ast(
+    name: Rc<PathBuf>,
+    source: &str,
+    color: ColorChoice,
+    messages: &Rc<Messages>,
+    tab_width: usize,
+) -> Result<AST, ErrorVec> {
+    let lexer = Lexer::with_messages(name.clone(), source, color, messages.clone(), tab_width)?;
     let parser = Parser::new(name, lexer);
     let ast = parser.into_ast()?;
 
     Ok(ast)
 }
 
-fn load_lib_recursive(lib: &str, map: &mut HashMap<String, AST>) -> Result<(), ErrorVec> {
+fn load_lib_recursive(
+    lib: &str,
+    map: &mut HashMap<String, AST>,
+    color: ColorChoice,
+    messages: &Rc<Messages>,
+    tab_width: usize,
+) -> Result<(), ErrorVec> {
     if lib == "std"
         && !map.contains_key(lib)
         && let Ok(source) = fs::read_to_string(files::stdlib())
     {
         // it's ok if file doesn't exist. semantic analysis will flag it.
         let source_name = Rc::new(files::stdlib());
-        let mut ast = load_ast(source_name, &source)?;
+        let mut ast = load_ast(source_name, &source, color, messages, tab_width)?;
         ast.mangle(lib);
         map.insert(String::from("std"), ast);
+    } else if lib == "libc" {
+        // libc is linked dynamically, its functions are resolved by dyld at load time.
     } else {
         todo!()
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesize::{arch::x64::X64Assembler, exe::DummyExecutable, target::Arch};
+
+    /// Serves one snippet of source as the source of every span.
+    struct Snippet(Source);
+
+    impl Cache<Rc<PathBuf>> for Snippet {
+        type Storage = String;
+
+        fn fetch(&mut self, _path: &Rc<PathBuf>) -> Result<&Source, impl std::fmt::Debug> {
+            Ok::<_, ()>(&self.0)
+        }
+
+        fn display<'a>(&self, path: &'a Rc<PathBuf>) -> Option<impl std::fmt::Display + 'a> {
+            Some(path.display())
+        }
+    }
+
+    fn compiler() -> Compiler<DummyExecutable, ArmAssembler> {
+        Compiler::default().with_color(ColorChoice::Never)
+    }
+
+    #[test]
+    fn target_follows_the_assembler() {
+        assert_eq!(compiler().target(), Target::new(Arch::Aarch64, Os::host()));
+
+        let x64: Compiler<DummyExecutable, X64Assembler> = Compiler::default();
+        assert_eq!(x64.target().arch, Arch::X86_64);
+    }
+
+    #[test]
+    fn cfg_dump_is_written_to_a_file() {
+        let path = env::temp_dir().join(format!("istind-cfg-{}.dot", process::id()));
+        compiler()
+            .with_cfg_dump(Some(path.clone()))
+            .compile_source(
+                Rc::new(PathBuf::from("main")),
+                "fn main() -> i64 { return 0; }",
+            )
+            .unwrap();
+
+        let dot = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!(dot.starts_with("digraph cfg {"), "{}", dot);
+    }
+
+    #[test]
+    #[should_panic(expected = "the assembler cannot generate code for x86_64-apple-darwin")]
+    fn target_for_another_architecture() {
+        let _ = compiler().with_target(Target::new(Arch::X86_64, Os::MacOs));
+    }
+
+    #[test]
+    #[should_panic(expected = "tab width must be at least 1")]
+    fn zero_tab_width() {
+        let _ = compiler().with_tab_width(0);
+    }
+
+    #[test]
+    fn eval_expr() {
+        assert_eq!(compiler().eval_expr("2*(3+4)").unwrap(), 14);
+    }
+
+    #[test]
+    fn eval_expr_precedence() {
+        assert_eq!(compiler().eval_expr("2 * (3) + 4").unwrap(), 10);
+        assert_eq!(compiler().eval_expr("10 - 3 - 2").unwrap(), 5);
+        assert_eq!(compiler().eval_expr("1 + 2 * 3 - 4 / 2").unwrap(), 5);
+    }
+
+    #[test]
+    fn eval_negation() {
+        assert_eq!(compiler().eval_expr("1 - -2").unwrap(), 3);
+        assert_eq!(compiler().eval_expr("- -5").unwrap(), 5);
+        assert_eq!(compiler().eval_expr("-(2 * 3) + 1").unwrap(), -5);
+        assert_eq!(compiler().eval_expr("(-(1.5 + 1.0)) as i64").unwrap(), -2);
+    }
+
+    #[test]
+    fn eval_float_expr() {
+        assert_eq!(compiler().eval_expr("(1.5 + 2.5) as i64").unwrap(), 4);
+        assert_eq!(
+            compiler()
+                .eval_expr("((7.5 * 2.0 - 1.0) / 2.0) as i64")
+                .unwrap(),
+            7
+        );
+        assert_eq!(compiler().eval_expr("(3 as f64 / 2.0) as i64").unwrap(), 1);
+    }
+
+    #[test]
+    fn eval_expr_error_points_into_expression() {
+        let Err(CompileError::Compile(errors)) = compiler().eval_expr("1 + x") else {
+            panic!("unknown variable was accepted");
+        };
+
+        let mut out = Vec::new();
+        let error = errors.iter().next().unwrap();
+        error
+            .write(Snippet(Source::from("1 + x".to_owned())), &mut out)
+            .unwrap();
+
+        assert!(String::from_utf8(out).unwrap().contains("expr:1:5"));
+    }
+
+    #[test]
+    fn eval_expr_rejects_trailing_tokens() {
+        assert!(compiler().eval_expr("1 + 2 3").is_err());
+    }
+
+    #[test]
+    fn custom_missing_semicolon_message() {
+        let source = "fn main() -> i64 { return 1 }";
+        let compiler = compiler().with_messages(Messages {
+            missing_semicolon: "a semicolon is missing".into(),
+            missing_semicolon_label: "add one after this".into(),
+            ..Default::default()
+        });
+
+        let Err(errors) = compiler.compile_source(Rc::new(PathBuf::from("main")), source) else {
+            panic!("missing semicolon was accepted");
+        };
+
+        let mut out = Vec::new();
+        let error = errors.iter().next().unwrap();
+        error
+            .write(Snippet(Source::from(source.to_owned())), &mut out)
+            .unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("a semicolon is missing"));
+        assert!(out.contains("add one after this"));
+        assert!(!out.contains("dummy"));
+    }
+}
@@ -1,14 +1,27 @@
 use std::{
-    io,
+    ffi::{OsStr, OsString},
+    fs,
+    io::{self, Read},
     path::{Path, PathBuf},
+    rc::Rc,
     time::Instant,
 };
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use colored::Colorize;
 use istind::{
-    Compiler,
-    synthesize::{arch::arm::ArmAssembler, exe::mac::AppleExecutable},
+    CompileError, Compiler,
+    analyze::ColorChoice,
+    files,
+    synthesize::{
+        arch::{
+            AssembleOptions, RegisterAllocator,
+            arm::{ArmAssembler, disasm},
+            wasm::WasmAssembler,
+        },
+        exe::{mac::AppleExecutable, wasm::WasmModule},
+        target::{Arch, Os, Target},
+    },
 };
 
 #[derive(Parser)]
@@ -16,18 +29,116 @@ use istind::{
 struct Cli {
     #[clap(subcommand)]
     command: Command,
+
+    #[arg(
+        long,
+        global = true,
+        default_value = "auto",
+        help = "When to use colored output"
+    )]
+    color: Color,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Color {
+    Auto,
+    Always,
+    Never,
+}
+
+impl From<Color> for ColorChoice {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::Auto => ColorChoice::Auto,
+            Color::Always => ColorChoice::Always,
+            Color::Never => ColorChoice::Never,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum RegAlloc {
+    Linear,
+    Graph,
+}
+
+impl From<RegAlloc> for RegisterAllocator {
+    fn from(regalloc: RegAlloc) -> Self {
+        match regalloc {
+            RegAlloc::Linear => RegisterAllocator::Linear,
+            RegAlloc::Graph => RegisterAllocator::Graph,
+        }
+    }
+}
+
+/// Options for `-C`, which change the code that is generated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Codegen {
+    /// Trap on signed integer overflow at runtime
+    OverflowChecks,
 }
 
 #[derive(Debug, Subcommand)]
 enum Command {
     Build {
-        file: PathBuf,
+        #[arg(help = "Source file to compile, or `-` to read it from stdin")]
+        file: Option<PathBuf>,
 
-        #[arg(long = "asm", help = "Show generated assembly")]
+        #[arg(
+            short,
+            long,
+            help = "Where to write the output, named after the input in the build directory by default"
+        )]
+        output: Option<PathBuf>,
+
+        #[arg(
+            long = "asm",
+            help = "Print the generated assembly instead of building an executable"
+        )]
         asm: bool,
+
+        #[arg(long = "dump-ir", help = "Print the IR before it is assembled")]
+        dump_ir: bool,
+
+        #[arg(
+            long = "dump-cfg",
+            value_name = "FILE",
+            help = "Write the control-flow graph to a file as Graphviz"
+        )]
+        dump_cfg: Option<PathBuf>,
+
+        #[arg(
+            long = "wasm",
+            help = "Build a WebAssembly module instead of an executable"
+        )]
+        wasm: bool,
+
+        #[arg(
+            long = "regalloc",
+            default_value = "linear",
+            help = "How to allocate registers"
+        )]
+        regalloc: RegAlloc,
+
+        #[arg(
+            short = 'C',
+            value_name = "OPTION",
+            help = "Code generation option to turn on"
+        )]
+        codegen: Vec<Codegen>,
+
+        #[arg(
+            long,
+            help = "Target triple to build for, the host by default if executables can be built for it"
+        )]
+        target: Option<Target>,
     },
     Run {
-        file: PathBuf,
+        #[arg(help = "Source file to compile, or `-` to read it from stdin")]
+        file: Option<PathBuf>,
+
+        #[arg(long, help = "Target triple to build for, the host by default")]
+        target: Option<Target>,
     },
 }
 
@@ -42,20 +153,132 @@ fn main() -> Result<(), Error> {
 }
 
 fn cli(args: Cli) -> Result<(), Error> {
+    let color = ColorChoice::from(args.color);
+    colored::control::set_override(color.enabled());
+
     match args.command {
-        Command::Build { file, asm } => {
-            build(&file, asm)?;
+        Command::Build {
+            file,
+            output,
+            asm,
+            dump_ir,
+            dump_cfg,
+            wasm,
+            regalloc,
+            codegen,
+            target,
+        } => {
+            let target = target.unwrap_or_else(default_target);
+            let options = AssembleOptions {
+                allocator: regalloc.into(),
+                overflow_checks: codegen.contains(&Codegen::OverflowChecks),
+                ..Default::default()
+            };
+
+            let dumps = Dumps {
+                ir: dump_ir,
+                cfg: dump_cfg,
+            };
+
+            let input = Input::read(file)?;
+
+            if wasm || target.arch == Arch::Wasm32 {
+                // WebAssembly modules have no way to trap on overflow yet
+                if options.overflow_checks {
+                    return Err(Error::UnsupportedCodegen("overflow-checks", "WebAssembly"));
+                }
+                build_wasm(&input, output, &dumps, color)?;
+            } else if asm {
+                print_assembly(&input, &dumps, color, options, target)?;
+            } else {
+                build(&input, output, &dumps, color, options, target)?;
+            }
         }
-        Command::Run { file } => {
-            build_and_run(&file)?;
+        Command::Run { file, target } => {
+            let input = Input::read(file)?;
+            let target = match target {
+                Some(target) => target,
+                None => host()?,
+            };
+            build_and_run(&input, color, target)?;
         }
     }
 
     Ok(())
 }
 
-fn build_and_run(file: &Path) -> Result<(), Error> {
-    let exe = build(file, false)?;
+/// What to build for when no target is given: the host if executables can be built for it, or
+/// else Apple Silicon, so the compiler can still be used on other machines.
+fn default_target() -> Target {
+    match Target::host() {
+        Ok(host) if host.arch == Arch::Aarch64 => host,
+        _ => Target::new(Arch::Aarch64, Os::MacOs),
+    }
+}
+
+/// The machine the compiler is running on, which is where programs are run.
+fn host() -> Result<Target, Error> {
+    Target::host().map_err(|err| Error::UnsupportedTarget(format!("this host ({})", err)))
+}
+
+/// What to write out about the program while compiling it, for debugging the compiler.
+#[derive(Debug, Default)]
+struct Dumps {
+    ir: bool,
+    /// Where to write the control-flow graph.
+    cfg: Option<PathBuf>,
+}
+
+/// A program to compile, read from a file or from stdin.
+struct Input {
+    /// What diagnostics call the source.
+    name: PathBuf,
+    /// Name of the output, without an extension.
+    module: OsString,
+    source: String,
+}
+
+impl Input {
+    /// Reads `file`, or stdin if there is no file or it is `-`.
+    fn read(file: Option<PathBuf>) -> Result<Self, Error> {
+        match file {
+            Some(file) if file != Path::new("-") => {
+                let Some(module) = file.file_stem() else {
+                    return Err(Error::InvalidFile);
+                };
+                let module = module.to_owned();
+                let source = fs::read_to_string(&file)
+                    .map_err(|err| CompileError::Read(file.clone(), err))?;
+
+                Ok(Self {
+                    name: file,
+                    module,
+                    source,
+                })
+            }
+            _ => {
+                let mut source = String::new();
+                io::stdin().read_to_string(&mut source)?;
+
+                Ok(Self {
+                    name: PathBuf::from(files::STDIN_NAME),
+                    module: OsString::from("stdin"),
+                    source,
+                })
+            }
+        }
+    }
+}
+
+fn build_and_run(input: &Input, color: ColorChoice, target: Target) -> Result<(), Error> {
+    let exe = build(
+        input,
+        None,
+        &Dumps::default(),
+        color,
+        AssembleOptions::default(),
+        target,
+    )?;
 
     println!(
         "{:>12} `{}`",
@@ -67,57 +290,147 @@ fn build_and_run(file: &Path) -> Result<(), Error> {
     std::process::exit(status.code().unwrap_or(-1));
 }
 
-fn build(file: &Path, asm: bool) -> Result<PathBuf, Error> {
-    let Some(module) = file.file_stem() else {
-        return Err(Error::InvalidFile);
-    };
+fn build(
+    input: &Input,
+    output: Option<PathBuf>,
+    dumps: &Dumps,
+    color: ColorChoice,
+    options: AssembleOptions,
+    target: Target,
+) -> Result<PathBuf, Error> {
+    // only arm64 code can be put in an executable so far
+    if target.arch != Arch::Aarch64 {
+        return Err(Error::UnsupportedTarget(target.to_string()));
+    }
 
     println!(
         "{:>12} {}",
         "Compiling".bright_green().bold(),
-        module.to_string_lossy(),
+        input.module.to_string_lossy(),
     );
 
-    let compiler = Compiler::<AppleExecutable, ArmAssembler>::default();
+    let compiler = Compiler::<AppleExecutable, ArmAssembler>::default()
+        .with_color(color)
+        .with_ir_dump(dumps.ir)
+        .with_cfg_dump(dumps.cfg.clone())
+        .with_target(target)
+        .with_assemble_options(options);
 
-    let out_path = istind::files::target_mod(module)?;
+    let out_path = output_path(&input.module, output)?;
 
     let start = Instant::now();
-    if let Err(error_count) = compiler.compile(file, &out_path) {
-        return Err(Error::CompilationFailed(error_count));
-    }
+    compiler.compile_str(&input.name, &input.source, &out_path)?;
     let end = Instant::now();
     let dur = end - start;
 
     // println!(
     //     "\r{:>12} {} in {:.2}s",
     //     "Compiled".bright_green().bold(),
-    //     module.to_string_lossy(),
+    //     input.module.to_string_lossy(),
     //     dur.as_secs_f32(),
     // );
 
-    if asm {
-        print_assembly(&out_path);
+    Ok(out_path)
+}
+
+/// Compiles `input` and prints a listing of the generated instructions, which can be
+/// assembled again with GNU as.
+fn print_assembly(
+    input: &Input,
+    dumps: &Dumps,
+    color: ColorChoice,
+    options: AssembleOptions,
+    target: Target,
+) -> Result<(), Error> {
+    if target.arch != Arch::Aarch64 {
+        return Err(Error::UnsupportedTarget(target.to_string()));
     }
 
+    let compiler = Compiler::<AppleExecutable, ArmAssembler>::default()
+        .with_color(color)
+        .with_ir_dump(dumps.ir)
+        .with_cfg_dump(dumps.cfg.clone())
+        .with_target(target)
+        .with_assemble_options(options);
+
+    let name = Rc::new(input.name.clone());
+    let code = match compiler.compile_source(name.clone(), &input.source) {
+        Ok(code) => code,
+        Err(errors) => {
+            errors.dump_with_source(&name, &input.source);
+            return Err(Error::CompilationFailed(errors.len()));
+        }
+    };
+    let size = code.size();
+    let code = code.finalize(size, 0);
+
+    print!("{}", disasm::listing(&code));
+
+    Ok(())
+}
+
+fn build_wasm(
+    input: &Input,
+    output: Option<PathBuf>,
+    dumps: &Dumps,
+    color: ColorChoice,
+) -> Result<PathBuf, Error> {
+    println!(
+        "{:>12} {}",
+        "Compiling".bright_green().bold(),
+        input.module.to_string_lossy(),
+    );
+
+    let compiler = Compiler::<WasmModule, WasmAssembler>::default()
+        .with_color(color)
+        .with_ir_dump(dumps.ir)
+        .with_cfg_dump(dumps.cfg.clone());
+    let out_path = match output {
+        Some(output) => output_path(&input.module, Some(output))?,
+        None => files::target_mod(&input.module)?.with_extension("wasm"),
+    };
+
+    compiler.compile_str(&input.name, &input.source, &out_path)?;
+
     Ok(out_path)
 }
 
+/// Where to write the output of `module`: `output` if it is given, with the directories it is
+/// in created, or else the build directory.
+fn output_path(module: &OsStr, output: Option<PathBuf>) -> Result<PathBuf, Error> {
+    match output {
+        Some(output) => {
+            if let Some(dir) = output.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            Ok(output)
+        }
+        None => Ok(files::target_mod(module)?),
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 enum Error {
     #[error("path is not a compilable file")]
     InvalidFile,
     #[error("failed to compile due to {0} error(s)")]
     CompilationFailed(usize),
+    #[error("cannot build executables for {0}")]
+    UnsupportedTarget(String),
+    #[error("-C {0} is not supported for {1}")]
+    UnsupportedCodegen(&'static str, &'static str),
+    #[error(transparent)]
+    Compile(CompileError),
     #[error("io error")]
     Io(#[from] io::Error),
 }
 
-#[cfg(target_os = "macos")]
-fn print_assembly(exe: &Path) {
-    std::process::Command::new("otool")
-        .arg("-vt")
-        .arg(exe)
-        .status()
-        .expect("program failed");
+impl From<CompileError> for Error {
+    fn from(err: CompileError) -> Self {
+        match err {
+            // the errors themselves have been printed already
+            CompileError::Compile(errors) => Error::CompilationFailed(errors.len()),
+            err => Error::Compile(err),
+        }
+    }
 }
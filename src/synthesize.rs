@@ -1,2 +1,3 @@
 pub mod arch;
 pub mod exe;
+pub mod target;
@@ -1,6 +1,12 @@
-use crate::ir::IR;
+use crate::{
+    analyze::{ColorChoice, ErrorVec},
+    ir::IR,
+    synthesize::target::Arch,
+};
 
 pub mod arm;
+pub mod wasm;
+pub mod x64;
 
 pub struct UnfinishedCode<A: Assembler>(pub(self) A);
 
@@ -9,23 +15,110 @@ impl<A: Assembler> UnfinishedCode<A> {
         self.0.current_offset()
     }
 
-    pub fn finalize(mut self, str_literal_offset: usize) -> MachineCode {
-        self.0.into_machine_code(str_literal_offset)
+    /// Total size of the string literals, including their nul terminators.
+    pub fn str_literals_size(&self) -> usize {
+        self.0.str_literals().iter().map(|s| s.len() + 1).sum()
+    }
+
+    /// Number of external symbols the code imports.
+    pub fn extern_count(&self) -> usize {
+        self.0.externs().len()
+    }
+
+    /// Resolves references to the string literals and the global offset table, given their
+    /// offsets from the start of the segment the code is placed in.
+    pub fn finalize(self, str_literal_offset: usize, got_offset: usize) -> MachineCode {
+        self.finalize_reusable(str_literal_offset, got_offset).0
+    }
+
+    /// Like [UnfinishedCode::finalize], but also hands back the assembler so its allocations
+    /// can be reused for the next compilation.
+    pub fn finalize_reusable(
+        mut self,
+        str_literal_offset: usize,
+        got_offset: usize,
+    ) -> (MachineCode, A) {
+        let code = self.0.take_machine_code(str_literal_offset, got_offset);
+        (code, self.0)
+    }
+}
+
+/// Options that control how code is generated.
+#[derive(Debug, Clone, Copy)]
+pub struct AssembleOptions {
+    /// Alignment in bytes of every function entry, relative to the start of the code. The gap
+    /// before a function is padded with no-ops. Must be a power of two and at least the size
+    /// of an instruction.
+    pub function_alignment: usize,
+    /// Whether diagnostics are printed with colors.
+    pub color: ColorChoice,
+    /// How virtual registers are assigned to machine registers, for backends that have them.
+    pub allocator: RegisterAllocator,
+    /// Exits the process with the result of `main` once it returns. Without it, the code is
+    /// freestanding: `main` is the entry point and returns to its caller, and the builtins that
+    /// make syscalls are only emitted if they are called.
+    pub emit_exit: bool,
+    /// Traps with a breakpoint when a signed addition, subtraction or multiplication overflows
+    /// at runtime, instead of wrapping around.
+    pub overflow_checks: bool,
+}
+
+impl Default for AssembleOptions {
+    fn default() -> Self {
+        Self {
+            function_alignment: 4,
+            color: ColorChoice::default(),
+            allocator: RegisterAllocator::default(),
+            emit_exit: true,
+            overflow_checks: false,
+        }
     }
 }
 
+/// Strategies for assigning machine registers to virtual registers.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RegisterAllocator {
+    /// Walks the operations in order, handing out a register when a value is first needed and
+    /// taking it back after its last use.
+    #[default]
+    Linear,
+    /// Colors a graph of the values that are live at the same time, so a value keeps one
+    /// register for as long as it lives, and only the values that don't fit are spilled.
+    Graph,
+}
+
 #[derive(Default)]
 pub struct MachineCode {
     pub instructions: Vec<u8>,
     pub entry_point_offset: u64,
     pub symbols: Vec<(String, u64)>,
     pub str_literals: Vec<String>,
+    /// External symbols, in the order of their slots in the global offset table.
+    pub externs: Vec<String>,
+    /// Offset of the stubs that jump through the global offset table. They are placed after all
+    /// other instructions.
+    pub stubs_offset: u64,
 }
 
 pub trait Assembler: Sized {
-    fn assemble(ir: IR) -> UnfinishedCode<Self>;
+    /// The instruction set the code is generated for.
+    const ARCH: Arch;
+
+    fn assemble(ir: IR) -> Result<UnfinishedCode<Self>, ErrorVec> {
+        Self::assemble_with_options(ir, AssembleOptions::default())
+    }
+
+    /// Assembles `ir`, or returns every problem found in it that the target cannot express.
+    fn assemble_with_options(
+        ir: IR,
+        options: AssembleOptions,
+    ) -> Result<UnfinishedCode<Self>, ErrorVec>;
 
     fn current_offset(&self) -> usize;
 
-    fn into_machine_code(self, str_literal_offset: usize) -> MachineCode;
+    fn str_literals(&self) -> &[String];
+
+    fn externs(&self) -> &[String];
+
+    fn take_machine_code(&mut self, str_literal_offset: usize, got_offset: usize) -> MachineCode;
 }
@@ -1,21 +1,32 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    rc::Rc,
+};
 
 use num_traits::FromPrimitive;
 use strum::IntoEnumIterator;
-use ux::{i7, i12, i19, i21, i26, u9, u12};
+use ux::{i7, i19, i21, i26, u6, u9, u12};
 
 use crate::{
-    ir::{Condition, IR, Item, Label, OpIndex, Operation, SourceVal, StrId, VarSize, VirtualReg},
-    synthesize::arch::{
-        Assembler, MachineCode, UnfinishedCode,
-        arm::{
-            instr::{ImmShift16, Instruction},
-            reg::{Allocator, Reg, Register},
+    analyze::{ErrorContext, ErrorVec, Span},
+    ir::{
+        BasicBlock, Condition, FloatOp, IR, Item, Label, OpIndex, Operation, SourceVal, StrId,
+        VarSize, VirtualReg,
+    },
+    synthesize::{
+        arch::{
+            AssembleOptions, Assembler, MachineCode, UnfinishedCode,
+            arm::{
+                instr::{AddImm, ImmShift16, Instruction},
+                reg::{Allocator, FReg, Reg, Register, RegisterClass},
+            },
         },
+        target::Arch,
     },
 };
 
 pub mod builtin;
+pub mod disasm;
 pub mod instr;
 pub mod reg;
 
@@ -23,22 +34,77 @@ pub mod reg;
 
 const MAIN_FN: &str = "main";
 
+/// Arguments are passed in X0-X7 only.
+const MAX_ARGS: usize = 8;
+
 type InstrIndex = usize;
 
 #[derive(Default)]
 pub struct ArmAssembler {
     code: MachineCode,
     functions: HashMap<String, InstrIndex>,
-    fn_calls: Vec<(String, InstrIndex)>,
-    stacks: Vec<i12>,
+    /// Calls to patch once all functions are placed, with the source of the call.
+    fn_calls: Vec<(String, InstrIndex, Option<Span>)>,
+    /// Size of the stack frame of the function being assembled. Every return jumps to the
+    /// epilogue, which reads it to tear the frame down again.
+    frame_size: u12,
     str_literal_offsets: HashMap<StrId, usize>,
+    stubs: HashMap<String, InstrIndex>,
+    options: AssembleOptions,
+    errors: ErrorContext,
 
-    lazy_emitters: Vec<Box<dyn Fn(&mut ArmAssembler, usize)>>,
+    lazy_emitters: Vec<Box<dyn Fn(&mut ArmAssembler, usize, usize)>>,
 }
 
 impl Assembler for ArmAssembler {
-    fn assemble(ir: IR) -> UnfinishedCode<Self> {
-        let mut asm = ArmAssembler::default();
+    const ARCH: Arch = Arch::Aarch64;
+
+    fn assemble_with_options(
+        ir: IR,
+        options: AssembleOptions,
+    ) -> Result<UnfinishedCode<Self>, ErrorVec> {
+        ArmAssembler::default()
+            .with_options(options)
+            .assemble_with(ir)
+    }
+
+    fn current_offset(&self) -> usize {
+        self.code.instructions.len()
+    }
+
+    fn str_literals(&self) -> &[String] {
+        &self.code.str_literals
+    }
+
+    fn externs(&self) -> &[String] {
+        &self.code.externs
+    }
+
+    fn take_machine_code(&mut self, str_literal_offset: usize, got_offset: usize) -> MachineCode {
+        for emit in std::mem::take(&mut self.lazy_emitters) {
+            emit(self, str_literal_offset, got_offset);
+        }
+
+        std::mem::take(&mut self.code)
+    }
+}
+
+impl ArmAssembler {
+    pub fn with_options(mut self, options: AssembleOptions) -> Self {
+        assert!(
+            options.function_alignment.is_power_of_two() && options.function_alignment >= 4,
+            "function alignment must be a power of two of at least 4 bytes"
+        );
+
+        self.options = options;
+        self
+    }
+
+    /// Assembles `ir` with this assembler, reusing the allocations left over from a previous
+    /// compilation. The assembler is recovered with [UnfinishedCode::finalize_reusable].
+    pub fn assemble_with(mut self, ir: IR) -> Result<UnfinishedCode<Self>, ErrorVec> {
+        self.reset();
+        let mut asm = self;
 
         let mut str_offset = 0;
         for (string, id) in ir.strings {
@@ -53,17 +119,47 @@ impl Assembler for ArmAssembler {
 
         builtin::assemble(&mut asm);
 
-        let entry_point_offset = asm.current_offset();
-        let mut emitter = ScopedEmitter::new(&mut asm, Allocator::default(), HashMap::new());
-        emitter.emit_call(MAIN_FN.to_owned(), vec![], None, 0);
-
-        builtin::exit(&mut asm);
+        let entry_point_offset = if asm.options.emit_exit {
+            // With LC_MAIN, dyld calls the entry point with argc and argv in X0 and X1. The call
+            // to main takes no arguments of its own, so they are passed through untouched.
+            asm.align_function();
+            let entry_point_offset = asm.current_offset();
+            let mut emitter = ScopedEmitter::new(
+                &mut asm,
+                Allocator::default(),
+                HashMap::new(),
+                Vec::new(),
+                None,
+            );
+            emitter.emit_call(MAIN_FN.to_owned(), vec![], None, 0);
+
+            builtin::exit(&mut asm);
+            entry_point_offset
+        } else {
+            asm.functions.get(MAIN_FN).copied().unwrap_or_default()
+        };
+
+        asm.code.stubs_offset = asm.current_offset() as u64;
+        for name in ir.externs {
+            // builtins are forward declared too, but they are already assembled
+            if !asm.functions.contains_key(&name) {
+                asm.emit_stub(name);
+            }
+        }
 
-        for (function, call_offset) in std::mem::take(&mut asm.fn_calls) {
-            let fn_offset = asm
+        for (function, call_offset, span) in std::mem::take(&mut asm.fn_calls) {
+            let Some(fn_offset) = asm
                 .functions
                 .get(&function)
-                .unwrap_or_else(|| panic!("call to unknown function {}", function));
+                .or_else(|| asm.stubs.get(&function))
+            else {
+                asm.report(
+                    span,
+                    format!("call to unknown function {}", function),
+                    "this function is not defined",
+                );
+                continue;
+            };
 
             let rel_offset = (*fn_offset as i32 - call_offset as i32) / 4;
             asm.emit_at(
@@ -84,23 +180,52 @@ impl Assembler for ArmAssembler {
             .symbols
             .push((String::from("_entry_point"), entry_point_offset as u64));
 
-        UnfinishedCode(asm)
+        if !asm.errors.is_empty() {
+            return Err(asm.errors.take_errors());
+        }
+
+        Ok(UnfinishedCode(asm))
     }
 
-    fn current_offset(&self) -> usize {
-        self.code.instructions.len()
+    /// Clears all state from a previous compilation, keeping the allocated capacity and the
+    /// options.
+    pub fn reset(&mut self) {
+        self.code.instructions.clear();
+        self.code.symbols.clear();
+        self.code.str_literals.clear();
+        self.code.externs.clear();
+        self.code.entry_point_offset = 0;
+        self.code.stubs_offset = 0;
+
+        self.functions.clear();
+        self.fn_calls.clear();
+        self.frame_size = u12::new(0);
+        self.str_literal_offsets.clear();
+        self.stubs.clear();
+        self.lazy_emitters.clear();
+        self.errors = ErrorContext::with_color(self.options.color);
     }
 
-    fn into_machine_code(mut self, str_literal_offset: usize) -> MachineCode {
-        for emit in std::mem::take(&mut self.lazy_emitters) {
-            emit(&mut self, str_literal_offset);
+    /// Reports a problem in the code being assembled. Assembly carries on to find any further
+    /// problems, and fails once it is done. Without a source location, like for the call to
+    /// `main` from the entry point, only the message is shown.
+    fn report(&mut self, span: Option<Span>, message: String, label: impl ToString) {
+        match span {
+            Some(span) => self
+                .errors
+                .error(span.clone())
+                .with_message(message)
+                .with_label(span, label)
+                .report(),
+            // a report is placed at a span, but without labels nothing is shown of it
+            None => self
+                .errors
+                .error((Rc::default(), 0..0))
+                .with_message(message)
+                .report(),
         }
-
-        self.code
     }
-}
 
-impl ArmAssembler {
     fn emit(&mut self, instr: impl Instruction) {
         self.code.instructions.extend(instr.encode().to_le_bytes());
     }
@@ -110,16 +235,59 @@ impl ArmAssembler {
         self.code.instructions[offset..(offset + 4)].copy_from_slice(&bytes);
     }
 
+    /// Pads the code with no-ops up to the alignment of a function entry.
+    fn align_function(&mut self) {
+        while !self
+            .current_offset()
+            .is_multiple_of(self.options.function_alignment)
+        {
+            self.emit_nop();
+        }
+    }
+
     fn asm_item(&mut self, item: Item) {
-        let Item::Function { name, args, bb } = item;
+        let Item::Function {
+            name,
+            args,
+            bb,
+            span,
+            ..
+        } = item;
+        self.align_function();
         self.functions.insert(name.clone(), self.current_offset());
 
-        let mut alloc = reg::allocate(&bb, &args);
+        if args.len() > MAX_ARGS {
+            self.report(
+                Some(span),
+                format!("too many parameters for function {}", name),
+                format!("at most {} parameters are supported", MAX_ARGS),
+            );
+            return;
+        }
+
+        let mut alloc = reg::allocate_with(&bb, &args, self.options.allocator);
+
+        let frame_size = frame_size(alloc.stack_size());
+        if frame_size > u16::from(u12::MAX) {
+            self.report(
+                Some(span),
+                format!("stack frame of function {} is too large", name),
+                format!(
+                    "needs {} bytes of stack, at most {} are supported",
+                    frame_size,
+                    u12::MAX
+                ),
+            );
+            return;
+        }
 
         self.begin_stack(alloc.stack_size());
 
         for &vreg in args.iter() {
-            let register = alloc.map(vreg, 0).inner_reg();
+            let register = alloc
+                .map(vreg, 0)
+                .expect("arguments are mapped on entry")
+                .inner_reg();
             let offset = alloc.stack_index_of(&vreg);
             self.emit(instr::Store {
                 base: Reg::SP,
@@ -128,7 +296,9 @@ impl ArmAssembler {
             });
         }
 
-        let mut emitter = ScopedEmitter::new(self, alloc, bb.labels);
+        let returned_calls = returned_calls(&bb);
+        let mut emitter = ScopedEmitter::new(self, alloc, bb.labels, bb.spans, Some(span));
+        emitter.returned_calls = returned_calls;
         for (idx, op) in bb.ops.into_iter().enumerate() {
             emitter.asm_op(op, idx);
         }
@@ -151,33 +321,28 @@ impl ArmAssembler {
             dest: Reg::FP,
         });
 
-        if stack_size != u12::new(0) {
-            let mut stack_size: u16 = stack_size.into();
-            // align to 16 bytes
-            if !stack_size.is_multiple_of(2) {
-                stack_size += 1;
-            }
-
-            let stack_size = stack_size as i16;
-            let stack_size = i12::new(stack_size * 8);
-
-            self.stacks.push(stack_size);
+        self.frame_size = if stack_size != u12::new(0) {
+            u12::new(frame_size(stack_size))
+        } else {
+            u12::new(0)
+        };
 
+        if self.frame_size != u12::new(0) {
             self.emit(instr::Sub {
                 a: Reg::SP,
-                b: instr::Input::Imm(stack_size),
+                b: instr::Input::Imm(self.frame_size.into()),
                 dest: Reg::SP,
             });
         }
     }
 
+    /// Emits the epilogue of the function set up by the last [begin_stack](Self::begin_stack).
+    /// It can be emitted any number of times.
     fn end_stack(&mut self) {
-        if let Some(stack_size) = self.stacks.pop()
-            && stack_size != i12::new(0)
-        {
+        if self.frame_size != u12::new(0) {
             self.emit(instr::Add {
                 a: Reg::SP,
-                b: instr::Input::Imm(stack_size),
+                b: instr::Input::Imm(self.frame_size.into()),
                 dest: Reg::SP,
             });
         }
@@ -200,12 +365,45 @@ impl ArmAssembler {
         });
     }
 
-    fn emit_movz(&mut self, n: i64, dest: Register) {
-        self.emit(instr::Movz {
-            shift: ImmShift16::L0,
-            imm_value: n as u16,
-            dest,
-        });
+    /// Moves a 64-bit immediate into `dest`, with a MOVZ for the low 16 bits and a MOVK for
+    /// every other nonzero part. When more of the parts are all ones than zero, as in small
+    /// negative numbers, it starts with a MOVN instead and fills in the parts that are not all
+    /// ones.
+    fn emit_imm64(&mut self, n: i64, dest: Register) {
+        let part = |shift: ImmShift16| (n as u64 >> (16 * shift as u32)) as u16;
+        let upper = [ImmShift16::L16, ImmShift16::L32, ImmShift16::L48];
+
+        let ones = upper
+            .iter()
+            .filter(|&&shift| part(shift) == u16::MAX)
+            .count();
+        let zeros = upper.iter().filter(|&&shift| part(shift) == 0).count();
+        let fill = if ones > zeros {
+            self.emit(instr::Movn {
+                shift: ImmShift16::L0,
+                imm_value: !part(ImmShift16::L0),
+                dest,
+            });
+            u16::MAX
+        } else {
+            self.emit(instr::Movz {
+                shift: ImmShift16::L0,
+                imm_value: part(ImmShift16::L0),
+                dest,
+            });
+            0
+        };
+
+        for shift in upper {
+            let part = part(shift);
+            if part != fill {
+                self.emit(instr::Movk {
+                    shift,
+                    imm_value: part,
+                    dest,
+                });
+            }
+        }
     }
 
     fn emit_nop(&mut self) {
@@ -218,21 +416,102 @@ impl ArmAssembler {
         I: Instruction,
     {
         let instr_offset = self.current_offset();
-        self.lazy_emitters.push(Box::new(move |asm, str_offset| {
+        self.lazy_emitters.push(Box::new(move |asm, str_offset, _| {
             let instr = emit(str_offset);
             asm.emit_at(instr_offset, instr);
         }));
 
         self.emit_nop();
     }
+
+    /// Emits a stub that jumps to an external function through its slot in the global offset
+    /// table, which dyld fills in at load time.
+    fn emit_stub(&mut self, name: String) {
+        let got_slot = self.code.externs.len() * 8;
+        let stub_offset = self.current_offset();
+
+        self.stubs.insert(name.clone(), stub_offset);
+        self.code.externs.push(name);
+
+        self.lazy_emitters
+            .push(Box::new(move |asm, str_offset, got_offset| {
+                // the string literals follow right after the code
+                let code_offset = str_offset - asm.current_offset();
+                let pc = code_offset + stub_offset;
+                let slot_addr = got_offset + got_slot;
+
+                asm.emit_at(
+                    stub_offset,
+                    instr::Adrp {
+                        page_addr: i21::new((slot_addr / 4096) as i32 - (pc / 4096) as i32),
+                        dest: Reg::X16,
+                    },
+                );
+
+                asm.emit_at(
+                    stub_offset + 4,
+                    instr::Load {
+                        base: Reg::X16,
+                        offset: u12::new((slot_addr % 4096 / 8) as u16),
+                        dest: Reg::X16,
+                    },
+                );
+            }));
+
+        self.emit_nop();
+        self.emit_nop();
+        self.emit(instr::BranchReg { reg: Reg::X16 });
+    }
+}
+
+/// Switches with at least this many cases may be lowered to a jump table.
+const JUMP_TABLE_MIN_CASES: usize = 4;
+
+/// Calls whose result is returned by the operation right after. Nothing may branch to the
+/// return, since it would return a value from elsewhere. Intrinsics are left out, as their
+/// results are never in X0.
+fn returned_calls(bb: &BasicBlock) -> HashSet<OpIndex> {
+    bb.ops
+        .windows(2)
+        .enumerate()
+        .filter_map(|(idx, ops)| match ops {
+            [
+                Operation::Call {
+                    function,
+                    dest: Some(dest),
+                    ..
+                },
+                Operation::Return {
+                    value: SourceVal::VReg(value),
+                },
+            ] if dest == value
+                && !bb.labels.contains_key(&(idx + 1))
+                && builtin::intrinsic(function).is_none() =>
+            {
+                Some(idx)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Size in bytes of a stack frame with `slots` 8-byte slots, rounded up to keep the stack
+/// pointer 16-byte aligned.
+fn frame_size(slots: u12) -> u16 {
+    u16::from(slots).next_multiple_of(2) * 8
 }
 
 struct ScopedEmitter<'c> {
     asm: &'c mut ArmAssembler,
     alloc: Allocator,
     ir_labels: HashMap<OpIndex, Vec<Label>>,
+    spans: Vec<Option<Span>>,
+    /// Source of the function being emitted, for operations that have no source of their own.
+    fn_span: Option<Span>,
     mapped_labels: HashMap<Label, InstrIndex>,
     lazy_emits: Vec<Box<dyn FnOnce(&mut ScopedEmitter)>>,
+    /// Calls whose result is returned right after, which is left in X0 where the call put it.
+    returned_calls: HashSet<OpIndex>,
 }
 
 impl<'c> ScopedEmitter<'c> {
@@ -240,22 +519,68 @@ impl<'c> ScopedEmitter<'c> {
         asm: &'c mut ArmAssembler,
         alloc: Allocator,
         ir_labels: HashMap<OpIndex, Vec<Label>>,
+        spans: Vec<Option<Span>>,
+        fn_span: Option<Span>,
     ) -> Self {
         Self {
             asm,
             alloc,
             ir_labels,
+            spans,
+            fn_span,
             mapped_labels: HashMap::new(),
             lazy_emits: Vec::new(),
+            returned_calls: HashSet::new(),
         }
     }
 
+    /// The source of the operation at `idx`. Operations that were not written in the source,
+    /// like those made by inlining or unrolling, fall back to the function they are in.
+    fn span(&self, idx: OpIndex) -> Option<Span> {
+        self.spans
+            .get(idx)
+            .cloned()
+            .flatten()
+            .or_else(|| self.fn_span.clone())
+    }
+
     fn map_reg_use(&mut self, vreg: VirtualReg, instr_index: usize) -> Register {
-        self.alloc.map(vreg, instr_index).unwrap(self.asm)
+        match self.alloc.map(vreg, instr_index) {
+            Some(guard) => guard.unwrap(self.asm),
+            None => {
+                self.asm.report(
+                    self.span(instr_index),
+                    format!("no register allocated for {}", vreg),
+                    "while compiling this",
+                );
+                // the code is thrown away, any register does to keep going
+                Register::X16
+            }
+        }
     }
 
     fn map_reg_assign(&mut self, dest: VirtualReg, idx: usize) -> (Register, u12) {
-        let reg = self.alloc.map(dest, idx).unwrap(self.asm);
+        let reg = self.map_reg_use(dest, idx);
+        let stack_idx = self.alloc.stack_index_of(&dest);
+        (reg, stack_idx)
+    }
+
+    fn map_freg_use(&mut self, vreg: VirtualReg, instr_index: usize) -> FReg {
+        match self.alloc.map_float(vreg, instr_index) {
+            Some(guard) => guard.unwrap(self.asm),
+            None => {
+                self.asm.report(
+                    self.span(instr_index),
+                    format!("no floating-point register allocated for {}", vreg),
+                    "while compiling this",
+                );
+                FReg::V0
+            }
+        }
+    }
+
+    fn map_freg_assign(&mut self, dest: VirtualReg, idx: usize) -> (FReg, u12) {
+        let reg = self.map_freg_use(dest, idx);
         let stack_idx = self.alloc.stack_index_of(&dest);
         (reg, stack_idx)
     }
@@ -281,14 +606,28 @@ impl<'c> ScopedEmitter<'c> {
 
             Operation::Add { a, b, dest } => self.emit_add(a, b, dest, idx),
             Operation::Subtract { a, b, dest } => self.emit_sub(a, b, dest, idx),
+            Operation::Negate { src, dest } => self.emit_neg(src, dest, idx),
             Operation::Multiply { a, b, dest } => self.emit_mul(a, b, dest, idx),
-            Operation::Divide { a, b, dest } => self.emit_div(a, b, dest, idx),
+            Operation::Divide { a, b, dest, signed } => self.emit_div(a, b, dest, signed, idx),
+            Operation::Modulo { a, b, dest, signed } => self.emit_mod(a, b, dest, signed, idx),
+            Operation::FloatArithmetic { op, a, b, dest } => {
+                self.emit_float_arith(op, a, b, dest, idx)
+            }
+            Operation::FloatToInt { src, dest } => self.emit_float_to_int(src, dest, idx),
+            Operation::IntToFloat { src, dest } => self.emit_int_to_float(src, dest, idx),
 
             Operation::Compare { a, b, cond, dest } => self.emit_cmp(a, b, cond, dest, idx),
 
+            Operation::Select { cond, a, b, dest } => self.emit_select(cond, a, b, dest, idx),
+
             Operation::Branch { label } => self.emit_jump(label),
             Operation::BranchIf { cond, label } => self.emit_branch_if(cond, label, idx),
             Operation::BranchIfNot { cond, label } => self.emit_branch_if_not(cond, label, idx),
+            Operation::Switch {
+                value,
+                cases,
+                default,
+            } => self.emit_switch(value, cases, default, idx),
 
             Operation::Return { value } => self.emit_return(value, idx),
             Operation::Call {
@@ -296,15 +635,22 @@ impl<'c> ScopedEmitter<'c> {
                 args,
                 dest,
             } => self.emit_call(function, args, dest, idx),
+            Operation::ReturnPair { first, second } => self.emit_return_pair(first, second, idx),
+            Operation::SecondResult { dest } => self.emit_second_result(dest, idx),
         }
     }
 
     fn emit_assign(&mut self, src: SourceVal, dest: VirtualReg, idx: usize) {
         let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
+        self.emit_source(src, dest, idx);
+        self.asm.emit_stack_store(stack_ptr, dest);
+    }
 
+    /// Puts the value of `src` in the physical register `dest`.
+    fn emit_source(&mut self, src: SourceVal, dest: Register, idx: usize) {
         match src {
             SourceVal::Immediate(n) => {
-                self.asm.emit_movz(n, dest);
+                self.asm.emit_imm64(n, dest);
             }
             SourceVal::VReg(vreg) => {
                 let src = self.map_reg_use(vreg, idx);
@@ -331,30 +677,40 @@ impl<'c> ScopedEmitter<'c> {
 
                 self.asm.lazy_emit(move |str_table_offset| {
                     let abs_offset = str_table_offset + rel_str_offset;
-                    let in_page_offset = i12::new((abs_offset % 4096) as i16);
+                    let in_page_offset = u12::new((abs_offset % 4096) as u16);
 
                     instr::Add {
                         a: dest,
-                        b: instr::Input::Imm(in_page_offset),
+                        b: instr::Input::Imm(in_page_offset.into()),
                         dest,
                     }
                 });
             }
         }
+    }
 
-        self.asm.emit_stack_store(stack_ptr, dest);
+    /// Maps an operand that may be a constant. Registers are used where they are, and
+    /// constants are put in `scratch`.
+    fn map_source_use(&mut self, src: SourceVal, idx: usize, scratch: Register) -> Register {
+        match src {
+            SourceVal::VReg(vreg) => self.map_reg_use(vreg, idx),
+            src => {
+                self.emit_source(src, scratch, idx);
+                scratch
+            }
+        }
     }
 
     fn emit_addr_of(&mut self, val: VirtualReg, dest: VirtualReg, idx: usize) {
         let stack_idx = self.alloc.stack_index_of(&val);
         let stack_idx: u16 = stack_idx.into();
-        let stack_idx = i12::new(stack_idx as i16 * 8);
+        let stack_idx = u12::new(stack_idx * 8);
 
         let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
 
         self.asm.emit(instr::Add {
             a: Register::SP,
-            b: instr::Input::Imm(stack_idx),
+            b: instr::Input::Imm(stack_idx.into()),
             dest,
         });
 
@@ -397,21 +753,40 @@ impl<'c> ScopedEmitter<'c> {
     fn emit_call(
         &mut self,
         function: String,
-        args: Vec<VirtualReg>,
+        args: Vec<SourceVal>,
         dest: Option<VirtualReg>,
         instr_index: usize,
     ) {
+        if let Some(intrinsic) = builtin::intrinsic(&function) {
+            intrinsic(self, &args, dest, instr_index);
+            return;
+        }
+
         if let Some(regs_to_save) = self.alloc.stack_save(instr_index) {
             for (reg, offset) in regs_to_save {
                 self.asm.emit_stack_store(*offset, *reg);
             }
         }
 
-        if args.len() > 8 {
-            todo!();
+        if let Some(regs_to_save) = self.alloc.float_stack_save(instr_index) {
+            for &(reg, offset) in regs_to_save {
+                FReg::emit_store(self.asm, offset, reg);
+            }
+        }
+
+        if args.len() > MAX_ARGS {
+            self.asm.report(
+                self.span(instr_index),
+                String::from("too many arguments"),
+                format!("at most {} arguments are supported", MAX_ARGS),
+            );
+            return;
         }
 
         for (i, &arg) in args.iter().enumerate() {
+            let SourceVal::VReg(arg) = arg else {
+                continue;
+            };
             let src = self.map_reg_use(arg, instr_index);
             let dest = Register::from_usize(i).unwrap();
 
@@ -422,16 +797,27 @@ impl<'c> ScopedEmitter<'c> {
                     offset: self.alloc.stack_index_of(&arg),
                     dest,
                 });
-            } else {
+            } else if src != dest {
                 self.asm.emit(instr::MovReg { src, dest });
             }
         }
 
+        // constants are put in place last, since they don't live in any register that the
+        // moves above could read
+        for (i, &arg) in args.iter().enumerate() {
+            if !matches!(arg, SourceVal::VReg(_)) {
+                self.emit_source(arg, Register::from_usize(i).unwrap(), instr_index);
+            }
+        }
+
         let offset = self.asm.current_offset();
         self.asm.emit_nop();
-        self.asm.fn_calls.push((function.clone(), offset));
+        let span = self.span(instr_index);
+        self.asm.fn_calls.push((function.clone(), offset, span));
 
-        if let Some(dest) = dest {
+        if let Some(dest) = dest
+            && !self.returned_calls.contains(&instr_index)
+        {
             let (dest, stack_ptr) = self.map_reg_assign(dest, instr_index);
 
             self.asm.emit(instr::MovReg { src: Reg::X0, dest });
@@ -439,42 +825,136 @@ impl<'c> ScopedEmitter<'c> {
         }
     }
 
-    fn emit_add(&mut self, a: VirtualReg, b: VirtualReg, dest: VirtualReg, idx: usize) {
+    fn emit_add(&mut self, a: VirtualReg, b: SourceVal, dest: VirtualReg, idx: usize) {
         let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
         let a = self.map_reg_use(a, idx);
-        let b = self.map_reg_use(b, idx);
 
-        self.asm.emit(instr::Add {
-            a,
-            b: instr::Input::Reg(b),
-            dest,
+        let checked = self.asm.options.overflow_checks;
+        match self.map_add_operand(b, idx) {
+            (b, false) if checked => self.asm.emit(instr::Adds { a, b, dest }),
+            (b, true) if checked => self.asm.emit(instr::Subs { a, b, dest }),
+            (b, false) => self.asm.emit(instr::Add { a, b, dest }),
+            (b, true) => self.asm.emit(instr::Sub { a, b, dest }),
+        }
+        if checked {
+            self.emit_overflow_trap(Condition::Overflow);
+        }
+        self.asm.emit_stack_store(stack_ptr, dest);
+    }
+
+    fn emit_sub(&mut self, a: VirtualReg, b: SourceVal, dest: VirtualReg, idx: usize) {
+        let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
+        let a = self.map_reg_use(a, idx);
+
+        let checked = self.asm.options.overflow_checks;
+        match self.map_add_operand(b, idx) {
+            (b, false) if checked => self.asm.emit(instr::Subs { a, b, dest }),
+            (b, true) if checked => self.asm.emit(instr::Adds { a, b, dest }),
+            (b, false) => self.asm.emit(instr::Sub { a, b, dest }),
+            (b, true) => self.asm.emit(instr::Add { a, b, dest }),
+        }
+        if checked {
+            self.emit_overflow_trap(Condition::Overflow);
+        }
+        self.asm.emit_stack_store(stack_ptr, dest);
+    }
+
+    /// Skips over a breakpoint unless `cond` holds after an operation that could overflow, so
+    /// the program traps instead of going on with a wrapped result.
+    fn emit_overflow_trap(&mut self, cond: Condition) {
+        // BranchCond branches when its condition does not hold
+        self.asm.emit(instr::BranchCond {
+            offset: i19::new(2),
+            cond,
         });
+        self.asm.emit(instr::Brk { imm: 1 });
+    }
+
+    /// Maps the second operand of an addition or subtraction. Constants are encoded directly
+    /// when they fit in a (possibly shifted) 12-bit immediate, and negative ones by flipping
+    /// the operation, which is signalled by the returned flag. Other constants are materialized
+    /// in the scratch register X16.
+    fn map_add_operand(&mut self, b: SourceVal, idx: usize) -> (instr::Input<AddImm>, bool) {
+        match b {
+            SourceVal::VReg(vreg) => (instr::Input::Reg(self.map_reg_use(vreg, idx)), false),
+            SourceVal::Immediate(n) => {
+                if let Some(imm) = AddImm::new(n.unsigned_abs()) {
+                    (instr::Input::Imm(imm), n < 0)
+                } else {
+                    self.asm.emit_imm64(n, Register::X16);
+                    (instr::Input::Reg(Register::X16), false)
+                }
+            }
+            SourceVal::String(_) => unreachable!("string operand in arithmetic"),
+        }
+    }
+
+    fn emit_neg(&mut self, src: VirtualReg, dest: VirtualReg, idx: usize) {
+        let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
+        let src = self.map_reg_use(src, idx);
+
+        self.asm.emit(instr::Neg { src, dest });
         self.asm.emit_stack_store(stack_ptr, dest);
     }
 
-    fn emit_sub(&mut self, a: VirtualReg, b: VirtualReg, dest: VirtualReg, idx: usize) {
+    fn emit_mul(&mut self, a: VirtualReg, b: VirtualReg, dest: VirtualReg, idx: usize) {
         let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
         let a = self.map_reg_use(a, idx);
         let b = self.map_reg_use(b, idx);
 
-        self.asm.emit(instr::Sub {
+        if !self.asm.options.overflow_checks {
+            self.asm.emit(instr::Mul { a, b, dest });
+            self.asm.emit_stack_store(stack_ptr, dest);
+            return;
+        }
+
+        // MUL sets no flags, so the product overflowed if its upper half is not just the sign
+        // of the lower half
+        self.asm.emit(instr::Smulh {
             a,
-            b: instr::Input::Reg(b),
-            dest,
+            b,
+            dest: Register::X16,
         });
+        self.asm.emit(instr::Mul { a, b, dest });
+        self.asm.emit(instr::Asr {
+            src: dest,
+            shift: u6::new(63),
+            dest: Register::X17,
+        });
+        self.asm.emit(instr::Cmp {
+            a: Register::X16,
+            b: instr::Input::Reg(Register::X17),
+        });
+        self.emit_overflow_trap(Condition::NotEqual);
         self.asm.emit_stack_store(stack_ptr, dest);
     }
 
-    fn emit_mul(&mut self, a: VirtualReg, b: VirtualReg, dest: VirtualReg, idx: usize) {
+    fn emit_div(
+        &mut self,
+        a: VirtualReg,
+        b: VirtualReg,
+        dest: VirtualReg,
+        signed: bool,
+        idx: usize,
+    ) {
         let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
         let a = self.map_reg_use(a, idx);
         let b = self.map_reg_use(b, idx);
 
-        self.asm.emit(instr::Mul { a, b, dest });
+        self.asm.emit(instr::Div { a, b, dest, signed });
         self.asm.emit_stack_store(stack_ptr, dest);
     }
 
-    fn emit_div(&mut self, a: VirtualReg, b: VirtualReg, dest: VirtualReg, idx: usize) {
+    /// Divides into the scratch register X16, and subtracts the quotient times the divisor
+    /// from the dividend.
+    fn emit_mod(
+        &mut self,
+        a: VirtualReg,
+        b: VirtualReg,
+        dest: VirtualReg,
+        signed: bool,
+        idx: usize,
+    ) {
         let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
         let a = self.map_reg_use(a, idx);
         let b = self.map_reg_use(b, idx);
@@ -482,12 +962,56 @@ impl<'c> ScopedEmitter<'c> {
         self.asm.emit(instr::Div {
             a,
             b,
+            dest: Register::X16,
+            signed,
+        });
+        self.asm.emit(instr::Msub {
+            a: Register::X16,
+            b,
+            minuend: a,
             dest,
-            signed: true,
         });
         self.asm.emit_stack_store(stack_ptr, dest);
     }
 
+    fn emit_float_arith(
+        &mut self,
+        op: FloatOp,
+        a: VirtualReg,
+        b: VirtualReg,
+        dest: VirtualReg,
+        idx: usize,
+    ) {
+        let a = self.map_freg_use(a, idx);
+        let b = self.map_freg_use(b, idx);
+        let (dest, stack_ptr) = self.map_freg_assign(dest, idx);
+
+        match op {
+            FloatOp::Add => self.asm.emit(instr::FAdd { a, b, dest }),
+            FloatOp::Subtract => self.asm.emit(instr::FSub { a, b, dest }),
+            FloatOp::Multiply => self.asm.emit(instr::FMul { a, b, dest }),
+            FloatOp::Divide => self.asm.emit(instr::FDiv { a, b, dest }),
+        }
+
+        FReg::emit_store(self.asm, stack_ptr, dest);
+    }
+
+    fn emit_float_to_int(&mut self, src: VirtualReg, dest: VirtualReg, idx: usize) {
+        let src = self.map_freg_use(src, idx);
+        let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
+
+        self.asm.emit(instr::Fcvtzs { src, dest });
+        self.asm.emit_stack_store(stack_ptr, dest);
+    }
+
+    fn emit_int_to_float(&mut self, src: VirtualReg, dest: VirtualReg, idx: usize) {
+        let src = self.map_reg_use(src, idx);
+        let (dest, stack_ptr) = self.map_freg_assign(dest, idx);
+
+        self.asm.emit(instr::Scvtf { src, dest });
+        FReg::emit_store(self.asm, stack_ptr, dest);
+    }
+
     fn emit_cmp(
         &mut self,
         a: VirtualReg,
@@ -498,27 +1022,50 @@ impl<'c> ScopedEmitter<'c> {
     ) {
         let a = self.map_reg_use(a, idx);
         let b = self.map_reg_use(b, idx);
-        let dest = self.map_reg_use(dest, idx);
+        let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
 
-        self.asm.emit(instr::Cmp { a, b });
-        self.asm.emit(instr::BranchCond {
-            cond,
-            offset: i19::new(3),
+        self.asm.emit(instr::Cmp {
+            a,
+            b: instr::Input::Reg(b),
+        });
+        self.asm.emit(instr::Cset { cond, dest });
+        self.asm.emit_stack_store(stack_ptr, dest);
+    }
+
+    fn emit_select(
+        &mut self,
+        cond: VirtualReg,
+        a: VirtualReg,
+        b: VirtualReg,
+        dest: VirtualReg,
+        idx: usize,
+    ) {
+        let cond = self.map_reg_use(cond, idx);
+        let a = self.map_reg_use(a, idx);
+        let b = self.map_reg_use(b, idx);
+        let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
+
+        self.asm.emit(instr::Cmp {
+            a: cond,
+            b: instr::Input::Reg(Register::XZR),
         });
-        self.asm.emit_movz(1, dest);
-        self.asm.emit(instr::Branch {
-            offset: i26::new(2),
+        self.asm.emit(instr::Csel {
+            cond: Condition::NotEqual,
+            a,
+            b,
+            dest,
         });
-        self.asm.emit_movz(0, dest);
+        self.asm.emit_stack_store(stack_ptr, dest);
     }
 
     fn emit_branch_if(&mut self, cond: VirtualReg, label: Label, idx: OpIndex) {
         let cond = self.map_reg_use(cond, idx);
 
         let instr_idx = self.asm.current_offset();
-        self.lazy_emit(label, move |offset| instr::BranchNotZero {
-            addr: i19::new((offset as i32 - instr_idx as i32) / 4),
+        self.lazy_emit(label, move |offset| instr::CompareBranchZero {
             reg: cond,
+            nonzero: true,
+            addr: i19::new((offset as i32 - instr_idx as i32) / 4),
         });
     }
 
@@ -526,9 +1073,97 @@ impl<'c> ScopedEmitter<'c> {
         let cond = self.map_reg_use(cond, idx);
 
         let instr_idx = self.asm.current_offset();
-        self.lazy_emit(label, move |offset| instr::BranchZero {
-            addr: i19::new((offset as i32 - instr_idx as i32) / 4),
+        self.lazy_emit(label, move |offset| instr::CompareBranchZero {
             reg: cond,
+            nonzero: false,
+            addr: i19::new((offset as i32 - instr_idx as i32) / 4),
+        });
+    }
+
+    /// Jumps through a table of branches when the cases are dense, and compares against each
+    /// case in turn otherwise.
+    fn emit_switch(
+        &mut self,
+        value: VirtualReg,
+        cases: Vec<(i64, Label)>,
+        default: Label,
+        idx: OpIndex,
+    ) {
+        let value = self.map_reg_use(value, idx);
+
+        let min = cases.iter().map(|&(n, _)| n).min().unwrap_or(0);
+        let max = cases.iter().map(|&(n, _)| n).max().unwrap_or(0);
+        let table_len = max.wrapping_sub(min).wrapping_add(1) as u64;
+
+        let dense =
+            cases.len() >= JUMP_TABLE_MIN_CASES && table_len <= 2 * cases.len() as u64 && min >= 0;
+        let min_imm = AddImm::new(min as u64);
+
+        let (true, Some(min_imm)) = (dense, min_imm) else {
+            for (n, label) in cases {
+                let b = match u64::try_from(n).ok().and_then(AddImm::new) {
+                    Some(imm) => instr::Input::Imm(imm),
+                    None => {
+                        self.asm.emit_imm64(n, Reg::X16);
+                        instr::Input::Reg(Reg::X16)
+                    }
+                };
+                self.asm.emit(instr::Cmp { a: value, b });
+                self.emit_branch_cond(Condition::Equal, label);
+            }
+
+            self.emit_jump(default);
+            return;
+        };
+
+        // X16 = value - min, which is out of range unsigned if value < min
+        self.asm.emit(instr::Sub {
+            a: value,
+            b: instr::Input::Imm(min_imm),
+            dest: Reg::X16,
+        });
+        self.asm.emit_imm64(table_len as i64, Reg::X17);
+        self.asm.emit(instr::Cmp {
+            a: Reg::X16,
+            b: instr::Input::Reg(Reg::X17),
+        });
+        self.emit_branch_cond(Condition::UnsignedGreaterOrEqual, default);
+
+        // each entry is a single branch, 4 bytes
+        for _ in 0..2 {
+            self.asm.emit(instr::Add {
+                a: Reg::X16,
+                b: instr::Input::Reg(Reg::X16),
+                dest: Reg::X16,
+            });
+        }
+        self.asm.emit(instr::Adr {
+            offset: i21::new(12),
+            dest: Reg::X17,
+        });
+        self.asm.emit(instr::Add {
+            a: Reg::X17,
+            b: instr::Input::Reg(Reg::X16),
+            dest: Reg::X17,
+        });
+        self.asm.emit(instr::BranchReg { reg: Reg::X17 });
+
+        for n in min..=max {
+            let label = cases
+                .iter()
+                .find(|&&(case, _)| case == n)
+                .map_or(default, |&(_, label)| label);
+            self.emit_jump(label);
+        }
+    }
+
+    /// Branches to `label` if the flags satisfy `cond`.
+    fn emit_branch_cond(&mut self, cond: Condition, label: Label) {
+        let instr_idx = self.asm.current_offset();
+        self.lazy_emit(label, move |offset| instr::BranchCond {
+            // BranchCond branches when its condition does not hold
+            cond: cond.inverted(),
+            offset: i19::new((offset as i32 - instr_idx as i32) / 4),
         });
     }
 
@@ -542,7 +1177,8 @@ impl<'c> ScopedEmitter<'c> {
 
     fn emit_return(&mut self, src: SourceVal, idx: usize) {
         match src {
-            SourceVal::Immediate(n) => self.asm.emit_movz(n, Reg::X0),
+            SourceVal::VReg(_) if idx > 0 && self.returned_calls.contains(&(idx - 1)) => {}
+            SourceVal::Immediate(n) => self.asm.emit_imm64(n, Reg::X0),
             SourceVal::VReg(vreg) => {
                 let src = self.map_reg_use(vreg, idx);
                 self.asm.emit(instr::MovReg { src, dest: Reg::X0 });
@@ -553,6 +1189,42 @@ impl<'c> ScopedEmitter<'c> {
         self.emit_jump(Label::FnRet);
     }
 
+    /// Returns the two fields of a record in X0 and X1.
+    fn emit_return_pair(&mut self, first: VirtualReg, second: VirtualReg, idx: usize) {
+        let first = self.map_reg_use(first, idx);
+        let second = self.map_reg_use(second, idx);
+
+        let mut moves = [(first, Reg::X0), (second, Reg::X1)];
+        if second == Reg::X0 {
+            // move the second field out of the way first
+            moves.reverse();
+            if first == Reg::X1 {
+                self.asm.emit(instr::MovReg {
+                    src: Reg::X1,
+                    dest: Reg::X16,
+                });
+                moves[1].0 = Reg::X16;
+            }
+        }
+
+        for (src, dest) in moves {
+            if src != dest {
+                self.asm.emit(instr::MovReg { src, dest });
+            }
+        }
+
+        self.emit_jump(Label::FnRet);
+    }
+
+    /// Takes the second field returned by the call right before, from X1. The allocator gives
+    /// the result of a call the last caller-saved register, so X1 has not been overwritten.
+    fn emit_second_result(&mut self, dest: VirtualReg, idx: usize) {
+        let (dest, stack_ptr) = self.map_reg_assign(dest, idx);
+
+        self.asm.emit(instr::MovReg { src: Reg::X1, dest });
+        self.asm.emit_stack_store(stack_ptr, dest);
+    }
+
     fn lazy_emit<F, I>(&mut self, label: Label, emit: F)
     where
         F: FnOnce(InstrIndex) -> I + 'static,
@@ -568,10 +1240,497 @@ impl<'c> ScopedEmitter<'c> {
     }
 
     pub fn end(mut self) {
-        self.mapped_labels
-            .insert(Label::FnRet, self.asm.current_offset());
+        let end = self.asm.current_offset();
+        self.mapped_labels.insert(Label::FnRet, end);
+
+        // labels placed after the last operation point to the epilogue
+        for label in self.ir_labels.values().flatten() {
+            self.mapped_labels.entry(*label).or_insert(end);
+        }
+
         for lazy_emit in std::mem::take(&mut self.lazy_emits) {
             lazy_emit(&mut self);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{generate, optimize::Optimizations};
+
+    /// Assembles `source` and returns the encoded instructions.
+    fn assemble(source: &str) -> Vec<u32> {
+        let code = ArmAssembler::assemble(generate(source)).unwrap();
+        let size = code.size();
+        code.finalize(size, 0)
+            .instructions
+            .chunks(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect()
+    }
+
+    /// Matches CMP against both a register and an immediate.
+    fn is_cmp(instr: u32) -> bool {
+        instr & 0xff20001f == 0xeb00001f || instr & 0xff80001f == 0xf100001f
+    }
+
+    fn is_cbz(instr: u32) -> bool {
+        instr & 0xff000000 == 0xb4000000
+    }
+
+    fn is_cbnz(instr: u32) -> bool {
+        instr & 0xff000000 == 0xb5000000
+    }
+
+    /// Matches MOV between two general-purpose registers.
+    fn is_mov_reg(instr: u32) -> bool {
+        instr & 0xffe0ffe0 == 0xaa0003e0
+    }
+
+    #[test]
+    fn copy_of_dead_variable_is_not_moved() {
+        let code = assemble("fn main() -> i64 { a := 2; b := a; return b; }");
+
+        // only the move of the return value into x0 is left
+        let moves: Vec<u32> = code.into_iter().filter(|&i| is_mov_reg(i)).collect();
+        assert_eq!(moves.len(), 1, "{:08x?}", moves);
+        assert_eq!(moves[0] & 0x1f, 0);
+    }
+
+    #[test]
+    fn compare_with_zero_branches_on_value() {
+        let code = assemble(
+            "
+            fn main() -> i64 {
+                x := 3;
+                if x != 0 {
+                    x = x - 1;
+                }
+                while x != 0 {
+                    x = x - 1;
+                }
+                return x;
+            }
+            ",
+        );
+
+        assert!(!code.iter().any(|&i| is_cmp(i)));
+        // the if skips its body with CBZ, the while loops back with CBNZ
+        assert_eq!(code.iter().filter(|&&i| is_cbz(i)).count(), 1);
+        assert_eq!(code.iter().filter(|&&i| is_cbnz(i)).count(), 1);
+    }
+
+    #[test]
+    fn equal_to_zero_inverts_branch() {
+        let code = assemble(
+            "
+            fn main() -> i64 {
+                x := 3;
+                if x == 0 {
+                    x = 1;
+                }
+                return x;
+            }
+            ",
+        );
+
+        assert!(!code.iter().any(|&i| is_cmp(i)));
+        assert_eq!(code.iter().filter(|&&i| is_cbnz(i)).count(), 1);
+    }
+
+    #[test]
+    fn reused_assembler_matches_fresh() {
+        let sources = [
+            "fn main() -> i64 { x := 2; return add(x, 3); } fn add(a: i64, b: i64) -> i64 { return a + b; }",
+            "fn main() -> i64 { x := 10; while x != 0 { x = x - 1; } return x; }",
+        ];
+
+        let mut asm = ArmAssembler::default();
+        for source in sources {
+            let fresh = ArmAssembler::assemble(generate(source))
+                .unwrap()
+                .finalize(0, 0);
+
+            let (reused, recycled) = asm
+                .assemble_with(generate(source))
+                .unwrap()
+                .finalize_reusable(0, 0);
+            asm = recycled;
+
+            assert_eq!(reused.instructions, fresh.instructions);
+            assert_eq!(reused.stubs_offset, fresh.stubs_offset);
+        }
+    }
+
+    #[test]
+    fn large_addend_uses_shifted_immediate() {
+        let code = assemble("fn main() -> i64 { a := 1; b := a + 8192; return b - 4096; }");
+
+        // add xN, xM, #2, lsl #12 and sub xN, xM, #1, lsl #12
+        assert!(code.iter().any(|&i| i & 0xfffffc00 == 0x91400800));
+        assert!(code.iter().any(|&i| i & 0xfffffc00 == 0xd1400400));
+    }
+
+    #[test]
+    fn wide_immediate_is_built_from_halfwords() {
+        let code = assemble("fn main() -> i64 { return 4294967297; }");
+
+        // movz x0, #1 and movk x0, #1, lsl #32, skipping the zero halfwords in between
+        let start = code.iter().position(|&i| i == 0xd2800020).unwrap();
+        assert_eq!(code[start + 1], 0xf2c00020);
+        assert_ne!(code[start + 2] & 0xff800000, 0xf2800000);
+
+        // movz x0, #0x1170 and movk x0, #1, lsl #16
+        let code = assemble("fn main() -> i64 { return 70000; }");
+        let start = code.iter().position(|&i| i == 0xd2822e00).unwrap();
+        assert_eq!(code[start + 1], 0xf2a00020);
+    }
+
+    #[test]
+    fn remainder_divides_then_subtracts() {
+        let code = assemble("fn main() -> i64 { a := 17; return a % 5; }");
+
+        // sdiv x16, a, b followed by msub dest, x16, b, a
+        let div = code
+            .iter()
+            .position(|&i| i & 0xffe0fc1f == 0x9ac00c10)
+            .unwrap();
+        let msub = code[div + 1];
+        assert_eq!(msub & 0xffe08000, 0x9b008000);
+        assert_eq!((msub >> 5) & 0x1f, 16);
+        assert_eq!((msub >> 16) & 0x1f, (code[div] >> 16) & 0x1f);
+        assert_eq!((msub >> 10) & 0x1f, (code[div] >> 5) & 0x1f);
+    }
+
+    #[test]
+    fn call_arguments_are_put_in_argument_registers() {
+        let code = assemble(
+            "
+            @noinline fn add(a: i64, b: i64) -> i64 => a + b;
+            fn main() -> i64 { x := 4; return add(x, 3); }
+            ",
+        );
+
+        // mov x0, xN for the variable, then mov x1, #3 for the constant, right before the call
+        let mov = code.iter().position(|&i| i == 0xd2800061).unwrap();
+        assert_eq!(code[mov - 1] & 0xffe0ffff, 0xaa0003e0);
+        assert_eq!(code[mov + 1] & 0xfc000000, 0x94000000);
+    }
+
+    #[test]
+    fn returned_call_result_stays_in_x0() {
+        let code = assemble(
+            "
+            fn main() -> i64 { return seven(); }
+            @noinline fn seven() -> i64 => 7;
+            ",
+        );
+
+        // mov x0, xN and mov xN, x0
+        let moves_x0 = |i: u32| i & 0xffe0ffff == 0xaa0003e0 || i & 0xffffffe0 == 0xaa0003e0;
+        let call = code.iter().position(|&i| i >> 26 == 0b100101).unwrap();
+        let ret = call
+            + code[call..]
+                .iter()
+                .position(|&i| i == instr::Ret.encode())
+                .unwrap();
+
+        // the call is followed by the branch to the epilogue
+        assert_eq!(code[call + 1] >> 26, 0b000101);
+        assert!(!code[call..ret].iter().any(|&i| moves_x0(i)));
+    }
+
+    #[test]
+    fn print_is_emitted_only_when_called() {
+        let is_svc = |&&i: &&u32| i == 0xd4001001;
+        let without = assemble("fn main() -> i64 { return 0; }");
+        let with = assemble("fn main() -> i64 { println(7); println(8); return 0; }");
+
+        assert_eq!(
+            with.iter().filter(is_svc).count(),
+            without.iter().filter(is_svc).count() + 1
+        );
+    }
+
+    #[test]
+    fn negative_immediate_starts_with_movn() {
+        // movn x0, #1
+        let code = assemble("fn main() -> i64 { return -2; }");
+        let start = code.iter().position(|&i| i == 0x92800020).unwrap();
+        assert_ne!(code[start + 1] & 0xff800000, 0xf2800000);
+
+        // movn x0, #0 and movk x0, #0xfffe, lsl #32
+        let code = assemble("fn main() -> i64 { return -4294967297; }");
+        let start = code.iter().position(|&i| i == 0x92800000).unwrap();
+        assert_eq!(code[start + 1], 0xf2dfffc0);
+    }
+
+    #[test]
+    fn self_assignment_emits_nothing() {
+        let with = assemble("fn main(x: i64) -> i64 { x = x; x; return x; }");
+        let without = assemble("fn main(x: i64) -> i64 { return x; }");
+
+        assert_eq!(with, without);
+    }
+
+    fn is_br(instr: u32) -> bool {
+        instr & 0xfffffc1f == 0xd61f0000
+    }
+
+    #[test]
+    fn dense_switch_uses_jump_table() {
+        let code = assemble(
+            "
+            fn main(x: i64) -> i64 {
+                switch x {
+                    0 => { return 10; },
+                    1 => { return 20; },
+                    3 => { return 40; },
+                    4 => { return 50; },
+                    _ => { return 99; },
+                }
+                return 0;
+            }
+            ",
+        );
+
+        assert_eq!(code.iter().filter(|&&i| is_br(i)).count(), 1);
+        assert_eq!(code.iter().filter(|&&i| is_cmp(i)).count(), 1);
+    }
+
+    #[test]
+    fn sparse_switch_uses_compare_chain() {
+        let code = assemble(
+            "
+            fn main(x: i64) -> i64 {
+                switch x {
+                    1 => { return 10; },
+                    100 => { return 20; },
+                    1000 => { return 30; },
+                    5000 => { return 40; },
+                }
+                return 0;
+            }
+            ",
+        );
+
+        assert!(!code.iter().any(|&i| is_br(i)));
+        assert_eq!(code.iter().filter(|&&i| is_cmp(i)).count(), 4);
+    }
+
+    #[test]
+    fn every_return_tears_down_its_own_frame() {
+        let code = assemble(
+            "
+            fn main() -> i64 { return leaf() + f(1) + f(5); }
+            fn leaf() -> i64 { return 1; }
+            fn f(x: i64) -> i64 { if x < 3 { return x; } return x * 2; }
+            ",
+        );
+
+        let sp_imm = |instr: u32, opcode: u32| {
+            (instr & 0xffc003ff == opcode | (31 << 5) | 31).then_some((instr >> 10) & 0xfff)
+        };
+        let sub_sp = |instr| sp_imm(instr, 0xd1000000);
+        let add_sp = |instr| sp_imm(instr, 0x91000000);
+        let is_ret = |instr: u32| instr == instr::Ret.encode();
+
+        assert!(code.iter().any(|&i| sub_sp(i).is_some()));
+
+        let mut frame = None;
+        for (i, &instr) in code.iter().enumerate() {
+            if let Some(size) = sub_sp(instr) {
+                frame = Some(size);
+            } else if is_ret(instr) {
+                // ADD SP, LDP, RET
+                assert_eq!(add_sp(code[i - 2]), frame.take(), "at instruction {}", i);
+            }
+        }
+    }
+
+    #[test]
+    fn functions_are_aligned() {
+        let ir = generate(
+            "
+            fn main() -> i64 { return one() + two(); }
+            fn one() -> i64 { return 1; }
+            fn two() -> i64 { x := one(); return x + 1; }
+            ",
+        );
+
+        let options = AssembleOptions {
+            function_alignment: 16,
+            ..Default::default()
+        };
+        let code = ArmAssembler::assemble_with_options(ir, options)
+            .unwrap()
+            .finalize(0, 0);
+
+        assert!(code.symbols.len() > 3);
+        for (name, offset) in code.symbols.iter() {
+            assert_eq!(offset % 16, 0, "{} is not aligned", name);
+        }
+
+        // calls still land on function entries
+        for (i, bytes) in code.instructions.chunks(4).enumerate() {
+            let instr = u32::from_le_bytes(bytes.try_into().unwrap());
+            if instr >> 26 == 0b100101 {
+                let rel = (instr << 6) as i32 >> 6;
+                let target = (i as i64 + rel as i64) as u64 * 4;
+                assert!(code.symbols.iter().any(|&(_, offset)| offset == target));
+            }
+        }
+    }
+
+    #[test]
+    fn freestanding_code_makes_no_syscalls() {
+        let options = AssembleOptions {
+            emit_exit: false,
+            ..Default::default()
+        };
+        let ir = generate("fn main() -> i64 { return 3; }");
+        let code = ArmAssembler::assemble_with_options(ir, options)
+            .unwrap()
+            .finalize(0, 0);
+
+        let is_svc = |instr: u32| instr & 0xffe0001f == 0xd4000001;
+        assert!(
+            !code
+                .instructions
+                .chunks(4)
+                .any(|bytes| is_svc(u32::from_le_bytes(bytes.try_into().unwrap())))
+        );
+
+        // main is entered directly, and returns to whoever called it
+        let symbol = |name: &str| code.symbols.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(symbol("_entry_point"), symbol(MAIN_FN));
+    }
+
+    #[test]
+    fn overflow_checks_trap_after_arithmetic() {
+        let source = "fn f(a: i64, b: i64) -> i64 { return (a + b) * (a - b); }
+                      fn main() -> i64 { return f(1, 2); }";
+        let brks = |overflow_checks| {
+            let options = AssembleOptions {
+                overflow_checks,
+                ..Default::default()
+            };
+            let code = ArmAssembler::assemble_with_options(generate(source), options)
+                .unwrap()
+                .finalize(0, 0);
+            code.instructions
+                .chunks(4)
+                .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+                .filter(|&instr| instr == instr::Brk { imm: 1 }.encode())
+                .count()
+        };
+
+        assert_eq!(brks(false), 0);
+        assert_eq!(brks(true), 3);
+    }
+
+    #[test]
+    fn propagated_constant_is_returned_directly() {
+        let mut ir = generate("fn main() -> i64 { a := 5; return a; }");
+        ir.optimize(Optimizations {
+            propagate_constants: true,
+            ..Default::default()
+        });
+        let code = ArmAssembler::assemble(ir).unwrap().finalize(0, 0);
+
+        let main = code
+            .symbols
+            .iter()
+            .find(|(name, _)| name == MAIN_FN)
+            .unwrap()
+            .1 as usize;
+        let code: Vec<u32> = code.instructions[main..]
+            .chunks(4)
+            .map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+            .collect();
+
+        // stp and mov x29, sp, then movz x0, #5 and the jump to the epilogue, then ldp and ret
+        assert_eq!(code[2], 0xd28000a0);
+        assert_eq!(code[3] >> 26, 0b000101);
+        assert_eq!(code[5], instr::Ret.encode());
+    }
+
+    #[test]
+    fn dead_functions_are_not_emitted() {
+        let size = |source: &str| {
+            let mut ir = generate(source);
+            ir.optimize(Optimizations {
+                eliminate_dead_functions: true,
+                ..Default::default()
+            });
+            let code = ArmAssembler::assemble(ir).unwrap();
+            (code.size(), code.finalize(0, 0).symbols)
+        };
+
+        let main = "fn main() -> i64 { return used(); } fn used() -> i64 { return 1; }";
+        let with_dead = format!(
+            "{} fn unused() -> i64 {{ return dead(); }} fn dead() -> i64 {{ return 2; }}",
+            main
+        );
+
+        let (size_with_dead, symbols) = size(&with_dead);
+        assert_eq!(size_with_dead, size(main).0);
+        assert!(symbols.iter().any(|(name, _)| name == "used"));
+        assert!(
+            !symbols
+                .iter()
+                .any(|(name, _)| name == "unused" || name == "dead")
+        );
+    }
+
+    #[test]
+    fn reports_every_codegen_error() {
+        let params: Vec<String> = (0..9).map(|i| format!("a{}: i64", i)).collect();
+        let defs: String = (0..600).map(|i| format!("x{} := {}; ", i, i)).collect();
+        let uses: String = (0..600).map(|i| format!("s = s + x{}; ", i)).collect();
+
+        let source = format!(
+            "
+            extern libc;
+            fn ext({}) -> i64;
+            fn big() -> i64 {{ {} s := 0; {} return s; }}
+            fn main() -> i64 {{ x := big(); return ext(1, 2, 3, 4, 5, 6, 7, 8, 9); }}
+            ",
+            params.join(", "),
+            defs,
+            uses
+        );
+
+        // both the oversized frame and the call with too many arguments are reported
+        let Err(errors) = ArmAssembler::assemble(generate(&source)) else {
+            panic!("assembly should fail");
+        };
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn reports_errors_without_a_source() {
+        // like operations made by inlining, which have no source of their own
+        let mut ir = generate(
+            "
+            extern libc;
+            fn ext(a: i64, b: i64, c: i64, d: i64, e: i64, f: i64, g: i64, h: i64, i: i64) -> i64;
+            fn main() -> i64 { return ext(1, 2, 3, 4, 5, 6, 7, 8, 9); }
+            ",
+        );
+        for Item::Function { bb, .. } in &mut ir.items {
+            bb.spans.fill(None);
+        }
+        let Err(errors) = ArmAssembler::assemble(ir) else {
+            panic!("assembly should fail");
+        };
+        assert_eq!(errors.len(), 1);
+
+        // the entry point calls main, which is not in the source
+        let Err(errors) = ArmAssembler::assemble(generate("fn f() -> i64 { return 1; }")) else {
+            panic!("assembly should fail");
+        };
+        assert_eq!(errors.len(), 1);
+    }
+}
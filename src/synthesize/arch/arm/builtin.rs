@@ -1,17 +1,22 @@
-use ux::u12;
+use ux::{i9, i19, u9, u12};
 
-use crate::synthesize::arch::{
-    Assembler,
-    arm::{
-        instr::{self, ImmShift16},
-        reg::Reg,
+use crate::{
+    ir::{Condition, SourceVal, VirtualReg},
+    synthesize::arch::{
+        Assembler,
+        arm::{
+            instr::{self, ImmShift16},
+            reg::{Reg, Register},
+        },
     },
 };
 
-use super::ArmAssembler;
+use super::{ArmAssembler, ScopedEmitter};
 
 type BuiltinFn = fn(&mut ArmAssembler);
 
+type IntrinsicFn = fn(&mut ScopedEmitter, &[SourceVal], Option<VirtualReg>, usize);
+
 const PREFIX: &str = "std::";
 
 const PAGE_SIZE: u64 = 16384;
@@ -20,10 +25,149 @@ pub fn assemble(asm: &mut ArmAssembler) {
     let builtins: &[(&str, BuiltinFn)] = &[("exit", exit), ("write", write)];
 
     for (name, assemble_fn) in builtins {
-        asm.functions
-            .insert(format!("{}{}", PREFIX, name), asm.current_offset());
+        let name = format!("{}{}", PREFIX, name);
+        // freestanding code has no system to call into unless asked to
+        if !asm.options.emit_exit && !asm.fn_calls.iter().any(|(function, ..)| *function == name) {
+            continue;
+        }
+
+        asm.align_function();
+        asm.functions.insert(name, asm.current_offset());
         assemble_fn(asm);
     }
+
+    // too large to emit inline at every call, so they are emitted once, and only when called
+    let prelude: &[(&str, BuiltinFn)] = &[("print", print), ("println", println)];
+
+    for (name, assemble_fn) in prelude {
+        if asm.fn_calls.iter().any(|(function, ..)| function == name) {
+            asm.align_function();
+            asm.functions.insert(name.to_string(), asm.current_offset());
+            assemble_fn(asm);
+        }
+    }
+}
+
+/// Looks up a function that is emitted inline at the call site instead of being called.
+pub(super) fn intrinsic(name: &str) -> Option<IntrinsicFn> {
+    let intrinsic: IntrinsicFn = match name {
+        "assert" => assert,
+        "abs" => abs,
+        "min" => min,
+        "max" => max,
+        "strlen" => strlen,
+        _ => return None,
+    };
+
+    Some(intrinsic)
+}
+
+/// Traps with `BRK #0` if the condition is zero, otherwise does nothing.
+fn assert(emitter: &mut ScopedEmitter, args: &[SourceVal], _dest: Option<VirtualReg>, idx: usize) {
+    let cond = emitter.map_source_use(args[0], idx, Register::X16);
+
+    emitter.asm.emit(instr::CompareBranchZero {
+        reg: cond,
+        nonzero: true,
+        addr: i19::new(2),
+    });
+    emitter.asm.emit(instr::Brk { imm: 0 });
+}
+
+/// Negates the argument if it is below zero.
+fn abs(emitter: &mut ScopedEmitter, args: &[SourceVal], dest: Option<VirtualReg>, idx: usize) {
+    let src = emitter.map_source_use(args[0], idx, Register::X16);
+    let Some(dest) = dest else {
+        return;
+    };
+    let (dest, stack_ptr) = emitter.map_reg_assign(dest, idx);
+
+    emitter.asm.emit(instr::Cmp {
+        a: src,
+        b: instr::Input::Reg(Register::XZR),
+    });
+    emitter.asm.emit(instr::Cneg {
+        cond: Condition::SignedLess,
+        src,
+        dest,
+    });
+    emitter.asm.emit_stack_store(stack_ptr, dest);
+}
+
+fn min(emitter: &mut ScopedEmitter, args: &[SourceVal], dest: Option<VirtualReg>, idx: usize) {
+    select(emitter, args, dest, idx, Condition::SignedLess);
+}
+
+fn max(emitter: &mut ScopedEmitter, args: &[SourceVal], dest: Option<VirtualReg>, idx: usize) {
+    select(emitter, args, dest, idx, Condition::SignedGreater);
+}
+
+/// Compares the two arguments and picks the first one if `cond` holds, the second otherwise.
+fn select(
+    emitter: &mut ScopedEmitter,
+    args: &[SourceVal],
+    dest: Option<VirtualReg>,
+    idx: usize,
+    cond: Condition,
+) {
+    let a = emitter.map_source_use(args[0], idx, Register::X16);
+    let b = emitter.map_source_use(args[1], idx, Register::X17);
+    let Some(dest) = dest else {
+        return;
+    };
+    let (dest, stack_ptr) = emitter.map_reg_assign(dest, idx);
+
+    emitter.asm.emit(instr::Cmp {
+        a,
+        b: instr::Input::Reg(b),
+    });
+    emitter.asm.emit(instr::Csel { cond, a, b, dest });
+    emitter.asm.emit_stack_store(stack_ptr, dest);
+}
+
+/// Counts the bytes before the terminating zero of a string.
+fn strlen(emitter: &mut ScopedEmitter, args: &[SourceVal], dest: Option<VirtualReg>, idx: usize) {
+    let start = emitter.map_source_use(args[0], idx, Register::X16);
+    emitter.asm.emit(instr::MovReg {
+        src: start,
+        dest: Register::X17,
+    });
+
+    // X17 walks the string, and ends up one past the zero
+    emitter.asm.emit(instr::LoadByte {
+        base: Register::X17,
+        offset: u9::new(0),
+        dest: Register::X16,
+    });
+    emitter.asm.emit(instr::Add {
+        a: Register::X17,
+        b: instr::Input::Imm(u12::new(1).into()),
+        dest: Register::X17,
+    });
+    emitter.asm.emit(instr::CompareBranchZero {
+        reg: Register::X16,
+        nonzero: true,
+        addr: i19::new(-2),
+    });
+
+    let Some(dest) = dest else {
+        return;
+    };
+    // a literal was put in X16, which the loop overwrote, so it is put there again
+    let start = emitter.map_source_use(args[0], idx, Register::X16);
+    let (dest, stack_ptr) = emitter.map_reg_assign(dest, idx);
+
+    emitter.asm.emit(instr::Sub {
+        a: Register::X17,
+        b: instr::Input::Reg(start),
+        dest,
+    });
+    emitter.asm.emit(instr::Sub {
+        a: dest,
+        b: instr::Input::Imm(u12::new(1).into()),
+        dest,
+    });
+    emitter.asm.emit_stack_store(stack_ptr, dest);
 }
 
 pub fn write(asm: &mut ArmAssembler) {
@@ -32,6 +176,112 @@ pub fn write(asm: &mut ArmAssembler) {
     asm.end_stack();
 }
 
+pub fn print(asm: &mut ArmAssembler) {
+    print_decimal(asm, false);
+}
+
+pub fn println(asm: &mut ArmAssembler) {
+    print_decimal(asm, true);
+}
+
+/// Writes the signed integer in X0 to stdout in decimal, optionally followed by a newline. The
+/// digits are written backwards into a buffer on the stack, from the lowest one up.
+fn print_decimal(asm: &mut ArmAssembler, newline: bool) {
+    // room for "-9223372036854775808\n"
+    asm.begin_stack(u12::new(3));
+
+    // X9 points at the last byte written, starting at the end of the buffer
+    asm.emit(instr::MovReg {
+        src: Reg::FP,
+        dest: Reg::X9,
+    });
+    let push_byte = |asm: &mut ArmAssembler, register| {
+        asm.emit(instr::StoreByte {
+            base: Reg::X9,
+            offset: i9::new(-1),
+            register,
+        })
+    };
+
+    if newline {
+        asm.emit_imm64(b'\n' as i64, Reg::X14);
+        push_byte(asm, Reg::X14);
+    }
+
+    // the magnitude is divided as unsigned, so that it is right for i64::MIN too
+    asm.emit_imm64(10, Reg::X13);
+    asm.emit(instr::Cmp {
+        a: Reg::X0,
+        b: instr::Input::Reg(Register::XZR),
+    });
+    asm.emit(instr::Cneg {
+        cond: Condition::SignedLess,
+        src: Reg::X0,
+        dest: Reg::X10,
+    });
+
+    // X10 = X10 / 10, and the remainder is pushed as a digit
+    let digit_loop = asm.current_offset();
+    asm.emit(instr::Div {
+        a: Reg::X10,
+        b: Reg::X13,
+        dest: Reg::X12,
+        signed: false,
+    });
+    asm.emit(instr::Mul {
+        a: Reg::X12,
+        b: Reg::X13,
+        dest: Reg::X15,
+    });
+    asm.emit(instr::Sub {
+        a: Reg::X10,
+        b: instr::Input::Reg(Reg::X15),
+        dest: Reg::X14,
+    });
+    asm.emit(instr::Add {
+        a: Reg::X14,
+        b: instr::Input::Imm(u12::new(b'0' as u16).into()),
+        dest: Reg::X14,
+    });
+    push_byte(asm, Reg::X14);
+    asm.emit(instr::MovReg {
+        src: Reg::X12,
+        dest: Reg::X10,
+    });
+    asm.emit(instr::CompareBranchZero {
+        reg: Reg::X10,
+        nonzero: true,
+        addr: i19::new((digit_loop as i32 - asm.current_offset() as i32) / 4),
+    });
+
+    // BranchCond branches when its condition does not hold, skipping the sign
+    asm.emit(instr::Cmp {
+        a: Reg::X0,
+        b: instr::Input::Reg(Register::XZR),
+    });
+    asm.emit(instr::BranchCond {
+        offset: i19::new(3),
+        cond: Condition::SignedLess,
+    });
+    asm.emit_imm64(b'-' as i64, Reg::X14);
+    push_byte(asm, Reg::X14);
+
+    // write(stdout, X9, end - X9)
+    asm.emit_imm64(1, Reg::X0);
+    asm.emit(instr::MovReg {
+        src: Reg::X9,
+        dest: Reg::X1,
+    });
+    asm.emit(instr::Sub {
+        a: Reg::FP,
+        b: instr::Input::Reg(Reg::X9),
+        dest: Reg::X2,
+    });
+    syscall(asm, SyscallType::Write);
+
+    asm.end_stack();
+}
+
 pub fn exit(asm: &mut ArmAssembler) {
     syscall(asm, SyscallType::Exit);
 }
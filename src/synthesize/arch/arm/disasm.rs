@@ -0,0 +1,716 @@
+use std::fmt::Write;
+
+use crate::synthesize::arch::MachineCode;
+
+/// Lists the instructions of `code` in GNU assembler syntax, with a label before the first
+/// instruction of every symbol.
+pub fn listing(code: &MachineCode) -> String {
+    let mut out = String::new();
+
+    for (i, instr) in instructions(code).into_iter().enumerate() {
+        let offset = i as u64 * 4;
+
+        for (name, _) in code.symbols.iter().filter(|&&(_, at)| at == offset) {
+            writeln!(out, "{}:", label(name)).unwrap();
+        }
+        if offset == code.stubs_offset {
+            writeln!(out, "stubs:").unwrap();
+        }
+
+        writeln!(out, "    {}", instr).unwrap();
+    }
+
+    out
+}
+
+/// Disassembles every instruction in `code`, in order, so the instruction at byte offset `n`
+/// is at index `n / 4`.
+pub fn instructions(code: &MachineCode) -> Vec<String> {
+    code.instructions
+        .chunks_exact(4)
+        .map(|word| disassemble(u32::from_le_bytes(word.try_into().unwrap())))
+        .collect()
+}
+
+/// Quotes a symbol name that the assembler would not take as a label as it is, such as the
+/// `std::` builtins.
+fn label(name: &str) -> String {
+    if name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+    {
+        name.to_owned()
+    } else {
+        format!("\"{}\"", name)
+    }
+}
+
+/// Turns an encoded instruction back into text. Only the instructions in
+/// [instr](super::instr) are recognized, and anything else is written as a raw `.inst`.
+///
+/// PC-relative targets are written relative to the instruction, like `b .+8`.
+pub fn disassemble(instr: u32) -> String {
+    let rd = instr & 0x1f;
+    let rn = (instr >> 5) & 0x1f;
+    let rm = (instr >> 16) & 0x1f;
+    let ra = (instr >> 10) & 0x1f;
+
+    // register 31 is the stack pointer as a base or in immediate ADD and SUB, and the zero
+    // register everywhere else
+    let sp = |r: u32| match r {
+        31 => String::from("sp"),
+        r => format!("x{}", r),
+    };
+    let x = |r: u32| match r {
+        31 => String::from("xzr"),
+        r => format!("x{}", r),
+    };
+    let w = |r: u32| match r {
+        31 => String::from("wzr"),
+        r => format!("w{}", r),
+    };
+    let d = |r: u32| format!("d{}", r);
+
+    let add_imm = || {
+        let imm = (instr >> 10) & 0xfff;
+        match (instr >> 22) & 1 {
+            0 => format!("#{}", imm),
+            _ => format!("#{}, lsl #12", imm),
+        }
+    };
+    let wide_imm = || {
+        let imm = (instr >> 5) & 0xffff;
+        match (instr >> 21) & 0b11 {
+            0 => format!("#{}", imm),
+            hw => format!("#{}, lsl #{}", imm, hw * 16),
+        }
+    };
+    let cond = || COND[((instr >> 12) & 0xf) as usize];
+    let inverted_cond = || COND[(((instr >> 12) & 0xf) ^ 1) as usize];
+
+    match instr {
+        0xd503201f => String::from("nop"),
+        0xd65f03c0 => String::from("ret"),
+        _ if instr & 0xfffffc1f == 0xd65f0000 => format!("ret {}", x(rn)),
+        _ if instr & 0xfffffc1f == 0xd61f0000 => format!("br {}", x(rn)),
+        _ if instr & 0xffe0001f == 0xd4000001 => format!("svc #{:#x}", (instr >> 5) & 0xffff),
+        _ if instr & 0xffe0001f == 0xd4200000 => format!("brk #{:#x}", (instr >> 5) & 0xffff),
+
+        _ if instr & 0xfc000000 == 0x14000000 => {
+            format!("b {}", relative(sign_extend(instr, 26) * 4))
+        }
+        _ if instr & 0xfc000000 == 0x94000000 => {
+            format!("bl {}", relative(sign_extend(instr, 26) * 4))
+        }
+        _ if instr & 0xff000010 == 0x54000000 => format!(
+            "b.{} {}",
+            COND[(instr & 0xf) as usize],
+            relative(sign_extend(instr >> 5, 19) * 4)
+        ),
+        _ if instr & 0xfe000000 == 0xb4000000 => format!(
+            "{} {}, {}",
+            if instr & (1 << 24) == 0 {
+                "cbz"
+            } else {
+                "cbnz"
+            },
+            x(rd),
+            relative(sign_extend(instr >> 5, 19) * 4)
+        ),
+        _ if instr & 0x1f000000 == 0x10000000 => {
+            let imm = sign_extend((((instr >> 5) & 0x7ffff) << 2) | ((instr >> 29) & 0b11), 21);
+            match instr >> 31 {
+                0 => format!("adr {}, {}", x(rd), relative(imm)),
+                _ => format!("adrp {}, {}", x(rd), relative(imm * 4096)),
+            }
+        }
+
+        _ if instr & 0xff800000 == 0x91000000 => {
+            if instr & 0x3ffc00 == 0 && (rd == 31 || rn == 31) {
+                format!("mov {}, {}", sp(rd), sp(rn))
+            } else {
+                format!("add {}, {}, {}", sp(rd), sp(rn), add_imm())
+            }
+        }
+        _ if instr & 0xff800000 == 0xd1000000 => {
+            format!("sub {}, {}, {}", sp(rd), sp(rn), add_imm())
+        }
+        _ if instr & 0xff80001f == 0xf100001f => format!("cmp {}, {}", sp(rn), add_imm()),
+        _ if instr & 0xff800000 == 0xb1000000 => {
+            format!("adds {}, {}, {}", x(rd), sp(rn), add_imm())
+        }
+        _ if instr & 0xff800000 == 0xf1000000 => {
+            format!("subs {}, {}, {}", x(rd), sp(rn), add_imm())
+        }
+        _ if instr & 0xffe0fc1f == 0xeb00001f => format!("cmp {}, {}", x(rn), x(rm)),
+        _ if instr & 0xffe0fc00 == 0xab000000 => {
+            format!("adds {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0xeb000000 => {
+            format!("subs {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0x8b000000 => {
+            format!("add {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffe0ffe0 == 0xcb0003e0 => format!("neg {}, {}", x(rd), x(rm)),
+        _ if instr & 0xffe0fc00 == 0xcb000000 => {
+            format!("sub {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffe0ffe0 == 0xaa0003e0 => format!("mov {}, {}", x(rd), x(rm)),
+
+        _ if instr & 0xff800000 == 0xd2800000 => format!("movz {}, {}", x(rd), wide_imm()),
+        _ if instr & 0xff800000 == 0xf2800000 => format!("movk {}, {}", x(rd), wide_imm()),
+        _ if instr & 0xff800000 == 0x92800000 => format!("movn {}, {}", x(rd), wide_imm()),
+
+        _ if instr & 0xffe00c00 == 0x9a800000 => {
+            format!("csel {}, {}, {}, {}", x(rd), x(rn), x(rm), cond())
+        }
+        _ if instr & 0xffff0fe0 == 0x9a9f07e0 => {
+            format!("cset {}, {}", x(rd), inverted_cond())
+        }
+        _ if instr & 0xffe00c00 == 0xda800400 && rn == rm => {
+            format!("cneg {}, {}, {}", x(rd), x(rn), inverted_cond())
+        }
+        _ if instr & 0xffe0fc00 == 0x9ac00800 => {
+            format!("udiv {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0x9ac00c00 => {
+            format!("sdiv {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0x9b007c00 => {
+            format!("mul {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0x9b407c00 => {
+            format!("smulh {}, {}, {}", x(rd), x(rn), x(rm))
+        }
+        _ if instr & 0xffc0fc00 == 0x9340fc00 => {
+            format!("asr {}, {}, #{}", x(rd), x(rn), (instr >> 16) & 0x3f)
+        }
+        _ if instr & 0xffe08000 == 0x9b008000 => {
+            format!("msub {}, {}, {}, {}", x(rd), x(rn), x(rm), x(ra))
+        }
+
+        _ if instr & 0xffc00000 == 0xf9400000 => {
+            format!(
+                "ldr {}, [{}, #{}]",
+                x(rd),
+                sp(rn),
+                ((instr >> 10) & 0xfff) * 8
+            )
+        }
+        _ if instr & 0xffc00000 == 0xf9000000 => {
+            format!(
+                "str {}, [{}, #{}]",
+                x(rd),
+                sp(rn),
+                ((instr >> 10) & 0xfff) * 8
+            )
+        }
+        _ if instr & 0xffe0fc00 == 0xf820e800 => {
+            format!("str {}, [{}, {}, sxtx]", x(rd), sp(rn), x(rm))
+        }
+        _ if instr & 0xffe00c00 == 0x38400400 => format!(
+            "ldrb {}, [{}], #{}",
+            w(rd),
+            sp(rn),
+            sign_extend(instr >> 12, 9)
+        ),
+        _ if instr & 0xffe00c00 == 0x38000c00 => format!(
+            "strb {}, [{}, #{}]!",
+            w(rd),
+            sp(rn),
+            sign_extend(instr >> 12, 9)
+        ),
+        _ if instr & 0xffc00000 == 0xa8c00000 => format!(
+            "ldp {}, {}, [{}], #{}",
+            x(rd),
+            x(ra),
+            sp(rn),
+            sign_extend(instr >> 15, 7) * 8
+        ),
+        _ if instr & 0xffc00000 == 0xa9800000 => format!(
+            "stp {}, {}, [{}, #{}]!",
+            x(rd),
+            x(ra),
+            sp(rn),
+            sign_extend(instr >> 15, 7) * 8
+        ),
+
+        _ if instr & 0xffc00000 == 0xfd400000 => {
+            format!(
+                "ldr {}, [{}, #{}]",
+                d(rd),
+                sp(rn),
+                ((instr >> 10) & 0xfff) * 8
+            )
+        }
+        _ if instr & 0xffc00000 == 0xfd000000 => {
+            format!(
+                "str {}, [{}, #{}]",
+                d(rd),
+                sp(rn),
+                ((instr >> 10) & 0xfff) * 8
+            )
+        }
+        _ if instr & 0xffe0fc00 == 0x1e602800 => {
+            format!("fadd {}, {}, {}", d(rd), d(rn), d(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0x1e603800 => {
+            format!("fsub {}, {}, {}", d(rd), d(rn), d(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0x1e600800 => {
+            format!("fmul {}, {}, {}", d(rd), d(rn), d(rm))
+        }
+        _ if instr & 0xffe0fc00 == 0x1e601800 => {
+            format!("fdiv {}, {}, {}", d(rd), d(rn), d(rm))
+        }
+        _ if instr & 0xfffffc00 == 0x1e604000 => format!("fmov {}, {}", d(rd), d(rn)),
+        _ if instr & 0xfffffc00 == 0x9e670000 => format!("fmov {}, {}", d(rd), x(rn)),
+        _ if instr & 0xfffffc00 == 0x9e660000 => format!("fmov {}, {}", x(rd), d(rn)),
+        _ if instr & 0xfffffc00 == 0x9e780000 => format!("fcvtzs {}, {}", x(rd), d(rn)),
+        _ if instr & 0xfffffc00 == 0x9e620000 => format!("scvtf {}, {}", d(rd), x(rn)),
+
+        _ => format!(".inst {:#010x}", instr),
+    }
+}
+
+/// Condition codes by their encoding.
+const COND: [&str; 16] = [
+    "eq", "ne", "hs", "lo", "mi", "pl", "vs", "vc", "hi", "ls", "ge", "lt", "gt", "le", "al", "nv",
+];
+
+/// Sign-extends the lowest `bits` bits of `value`.
+fn sign_extend(value: u32, bits: u32) -> i64 {
+    let shift = 32 - bits;
+    ((value << shift) as i32 >> shift) as i64
+}
+
+fn relative(offset: i64) -> String {
+    if offset < 0 {
+        format!(".-{}", -offset)
+    } else {
+        format!(".+{}", offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ux::{i7, i9, i19, i21, i26, u6, u9, u12};
+
+    use super::*;
+    use crate::{
+        ir::Condition,
+        synthesize::arch::arm::{
+            instr::*,
+            reg::{FReg, Register, Register::*},
+        },
+    };
+
+    fn text(instr: impl Instruction) -> String {
+        disassemble(instr.encode())
+    }
+
+    #[test]
+    fn arithmetic() {
+        let imm = |n| Input::Imm(AddImm::new(n).unwrap());
+
+        assert_eq!(
+            text(Add {
+                a: X1,
+                b: imm(4),
+                dest: X0
+            }),
+            "add x0, x1, #4"
+        );
+        assert_eq!(
+            text(Add {
+                a: X1,
+                b: imm(8192),
+                dest: X0
+            }),
+            "add x0, x1, #2, lsl #12"
+        );
+        assert_eq!(
+            text(Add {
+                a: X1,
+                b: Input::Reg(X2),
+                dest: X0
+            }),
+            "add x0, x1, x2"
+        );
+        assert_eq!(
+            text(Sub {
+                a: SP,
+                b: imm(16),
+                dest: SP
+            }),
+            "sub sp, sp, #16"
+        );
+        assert_eq!(
+            text(Sub {
+                a: X3,
+                b: Input::Reg(X4),
+                dest: X5
+            }),
+            "sub x5, x3, x4"
+        );
+        assert_eq!(
+            text(Adds {
+                a: X1,
+                b: imm(4),
+                dest: X0
+            }),
+            "adds x0, x1, #4"
+        );
+        assert_eq!(
+            text(Subs {
+                a: X3,
+                b: Input::Reg(X4),
+                dest: X5
+            }),
+            "subs x5, x3, x4"
+        );
+        assert_eq!(text(Neg { src: X9, dest: X5 }), "neg x5, x9");
+        assert_eq!(
+            text(Smulh {
+                a: X1,
+                b: X2,
+                dest: X16
+            }),
+            "smulh x16, x1, x2"
+        );
+        assert_eq!(
+            text(Asr {
+                src: X0,
+                shift: u6::new(63),
+                dest: X17
+            }),
+            "asr x17, x0, #63"
+        );
+        assert_eq!(
+            text(Mul {
+                a: X1,
+                b: X2,
+                dest: X0
+            }),
+            "mul x0, x2, x1"
+        );
+        assert_eq!(
+            text(Msub {
+                a: X1,
+                b: X2,
+                minuend: X3,
+                dest: X0
+            }),
+            "msub x0, x1, x2, x3"
+        );
+        assert_eq!(
+            text(Div {
+                a: X1,
+                b: X2,
+                dest: X0,
+                signed: true
+            }),
+            "sdiv x0, x1, x2"
+        );
+        assert_eq!(
+            text(Div {
+                a: X1,
+                b: X2,
+                dest: X0,
+                signed: false
+            }),
+            "udiv x0, x1, x2"
+        );
+    }
+
+    #[test]
+    fn moves() {
+        assert_eq!(text(MovReg { src: X14, dest: X0 }), "mov x0, x14");
+        assert_eq!(text(MovReg { src: SP, dest: FP }), "mov x29, sp");
+        assert_eq!(
+            text(Movz {
+                shift: ImmShift16::L0,
+                imm_value: 7,
+                dest: X2
+            }),
+            "movz x2, #7"
+        );
+        assert_eq!(
+            text(Movk {
+                shift: ImmShift16::L48,
+                imm_value: 0x4010,
+                dest: X2
+            }),
+            "movk x2, #16400, lsl #48"
+        );
+        assert_eq!(
+            text(Movn {
+                shift: ImmShift16::L16,
+                imm_value: 1,
+                dest: X2
+            }),
+            "movn x2, #1, lsl #16"
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        assert_eq!(
+            text(Cmp {
+                a: X1,
+                b: Input::Reg(Register::XZR)
+            }),
+            "cmp x1, xzr"
+        );
+        assert_eq!(
+            text(Cmp {
+                a: X3,
+                b: Input::Imm(AddImm::new(42).unwrap())
+            }),
+            "cmp x3, #42"
+        );
+        assert_eq!(
+            text(Cset {
+                cond: Condition::SignedLess,
+                dest: X5
+            }),
+            "cset x5, lt"
+        );
+        assert_eq!(
+            text(Cneg {
+                cond: Condition::SignedLess,
+                src: X1,
+                dest: X0
+            }),
+            "cneg x0, x1, lt"
+        );
+        assert_eq!(
+            text(Csel {
+                cond: Condition::SignedGreater,
+                a: X4,
+                b: X5,
+                dest: X3
+            }),
+            "csel x3, x4, x5, gt"
+        );
+    }
+
+    #[test]
+    fn branches() {
+        assert_eq!(text(Ret), "ret");
+        assert_eq!(text(Nop), "nop");
+        assert_eq!(
+            text(Branch {
+                offset: i26::new(1)
+            }),
+            "b .+4"
+        );
+        assert_eq!(text(BranchLink { addr: i26::new(-3) }), "bl .-12");
+        assert_eq!(text(BranchReg { reg: X17 }), "br x17");
+        assert_eq!(text(Brk { imm: 0 }), "brk #0x0");
+        assert_eq!(text(Syscall), "svc #0x80");
+        assert_eq!(
+            text(CompareBranchZero {
+                reg: X3,
+                nonzero: false,
+                addr: i19::new(-2)
+            }),
+            "cbz x3, .-8"
+        );
+        // BranchCond branches when its condition does not hold
+        assert_eq!(
+            text(BranchCond {
+                offset: i19::new(3),
+                cond: Condition::SignedLess
+            }),
+            "b.ge .+12"
+        );
+        assert_eq!(
+            text(Adr {
+                offset: i21::new(12),
+                dest: X17
+            }),
+            "adr x17, .+12"
+        );
+        assert_eq!(
+            text(Adrp {
+                page_addr: i21::new(2),
+                dest: X16
+            }),
+            "adrp x16, .+8192"
+        );
+    }
+
+    #[test]
+    fn memory() {
+        assert_eq!(
+            text(Load {
+                base: SP,
+                offset: u12::new(2),
+                dest: X14
+            }),
+            "ldr x14, [sp, #16]"
+        );
+        assert_eq!(
+            text(Store {
+                base: SP,
+                offset: Input::Imm(u12::new(0)),
+                register: X15
+            }),
+            "str x15, [sp, #0]"
+        );
+        assert_eq!(
+            text(Store {
+                base: X1,
+                offset: Input::Reg(X2),
+                register: X3
+            }),
+            "str x3, [x1, x2, sxtx]"
+        );
+        assert_eq!(
+            text(LoadByte {
+                base: X17,
+                offset: u9::new(0),
+                dest: X16
+            }),
+            "ldrb w16, [x17], #0"
+        );
+        assert_eq!(
+            text(StoreByte {
+                base: X9,
+                offset: i9::new(-1),
+                register: X14
+            }),
+            "strb w14, [x9, #-1]!"
+        );
+        assert_eq!(
+            text(StorePair {
+                base: SP,
+                first: FP,
+                second: LR,
+                offset: i7::new(-2)
+            }),
+            "stp x29, x30, [sp, #-16]!"
+        );
+        assert_eq!(
+            text(LoadPair {
+                base: SP,
+                first: FP,
+                second: LR,
+                offset: i7::new(2)
+            }),
+            "ldp x29, x30, [sp], #16"
+        );
+    }
+
+    #[test]
+    fn floating_point() {
+        let (a, b, dest) = (FReg::V1, FReg::V2, FReg::V0);
+        assert_eq!(text(FAdd { a, b, dest }), "fadd d0, d1, d2");
+        assert_eq!(text(FSub { a, b, dest }), "fsub d0, d1, d2");
+        assert_eq!(text(FMul { a, b, dest }), "fmul d0, d1, d2");
+        assert_eq!(text(FDiv { a, b, dest }), "fdiv d0, d1, d2");
+        assert_eq!(
+            text(FMov {
+                src: FReg::V2,
+                dest: FReg::V1
+            }),
+            "fmov d1, d2"
+        );
+        assert_eq!(
+            text(FMovFromGeneral {
+                src: X3,
+                dest: FReg::V4
+            }),
+            "fmov d4, x3"
+        );
+        assert_eq!(
+            text(FMovToGeneral {
+                src: FReg::V4,
+                dest: X3
+            }),
+            "fmov x3, d4"
+        );
+        assert_eq!(
+            text(Fcvtzs {
+                src: FReg::V1,
+                dest: X0
+            }),
+            "fcvtzs x0, d1"
+        );
+        assert_eq!(
+            text(Scvtf {
+                src: X0,
+                dest: FReg::V1
+            }),
+            "scvtf d1, x0"
+        );
+        assert_eq!(
+            text(FLoad {
+                base: SP,
+                offset: u12::new(2),
+                dest: FReg::V3
+            }),
+            "ldr d3, [sp, #16]"
+        );
+        assert_eq!(
+            text(FStore {
+                base: SP,
+                offset: u12::new(2),
+                register: FReg::V3
+            }),
+            "str d3, [sp, #16]"
+        );
+    }
+
+    #[test]
+    fn machine_code() {
+        let words = [
+            StorePair {
+                base: SP,
+                first: FP,
+                second: LR,
+                offset: i7::new(-2),
+            }
+            .encode(),
+            BranchLink { addr: i26::new(2) }.encode(),
+            Syscall.encode(),
+            LoadPair {
+                base: SP,
+                first: FP,
+                second: LR,
+                offset: i7::new(2),
+            }
+            .encode(),
+            Ret.encode(),
+        ];
+        let code = MachineCode {
+            instructions: words.iter().flat_map(|word| word.to_le_bytes()).collect(),
+            symbols: vec![(String::from("main"), 0), (String::from("std::exit"), 8)],
+            stubs_offset: 20,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            instructions(&code),
+            [
+                "stp x29, x30, [sp, #-16]!",
+                "bl .+8",
+                "svc #0x80",
+                "ldp x29, x30, [sp], #16",
+                "ret",
+            ]
+        );
+        assert_eq!(
+            listing(&code),
+            "main:\n    stp x29, x30, [sp, #-16]!\n    bl .+8\n\"std::exit\":\n    svc #0x80\n    \
+             ldp x29, x30, [sp], #16\n    ret\n"
+        );
+    }
+
+    #[test]
+    fn unknown_instruction() {
+        assert_eq!(disassemble(0), ".inst 0x00000000");
+    }
+}
@@ -1,10 +1,10 @@
 #![allow(clippy::unusual_byte_groupings)]
 
-use ux::{i7, i12, i19, i21, i26, u9, u12};
+use ux::{i7, i9, i19, i21, i26, u6, u9, u12};
 
 use crate::ir::Condition;
 
-use super::reg::Register;
+use super::reg::{FReg, Register};
 
 /// An Armv8 instruction.
 ///
@@ -36,6 +36,47 @@ pub enum Input<I> {
     Imm(I),
 }
 
+/// Unsigned 12-bit immediate of [ADD](Add) and [SUB](Sub), optionally shifted left by 12 bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddImm {
+    pub imm: u12,
+    pub shift12: bool,
+}
+
+impl AddImm {
+    /// Encodes `value` in the unshifted form if it fits, otherwise in the shifted form if it is
+    /// a multiple of 4096 small enough.
+    pub fn new(value: u64) -> Option<Self> {
+        if value <= 0xfff {
+            Some(Self {
+                imm: u12::new(value as u16),
+                shift12: false,
+            })
+        } else if value.trailing_zeros() >= 12 && value >> 12 <= 0xfff {
+            Some(Self {
+                imm: u12::new((value >> 12) as u16),
+                shift12: true,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn encode(self) -> u32 {
+        let imm: u16 = self.imm.into();
+        ((self.shift12 as u32) << 22) | ((imm as u32) << 10)
+    }
+}
+
+impl From<u12> for AddImm {
+    fn from(imm: u12) -> Self {
+        Self {
+            imm,
+            shift12: false,
+        }
+    }
+}
+
 fn cond_to_u32(cond: Condition) -> u32 {
     use Condition::*;
 
@@ -71,19 +112,24 @@ fn i32_to_u32(n: impl Into<i32>, bits: i32) -> u32 {
 
 /// ADD instruction.
 ///
-/// Encoding:
+/// Encoding (shifted register):
 /// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
 /// 1  0  0  0  1  0  1  1  shift 0  Rm             imm6              Rn             Rd
 ///
+/// Encoding (immediate):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  0  0  0  1  0  sh imm12                               Rn             Rd
+///
 /// - shift: (00) LSL (01) LSR (10) ASR (11) Reserved
 /// - imm6: shift amount (0-63)
+/// - sh: shift imm12 left by 12 bits
 /// - Rn: first source register
 /// - Rm: second source register
 /// - Rd: destination register
 #[derive(Debug, Clone, Copy)]
 pub struct Add {
     pub a: Register,
-    pub b: Input<i12>,
+    pub b: Input<AddImm>,
     pub dest: Register,
 }
 
@@ -93,15 +139,72 @@ impl Instruction for Add {
         let dest = self.dest as u32;
 
         match self.b {
-            Input::Reg(reg) => (0b10001011 << 24) | (a << 16) | ((reg as u32) << 5) | dest,
-            Input::Imm(imm) => {
-                let imm: i32 = imm.into();
-                (0b1001000100 << 22) | ((imm as u32) << 10) | (a << 5) | dest
-            }
+            Input::Reg(reg) => (0b10001011 << 24) | ((reg as u32) << 16) | (a << 5) | dest,
+            Input::Imm(imm) => (0b100100010 << 23) | imm.encode() | (a << 5) | dest,
+        }
+    }
+}
+
+/// ADDS instruction.
+///
+/// Like [ADD](Add), but also sets the condition flags, so a signed overflow can be caught with
+/// the V flag.
+///
+/// Encoding (shifted register):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  1  0  1  0  1  1  shift 0  Rm             imm6              Rn             Rd
+///
+/// Encoding (immediate):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  1  1  0  0  0  1  0  sh imm12                               Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct Adds {
+    pub a: Register,
+    pub b: Input<AddImm>,
+    pub dest: Register,
+}
+
+impl Instruction for Adds {
+    fn encode(&self) -> u32 {
+        let a = self.a as u32;
+        let dest = self.dest as u32;
+
+        match self.b {
+            Input::Reg(reg) => (0b10101011 << 24) | ((reg as u32) << 16) | (a << 5) | dest,
+            Input::Imm(imm) => (0b101100010 << 23) | imm.encode() | (a << 5) | dest,
         }
     }
 }
 
+/// ADR instruction.
+///
+/// Forms a PC-relative address.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 0  immlo 1  0  0  0  0  immhi                                                    Rd
+///
+/// - immlo: lowest 2 bits of the offset
+/// - immhi: highest 19 bits of the offset
+/// - Rd: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct Adr {
+    pub offset: i21,
+    pub dest: Register,
+}
+
+impl Instruction for Adr {
+    fn encode(&self) -> u32 {
+        let offset = i32_to_u32(self.offset, 21);
+        let dest = self.dest as u32;
+
+        let up19 = offset >> 2;
+        let lo2 = offset & 0b11;
+
+        (0b0_00_10000 << 24) | (lo2 << 29) | (up19 << 5) | dest
+    }
+}
+
 /// ADRP instruction.
 ///
 /// Forms a PC-relative address to a 4KB page. The immediate value is left-shifted by 12 bits to
@@ -126,13 +229,40 @@ impl Instruction for Adrp {
         let page_addr = page_addr as u32;
         let dest = self.dest as u32;
 
-        let up19 = page_addr & (0b1111111111111111111 << 2);
+        let up19 = (page_addr >> 2) & 0b1111111111111111111;
         let lo2 = page_addr & 0b11;
 
         (0b1_00_10000 << 24) | (lo2 << 29) | (up19 << 5) | dest
     }
 }
 
+/// Alias for SBFM instruction.
+///
+/// Shifts right by an immediate, filling in copies of the sign bit.
+/// Equivalent to SBFM <Xd>, <Xn>, #shift, #63
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  0  0  1  1  0  1  immr              1  1  1  1  1  1  Rn             Rd
+///
+/// - immr: shift amount (0-63)
+#[derive(Debug, Clone, Copy)]
+pub struct Asr {
+    pub src: Register,
+    pub shift: u6,
+    pub dest: Register,
+}
+
+impl Instruction for Asr {
+    fn encode(&self) -> u32 {
+        let shift: u8 = self.shift.into();
+        let src = self.src as u32;
+        let dest = self.dest as u32;
+
+        (0b1001001101 << 22) | ((shift as u32) << 16) | (0b111111 << 10) | (src << 5) | dest
+    }
+}
+
 /// B instruction.
 ///
 /// Branches unconditionally to a pc-relative offset.
@@ -200,85 +330,204 @@ impl Instruction for BranchLink {
     }
 }
 
-/// CBNZ instruction.
+/// BR instruction.
 ///
-/// Branch if register is not zero.
+/// Branches unconditionally to an address in a register.
 ///
 /// Encoding:
 /// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
-/// 1  0  1  1  0  1  0  1  imm19                                                    Rt
+/// 1  1  0  1  0  1  1  0  0  0  0  1  1  1  1  1  0  0  0  0  0  0  Rn             0  0  0  0  0
 ///
-/// - imm19: jump offset (encoded offset/4)
-/// - Rt: register to compare against
+/// - Rn: register holding the address to jump to
 #[derive(Debug, Clone, Copy)]
-pub struct BranchNotZero {
-    pub addr: i19,
+pub struct BranchReg {
     pub reg: Register,
 }
 
-impl Instruction for BranchNotZero {
+impl Instruction for BranchReg {
     fn encode(&self) -> u32 {
-        let addr = i32_to_u32(self.addr, 19);
-        let reg = self.reg as u32;
+        (0b1101011000011111 << 16) | ((self.reg as u32) << 5)
+    }
+}
+
+/// BRK instruction.
+///
+/// Breakpoint. Raises a synchronous exception, which aborts the process with `SIGTRAP` when no
+/// debugger is attached.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  0  1  0  1  0  0  0  0  1  imm16                                           0  0  0  0  0
+///
+/// - imm16: 16-bit immediate value, ignored by the hardware
+#[derive(Debug, Clone, Copy)]
+pub struct Brk {
+    pub imm: u16,
+}
 
-        (0b10110101 << 24) | (addr << 5) | reg
+impl Instruction for Brk {
+    fn encode(&self) -> u32 {
+        (0b11010100001 << 21) | ((self.imm as u32) << 5)
     }
 }
 
-/// CBZ instruction.
+/// CBZ or CBNZ instruction.
 ///
-/// Branch if register is zero.
+/// Branches if a register is zero (CBZ) or not zero (CBNZ), without affecting the condition
+/// flags.
 ///
 /// Encoding:
 /// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
-/// 1  0  1  1  0  1  0  0  imm19                                                    Rt
+/// 1  0  1  1  0  1  0  op imm19                                                    Rt
 ///
+/// - op: 1 to branch if not zero (CBNZ), 0 to branch if zero (CBZ)
 /// - imm19: jump offset (encoded offset/4)
 /// - Rt: register to compare against
 #[derive(Debug, Clone, Copy)]
-pub struct BranchZero {
-    pub addr: i19,
+pub struct CompareBranchZero {
     pub reg: Register,
+    pub nonzero: bool,
+    pub addr: i19,
 }
 
-impl Instruction for BranchZero {
+impl Instruction for CompareBranchZero {
     fn encode(&self) -> u32 {
         let addr = i32_to_u32(self.addr, 19);
+        let nonzero = self.nonzero as u32;
         let reg = self.reg as u32;
 
-        (0b10110100 << 24) | (addr << 5) | reg
+        (0b1011010 << 25) | (nonzero << 24) | (addr << 5) | reg
     }
 }
 
-/// CMP instruction (alias of SUBS).
+/// CMP instruction (alias of SUBS with XZR as destination).
 ///
-/// Encoding:
+/// Encoding (shifted register):
 /// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
 /// 1  1  1  0  1  0  1  1  shift 0  Rm             imm6              Rn             1  1  1  1  1
 ///
+/// Encoding (immediate):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  1  1  0  0  0  1  0  sh imm12                               Rn             1  1  1  1  1
+///
+/// - Rn: register compared
+/// - Rm: register compared against
+/// - sh: shift imm12 left by 12 bits
 #[derive(Debug, Clone, Copy)]
 pub struct Cmp {
     pub a: Register,
-    pub b: Register,
+    pub b: Input<AddImm>,
 }
 
 impl Instruction for Cmp {
     fn encode(&self) -> u32 {
         let a = self.a as u32;
+
+        match self.b {
+            Input::Reg(b) => {
+                let b = b as u32;
+                (0b11101011_00_0 << 21) | (b << 16) | (a << 5) | 0b11111
+            }
+            Input::Imm(imm) => (0b111100010 << 23) | imm.encode() | (a << 5) | 0b11111,
+        }
+    }
+}
+
+/// CNEG instruction (alias of CSNEG).
+///
+/// Rd = cond ? -Rn : Rn
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  0  1  1  0  1  0  1  0  0  Rm             cond        0  1  Rn             Rd
+///
+/// - Rm, Rn: source register (both the same)
+/// - cond: inverted condition, CSNEG negates when it does not hold
+/// - Rd: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct Cneg {
+    pub cond: Condition,
+    pub src: Register,
+    pub dest: Register,
+}
+
+impl Instruction for Cneg {
+    fn encode(&self) -> u32 {
+        let cond = cond_to_u32(self.cond.inverted());
+        let src = self.src as u32;
+        let dest = self.dest as u32;
+
+        (0b11011010100 << 21) | (src << 16) | (cond << 12) | (0b01 << 10) | (src << 5) | dest
+    }
+}
+
+/// CSET instruction (alias of CSINC with XZR as both sources).
+///
+/// Rd = cond ? 1 : 0
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  0  1  0  1  0  0  1  1  1  1  1  cond        0  1  1  1  1  1  1  Rd
+///
+/// - cond: inverted condition, CSINC increments when it does not hold
+/// - Rd: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct Cset {
+    pub cond: Condition,
+    pub dest: Register,
+}
+
+impl Instruction for Cset {
+    fn encode(&self) -> u32 {
+        let cond = cond_to_u32(self.cond.inverted());
+        let dest = self.dest as u32;
+
+        (0b10011010100_11111 << 16) | (cond << 12) | (0b01_11111 << 5) | dest
+    }
+}
+
+/// CSEL instruction.
+///
+/// Rd = cond ? Rn : Rm
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  0  1  0  1  0  0  Rm             cond        0  0  Rn             Rd
+///
+/// - Rm: register selected if the condition does not hold
+/// - cond: condition to test the flags against
+/// - Rn: register selected if the condition holds
+/// - Rd: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct Csel {
+    pub cond: Condition,
+    pub a: Register,
+    pub b: Register,
+    pub dest: Register,
+}
+
+impl Instruction for Csel {
+    fn encode(&self) -> u32 {
+        let cond = cond_to_u32(self.cond);
+        let a = self.a as u32;
         let b = self.b as u32;
+        let dest = self.dest as u32;
 
-        (0b11101011_00_0 << 21) | (b << 16) | (a << 5) | 0b11111
+        (0b10011010100 << 21) | (b << 16) | (cond << 12) | (a << 5) | dest
     }
 }
 
 /// SDIV or UDIV instruction.
 ///
-/// Encoding (SDIV):
+/// Divides Rn by Rm. The quotient is rounded toward zero, and division by zero gives zero.
+///
+/// Encoding:
 /// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
-/// 1  0  0  1  1  0  1  0  1  1  0  Rm             0  0  0  0  1  1  Rn             Rd
+/// 1  0  0  1  1  0  1  0  1  1  0  Rm             0  0  0  0  1  S  Rn             Rd
 ///
-/// - Rn: first source register
-/// - Rm: second source register
+/// - Rm: divisor
+/// - S: 1 for signed (SDIV), 0 for unsigned (UDIV)
+/// - Rn: dividend
 /// - Rd: destination register
 #[derive(Debug, Clone, Copy)]
 pub struct Div {
@@ -294,11 +543,212 @@ impl Instruction for Div {
         let b = self.b as u32;
         let dest = self.dest as u32;
 
-        if self.signed {
-            (0b10011010110_00000_000011 << 10) | (b << 16) | (a << 5) | dest
-        } else {
-            todo!()
-        }
+        let signed = self.signed as u32;
+
+        (0b10011010110_00000_000010 << 10) | (signed << 10) | (b << 16) | (a << 5) | dest
+    }
+}
+
+/// Encodes a floating-point data-processing instruction with two double-precision sources.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 0  0  0  1  1  1  1  0  0  1  1  Rm             opcode      1  0  Rn             Rd
+fn fp_two_source(opcode: u32, a: FReg, b: FReg, dest: FReg) -> u32 {
+    (0b00011110011 << 21)
+        | ((b as u32) << 16)
+        | (opcode << 12)
+        | (0b10 << 10)
+        | ((a as u32) << 5)
+        | dest as u32
+}
+
+/// FADD instruction (double precision).
+///
+/// Dd = Dn + Dm
+#[derive(Debug, Clone, Copy)]
+pub struct FAdd {
+    pub a: FReg,
+    pub b: FReg,
+    pub dest: FReg,
+}
+
+impl Instruction for FAdd {
+    fn encode(&self) -> u32 {
+        fp_two_source(0b0010, self.a, self.b, self.dest)
+    }
+}
+
+/// FCVTZS instruction (double precision to 64-bit).
+///
+/// Converts a float to a signed integer, rounding toward zero. Values out of range saturate and
+/// NaN becomes zero.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  1  1  0  0  1  1  1  1  0  0  0  0  0  0  0  0  0  Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct Fcvtzs {
+    pub src: FReg,
+    pub dest: Register,
+}
+
+impl Instruction for Fcvtzs {
+    fn encode(&self) -> u32 {
+        0x9e780000 | ((self.src as u32) << 5) | self.dest as u32
+    }
+}
+
+/// FDIV instruction (double precision).
+///
+/// Dd = Dn / Dm
+#[derive(Debug, Clone, Copy)]
+pub struct FDiv {
+    pub a: FReg,
+    pub b: FReg,
+    pub dest: FReg,
+}
+
+impl Instruction for FDiv {
+    fn encode(&self) -> u32 {
+        fp_two_source(0b0001, self.a, self.b, self.dest)
+    }
+}
+
+/// LDR instruction (double precision).
+///
+/// Loads an 8 byte value from memory into a floating-point register.
+///
+/// Encoding (unsigned offset):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  1  1  1  1  0  1  0  1  imm12                               Rn             Rt
+///
+/// - imm12: offset from base (stored as a multiple of 8)
+/// - Rn: base pointer
+/// - Rt: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct FLoad {
+    pub base: Register,
+    pub offset: u12,
+    pub dest: FReg,
+}
+
+impl Instruction for FLoad {
+    fn encode(&self) -> u32 {
+        let offset: u32 = self.offset.into();
+        (0b1111110101 << 22) | (offset << 10) | ((self.base as u32) << 5) | self.dest as u32
+    }
+}
+
+/// FMOV instruction (register, double precision).
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 0  0  0  1  1  1  1  0  0  1  1  0  0  0  0  0  0  1  0  0  0  0  Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct FMov {
+    pub src: FReg,
+    pub dest: FReg,
+}
+
+impl Instruction for FMov {
+    fn encode(&self) -> u32 {
+        0x1e604000 | ((self.src as u32) << 5) | self.dest as u32
+    }
+}
+
+/// FMOV instruction (general to double precision).
+///
+/// Copies the bits of a general-purpose register into a floating-point register.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  1  1  0  0  1  1  0  0  1  1  1  0  0  0  0  0  0  Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct FMovFromGeneral {
+    pub src: Register,
+    pub dest: FReg,
+}
+
+impl Instruction for FMovFromGeneral {
+    fn encode(&self) -> u32 {
+        0x9e670000 | ((self.src as u32) << 5) | self.dest as u32
+    }
+}
+
+/// FMOV instruction (double precision to general).
+///
+/// Copies the bits of a floating-point register into a general-purpose register.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  1  1  0  0  1  1  0  0  1  1  0  0  0  0  0  0  0  Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct FMovToGeneral {
+    pub src: FReg,
+    pub dest: Register,
+}
+
+impl Instruction for FMovToGeneral {
+    fn encode(&self) -> u32 {
+        0x9e660000 | ((self.src as u32) << 5) | self.dest as u32
+    }
+}
+
+/// FMUL instruction (double precision).
+///
+/// Dd = Dn * Dm
+#[derive(Debug, Clone, Copy)]
+pub struct FMul {
+    pub a: FReg,
+    pub b: FReg,
+    pub dest: FReg,
+}
+
+impl Instruction for FMul {
+    fn encode(&self) -> u32 {
+        fp_two_source(0b0000, self.a, self.b, self.dest)
+    }
+}
+
+/// FSUB instruction (double precision).
+///
+/// Dd = Dn - Dm
+#[derive(Debug, Clone, Copy)]
+pub struct FSub {
+    pub a: FReg,
+    pub b: FReg,
+    pub dest: FReg,
+}
+
+impl Instruction for FSub {
+    fn encode(&self) -> u32 {
+        fp_two_source(0b0011, self.a, self.b, self.dest)
+    }
+}
+
+/// STR instruction (double precision).
+///
+/// Saves a floating-point register to memory.
+///
+/// Encoding (unsigned offset):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  1  1  1  1  0  1  0  0  imm12                               Rn             Rt
+///
+/// - imm12: offset from base (stored as a multiple of 8)
+/// - Rn: base pointer
+/// - Rt: source register
+#[derive(Debug, Clone, Copy)]
+pub struct FStore {
+    pub base: Register,
+    pub offset: u12,
+    pub register: FReg,
+}
+
+impl Instruction for FStore {
+    fn encode(&self) -> u32 {
+        let offset: u32 = self.offset.into();
+        (0b1111110100 << 22) | (offset << 10) | ((self.base as u32) << 5) | self.register as u32
     }
 }
 
@@ -453,6 +903,62 @@ impl Instruction for Movz {
     }
 }
 
+/// MOVK instruction.
+///
+/// Moves a 16-bit immediate value into a part of the destination register, keeping the other
+/// bits.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  1  1  0  0  1  0  1  hw    imm16                                           Rd
+///
+/// - hw: shift left (0/16/32/48 encoded as 0/1/2/3)
+/// - imm16: 16-bit immediate value to (optionally shift) into destination register
+/// - Rd: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct Movk {
+    pub shift: ImmShift16,
+    pub imm_value: u16,
+    pub dest: Register,
+}
+
+impl Instruction for Movk {
+    fn encode(&self) -> u32 {
+        (0b111100101 << 23)
+            | ((self.shift as u32) << 21)
+            | ((self.imm_value as u32) << 5)
+            | self.dest as u32
+    }
+}
+
+/// MOVN instruction.
+///
+/// Moves the inverse of a (optionally shifted) 16-bit immediate value into the destination
+/// register, so every other bit is set.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  0  0  1  0  1  hw    imm16                                           Rd
+///
+/// - hw: shift left (0/16/32/48 encoded as 0/1/2/3)
+/// - imm16: 16-bit immediate value to (optionally shift and) invert into destination register
+/// - Rd: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct Movn {
+    pub shift: ImmShift16,
+    pub imm_value: u16,
+    pub dest: Register,
+}
+
+impl Instruction for Movn {
+    fn encode(&self) -> u32 {
+        (0b100100101 << 23)
+            | ((self.shift as u32) << 21)
+            | ((self.imm_value as u32) << 5)
+            | self.dest as u32
+    }
+}
+
 /// MUL instruction. (alias of MADD)
 ///
 /// Rd = Rn * Rm
@@ -481,6 +987,37 @@ impl Instruction for Mul {
     }
 }
 
+/// MSUB instruction.
+///
+/// Rd = Ra - Rn * Rm
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  0  1  1  0  0  0  Rm             1  Ra             Rn             Rd
+///
+/// - Rn: first factor
+/// - Rm: second factor
+/// - Ra: value the product is subtracted from
+/// - Rd: destination register
+#[derive(Debug, Clone, Copy)]
+pub struct Msub {
+    pub a: Register,
+    pub b: Register,
+    pub minuend: Register,
+    pub dest: Register,
+}
+
+impl Instruction for Msub {
+    fn encode(&self) -> u32 {
+        let a = self.a as u32;
+        let b = self.b as u32;
+        let minuend = self.minuend as u32;
+        let dest = self.dest as u32;
+
+        (0b10011011000 << 21) | (b << 16) | (1 << 15) | (minuend << 10) | (a << 5) | dest
+    }
+}
+
 /// Alias for SUB instruction.
 ///
 /// Equivalent to SUB <Xd> XZR <Xm> (dest = zero - src)
@@ -490,8 +1027,8 @@ impl Instruction for Mul {
 /// 1  1  0  0  1  0  1  1  shift 0  Rm             imm6              1  1  1  1  1  Rd
 #[derive(Debug, Clone, Copy)]
 pub struct Neg {
-    src: Register,
-    dest: Register,
+    pub src: Register,
+    pub dest: Register,
 }
 
 impl Instruction for Neg {
@@ -499,7 +1036,7 @@ impl Instruction for Neg {
         let src = self.src as u32;
         let dest = self.dest as u32;
 
-        (0b11001011_00_0_00000_000000_11111 << 20) | (src << 16) | dest
+        (0b11001011_00_0_00000_000000_11111 << 5) | (src << 16) | dest
     }
 }
 
@@ -584,6 +1121,35 @@ impl Instruction for Store {
     }
 }
 
+/// STRB instruction.
+///
+/// Moves the base register by the offset, then stores the lowest byte of a register at the new
+/// address.
+///
+/// Encoding (pre-index):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 0  0  1  1  1  0  0  0  0  0  0  imm9                       1  1  Rn             Rt
+///
+/// - imm9: signed offset in bytes
+/// - Rn: base register (writeback)
+/// - Rt: source register
+#[derive(Debug, Clone, Copy)]
+pub struct StoreByte {
+    pub base: Register,
+    pub offset: i9,
+    pub register: Register,
+}
+
+impl Instruction for StoreByte {
+    fn encode(&self) -> u32 {
+        let offset = i32_to_u32(i16::from(self.offset), 9);
+        let base = self.base as u32;
+        let register = self.register as u32;
+
+        (0b00111000000_000000000_11 << 10) | (offset << 12) | (base << 5) | register
+    }
+}
+
 /// STP instruction.
 ///
 /// Stores two registers to memory and updates the base register.
@@ -616,6 +1182,49 @@ impl Instruction for StorePair {
     }
 }
 
+/// SCVTF instruction (64-bit to double precision).
+///
+/// Converts a signed integer to the nearest float.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  1  1  0  0  1  1  0  0  0  1  0  0  0  0  0  0  0  Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct Scvtf {
+    pub src: Register,
+    pub dest: FReg,
+}
+
+impl Instruction for Scvtf {
+    fn encode(&self) -> u32 {
+        0x9e620000 | ((self.src as u32) << 5) | self.dest as u32
+    }
+}
+
+/// SMULH instruction.
+///
+/// Multiplies two signed registers into a 128-bit product and keeps its upper 64 bits.
+///
+/// Encoding:
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  0  0  1  1  0  1  1  0  1  0  Rm             0  1  1  1  1  1  Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct Smulh {
+    pub a: Register,
+    pub b: Register,
+    pub dest: Register,
+}
+
+impl Instruction for Smulh {
+    fn encode(&self) -> u32 {
+        let a = self.a as u32;
+        let b = self.b as u32;
+        let dest = self.dest as u32;
+
+        (0b10011011010 << 21) | (b << 16) | (0b011111 << 10) | (a << 5) | dest
+    }
+}
+
 /// SUB instruction.
 ///
 /// Subtracts immediate value from register.
@@ -639,7 +1248,7 @@ impl Instruction for StorePair {
 #[derive(Debug, Clone, Copy)]
 pub struct Sub {
     pub a: Register,
-    pub b: Input<i12>,
+    pub b: Input<AddImm>,
     pub dest: Register,
 }
 
@@ -653,10 +1262,41 @@ impl Instruction for Sub {
                 let b = b as u32;
                 (0b11001011_00_0 << 21) | (b << 16) | (a << 5) | dest
             }
-            Input::Imm(imm) => {
-                let imm: i16 = imm.into();
-                (0b110100010_0 << 22) | ((imm as u32) << 10) | (a << 5) | dest
+            Input::Imm(imm) => (0b110100010 << 23) | imm.encode() | (a << 5) | dest,
+        }
+    }
+}
+
+/// SUBS instruction.
+///
+/// Like [SUB](Sub), but also sets the condition flags. [CMP](Cmp) is SUBS with the result
+/// discarded.
+///
+/// Encoding (immediate):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  1  1  0  0  0  1  0  sh imm12                               Rn             Rd
+///
+/// Encoding (shifted register):
+/// 31 30 29 28 27 26 25 24 23 22 21 20 19 18 17 16 15 14 13 12 11 10 9  8  7  6  5  4  3  2  1  0
+/// 1  1  1  0  1  0  1  1  shift 0  Rm             imm6              Rn             Rd
+#[derive(Debug, Clone, Copy)]
+pub struct Subs {
+    pub a: Register,
+    pub b: Input<AddImm>,
+    pub dest: Register,
+}
+
+impl Instruction for Subs {
+    fn encode(&self) -> u32 {
+        let a = self.a as u32;
+        let dest = self.dest as u32;
+
+        match self.b {
+            Input::Reg(b) => {
+                let b = b as u32;
+                (0b11101011_00_0 << 21) | (b << 16) | (a << 5) | dest
             }
+            Input::Imm(imm) => (0b111100010 << 23) | imm.encode() | (a << 5) | dest,
         }
     }
 }
@@ -692,3 +1332,378 @@ impl Instruction for Syscall {
         Svc { imm: 0x80 }.encode()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn store_byte_encoding() {
+        // strb w14, [x9, #-1]!
+        let strb = StoreByte {
+            base: Register::X9,
+            offset: i9::new(-1),
+            register: Register::X14,
+        };
+        assert_eq!(strb.encode(), 0x381ffd2e);
+    }
+
+    #[test]
+    fn neg_encoding() {
+        // neg x0, x1
+        let neg = Neg {
+            src: Register::X1,
+            dest: Register::X0,
+        };
+        assert_eq!(neg.encode(), 0xcb0103e0);
+
+        // neg x5, x9
+        let neg = Neg {
+            src: Register::X9,
+            dest: Register::X5,
+        };
+        assert_eq!(neg.encode(), 0xcb0903e5);
+    }
+
+    #[test]
+    fn cbz_encoding() {
+        // cbz x3, #-8
+        let cbz = CompareBranchZero {
+            reg: Register::X3,
+            nonzero: false,
+            addr: i19::new(-2),
+        };
+        assert_eq!(cbz.encode(), 0xb4ffffc3);
+    }
+
+    #[test]
+    fn cbnz_encoding() {
+        // cbnz x0, #8
+        let cbnz = CompareBranchZero {
+            reg: Register::X0,
+            nonzero: true,
+            addr: i19::new(2),
+        };
+        assert_eq!(cbnz.encode(), 0xb5000040);
+    }
+
+    #[test]
+    fn adr_encoding() {
+        // adr x17, #12
+        let adr = Adr {
+            offset: i21::new(12),
+            dest: Register::X17,
+        };
+        assert_eq!(adr.encode(), 0x10000071);
+
+        // adr x3, #-8
+        let adr = Adr {
+            offset: i21::new(-8),
+            dest: Register::X3,
+        };
+        assert_eq!(adr.encode(), 0x10ffffc3);
+    }
+
+    #[test]
+    fn branch_reg_encoding() {
+        // br x17
+        assert_eq!(BranchReg { reg: Register::X17 }.encode(), 0xd61f0220);
+        // br x0
+        assert_eq!(BranchReg { reg: Register::X0 }.encode(), 0xd61f0000);
+    }
+
+    #[test]
+    fn add_imm_forms() {
+        let unshifted = |imm| {
+            Some(AddImm {
+                imm: u12::new(imm),
+                shift12: false,
+            })
+        };
+        let shifted = |imm| {
+            Some(AddImm {
+                imm: u12::new(imm),
+                shift12: true,
+            })
+        };
+
+        assert_eq!(AddImm::new(4095), unshifted(4095));
+        assert_eq!(AddImm::new(4096), shifted(1));
+        assert_eq!(AddImm::new(8192), shifted(2));
+        assert_eq!(AddImm::new(4095 << 12), shifted(4095));
+        assert_eq!(AddImm::new(4097), None);
+        assert_eq!(AddImm::new(4096 << 12), None);
+    }
+
+    #[test]
+    fn add_encoding() {
+        // add x0, x1, #4
+        let add = Add {
+            a: Register::X1,
+            b: Input::Imm(u12::new(4).into()),
+            dest: Register::X0,
+        };
+        assert_eq!(add.encode(), 0x91001020);
+
+        // add x0, x1, x2
+        let add = Add {
+            a: Register::X1,
+            b: Input::Reg(Register::X2),
+            dest: Register::X0,
+        };
+        assert_eq!(add.encode(), 0x8b020020);
+    }
+
+    #[test]
+    fn add_shifted_imm_encoding() {
+        // add x0, x1, #2, lsl #12
+        let add = Add {
+            a: Register::X1,
+            b: Input::Imm(AddImm::new(8192).unwrap()),
+            dest: Register::X0,
+        };
+        assert_eq!(add.encode(), 0x91400820);
+    }
+
+    #[test]
+    fn sub_shifted_imm_encoding() {
+        // sub x0, x1, #2, lsl #12
+        let sub = Sub {
+            a: Register::X1,
+            b: Input::Imm(AddImm::new(8192).unwrap()),
+            dest: Register::X0,
+        };
+        assert_eq!(sub.encode(), 0xd1400820);
+    }
+
+    #[test]
+    fn sdiv_encoding() {
+        // sdiv x0, x1, x2
+        let div = Div {
+            a: Register::X1,
+            b: Register::X2,
+            dest: Register::X0,
+            signed: true,
+        };
+        assert_eq!(div.encode(), 0x9ac20c20);
+    }
+
+    #[test]
+    fn udiv_encoding() {
+        // udiv x0, x1, x2
+        let div = Div {
+            a: Register::X1,
+            b: Register::X2,
+            dest: Register::X0,
+            signed: false,
+        };
+        assert_eq!(div.encode(), 0x9ac20820);
+    }
+
+    #[test]
+    fn msub_encoding() {
+        // msub x0, x1, x2, x3
+        let msub = Msub {
+            a: Register::X1,
+            b: Register::X2,
+            minuend: Register::X3,
+            dest: Register::X0,
+        };
+        assert_eq!(msub.encode(), 0x9b028c20);
+    }
+
+    #[test]
+    fn csel_encoding() {
+        // csel x0, x1, x2, lt
+        let csel = Csel {
+            cond: Condition::SignedLess,
+            a: Register::X1,
+            b: Register::X2,
+            dest: Register::X0,
+        };
+        assert_eq!(csel.encode(), 0x9a82b020);
+
+        // csel x3, x4, x5, gt
+        let csel = Csel {
+            cond: Condition::SignedGreater,
+            a: Register::X4,
+            b: Register::X5,
+            dest: Register::X3,
+        };
+        assert_eq!(csel.encode(), 0x9a85c083);
+    }
+
+    #[test]
+    fn cneg_encoding() {
+        // cneg x0, x1, lt
+        let cneg = Cneg {
+            cond: Condition::SignedLess,
+            src: Register::X1,
+            dest: Register::X0,
+        };
+        assert_eq!(cneg.encode(), 0xda81a420);
+
+        // cneg x2, x7, mi
+        let cneg = Cneg {
+            cond: Condition::Negative,
+            src: Register::X7,
+            dest: Register::X2,
+        };
+        assert_eq!(cneg.encode(), 0xda8754e2);
+    }
+
+    #[test]
+    fn cmp_zero_register_encoding() {
+        // cmp x1, xzr
+        let cmp = Cmp {
+            a: Register::X1,
+            b: Input::Reg(Register::XZR),
+        };
+        assert_eq!(cmp.encode(), 0xeb1f003f);
+    }
+
+    #[test]
+    fn cmp_immediate_encoding() {
+        // cmp x3, #42
+        let cmp = Cmp {
+            a: Register::X3,
+            b: Input::Imm(AddImm::new(42).unwrap()),
+        };
+        assert_eq!(cmp.encode(), 0xf100a87f);
+
+        // cmp x1, #1, lsl #12
+        let cmp = Cmp {
+            a: Register::X1,
+            b: Input::Imm(AddImm::new(4096).unwrap()),
+        };
+        assert_eq!(cmp.encode(), 0xf140043f);
+    }
+
+    #[test]
+    fn cset_encoding() {
+        // cset x5, lt
+        let cset = Cset {
+            cond: Condition::SignedLess,
+            dest: Register::X5,
+        };
+        assert_eq!(cset.encode(), 0x9a9fa7e5);
+
+        // cset x0, eq
+        let cset = Cset {
+            cond: Condition::Equal,
+            dest: Register::X0,
+        };
+        assert_eq!(cset.encode(), 0x9a9f17e0);
+    }
+
+    #[test]
+    fn fp_arithmetic_encoding() {
+        let (a, b, dest) = (FReg::V1, FReg::V2, FReg::V0);
+
+        // fadd d0, d1, d2
+        assert_eq!(FAdd { a, b, dest }.encode(), 0x1e622820);
+        // fsub d0, d1, d2
+        assert_eq!(FSub { a, b, dest }.encode(), 0x1e623820);
+        // fmul d0, d1, d2
+        assert_eq!(FMul { a, b, dest }.encode(), 0x1e620820);
+        // fdiv d0, d1, d2
+        assert_eq!(FDiv { a, b, dest }.encode(), 0x1e621820);
+        // fadd d31, d30, d29
+        let fadd = FAdd {
+            a: FReg::V30,
+            b: FReg::V29,
+            dest: FReg::V31,
+        };
+        assert_eq!(fadd.encode(), 0x1e7d2bdf);
+    }
+
+    #[test]
+    fn fmov_encoding() {
+        // fmov d1, d2
+        let fmov = FMov {
+            src: FReg::V2,
+            dest: FReg::V1,
+        };
+        assert_eq!(fmov.encode(), 0x1e604041);
+
+        // fmov d4, x3
+        let fmov = FMovFromGeneral {
+            src: Register::X3,
+            dest: FReg::V4,
+        };
+        assert_eq!(fmov.encode(), 0x9e670064);
+
+        // fmov x3, d4
+        let fmov = FMovToGeneral {
+            src: FReg::V4,
+            dest: Register::X3,
+        };
+        assert_eq!(fmov.encode(), 0x9e660083);
+    }
+
+    #[test]
+    fn fp_load_store_encoding() {
+        // ldr d3, [sp, #16]
+        let ldr = FLoad {
+            base: Register::SP,
+            offset: u12::new(2),
+            dest: FReg::V3,
+        };
+        assert_eq!(ldr.encode(), 0xfd400be3);
+
+        // str d3, [sp, #16]
+        let str = FStore {
+            base: Register::SP,
+            offset: u12::new(2),
+            register: FReg::V3,
+        };
+        assert_eq!(str.encode(), 0xfd000be3);
+    }
+
+    #[test]
+    fn fp_conversion_encoding() {
+        // fcvtzs x0, d1
+        let fcvtzs = Fcvtzs {
+            src: FReg::V1,
+            dest: Register::X0,
+        };
+        assert_eq!(fcvtzs.encode(), 0x9e780020);
+
+        // scvtf d1, x0
+        let scvtf = Scvtf {
+            src: Register::X0,
+            dest: FReg::V1,
+        };
+        assert_eq!(scvtf.encode(), 0x9e620001);
+    }
+
+    #[test]
+    fn movk_encoding() {
+        // movk x2, #0x4010, lsl #48
+        let movk = Movk {
+            shift: ImmShift16::L48,
+            imm_value: 0x4010,
+            dest: Register::X2,
+        };
+        assert_eq!(movk.encode(), 0xf2e80202);
+    }
+
+    #[test]
+    fn movn_encoding() {
+        // movn x0, #4
+        let movn = Movn {
+            shift: ImmShift16::L0,
+            imm_value: 4,
+            dest: Register::X0,
+        };
+        assert_eq!(movn.encode(), 0x92800080);
+
+        // movn x2, #0x1234, lsl #16
+        let movn = Movn {
+            shift: ImmShift16::L16,
+            imm_value: 0x1234,
+            dest: Register::X2,
+        };
+        assert_eq!(movn.encode(), 0x92a24682);
+    }
+}
@@ -6,13 +6,24 @@ use strum::EnumIter;
 use ux::u12;
 
 use crate::{
-    ir::{BasicBlock, Op, Operation, VirtualReg},
-    synthesize::arch::arm::{
-        ArmAssembler,
-        instr::{self, Input},
+    analyze::ColorChoice,
+    ir::{
+        BasicBlock, Label, Op, Operation, RegClass, SourceVal, VirtualReg,
+        lifetime::{Lifetime, print_lifetimes},
+    },
+    synthesize::arch::{
+        RegisterAllocator,
+        arm::{
+            ArmAssembler,
+            instr::{self, Input},
+        },
     },
 };
 
+mod graph;
+
+pub use graph::allocate_graph;
+
 pub type Reg = Register;
 
 /// All general-purpose registers + stack pointer on the ARM architecture.
@@ -53,25 +64,121 @@ pub enum Register {
     SP = 31,  // stack pointer (X31) (not general purpose)
 }
 
+/// The SIMD and floating-point registers, used through their 64-bit D views.
+#[repr(u32)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FReg {
+    V0 = 0,
+    V1 = 1,
+    V2 = 2,
+    V3 = 3,
+    V4 = 4,
+    V5 = 5,
+    V6 = 6,
+    V7 = 7,
+    V8 = 8,
+    V9 = 9,
+    V10 = 10,
+    V11 = 11,
+    V12 = 12,
+    V13 = 13,
+    V14 = 14,
+    V15 = 15,
+    V16 = 16,
+    V17 = 17,
+    V18 = 18,
+    V19 = 19,
+    V20 = 20,
+    V21 = 21,
+    V22 = 22,
+    V23 = 23,
+    V24 = 24,
+    V25 = 25,
+    V26 = 26,
+    V27 = 27,
+    V28 = 28,
+    V29 = 29,
+    V30 = 30,
+    V31 = 31,
+}
+
+impl Register {
+    /// The zero register. It shares its number with [Register::SP], and instructions that don't
+    /// address memory read it as zero.
+    pub const XZR: Register = Register::SP;
+}
+
+/// A class of physical registers that virtual registers are allocated to.
+pub trait RegisterClass: Copy + Eq + std::fmt::Debug + 'static {
+    /// The registers of this class a function may use without saving them first.
+    const CALLER_SAVED: &'static [Self];
+
+    /// Emits a load of the stack slot at `offset` into `reg`.
+    fn emit_load(asm: &mut ArmAssembler, offset: u12, reg: Self);
+
+    /// Emits a store of `reg` to the stack slot at `offset`.
+    fn emit_store(asm: &mut ArmAssembler, offset: u12, reg: Self);
+}
+
+impl RegisterClass for Register {
+    const CALLER_SAVED: &'static [Register] = CALLER_SAVED_REGS;
+
+    fn emit_load(asm: &mut ArmAssembler, offset: u12, reg: Register) {
+        asm.emit(instr::Load {
+            base: Reg::SP,
+            offset,
+            dest: reg,
+        });
+    }
+
+    fn emit_store(asm: &mut ArmAssembler, offset: u12, reg: Register) {
+        asm.emit(instr::Store {
+            base: Reg::SP,
+            offset: Input::Imm(offset),
+            register: reg,
+        });
+    }
+}
+
+impl RegisterClass for FReg {
+    const CALLER_SAVED: &'static [FReg] = CALLER_SAVED_FREGS;
+
+    fn emit_load(asm: &mut ArmAssembler, offset: u12, reg: FReg) {
+        asm.emit(instr::FLoad {
+            base: Reg::SP,
+            offset,
+            dest: reg,
+        });
+    }
+
+    fn emit_store(asm: &mut ArmAssembler, offset: u12, reg: FReg) {
+        asm.emit(instr::FStore {
+            base: Reg::SP,
+            offset,
+            register: reg,
+        });
+    }
+}
+
 /// Used in register allocation when mapping a virtual register to a physical register. This
 /// protects a register if using the register for a value requires loading that value from stack,
 /// saving the existing register value to the stack, or both.
 ///
 /// To use this register in an operation, call the [unwrap()](Self::unwrap) method.
 #[derive(Debug, Clone, Copy)]
-pub enum RegisterGuard {
-    Ready(Register),
-    Load { load: u12, reg: Register },
-    Save { save: u12, reg: Register },
-    SaveAndLoad { save: u12, load: u12, reg: Register },
+pub enum RegisterGuard<R = Register> {
+    Ready(R),
+    Load { load: u12, reg: R },
+    Save { save: u12, reg: R },
+    SaveAndLoad { save: u12, load: u12, reg: R },
 }
 
-impl RegisterGuard {
+impl<R: RegisterClass> RegisterGuard<R> {
     /// Returns the register that this guard protects.
     ///
     /// Do not use this register if you are not sure it doesn't overwrite a value and where the
     /// virtual register is located.
-    pub fn inner_reg(&self) -> Register {
+    pub fn inner_reg(&self) -> R {
         match *self {
             RegisterGuard::Ready(reg) => reg,
             RegisterGuard::Load { load: _, reg } => reg,
@@ -87,33 +194,20 @@ impl RegisterGuard {
     /// Unwraps the inner register by potentially emitting a load and/or store instruction. Calling
     /// this function will ensure the value ends up in the returned register, and that the old
     /// value in the register is saved to the stack if necessary.
-    pub fn unwrap(&self, asm: &mut ArmAssembler) -> Register {
+    pub fn unwrap(&self, asm: &mut ArmAssembler) -> R {
         match *self {
             Self::Ready(reg) => reg,
             Self::Load { load, reg } => {
-                asm.emit(instr::Load {
-                    base: Reg::SP,
-                    offset: load,
-                    dest: reg,
-                });
+                R::emit_load(asm, load, reg);
                 reg
             }
             Self::Save { save, reg } => {
-                asm.emit_stack_store(save, reg);
+                R::emit_store(asm, save, reg);
                 reg
             }
             Self::SaveAndLoad { save, load, reg } => {
-                asm.emit(instr::Store {
-                    base: Reg::SP,
-                    offset: Input::Imm(save),
-                    register: reg,
-                });
-
-                asm.emit(instr::Load {
-                    base: Reg::SP,
-                    offset: load,
-                    dest: reg,
-                });
+                R::emit_store(asm, save, reg);
+                R::emit_load(asm, load, reg);
                 reg
             }
         }
@@ -137,8 +231,8 @@ struct Stack {
 impl Stack {
     pub fn alloc(&mut self, vreg: VirtualReg, bytes: u16) -> u12 {
         let index = self.free_slots.pop().unwrap_or_else(|| {
-            self.size = self.size + u12::new(bytes / 4);
-            self.size - u12::new(bytes / 4)
+            self.size = self.size + u12::new(bytes / 8);
+            self.size - u12::new(bytes / 8)
         });
 
         self.map.insert(vreg, index);
@@ -146,12 +240,19 @@ impl Stack {
         index
     }
 
-    // pub fn free(&mut self, slot: u12) {
-    //     assert!(slot < self.size);
-    //     assert!(!self.free_slots.contains(&slot));
-    //
-    //     self.free_slots.push(slot);
-    // }
+    /// Returns the slot of a virtual register that is no longer used, so a later allocation can
+    /// reuse it. The register keeps its mapping for the instructions emitted before it died.
+    pub fn free(&mut self, vreg: VirtualReg) {
+        let slot = *self
+            .map
+            .get(&vreg)
+            .unwrap_or_else(|| panic!("{} has no stack slot to free", vreg));
+
+        assert!(slot < self.size);
+        assert!(!self.free_slots.contains(&slot), "stack slot freed twice");
+
+        self.free_slots.push(slot);
+    }
 }
 
 use Register::*;
@@ -159,15 +260,246 @@ const CALLER_SAVED_REGS: &[Register] = &[
     X0, X1, X2, X3, X4, X5, X6, X7, X8, X9, X10, X11, X12, X13, X14, X15,
 ];
 
-#[derive(Debug, Clone, Copy)]
-struct AllocEntry {
-    reg: Option<Register>,
-    stack: u12,
+use FReg::*;
+/// V8-V15 are left out, since the callee must preserve their lower halves.
+const CALLER_SAVED_FREGS: &[FReg] = &[
+    V0, V1, V2, V3, V4, V5, V6, V7, V16, V17, V18, V19, V20, V21, V22, V23, V24, V25, V26, V27,
+    V28, V29, V30, V31,
+];
+
+/// The registers of one class while allocating.
+struct Pool<R> {
+    unused: Vec<R>,
+    /// Registers holding a value that is also in its stack slot.
+    clean: Vec<(R, VirtualReg)>,
+    /// Registers holding a value that is only in the register.
+    dirty: Vec<(R, VirtualReg)>,
+    /// The register each virtual register is currently in.
+    current: HashMap<VirtualReg, R>,
 }
 
+impl<R: RegisterClass> Pool<R> {
+    fn new(unused: Vec<R>) -> Self {
+        Self {
+            unused,
+            clean: Vec::new(),
+            dirty: Vec::new(),
+            current: HashMap::new(),
+        }
+    }
+
+    /// Picks a register for reading `vreg`, which is always loaded from its stack slot.
+    fn use_reg(&mut self, vreg: VirtualReg, slots: &Slots) -> RegisterGuard<R> {
+        self.current.remove(&vreg);
+        let load = slots.of(vreg);
+
+        let guard = if let Some(reg) = self.unused.pop() {
+            RegisterGuard::Load { load, reg }
+        } else if let Some((reg, old_vreg)) = self.clean.pop() {
+            self.current.remove(&old_vreg);
+            RegisterGuard::Load { load, reg }
+        } else if let Some((reg, old_vreg)) = self.dirty.pop() {
+            self.current.remove(&old_vreg);
+            RegisterGuard::SaveAndLoad {
+                save: slots.of(old_vreg),
+                load,
+                reg,
+            }
+        } else {
+            unreachable!()
+        };
+
+        self.clean.push((guard.inner_reg(), vreg));
+        self.current.insert(vreg, guard.inner_reg());
+        guard
+    }
+
+    /// Picks a register for writing `vreg`, keeping the one it is already in.
+    fn assign_reg(&mut self, vreg: VirtualReg, slots: &Slots) -> RegisterGuard<R> {
+        if let Some(&reg) = self.current.get(&vreg) {
+            return RegisterGuard::Ready(reg);
+        }
+
+        let guard = if let Some(reg) = self.unused.pop() {
+            RegisterGuard::Ready(reg)
+        } else if let Some((reg, old_vreg)) = self.clean.pop() {
+            self.current.remove(&old_vreg);
+            RegisterGuard::Ready(reg)
+        } else if let Some((reg, old_vreg)) = self.dirty.pop() {
+            self.current.remove(&old_vreg);
+            RegisterGuard::Save {
+                save: slots.of(old_vreg),
+                reg,
+            }
+        } else {
+            unreachable!()
+        };
+
+        self.clean.push((guard.inner_reg(), vreg));
+        self.current.insert(vreg, guard.inner_reg());
+        guard
+    }
+
+    /// Hands the register `src` was just loaded into over to `dest`, so copying one to the other
+    /// needs no move. Returns `None` if `dest` already has a register of its own.
+    fn coalesce(&mut self, src: VirtualReg, dest: VirtualReg) -> Option<RegisterGuard<R>> {
+        if self.current.contains_key(&dest) {
+            return None;
+        }
+
+        let reg = self.current.remove(&src)?;
+        for (_, vreg) in self.clean.iter_mut().chain(self.dirty.iter_mut()) {
+            if *vreg == src {
+                *vreg = dest;
+            }
+        }
+
+        self.current.insert(dest, reg);
+        Some(RegisterGuard::Ready(reg))
+    }
+
+    /// Gives up every register to a call, which may overwrite them. Returns the registers whose
+    /// values must be saved to their stack slots first.
+    fn call(&mut self, slots: &Slots) -> Vec<(R, u12)> {
+        for (_, vreg) in self.clean.drain(..) {
+            self.current.remove(&vreg);
+        }
+
+        let save = self
+            .dirty
+            .drain(..)
+            .map(|(reg, vreg)| {
+                self.current.remove(&vreg);
+                (reg, slots.of(vreg))
+            })
+            .collect();
+
+        self.unused = R::CALLER_SAVED.to_vec();
+        save
+    }
+}
+
+/// The stack slots of virtual registers, with a separate spill area for each register class.
+/// The float area is placed after the general-purpose one.
+#[derive(Default, Debug)]
+struct Slots {
+    general: Stack,
+    float: Stack,
+    floats: HashSet<VirtualReg>,
+}
+
+impl Slots {
+    fn of(&self, vreg: VirtualReg) -> u12 {
+        if self.floats.contains(&vreg) {
+            self.general.size + self.float.map[&vreg]
+        } else {
+            *self
+                .general
+                .map
+                .get(&vreg)
+                .unwrap_or_else(|| panic!("{} is not mapped to a stack index", vreg))
+        }
+    }
+
+    fn size(&self) -> u12 {
+        self.general.size + self.float.size
+    }
+}
+
+/// Gives every virtual register a stack slot in the spill area of its class. A slot is handed
+/// out again once its register is dead.
+fn assign_slots(bb: &BasicBlock, args: &[VirtualReg]) -> Slots {
+    let mut slots = Slots {
+        floats: bb
+            .classes
+            .iter()
+            .filter(|&(_, &class)| class == RegClass::Float)
+            .map(|(&vreg, _)| vreg)
+            .collect(),
+        ..Default::default()
+    };
+
+    let alloc = |slots: &mut Slots, vreg: VirtualReg| {
+        let stack = if slots.floats.contains(&vreg) {
+            &mut slots.float
+        } else {
+            &mut slots.general
+        };
+
+        if !stack.map.contains_key(&vreg) {
+            stack.alloc(vreg, 8);
+        }
+    };
+
+    for &vreg in args {
+        alloc(&mut slots, vreg);
+    }
+
+    // Values whose address is taken may be read through a pointer at any time, so their slot is
+    // never handed out again.
+    let addressed: HashSet<VirtualReg> = bb
+        .ops
+        .iter()
+        .filter_map(|op| match op {
+            Operation::AddressOf { val, .. } => Some(*val),
+            _ => None,
+        })
+        .collect();
+
+    let mut retiring: HashMap<usize, Vec<VirtualReg>> = HashMap::new();
+    for (vreg, last_use) in last_uses(bb) {
+        if !addressed.contains(&vreg) {
+            retiring.entry(last_use).or_default().push(vreg);
+        }
+    }
+
+    // Free slots in a fixed order so the same program always gets the same stack layout.
+    for vregs in retiring.values_mut() {
+        vregs.sort_unstable();
+    }
+
+    for (i, op) in bb.ops.iter().enumerate() {
+        let (uses, assigned) = op.vregs_used();
+        for vreg in uses.into_iter().chain(assigned) {
+            alloc(&mut slots, vreg);
+        }
+
+        if let Some(dead) = retiring.get(&i) {
+            for &vreg in dead {
+                if slots.floats.contains(&vreg) {
+                    slots.float.free(vreg);
+                } else {
+                    slots.general.free(vreg);
+                }
+            }
+        }
+    }
+
+    slots
+}
+
+/// Allocates physical registers with the given strategy.
+pub fn allocate_with(
+    bb: &BasicBlock,
+    args: &[VirtualReg],
+    allocator: RegisterAllocator,
+) -> Allocator {
+    match allocator {
+        RegisterAllocator::Linear => allocate(bb, args),
+        RegisterAllocator::Graph => allocate_graph(bb, args),
+    }
+}
+
+/// Allocates physical registers for each virtual register at every operation using it. An
+/// operation reads and writes its registers in the class it works on (see
+/// [Operation::operand_class]), while the class of a virtual register decides which spill area
+/// its stack slot is in.
 pub fn allocate(bb: &BasicBlock, args: &[VirtualReg]) -> Allocator {
     assert!(args.len() <= 8, "only 8 arguments supported");
 
+    let slots = assign_slots(bb, args);
+    let ends = last_uses(bb);
+
     let mut regmap: RegMap = args
         .iter()
         .enumerate()
@@ -178,151 +510,131 @@ pub fn allocate(bb: &BasicBlock, args: &[VirtualReg]) -> Allocator {
             )
         })
         .collect();
+    let mut float_regmap = RegMap::<FReg>::new();
 
-    let mut stack = Stack::default();
     let mut stack_saves: HashMap<usize, Vec<(Register, u12)>> = HashMap::new();
+    let mut float_stack_saves: HashMap<usize, Vec<(FReg, u12)>> = HashMap::new();
 
-    let mut locations: HashMap<VirtualReg, AllocEntry> = args
-        .iter()
-        .enumerate()
-        .map(|(i, &vreg)| {
-            (
-                vreg,
-                AllocEntry {
-                    reg: Some(Register::from_usize(i).unwrap()),
-                    stack: stack.alloc(vreg, 8),
-                },
-            )
-        })
-        .collect();
-
-    // let mut dirty_regs: Vec<(Register, VirtualReg)> = args
-    //     .iter()
-    //     .enumerate()
-    //     .map(|(i, &vreg)| (Register::from_usize(i).unwrap(), vreg))
-    //     .collect();
-
-    let mut dirty_regs = Vec::new();
-
-    let mut clean_regs: Vec<(Register, VirtualReg)> = args
-        .iter()
-        .enumerate()
-        .map(|(i, &vreg)| (Register::from_usize(i).unwrap(), vreg))
-        .collect();
+    let mut general = Pool::new(CALLER_SAVED_REGS[args.len()..].to_vec());
+    for (i, &vreg) in args.iter().enumerate() {
+        let reg = Register::from_usize(i).unwrap();
+        general.clean.push((reg, vreg));
+        general.current.insert(vreg, reg);
+    }
 
-    let mut unused_regs = CALLER_SAVED_REGS[args.len()..].to_vec();
+    let mut float = Pool::new(CALLER_SAVED_FREGS.to_vec());
 
     for (i, op) in bb.ops.iter().enumerate() {
         let (uses, assigned) = op.vregs_used();
 
         for &vreg in uses.iter() {
-            let mut entry = *locations.entry(vreg).or_insert_with(|| {
-                let stack_pos = stack.alloc(vreg, 8);
-                AllocEntry {
-                    reg: None,
-                    stack: stack_pos,
+            match op.operand_class(vreg) {
+                RegClass::General => {
+                    regmap.insert((vreg, i), general.use_reg(vreg, &slots));
+                }
+                RegClass::Float => {
+                    float_regmap.insert((vreg, i), float.use_reg(vreg, &slots));
                 }
-            });
-
-            entry.reg = None;
-
-            let guard: RegisterGuard = entry
-                .reg
-                .map(|reg| RegisterGuard::Load {
-                    load: entry.stack,
-                    reg,
-                })
-                .unwrap_or_else(|| {
-                    if let Some(reg) = unused_regs.pop() {
-                        clean_regs.push((reg, vreg));
-                        RegisterGuard::Load {
-                            load: entry.stack,
-                            reg,
-                        }
-                    } else if let Some((reg, old_vreg)) = clean_regs.pop() {
-                        locations.get_mut(&old_vreg).unwrap().reg = None;
-                        clean_regs.push((reg, vreg));
-                        RegisterGuard::Load {
-                            load: entry.stack,
-                            reg,
-                        }
-                    } else if let Some((reg, old_vreg)) = dirty_regs.pop() {
-                        let old_entry = locations.get_mut(&old_vreg).unwrap();
-                        old_entry.reg = None;
-                        clean_regs.push((reg, vreg));
-                        RegisterGuard::SaveAndLoad {
-                            save: old_entry.stack,
-                            load: entry.stack,
-                            reg,
-                        }
-                    } else {
-                        unreachable!()
-                    }
-                });
-
-            locations.get_mut(&vreg).unwrap().reg = Some(guard.inner_reg());
-            regmap.insert((vreg, i), guard);
+            }
         }
 
         if matches!(op, Operation::Call { .. }) {
-            for (_, vreg) in clean_regs.drain(..) {
-                locations.get_mut(&vreg).unwrap().reg = None;
-            }
-
-            let save = dirty_regs
-                .drain(..)
-                .map(|(reg, vreg)| {
-                    let entry = locations.get_mut(&vreg).unwrap();
-                    entry.reg = None;
-                    (reg, entry.stack)
-                })
-                .collect::<Vec<_>>();
-
-            stack_saves.insert(i, save);
-            unused_regs = CALLER_SAVED_REGS.to_vec();
+            stack_saves.insert(i, general.call(&slots));
+            float_stack_saves.insert(i, float.call(&slots));
         }
 
         if let Some(vreg) = assigned
             && !uses.contains(&vreg)
         {
-            let entry = *locations.entry(vreg).or_insert_with(|| AllocEntry {
-                reg: None,
-                stack: stack.alloc(vreg, 8),
-            });
-
-            let guard: RegisterGuard = entry.reg.map(RegisterGuard::Ready).unwrap_or_else(|| {
-                if let Some(reg) = unused_regs.pop() {
-                    clean_regs.push((reg, vreg));
-                    RegisterGuard::Ready(reg)
-                } else if let Some((reg, old_vreg)) = clean_regs.pop() {
-                    locations.get_mut(&old_vreg).unwrap().reg = None;
-                    clean_regs.push((reg, vreg));
-                    RegisterGuard::Ready(reg)
-                } else if let Some((reg, old_vreg)) = dirty_regs.pop() {
-                    let old_entry = locations.get_mut(&old_vreg).unwrap();
-                    old_entry.reg = None;
-                    clean_regs.push((reg, vreg));
-                    RegisterGuard::Save {
-                        save: old_entry.stack,
-                        reg,
-                    }
-                } else {
-                    unreachable!()
+            match op.operand_class(vreg) {
+                RegClass::General => {
+                    let guard = dying_copy_source(op, i, &ends)
+                        .and_then(|src| general.coalesce(src, vreg))
+                        .unwrap_or_else(|| general.assign_reg(vreg, &slots));
+                    regmap.insert((vreg, i), guard);
                 }
-            });
-
-            locations.get_mut(&vreg).unwrap().reg = Some(guard.inner_reg());
-            regmap.insert((vreg, i), guard);
+                RegClass::Float => {
+                    float_regmap.insert((vreg, i), float.assign_reg(vreg, &slots));
+                }
+            }
         }
     }
 
-    let a = Allocator {
+    Allocator {
         regmap,
-        stack,
+        float_regmap,
+        slots,
         stack_saves,
-    };
-    // a.print_debug();
-    a
+        float_stack_saves,
+    }
+}
+
+/// The source of a copy at operation `i` that is never read again, so the two don't interfere
+/// and the destination can be put in the register the source was loaded into.
+fn dying_copy_source(
+    op: &Operation,
+    i: usize,
+    ends: &HashMap<VirtualReg, usize>,
+) -> Option<VirtualReg> {
+    match op {
+        Operation::Assign {
+            src: SourceVal::VReg(src),
+            dest,
+        } if src != dest && ends[src] == i => Some(*src),
+        _ => None,
+    }
+}
+
+/// Finds the index of the last operation that uses or assigns each virtual register.
+///
+/// A register that is live when a loop starts stays live until the loop's backward branch, since
+/// the next iteration may read it again.
+fn last_uses(bb: &BasicBlock) -> HashMap<VirtualReg, usize> {
+    let mut first: HashMap<VirtualReg, usize> = HashMap::new();
+    let mut last: HashMap<VirtualReg, usize> = HashMap::new();
+
+    for (i, op) in bb.ops.iter().enumerate() {
+        let (uses, assigned) = op.vregs_used();
+        for vreg in uses.into_iter().chain(assigned) {
+            first.entry(vreg).or_insert(i);
+            last.insert(vreg, i);
+        }
+    }
+
+    let label_positions: HashMap<Label, usize> = bb
+        .labels
+        .iter()
+        .flat_map(|(&idx, labels)| labels.iter().map(move |&label| (label, idx)))
+        .collect();
+
+    let loops: Vec<(usize, usize)> = bb
+        .ops
+        .iter()
+        .enumerate()
+        .flat_map(|(i, op)| {
+            op.branch_targets()
+                .into_iter()
+                .filter_map(|label| label_positions.get(&label).copied())
+                .filter(move |&start| start <= i)
+                .map(move |start| (start, i))
+        })
+        .collect();
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+
+        for &(start, end) in &loops {
+            for (vreg, last_use) in last.iter_mut() {
+                if first[vreg] < start && (start..end).contains(last_use) {
+                    *last_use = end;
+                    changed = true;
+                }
+            }
+        }
+    }
+
+    last
 }
 
 /// Allocates physical registers for each virtual register at any given instruction.
@@ -350,7 +662,7 @@ pub fn _allocate(bb: &BasicBlock, args: &[VirtualReg]) -> Allocator {
         .map(|(vreg, lifetime)| (*vreg, lifetime.end().unwrap() - 1))
         .collect();
 
-    let mut regmap = RegMap::new();
+    let mut regmap: RegMap = RegMap::new();
     let mut stack = Stack::default();
     let mut stack_saves: HashMap<usize, Vec<(Register, u12)>> = HashMap::new();
 
@@ -511,40 +823,112 @@ pub fn _allocate(bb: &BasicBlock, args: &[VirtualReg]) -> Allocator {
 }
 
 /// A map from (vreg, instruction position) to a [RegisterGuard].
-type RegMap = BTreeMap<(VirtualReg, usize), RegisterGuard>;
+type RegMap<R = Register> = BTreeMap<(VirtualReg, usize), RegisterGuard<R>>;
 
 #[derive(Debug, Default)]
 pub struct Allocator {
     regmap: RegMap,
-    stack: Stack,
+    float_regmap: RegMap<FReg>,
+    slots: Slots,
     stack_saves: HashMap<usize, Vec<(Register, u12)>>,
+    float_stack_saves: HashMap<usize, Vec<(FReg, u12)>>,
 }
 
 impl Allocator {
-    pub fn map(&mut self, vreg: VirtualReg, instr_index: usize) -> RegisterGuard {
-        let entry = self
-            .regmap
-            .get_mut(&(vreg, instr_index))
-            .unwrap_or_else(|| {
-                panic!(
-                    "no physical register mapped to {} at index {}",
-                    vreg, instr_index
-                )
-            });
-
-        let guard = *entry;
-        *entry = RegisterGuard::Ready(guard.inner_reg());
-        guard
+    /// Returns the physical register of `vreg` at `instr_index`, or `None` if the allocator
+    /// gave it none there.
+    pub fn map(&mut self, vreg: VirtualReg, instr_index: usize) -> Option<RegisterGuard> {
+        take_guard(&mut self.regmap, vreg, instr_index)
+    }
+
+    /// Like [map](Self::map), for operations that work on `vreg` in a floating-point register.
+    pub fn map_float(
+        &mut self,
+        vreg: VirtualReg,
+        instr_index: usize,
+    ) -> Option<RegisterGuard<FReg>> {
+        take_guard(&mut self.float_regmap, vreg, instr_index)
     }
 
     pub fn stack_size(&self) -> u12 {
-        self.stack.size
+        self.slots.size()
     }
 
     pub fn stack_save(&self, instr_index: usize) -> Option<&Vec<(Register, u12)>> {
         self.stack_saves.get(&instr_index)
     }
 
+    pub fn float_stack_save(&self, instr_index: usize) -> Option<&Vec<(FReg, u12)>> {
+        self.float_stack_saves.get(&instr_index)
+    }
+
+    /// How many times a value is loaded from its stack slot before an operation, over all
+    /// operations.
+    pub fn loads(&self) -> usize {
+        let general = self.regmap.values().filter(|guard| {
+            matches!(
+                guard,
+                RegisterGuard::Load { .. } | RegisterGuard::SaveAndLoad { .. }
+            )
+        });
+        let float = self.float_regmap.values().filter(|guard| {
+            matches!(
+                guard,
+                RegisterGuard::Load { .. } | RegisterGuard::SaveAndLoad { .. }
+            )
+        });
+        general.count() + float.count()
+    }
+
+    /// The physical registers that virtual registers are mapped to, in ascending order.
+    pub fn used_registers(&self) -> Vec<Register> {
+        let mut registers: Vec<Register> = self
+            .regmap
+            .values()
+            .map(|guard| guard.inner_reg())
+            .collect();
+        registers.sort_by_key(|&reg| reg as u32);
+        registers.dedup();
+        registers
+    }
+
+    /// Every register assignment, as the physical register of a virtual register at an
+    /// instruction index. Sorted by virtual register, then by instruction index.
+    pub fn assignments(&self) -> Vec<(VirtualReg, usize, Register)> {
+        self.regmap
+            .iter()
+            .map(|(&(vreg, idx), guard)| (vreg, idx, guard.inner_reg()))
+            .collect()
+    }
+
+    /// Like [assignments](Self::assignments), for the floating-point registers.
+    pub fn float_assignments(&self) -> Vec<(VirtualReg, usize, FReg)> {
+        self.float_regmap
+            .iter()
+            .map(|(&(vreg, idx), guard)| (vreg, idx, guard.inner_reg()))
+            .collect()
+    }
+
+    /// Records the registers chosen for each virtual register in its lifetime intervals. Parts
+    /// of an interval where the value is not in a register are left as living on the stack.
+    pub fn annotate_lifetimes(&self, lifetimes: &mut HashMap<VirtualReg, Lifetime>) {
+        for (&(vreg, idx), guard) in self.regmap.iter() {
+            if let Some(lifetime) = lifetimes.get_mut(&vreg) {
+                lifetime.set_register(idx, Some(guard.inner_reg() as u32));
+            }
+        }
+    }
+
+    /// Prints the lifetimes of the virtual registers in `bb`, colored by the registers they
+    /// were allocated to.
+    pub fn print_lifetimes(&self, bb: &BasicBlock, color: ColorChoice) {
+        let mut lifetimes = bb.lifetimes();
+        self.annotate_lifetimes(&mut lifetimes);
+
+        let registers: Vec<u32> = self.used_registers().iter().map(|&r| r as u32).collect();
+        print_lifetimes(&lifetimes, &registers, color);
+    }
+
     pub fn print_debug(&self) {
         let mut vec: Vec<(VirtualReg, usize, RegisterGuard)> = self
             .regmap
@@ -571,219 +955,511 @@ impl Allocator {
     }
 
     pub fn stack_index_of(&self, vreg: &VirtualReg) -> u12 {
-        *self
-            .stack
-            .map
-            .get(vreg)
-            .unwrap_or_else(|| panic!("{} is not mapped to a stack index", vreg))
+        self.slots.of(*vreg)
     }
 }
 
-// #[cfg(test)]
-// mod tests {
-//     use ux::u12;
-//
-//     use super::*;
-//     use crate::ir::{BasicBlock, Operation, SourceVal, VirtualReg};
-//
-//     /// Constructs a [BasicBlock] from a list of operations for use in tests.
-//     fn make_bb(ops: Vec<Operation>) -> BasicBlock {
-//         BasicBlock {
-//             ops,
-//             labels: HashMap::new(),
-//         }
-//     }
-//
-//     // ---- Stack ----
-//
-//     #[test]
-//     fn stack_alloc_starts_at_zero() {
-//         let mut stack = Stack::default();
-//         assert_eq!(stack.size, u12::new(0));
-//         let slot = stack.alloc(8);
-//         assert_eq!(slot, u12::new(0));
-//         assert_eq!(stack.size, u12::new(1));
-//     }
-//
-//     #[test]
-//     fn stack_alloc_increments_each_call() {
-//         let mut stack = Stack::default();
-//         assert_eq!(stack.alloc(8), u12::new(0));
-//         assert_eq!(stack.alloc(8), u12::new(1));
-//         assert_eq!(stack.alloc(8), u12::new(2));
-//         assert_eq!(stack.size, u12::new(3));
-//     }
-//
-//     #[test]
-//     fn stack_free_recycles_slot() {
-//         let mut stack = Stack::default();
-//         let s0 = stack.alloc(8);
-//         let _s1 = stack.alloc(8);
-//         stack.free(s0);
-//         // stack_size does not shrink
-//         assert_eq!(stack.size, u12::new(2));
-//         // next alloc reuses the freed slot
-//         let reused = stack.alloc(8);
-//         assert_eq!(reused, s0);
-//         assert_eq!(stack.size, u12::new(2));
-//     }
-//
-//     #[test]
-//     #[should_panic]
-//     fn stack_free_out_of_bounds_panics() {
-//         let mut stack = Stack::default();
-//         stack.free(u12::new(0)); // nothing allocated yet
-//     }
-//
-//     #[test]
-//     #[should_panic]
-//     fn stack_double_free_panics() {
-//         let mut stack = Stack::default();
-//         let slot = stack.alloc(8);
-//         stack.free(slot);
-//         stack.free(slot); // second free of the same slot
-//     }
-//
-//     // ---- allocate() ----
-//
-//     #[test]
-//     fn allocate_empty_block_has_zero_stack_size() {
-//         let alloc = allocate(&make_bb(vec![]), &[]);
-//         assert_eq!(alloc.stack_size(), u12::new(0));
-//     }
-//
-//     #[test]
-//     fn allocate_single_vreg_uses_same_register() {
-//         // v0 is defined at op 0 and used at op 1; both uses should map to the
-//         // same physical register with no spill.
-//         let bb = make_bb(vec![
-//             Operation::Assign {
-//                 src: SourceVal::Immediate(42),
-//                 dest: VirtualReg(0),
-//             },
-//             Operation::Return {
-//                 value: SourceVal::VReg(VirtualReg(0)),
-//             },
-//         ]);
-//         let alloc = allocate(&bb, &[]);
-//         assert_eq!(alloc.stack_size(), u12::new(0));
-//         let g0 = alloc.map(VirtualReg(0), 0);
-//         let g1 = alloc.map(VirtualReg(0), 1);
-//         assert!(matches!(g0, RegisterGuard::Ready(_)));
-//         assert!(matches!(g1, RegisterGuard::Ready(_)));
-//         assert_eq!(g0.inner_reg(), g1.inner_reg());
-//     }
-//
-//     #[test]
-//     fn allocate_simultaneously_live_vregs_get_distinct_registers() {
-//         // v0 and v1 are both live at the Add (op 2), so they must occupy
-//         // different physical registers.
-//         let bb = make_bb(vec![
-//             Operation::Assign {
-//                 src: SourceVal::Immediate(1),
-//                 dest: VirtualReg(0),
-//             },
-//             Operation::Assign {
-//                 src: SourceVal::Immediate(2),
-//                 dest: VirtualReg(1),
-//             },
-//             Operation::Add {
-//                 a: VirtualReg(0),
-//                 b: VirtualReg(1),
-//                 dest: VirtualReg(2),
-//             },
-//             Operation::Return {
-//                 value: SourceVal::VReg(VirtualReg(2)),
-//             },
-//         ]);
-//         let alloc = allocate(&bb, &[]);
-//         assert_eq!(alloc.stack_size(), u12::new(0));
-//         let g0 = alloc.map(VirtualReg(0), 2);
-//         let g1 = alloc.map(VirtualReg(1), 2);
-//         let g2 = alloc.map(VirtualReg(2), 2);
-//         assert!(matches!(g0, RegisterGuard::Ready(_)));
-//         assert!(matches!(g1, RegisterGuard::Ready(_)));
-//         assert!(matches!(g2, RegisterGuard::Ready(_)));
-//         assert_ne!(g0.inner_reg(), g1.inner_reg());
-//         assert_ne!(g0.inner_reg(), g2.inner_reg());
-//         assert_ne!(g1.inner_reg(), g2.inner_reg());
-//     }
-//
-//     #[test]
-//     fn allocate_uses_caller_saved_registers() {
-//         let bb = make_bb(vec![
-//             Operation::Assign {
-//                 src: SourceVal::Immediate(5),
-//                 dest: VirtualReg(0),
-//             },
-//             Operation::Return {
-//                 value: SourceVal::VReg(VirtualReg(0)),
-//             },
-//         ]);
-//         let alloc = allocate(&bb, &[]);
-//         let reg = alloc.map(VirtualReg(0), 0).inner_reg();
-//         assert!(CALLER_SAVED_REGS.contains(&reg));
-//     }
-//
-//     // ---- Allocator::stack_save ----
-//
-//     #[test]
-//     fn allocator_stack_save_none_for_non_call_ops() {
-//         let bb = make_bb(vec![
-//             Operation::Assign {
-//                 src: SourceVal::Immediate(1),
-//                 dest: VirtualReg(0),
-//             },
-//             Operation::Return {
-//                 value: SourceVal::VReg(VirtualReg(0)),
-//             },
-//         ]);
-//         let alloc = allocate(&bb, &[]);
-//         assert!(alloc.stack_save(0).is_none());
-//         assert!(alloc.stack_save(1).is_none());
-//     }
-//
-//     #[test]
-//     fn allocator_stack_save_some_for_call_when_registers_are_live() {
-//         // v0 is assigned before a Call, so its register must be preserved.
-//         let bb = make_bb(vec![
-//             Operation::Assign {
-//                 src: SourceVal::Immediate(1),
-//                 dest: VirtualReg(0),
-//             },
-//             Operation::Call {
-//                 function: String::from("foo"),
-//                 args: vec![],
-//                 dest: None,
-//             },
-//             Operation::Return {
-//                 value: SourceVal::VReg(VirtualReg(0)),
-//             },
-//         ]);
-//         let alloc = allocate(&bb, &[]);
-//         let saves = alloc.stack_save(1);
-//         assert!(saves.is_some());
-//         assert!(!saves.unwrap().is_empty());
-//     }
-//
-//     #[test]
-//     fn allocator_stack_save_none_for_call_when_no_registers_are_live() {
-//         // No vregs have been assigned before the Call, so nothing needs saving.
-//         let bb = make_bb(vec![Operation::Call {
-//             function: String::from("foo"),
-//             args: vec![],
-//             dest: None,
-//         }]);
-//         let alloc = allocate(&bb, &[]);
-//         assert!(alloc.stack_save(0).is_none());
-//     }
-//
-//     // ---- Allocator::map ----
-//
-//     #[test]
-//     #[should_panic]
-//     fn allocator_map_panics_for_unknown_vreg() {
-//         let alloc = allocate(&make_bb(vec![]), &[]);
-//         alloc.map(VirtualReg(99), 0);
-//     }
-// }
+/// Takes the guard of `vreg` at `instr_index` from `regmap`. The value is in the register after
+/// the guard is unwrapped, so later lookups at the same index get the register as it is.
+fn take_guard<R: RegisterClass>(
+    regmap: &mut RegMap<R>,
+    vreg: VirtualReg,
+    instr_index: usize,
+) -> Option<RegisterGuard<R>> {
+    let entry = regmap.get_mut(&(vreg, instr_index))?;
+
+    let guard = *entry;
+    *entry = RegisterGuard::Ready(guard.inner_reg());
+    Some(guard)
+}
+
+#[cfg(test)]
+mod tests {
+    use ux::u12;
+
+    use super::*;
+
+    use crate::ir::{
+        BasicBlock, FloatOp, Item, Label, Operation, RegClass, VirtualReg, generate,
+        lifetime::write_lifetimes,
+    };
+
+    /// Compiles `source` down to IR and returns the block and arguments of `main`.
+    fn main_block(source: &str) -> (BasicBlock, Vec<VirtualReg>) {
+        let Item::Function { bb, args, .. } = generate(source)
+            .items
+            .into_iter()
+            .find(|Item::Function { name, .. }| name == "main")
+            .unwrap();
+
+        (bb, args)
+    }
+
+    /// Compiles `source` down to IR and returns the stack size allocated for `main`.
+    fn main_frame_size(source: &str) -> u12 {
+        let (bb, args) = main_block(source);
+        allocate(&bb, &args).stack_size()
+    }
+
+    // ---- Stack ----
+
+    #[test]
+    fn stack_alloc_starts_at_zero() {
+        let mut stack = Stack::default();
+        assert_eq!(stack.size, u12::new(0));
+        let slot = stack.alloc(VirtualReg(0), 8);
+        assert_eq!(slot, u12::new(0));
+        assert_eq!(stack.size, u12::new(1));
+    }
+
+    #[test]
+    fn stack_alloc_increments_each_call() {
+        let mut stack = Stack::default();
+        assert_eq!(stack.alloc(VirtualReg(0), 8), u12::new(0));
+        assert_eq!(stack.alloc(VirtualReg(1), 8), u12::new(1));
+        assert_eq!(stack.alloc(VirtualReg(2), 8), u12::new(2));
+        assert_eq!(stack.size, u12::new(3));
+    }
+
+    #[test]
+    fn stack_free_recycles_slot() {
+        let mut stack = Stack::default();
+        let s0 = stack.alloc(VirtualReg(0), 8);
+        let _s1 = stack.alloc(VirtualReg(1), 8);
+        stack.free(VirtualReg(0));
+        // stack_size does not shrink
+        assert_eq!(stack.size, u12::new(2));
+        // next alloc reuses the freed slot
+        let reused = stack.alloc(VirtualReg(2), 8);
+        assert_eq!(reused, s0);
+        assert_eq!(stack.size, u12::new(2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn stack_free_unallocated_panics() {
+        let mut stack = Stack::default();
+        stack.free(VirtualReg(0)); // nothing allocated yet
+    }
+
+    #[test]
+    #[should_panic]
+    fn stack_double_free_panics() {
+        let mut stack = Stack::default();
+        stack.alloc(VirtualReg(0), 8);
+        stack.free(VirtualReg(0));
+        stack.free(VirtualReg(0)); // second free of the same slot
+    }
+
+    #[test]
+    fn allocate_recycles_dead_stack_slots() {
+        // Each pair of vregs dies before the next pair is assigned, so the frame should only ever
+        // need room for one pair.
+        let ops = (0..32)
+            .flat_map(|n| {
+                let (a, b) = (VirtualReg(2 * n as u32), VirtualReg(2 * n as u32 + 1));
+                [Operation::assign_imm(a, n), Operation::add(b, a, a)]
+            })
+            .collect();
+
+        let alloc = allocate(&BasicBlock::from_ops(ops), &[]);
+        assert_eq!(alloc.stack_size(), u12::new(2));
+    }
+
+    #[test]
+    fn sibling_scopes_share_stack_slots() {
+        let block = "
+                a := 1;
+                b := 2;
+                c := 3;
+                d := 4;
+                e := 5;
+                f := 6;
+                g := a + b + c + d + e + f;
+                x = x + g;
+        ";
+
+        let one = format!("fn main() -> i64 {{ x := 0; if x == 0 {{ {block} }} return x; }}");
+        let two = format!(
+            "fn main() -> i64 {{ x := 0; if x == 0 {{ {block} }} if x != 0 {{ {block} }} return x; }}"
+        );
+
+        assert_eq!(main_frame_size(&two), main_frame_size(&one));
+    }
+
+    #[test]
+    fn allocate_keeps_slots_live_across_loops() {
+        // v0 is last read at op 2, but the branch at op 4 loops back to op 1 where it is read
+        // again, so v3 must not take its slot.
+        let mut bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1),
+            Operation::assign_imm(VirtualReg(1), 2),
+            Operation::add(VirtualReg(2), VirtualReg(0), VirtualReg(1)),
+            Operation::assign_imm(VirtualReg(3), 3),
+            Operation::BranchIf {
+                cond: VirtualReg(3),
+                label: Label::N(0),
+            },
+        ]);
+        bb.labels.insert(1, vec![Label::N(0)]);
+
+        let alloc = allocate(&bb, &[]);
+        assert_ne!(
+            alloc.stack_index_of(&VirtualReg(3)),
+            alloc.stack_index_of(&VirtualReg(0))
+        );
+    }
+
+    #[test]
+    fn assignments_of_simultaneously_live_vregs() {
+        // v0 and v1 are both live at the Add (op 2), so they must occupy different registers.
+        let bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1),
+            Operation::assign_imm(VirtualReg(1), 2),
+            Operation::add(VirtualReg(2), VirtualReg(0), VirtualReg(1)),
+            Operation::ret_reg(VirtualReg(2)),
+        ]);
+
+        let alloc = allocate(&bb, &[]);
+        assert_eq!(
+            alloc.assignments(),
+            vec![
+                (VirtualReg(0), 0, Register::X15),
+                (VirtualReg(0), 2, Register::X13),
+                (VirtualReg(1), 1, Register::X14),
+                (VirtualReg(1), 2, Register::X12),
+                (VirtualReg(2), 2, Register::X11),
+                (VirtualReg(2), 3, Register::X10),
+            ]
+        );
+    }
+
+    #[test]
+    fn float_vregs_use_float_registers() {
+        // v0 and v1 are added as floats and the sum is truncated into v3, which is then added
+        // to the integer v4.
+        let mut bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1.5f64.to_bits() as i64),
+            Operation::assign_imm(VirtualReg(1), 2.5f64.to_bits() as i64),
+            Operation::FloatArithmetic {
+                op: FloatOp::Add,
+                a: VirtualReg(0),
+                b: VirtualReg(1),
+                dest: VirtualReg(2),
+            },
+            Operation::FloatToInt {
+                src: VirtualReg(2),
+                dest: VirtualReg(3),
+            },
+            Operation::assign_imm(VirtualReg(4), 1),
+            Operation::add(VirtualReg(5), VirtualReg(3), VirtualReg(4)),
+            Operation::ret_reg(VirtualReg(5)),
+        ]);
+        let floats = [VirtualReg(0), VirtualReg(1), VirtualReg(2)];
+        bb.classes = floats.iter().map(|&v| (v, RegClass::Float)).collect();
+
+        let alloc = allocate(&bb, &[]);
+
+        let float_uses: Vec<(VirtualReg, usize)> = alloc
+            .float_assignments()
+            .into_iter()
+            .map(|(vreg, idx, _)| (vreg, idx))
+            .collect();
+        assert_eq!(
+            float_uses,
+            [
+                (VirtualReg(0), 2),
+                (VirtualReg(1), 2),
+                (VirtualReg(2), 2),
+                (VirtualReg(2), 3),
+            ]
+        );
+
+        // the float operations see no general-purpose registers
+        assert!(
+            alloc
+                .assignments()
+                .iter()
+                .all(|&(vreg, idx, _)| !(2..=3).contains(&idx) || vreg == VirtualReg(3))
+        );
+
+        // the float spill area comes after every general-purpose slot
+        let first_float = floats.iter().map(|v| alloc.stack_index_of(v)).min();
+        let last_general = (3..6).map(|n| alloc.stack_index_of(&VirtualReg(n))).max();
+        assert!(first_float > last_general);
+    }
+
+    #[test]
+    fn every_use_is_assigned_a_register() {
+        let bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1),
+            Operation::add(VirtualReg(1), VirtualReg(0), 2),
+            Operation::add(VirtualReg(2), VirtualReg(1), VirtualReg(0)),
+            Operation::ret_reg(VirtualReg(2)),
+        ]);
+
+        let alloc = allocate(&bb, &[]);
+        let assignments = alloc.assignments();
+        for (idx, op) in bb.ops.iter().enumerate() {
+            let mut vregs = Vec::new();
+            op.all_vregs(&mut vregs);
+            for vreg in vregs {
+                assert!(
+                    assignments.iter().any(|&(v, i, _)| v == vreg && i == idx),
+                    "{} has no register at op {}",
+                    vreg,
+                    idx
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn sources_of_one_op_get_distinct_registers() {
+        let bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1),
+            Operation::assign_imm(VirtualReg(1), 2),
+            Operation::assign_imm(VirtualReg(2), 3),
+            Operation::add(VirtualReg(3), VirtualReg(0), VirtualReg(1)),
+            Operation::add(VirtualReg(4), VirtualReg(3), VirtualReg(2)),
+            Operation::ret_reg(VirtualReg(4)),
+        ]);
+
+        let alloc = allocate(&bb, &[]);
+        for idx in [3, 4] {
+            let sources = bb.ops[idx].vregs_used().0;
+            let mut registers: Vec<Register> = alloc
+                .assignments()
+                .into_iter()
+                .filter(|&(vreg, i, _)| i == idx && sources.contains(&vreg))
+                .map(|(_, _, reg)| reg)
+                .collect();
+            let count = registers.len();
+            registers.sort_by_key(|&reg| reg as u32);
+            registers.dedup();
+            assert_eq!(registers.len(), count, "registers shared at op {}", idx);
+        }
+    }
+
+    #[test]
+    fn in_place_update_uses_one_register_per_op() {
+        // `a` is both read and written by each addition, so the source and destination of the
+        // op must share the one register mapped for it there.
+        let (bb, args) = main_block("fn main() -> i64 { a := 1; a = a + 1; a = a + 1; return a; }");
+        let alloc = allocate(&bb, &args);
+
+        let indices: Vec<usize> = alloc
+            .assignments()
+            .into_iter()
+            .filter(|&(vreg, ..)| vreg == VirtualReg(0))
+            .map(|(_, idx, _)| idx)
+            .collect();
+        assert_eq!(indices, [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn copy_takes_over_the_register_of_a_dying_source() {
+        let register_at = |alloc: &Allocator, vreg: VirtualReg, idx: usize| {
+            alloc
+                .assignments()
+                .into_iter()
+                .find(|&(v, i, _)| v == vreg && i == idx)
+                .map(|(.., reg)| reg)
+                .unwrap()
+        };
+
+        let bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1),
+            Operation::Assign {
+                src: VirtualReg(0).into(),
+                dest: VirtualReg(1),
+            },
+            Operation::ret_reg(VirtualReg(1)),
+        ]);
+        let alloc = allocate(&bb, &[]);
+        assert_eq!(
+            register_at(&alloc, VirtualReg(0), 1),
+            register_at(&alloc, VirtualReg(1), 1)
+        );
+
+        // the source is read again, so both need a register of their own
+        let bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1),
+            Operation::Assign {
+                src: VirtualReg(0).into(),
+                dest: VirtualReg(1),
+            },
+            Operation::add(VirtualReg(2), VirtualReg(1), VirtualReg(0)),
+            Operation::ret_reg(VirtualReg(2)),
+        ]);
+        let alloc = allocate(&bb, &[]);
+        assert_ne!(
+            register_at(&alloc, VirtualReg(0), 1),
+            register_at(&alloc, VirtualReg(1), 1)
+        );
+    }
+
+    //     // ---- allocate() ----
+    //
+    //     #[test]
+    //     fn allocate_empty_block_has_zero_stack_size() {
+    //         let alloc = allocate(&BasicBlock::from_ops(vec![]), &[]);
+    //         assert_eq!(alloc.stack_size(), u12::new(0));
+    //     }
+    //
+    //     #[test]
+    //     fn allocate_single_vreg_uses_same_register() {
+    //         // v0 is defined at op 0 and used at op 1; both uses should map to the
+    //         // same physical register with no spill.
+    //         let bb = BasicBlock::from_ops(vec![
+    //             Operation::Assign {
+    //                 src: SourceVal::Immediate(42),
+    //                 dest: VirtualReg(0),
+    //             },
+    //             Operation::Return {
+    //                 value: SourceVal::VReg(VirtualReg(0)),
+    //             },
+    //         ]);
+    //         let alloc = allocate(&bb, &[]);
+    //         assert_eq!(alloc.stack_size(), u12::new(0));
+    //         let g0 = alloc.map(VirtualReg(0), 0);
+    //         let g1 = alloc.map(VirtualReg(0), 1);
+    //         assert!(matches!(g0, RegisterGuard::Ready(_)));
+    //         assert!(matches!(g1, RegisterGuard::Ready(_)));
+    //         assert_eq!(g0.inner_reg(), g1.inner_reg());
+    //     }
+    //
+    //     #[test]
+    //     fn allocate_simultaneously_live_vregs_get_distinct_registers() {
+    //         // v0 and v1 are both live at the Add (op 2), so they must occupy
+    //         // different physical registers.
+    //         let bb = BasicBlock::from_ops(vec![
+    //             Operation::Assign {
+    //                 src: SourceVal::Immediate(1),
+    //                 dest: VirtualReg(0),
+    //             },
+    //             Operation::Assign {
+    //                 src: SourceVal::Immediate(2),
+    //                 dest: VirtualReg(1),
+    //             },
+    //             Operation::Add {
+    //                 a: VirtualReg(0),
+    //                 b: VirtualReg(1),
+    //                 dest: VirtualReg(2),
+    //             },
+    //             Operation::Return {
+    //                 value: SourceVal::VReg(VirtualReg(2)),
+    //             },
+    //         ]);
+    //         let alloc = allocate(&bb, &[]);
+    //         assert_eq!(alloc.stack_size(), u12::new(0));
+    //         let g0 = alloc.map(VirtualReg(0), 2);
+    //         let g1 = alloc.map(VirtualReg(1), 2);
+    //         let g2 = alloc.map(VirtualReg(2), 2);
+    //         assert!(matches!(g0, RegisterGuard::Ready(_)));
+    //         assert!(matches!(g1, RegisterGuard::Ready(_)));
+    //         assert!(matches!(g2, RegisterGuard::Ready(_)));
+    //         assert_ne!(g0.inner_reg(), g1.inner_reg());
+    //         assert_ne!(g0.inner_reg(), g2.inner_reg());
+    //         assert_ne!(g1.inner_reg(), g2.inner_reg());
+    //     }
+    //
+    //     #[test]
+    //     fn allocate_uses_caller_saved_registers() {
+    //         let bb = BasicBlock::from_ops(vec![
+    //             Operation::Assign {
+    //                 src: SourceVal::Immediate(5),
+    //                 dest: VirtualReg(0),
+    //             },
+    //             Operation::Return {
+    //                 value: SourceVal::VReg(VirtualReg(0)),
+    //             },
+    //         ]);
+    //         let alloc = allocate(&bb, &[]);
+    //         let reg = alloc.map(VirtualReg(0), 0).inner_reg();
+    //         assert!(CALLER_SAVED_REGS.contains(&reg));
+    //     }
+    //
+    //     // ---- Allocator::stack_save ----
+    //
+    //     #[test]
+    //     fn allocator_stack_save_none_for_non_call_ops() {
+    //         let bb = BasicBlock::from_ops(vec![
+    //             Operation::Assign {
+    //                 src: SourceVal::Immediate(1),
+    //                 dest: VirtualReg(0),
+    //             },
+    //             Operation::Return {
+    //                 value: SourceVal::VReg(VirtualReg(0)),
+    //             },
+    //         ]);
+    //         let alloc = allocate(&bb, &[]);
+    //         assert!(alloc.stack_save(0).is_none());
+    //         assert!(alloc.stack_save(1).is_none());
+    //     }
+    //
+    //     #[test]
+    //     fn allocator_stack_save_some_for_call_when_registers_are_live() {
+    //         // v0 is assigned before a Call, so its register must be preserved.
+    //         let bb = BasicBlock::from_ops(vec![
+    //             Operation::Assign {
+    //                 src: SourceVal::Immediate(1),
+    //                 dest: VirtualReg(0),
+    //             },
+    //             Operation::Call {
+    //                 function: String::from("foo"),
+    //                 args: vec![],
+    //                 dest: None,
+    //             },
+    //             Operation::Return {
+    //                 value: SourceVal::VReg(VirtualReg(0)),
+    //             },
+    //         ]);
+    //         let alloc = allocate(&bb, &[]);
+    //         let saves = alloc.stack_save(1);
+    //         assert!(saves.is_some());
+    //         assert!(!saves.unwrap().is_empty());
+    //     }
+    //
+    //     #[test]
+    //     fn allocator_stack_save_none_for_call_when_no_registers_are_live() {
+    //         // No vregs have been assigned before the Call, so nothing needs saving.
+    //         let bb = BasicBlock::from_ops(vec![Operation::Call {
+    //             function: String::from("foo"),
+    //             args: vec![],
+    //             dest: None,
+    //         }]);
+    //         let alloc = allocate(&bb, &[]);
+    //         assert!(alloc.stack_save(0).is_none());
+    //     }
+    //
+    //     // ---- Allocator::map ----
+    //
+    //     #[test]
+    //     #[should_panic]
+    //     fn allocator_map_panics_for_unknown_vreg() {
+    //         let alloc = allocate(&BasicBlock::from_ops(vec![]), &[]);
+    //         alloc.map(VirtualReg(99), 0);
+    //     }
+
+    // ---- Lifetime printer ----
+
+    #[test]
+    fn lifetime_legend_lists_assigned_registers() {
+        // assigning a takes the last unused register, and reading it back for the return the
+        // one before it
+        let (bb, args) = main_block("fn main() -> i64 { a := 2; return a; }");
+        let alloc = allocate(&bb, &args);
+
+        let mut lifetimes = bb.lifetimes();
+        alloc.annotate_lifetimes(&mut lifetimes);
+
+        let registers: Vec<u32> = alloc.used_registers().iter().map(|&r| r as u32).collect();
+
+        let mut out = Vec::new();
+        write_lifetimes(&mut out, &lifetimes, &registers, ColorChoice::Never).unwrap();
+        let out = String::from_utf8(out).unwrap();
+
+        assert_eq!(out.lines().next().unwrap(), "\u{2591} Stack X14 X15");
+    }
+}
@@ -0,0 +1,338 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use num_traits::FromPrimitive;
+
+use crate::{
+    ir::{BasicBlock, Operation, RegClass, VirtualReg},
+    synthesize::arch::arm::reg::{
+        Allocator, FReg, RegMap, Register, RegisterGuard, Slots, assign_slots, last_uses,
+    },
+};
+
+use FReg::*;
+use Register::*;
+
+/// General-purpose registers a value keeps for its whole life. The argument registers are left
+/// out, since calls and returns move values into them.
+const GENERAL_COLORS: &[Register] = &[X9, X10, X11, X12, X13, X14, X15];
+
+/// General-purpose registers spilled values are loaded into, for one operation at a time.
+/// Handed out from the back, so the result of a call is never put in X1, where a second result
+/// is returned.
+const GENERAL_SCRATCH: &[Register] = &[X0, X1, X2, X3, X4, X5, X6, X7, X8];
+
+const FLOAT_COLORS: &[FReg] = &[
+    V16, V17, V18, V19, V20, V21, V22, V23, V24, V25, V26, V27, V28, V29, V30, V31,
+];
+
+const FLOAT_SCRATCH: &[FReg] = &[V0, V1, V2, V3, V4, V5, V6, V7];
+
+/// Allocates physical registers by coloring a graph of the virtual registers that are live at
+/// the same time. A colored virtual register stays in its register from its first use to its
+/// last, while the others are spilled, and loaded into a scratch register at every use.
+///
+/// Values are still written through to their stack slots, like with [allocate](super::allocate).
+/// Virtual registers that are live across a call, whose address is taken, that are used in
+/// more than one class of register, or that are arguments are always spilled. When no
+/// register is left for a value, the one that interferes with the most others is spilled.
+pub fn allocate_graph(bb: &BasicBlock, args: &[VirtualReg]) -> Allocator {
+    assert!(args.len() <= 8, "only 8 arguments supported");
+
+    let slots = assign_slots(bb, args);
+
+    // first and last use of every virtual register, the last one stretched over the loops it
+    // is live in
+    let ends = last_uses(bb);
+    let ranges: HashMap<VirtualReg, (usize, usize)> = bb
+        .lifetimes()
+        .into_iter()
+        .filter_map(|(vreg, lifetime)| Some((vreg, (lifetime.start()?, ends[&vreg]))))
+        .collect();
+
+    let mut classes: HashMap<VirtualReg, RegClass> = HashMap::new();
+    let mut spilled: HashSet<VirtualReg> = args.iter().copied().collect();
+    for op in &bb.ops {
+        let (uses, assigned) = op.vregs_used();
+        for vreg in uses.into_iter().chain(assigned) {
+            let class = op.operand_class(vreg);
+            if *classes.entry(vreg).or_insert(class) != class {
+                spilled.insert(vreg);
+            }
+        }
+
+        if let Operation::AddressOf { val, .. } = *op {
+            spilled.insert(val);
+        }
+    }
+
+    let calls: Vec<usize> = bb
+        .ops
+        .iter()
+        .enumerate()
+        .filter(|(_, op)| matches!(op, Operation::Call { .. }))
+        .map(|(idx, _)| idx)
+        .collect();
+    for (&vreg, &(start, end)) in &ranges {
+        if calls.iter().any(|&call| start < call && call < end) {
+            spilled.insert(vreg);
+        }
+    }
+
+    let candidates = |class: RegClass| -> BTreeSet<VirtualReg> {
+        ranges
+            .keys()
+            .filter(|vreg| !spilled.contains(vreg) && classes.get(vreg) == Some(&class))
+            .copied()
+            .collect()
+    };
+    let general = color(&candidates(RegClass::General), &ranges, GENERAL_COLORS);
+    let float = color(&candidates(RegClass::Float), &ranges, FLOAT_COLORS);
+
+    let mut regmap: RegMap = args
+        .iter()
+        .enumerate()
+        .map(|(i, &vreg)| {
+            (
+                (vreg, 0),
+                RegisterGuard::Ready(Register::from_usize(i).unwrap()),
+            )
+        })
+        .collect();
+    let mut float_regmap = RegMap::<FReg>::new();
+
+    for (i, op) in bb.ops.iter().enumerate() {
+        let mut general_scratch = GENERAL_SCRATCH.to_vec();
+        let mut float_scratch = FLOAT_SCRATCH.to_vec();
+        if i == 0 {
+            // the arguments are still in the registers they were passed in
+            general_scratch.retain(|&reg| reg as usize >= args.len());
+        }
+
+        let (uses, assigned) = op.vregs_used();
+        let assigned = assigned.filter(|vreg| !uses.contains(vreg));
+        let vregs = assigned
+            .into_iter()
+            .map(|vreg| (vreg, false))
+            .chain(uses.into_iter().map(|vreg| (vreg, true)));
+
+        for (vreg, is_use) in vregs {
+            if i == 0 && args.contains(&vreg) {
+                continue;
+            }
+
+            match op.operand_class(vreg) {
+                RegClass::General => {
+                    let guard = guard(vreg, is_use, &general, &mut general_scratch, &slots);
+                    regmap.insert((vreg, i), guard);
+                }
+                RegClass::Float => {
+                    let guard = guard(vreg, is_use, &float, &mut float_scratch, &slots);
+                    float_regmap.insert((vreg, i), guard);
+                }
+            }
+        }
+    }
+
+    Allocator {
+        regmap,
+        float_regmap,
+        slots,
+        stack_saves: HashMap::new(),
+        float_stack_saves: HashMap::new(),
+    }
+}
+
+/// The register of `vreg` at one operation: its color if it has one, or else a scratch
+/// register it is loaded into if the operation reads it.
+fn guard<R: Copy>(
+    vreg: VirtualReg,
+    is_use: bool,
+    colored: &HashMap<VirtualReg, R>,
+    scratch: &mut Vec<R>,
+    slots: &Slots,
+) -> RegisterGuard<R> {
+    if let Some(&reg) = colored.get(&vreg) {
+        return RegisterGuard::Ready(reg);
+    }
+
+    let reg = scratch
+        .pop()
+        .expect("an operation uses more registers than there are scratch registers");
+    if is_use {
+        RegisterGuard::Load {
+            load: slots.of(vreg),
+            reg,
+        }
+    } else {
+        RegisterGuard::Ready(reg)
+    }
+}
+
+/// Colors `nodes` with `colors`, where two nodes interfere if their live ranges overlap. Nodes
+/// with fewer neighbors than there are colors are set aside until none are left, and then the
+/// node with the most neighbors is spilled. The nodes set aside are colored in reverse, each
+/// with a color none of its neighbors have.
+fn color<R: Copy + PartialEq>(
+    nodes: &BTreeSet<VirtualReg>,
+    ranges: &HashMap<VirtualReg, (usize, usize)>,
+    colors: &[R],
+) -> HashMap<VirtualReg, R> {
+    let neighbors: HashMap<VirtualReg, Vec<VirtualReg>> = nodes
+        .iter()
+        .map(|&vreg| {
+            let (start, end) = ranges[&vreg];
+            let interfering = nodes
+                .iter()
+                .copied()
+                .filter(|other| {
+                    let (other_start, other_end) = ranges[other];
+                    *other != vreg && start <= other_end && other_start <= end
+                })
+                .collect();
+            (vreg, interfering)
+        })
+        .collect();
+
+    let mut remaining = nodes.clone();
+    let degree = |remaining: &BTreeSet<VirtualReg>, vreg: &VirtualReg| {
+        neighbors[vreg]
+            .iter()
+            .filter(|other| remaining.contains(other))
+            .count()
+    };
+
+    let mut simplified = Vec::new();
+    while !remaining.is_empty() {
+        let next = remaining
+            .iter()
+            .copied()
+            .find(|vreg| degree(&remaining, vreg) < colors.len());
+
+        let vreg = match next {
+            Some(vreg) => {
+                simplified.push(vreg);
+                vreg
+            }
+            // spilled, and left without a color
+            None => remaining
+                .iter()
+                .copied()
+                .max_by_key(|vreg| degree(&remaining, vreg))
+                .unwrap(),
+        };
+        remaining.remove(&vreg);
+    }
+
+    let mut colored = HashMap::new();
+    while let Some(vreg) = simplified.pop() {
+        let taken: Vec<R> = neighbors[&vreg]
+            .iter()
+            .filter_map(|other| colored.get(other).copied())
+            .collect();
+        let reg = colors
+            .iter()
+            .copied()
+            .find(|reg| !taken.contains(reg))
+            .expect("a node set aside has fewer neighbors than colors");
+        colored.insert(vreg, reg);
+    }
+
+    colored
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesize::arch::arm::reg::allocate;
+
+    /// Assigns `count` constants and then adds them all up, so they are all live at once.
+    fn sum_of_constants(count: u32) -> BasicBlock {
+        let mut ops: Vec<Operation> = (0..count)
+            .map(|n| Operation::assign_imm(VirtualReg(n), n as i64))
+            .collect();
+
+        let mut sum = VirtualReg(0);
+        for n in 1..count {
+            let dest = VirtualReg(count + n);
+            ops.push(Operation::add(dest, sum, VirtualReg(n)));
+            sum = dest;
+        }
+        ops.push(Operation::ret_reg(sum));
+
+        BasicBlock::from_ops(ops)
+    }
+
+    #[test]
+    fn colored_values_are_never_loaded() {
+        let bb = sum_of_constants(4);
+
+        assert_eq!(allocate_graph(&bb, &[]).loads(), 0);
+        assert!(allocate(&bb, &[]).loads() > 0);
+    }
+
+    #[test]
+    fn spills_when_too_many_values_are_live() {
+        let bb = sum_of_constants(GENERAL_COLORS.len() as u32 + 3);
+
+        let graph = allocate_graph(&bb, &[]);
+        let linear = allocate(&bb, &[]);
+        assert!(graph.loads() > 0);
+        assert!(graph.loads() < linear.loads());
+
+        // values that are live at the same time never share a register
+        let ops = bb.ops.len();
+        for idx in 0..ops {
+            let registers: Vec<Register> = graph
+                .assignments()
+                .into_iter()
+                .filter(|&(_, i, _)| i == idx)
+                .map(|(_, _, reg)| reg)
+                .collect();
+            let mut unique = registers.clone();
+            unique.sort_by_key(|&reg| reg as u32);
+            unique.dedup();
+            assert_eq!(
+                unique.len(),
+                registers.len(),
+                "registers shared at op {}",
+                idx
+            );
+        }
+    }
+
+    #[test]
+    fn values_live_across_calls_are_reloaded() {
+        let bb = BasicBlock::from_ops(vec![
+            Operation::assign_imm(VirtualReg(0), 1),
+            Operation::Call {
+                function: String::from("f"),
+                args: Vec::new(),
+                dest: None,
+            },
+            Operation::ret_reg(VirtualReg(0)),
+        ]);
+
+        let mut alloc = allocate_graph(&bb, &[]);
+        assert!(matches!(
+            alloc.map(VirtualReg(0), 2),
+            Some(RegisterGuard::Load { .. })
+        ));
+    }
+
+    #[test]
+    fn arguments_are_in_their_registers_on_entry() {
+        let bb = BasicBlock::from_ops(vec![
+            Operation::add(VirtualReg(2), VirtualReg(0), VirtualReg(1)),
+            Operation::ret_reg(VirtualReg(2)),
+        ]);
+
+        let alloc = allocate_graph(&bb, &[VirtualReg(0), VirtualReg(1)]);
+        let at_entry: Vec<(VirtualReg, Register)> = alloc
+            .assignments()
+            .into_iter()
+            .filter(|&(vreg, idx, _)| idx == 0 && vreg != VirtualReg(2))
+            .map(|(vreg, _, reg)| (vreg, reg))
+            .collect();
+        assert_eq!(at_entry, [(VirtualReg(0), X0), (VirtualReg(1), X1)]);
+    }
+}
@@ -0,0 +1,826 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::{
+    analyze::{ErrorContext, ErrorVec, Span},
+    ir::{Condition, FloatOp, IR, Item, Label, Operation, SourceVal, VirtualReg},
+    synthesize::{
+        arch::{AssembleOptions, Assembler, MachineCode, UnfinishedCode},
+        target::Arch,
+    },
+};
+
+pub mod encode;
+
+use encode::{Section, op};
+
+const MAIN_FN: &str = "main";
+
+/// Module that functions which are declared but not defined are imported from.
+const IMPORT_MODULE: &str = "env";
+
+/// Functions that are emitted inline at the call site instead of being called.
+const INTRINSICS: &[&str] = &["assert", "abs", "min", "max"];
+
+/// Lowers the IR to a WebAssembly module, which ends up as the instructions of the
+/// [MachineCode]. Every virtual register becomes an `i64` local, with `f64` values kept as their
+/// bits like in the IR, so no registers have to be allocated. Every function takes and returns
+/// `i64` values, and `main` is exported.
+#[derive(Default)]
+pub struct WasmAssembler {
+    code: MachineCode,
+    options: AssembleOptions,
+    errors: ErrorContext,
+}
+
+impl Assembler for WasmAssembler {
+    const ARCH: Arch = Arch::Wasm32;
+
+    fn assemble_with_options(
+        ir: IR,
+        options: AssembleOptions,
+    ) -> Result<UnfinishedCode<Self>, ErrorVec> {
+        let mut asm = WasmAssembler {
+            errors: ErrorContext::with_color(options.color),
+            options,
+            ..Default::default()
+        };
+
+        asm.assemble_module(ir);
+
+        if !asm.errors.is_empty() {
+            return Err(asm.errors.take_errors());
+        }
+
+        Ok(UnfinishedCode(asm))
+    }
+
+    fn current_offset(&self) -> usize {
+        self.code.instructions.len()
+    }
+
+    fn str_literals(&self) -> &[String] {
+        &self.code.str_literals
+    }
+
+    fn externs(&self) -> &[String] {
+        &self.code.externs
+    }
+
+    /// The module refers to nothing outside of it, so there is nothing left to resolve.
+    fn take_machine_code(&mut self, _str_literal_offset: usize, _got_offset: usize) -> MachineCode {
+        std::mem::take(&mut self.code)
+    }
+}
+
+impl WasmAssembler {
+    fn assemble_module(&mut self, ir: IR) {
+        let defined: HashSet<&str> = ir
+            .items
+            .iter()
+            .map(|Item::Function { name, .. }| name.as_str())
+            .collect();
+
+        // Forward declared functions that are called but not defined are imported, with as many
+        // parameters as they are called with.
+        let mut imports: Vec<(String, usize)> = Vec::new();
+        for Item::Function { bb, .. } in &ir.items {
+            for op in &bb.ops {
+                if let Operation::Call { function, args, .. } = op
+                    && ir.externs.contains(function)
+                    && !defined.contains(function.as_str())
+                    && !INTRINSICS.contains(&function.as_str())
+                    && !imports.iter().any(|(name, _)| name == function)
+                {
+                    imports.push((function.clone(), args.len()));
+                }
+            }
+        }
+
+        let mut functions: HashMap<String, u32> = HashMap::new();
+        let mut signatures: Vec<usize> = Vec::new();
+        let mut type_of = |params: usize| match signatures.iter().position(|&p| p == params) {
+            Some(idx) => idx as u32,
+            None => {
+                signatures.push(params);
+                signatures.len() as u32 - 1
+            }
+        };
+
+        let mut import_section = Section::default();
+        import_section.uleb(imports.len() as u64);
+        for (name, params) in &imports {
+            functions.insert(name.clone(), functions.len() as u32);
+            import_section.name(IMPORT_MODULE);
+            import_section.name(name);
+            import_section.byte(0x00); // function
+            import_section.uleb(type_of(*params) as u64);
+        }
+
+        let mut function_section = Section::default();
+        function_section.uleb(ir.items.len() as u64);
+        for Item::Function { name, args, .. } in &ir.items {
+            functions.insert(name.clone(), functions.len() as u32);
+            function_section.uleb(type_of(args.len()) as u64);
+        }
+
+        let mut code_section = Section::default();
+        code_section.uleb(ir.items.len() as u64);
+        for item in ir.items {
+            let body = FunctionEmitter::new(self, &functions, &item).emit();
+            code_section.uleb(body.len() as u64);
+            code_section.bytes(&body);
+        }
+
+        let mut type_section = Section::default();
+        type_section.uleb(signatures.len() as u64);
+        for &params in &signatures {
+            type_section.byte(0x60);
+            type_section.uleb(params as u64);
+            for _ in 0..params {
+                type_section.byte(encode::I64);
+            }
+            type_section.uleb(1);
+            type_section.byte(encode::I64);
+        }
+
+        let mut export_section = Section::default();
+        match functions.get(MAIN_FN) {
+            Some(&main) => {
+                export_section.uleb(1);
+                export_section.name(MAIN_FN);
+                export_section.byte(0x00); // function
+                export_section.uleb(main as u64);
+            }
+            None => export_section.uleb(0),
+        }
+
+        let module = &mut self.code.instructions;
+        module.extend(encode::MAGIC);
+        module.extend(encode::VERSION);
+        type_section.write(encode::TYPE_SECTION, module);
+        import_section.write(encode::IMPORT_SECTION, module);
+        function_section.write(encode::FUNCTION_SECTION, module);
+        export_section.write(encode::EXPORT_SECTION, module);
+        code_section.write(encode::CODE_SECTION, module);
+
+        self.code.externs = imports.into_iter().map(|(name, _)| name).collect();
+        self.code.symbols = functions
+            .into_iter()
+            .map(|(name, idx)| (name, idx as u64))
+            .collect();
+    }
+
+    fn report(&mut self, span: Span, message: String, label: impl ToString) {
+        self.errors
+            .error(span.clone())
+            .with_message(message)
+            .with_label(span, label)
+            .report();
+    }
+}
+
+/// Emits the body of one function. The operations are split into blocks at every label and
+/// after every jump, and the blocks are laid out one after the other inside a dispatch loop:
+///
+/// ```text
+/// loop
+///   block ... block block
+///     br_table $pc      ;; jumps to the end of block $pc
+///   end [block 0] end [block 1] ... end [block n-1]
+/// end
+/// ```
+///
+/// Every block falls through to the next one, and a jump sets `$pc` to the block it targets and
+/// branches back to the start of the loop. A function without labels is emitted as it is.
+struct FunctionEmitter<'a> {
+    asm: &'a mut WasmAssembler,
+    functions: &'a HashMap<String, u32>,
+    item: &'a Item,
+    body: Section,
+    locals: HashMap<VirtualReg, u32>,
+    /// Local holding the index of the block to run next.
+    pc: u32,
+    /// Index of the first operation of every block.
+    block_starts: Vec<usize>,
+    /// Number of blocks and loops the current operation is nested in, counted from the
+    /// dispatch loop.
+    depth: u32,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(
+        asm: &'a mut WasmAssembler,
+        functions: &'a HashMap<String, u32>,
+        item: &'a Item,
+    ) -> Self {
+        let Item::Function { args, bb, .. } = item;
+
+        let mut vregs: BTreeSet<VirtualReg> = BTreeSet::new();
+        for op in &bb.ops {
+            let (used, assigned) = op.vregs_used();
+            vregs.extend(used);
+            vregs.extend(assigned);
+        }
+
+        // parameters are the first locals
+        let mut locals: HashMap<VirtualReg, u32> = args
+            .iter()
+            .enumerate()
+            .map(|(i, &vreg)| (vreg, i as u32))
+            .collect();
+        for vreg in vregs {
+            let next = locals.len() as u32;
+            locals.entry(vreg).or_insert(next);
+        }
+
+        let mut block_starts: BTreeSet<usize> = BTreeSet::from([0]);
+        block_starts.extend(bb.labels.keys().copied());
+        for (idx, op) in bb.ops.iter().enumerate() {
+            if matches!(
+                op,
+                Operation::Branch { .. }
+                    | Operation::BranchIf { .. }
+                    | Operation::BranchIfNot { .. }
+                    | Operation::Switch { .. }
+                    | Operation::Return { .. }
+            ) {
+                block_starts.insert(idx + 1);
+            }
+        }
+        block_starts.retain(|&start| start <= bb.ops.len());
+
+        Self {
+            asm,
+            functions,
+            item,
+            body: Section::default(),
+            pc: locals.len() as u32,
+            locals,
+            block_starts: block_starts.into_iter().collect(),
+            depth: 0,
+        }
+    }
+
+    /// Returns the encoded function body, with its local declarations.
+    fn emit(mut self) -> Vec<u8> {
+        let Item::Function { args, bb, .. } = self.item;
+        let dispatch = !bb.labels.is_empty();
+        let blocks = self.block_starts.len() as u32;
+
+        if dispatch {
+            self.body.byte(op::LOOP);
+            self.body.byte(encode::EMPTY_BLOCK);
+            for _ in 0..blocks {
+                self.body.byte(op::BLOCK);
+                self.body.byte(encode::EMPTY_BLOCK);
+            }
+
+            self.body.byte(op::LOCAL_GET);
+            self.body.uleb(self.pc as u64);
+            self.body.byte(op::BR_TABLE);
+            self.body.uleb(blocks as u64);
+            for block in 0..blocks {
+                self.body.uleb(block as u64);
+            }
+            self.body.uleb(blocks as u64 - 1);
+        }
+
+        for (block, &start) in self.block_starts.clone().iter().enumerate() {
+            let end = self
+                .block_starts
+                .get(block + 1)
+                .copied()
+                .unwrap_or(bb.ops.len());
+
+            if dispatch {
+                self.body.byte(op::END);
+                // the blocks still open around this one, and the loop
+                self.depth = blocks - 1 - block as u32;
+            }
+
+            for idx in start..end {
+                self.emit_op(&bb.ops[idx], idx);
+            }
+        }
+
+        if dispatch {
+            self.body.byte(op::END);
+        }
+
+        // running off the end of the function returns zero
+        self.body.byte(op::I64_CONST);
+        self.body.sleb(0);
+        self.body.byte(op::END);
+
+        let mut out = Section::default();
+        let locals = self.locals.len() - args.len();
+        out.uleb(if dispatch { 2 } else { 1 });
+        out.uleb(locals as u64);
+        out.byte(encode::I64);
+        if dispatch {
+            out.uleb(1);
+            out.byte(encode::I32);
+        }
+        out.bytes(&self.body.into_bytes());
+        out.into_bytes()
+    }
+
+    fn span(&self, idx: usize) -> Span {
+        let Item::Function { bb, span, .. } = self.item;
+        bb.spans
+            .get(idx)
+            .cloned()
+            .flatten()
+            .unwrap_or_else(|| span.clone())
+    }
+
+    fn unsupported(&mut self, idx: usize, what: &str) {
+        let span = self.span(idx);
+        self.asm.report(
+            span,
+            format!("{} are not supported when compiling to WebAssembly", what),
+            "while compiling this",
+        );
+    }
+
+    fn get(&mut self, vreg: VirtualReg) {
+        self.body.byte(op::LOCAL_GET);
+        self.body.uleb(self.locals[&vreg] as u64);
+    }
+
+    fn set(&mut self, vreg: VirtualReg) {
+        self.body.byte(op::LOCAL_SET);
+        self.body.uleb(self.locals[&vreg] as u64);
+    }
+
+    fn push_source(&mut self, src: SourceVal, idx: usize) {
+        match src {
+            SourceVal::Immediate(n) => {
+                self.body.byte(op::I64_CONST);
+                self.body.sleb(n);
+            }
+            SourceVal::VReg(vreg) => self.get(vreg),
+            SourceVal::String(_) => {
+                self.unsupported(idx, "string literals");
+                self.body.byte(op::I64_CONST);
+                self.body.sleb(0);
+            }
+        }
+    }
+
+    /// Continues at the block starting at `label`.
+    fn jump(&mut self, label: Label, idx: usize) {
+        let Item::Function { bb, .. } = self.item;
+        let target = match label {
+            Label::FnRet => bb.ops.len(),
+            label => bb
+                .labels
+                .iter()
+                .find(|(_, labels)| labels.contains(&label))
+                .map(|(&pos, _)| pos)
+                .unwrap_or_else(|| panic!("label {} is not placed", label)),
+        };
+
+        if target == bb.ops.len() && !self.block_starts.contains(&target) {
+            // nothing runs after the last operation
+            self.body.byte(op::I64_CONST);
+            self.body.sleb(0);
+            self.body.byte(op::RETURN);
+            return;
+        }
+
+        let block = self
+            .block_starts
+            .binary_search(&target)
+            .unwrap_or_else(|_| {
+                panic!("op {} jumps into the middle of a block", idx);
+            });
+
+        self.body.byte(op::I32_CONST);
+        self.body.sleb(block as i64);
+        self.body.byte(op::LOCAL_SET);
+        self.body.uleb(self.pc as u64);
+        self.body.byte(op::BR);
+        self.body.uleb(self.depth as u64);
+    }
+
+    /// Jumps to `label` if the `i32` on top of the stack is nonzero.
+    fn jump_if(&mut self, label: Label, idx: usize) {
+        self.body.byte(op::IF);
+        self.body.byte(encode::EMPTY_BLOCK);
+        self.depth += 1;
+        self.jump(label, idx);
+        self.depth -= 1;
+        self.body.byte(op::END);
+    }
+
+    fn emit_op(&mut self, operation: &Operation, idx: usize) {
+        match *operation {
+            Operation::Assign { src, dest } => {
+                self.push_source(src, idx);
+                self.set(dest);
+            }
+            Operation::AddressOf { .. }
+            | Operation::LoadPointer { .. }
+            | Operation::StorePointer { .. } => self.unsupported(idx, "pointers"),
+            Operation::ReturnPair { .. } | Operation::SecondResult { .. } => {
+                self.unsupported(idx, "records")
+            }
+
+            Operation::Add { a, b, dest } => self.binary(a, b, op::I64_ADD, dest, idx),
+            Operation::Subtract { a, b, dest } => self.binary(a, b, op::I64_SUB, dest, idx),
+            Operation::Negate { src, dest } => {
+                self.body.byte(op::I64_CONST);
+                self.body.sleb(0);
+                self.get(src);
+                self.body.byte(op::I64_SUB);
+                self.set(dest);
+            }
+            Operation::Multiply { a, b, dest } => {
+                self.binary(a, SourceVal::VReg(b), op::I64_MUL, dest, idx)
+            }
+            Operation::Divide { a, b, dest, signed } => self.divide(a, b, dest, signed),
+            Operation::Modulo { a, b, dest, signed } => self.remainder(a, b, dest, signed),
+            Operation::FloatArithmetic { op, a, b, dest } => {
+                self.get(a);
+                self.body.byte(op::F64_REINTERPRET_I64);
+                self.get(b);
+                self.body.byte(op::F64_REINTERPRET_I64);
+                self.body.byte(match op {
+                    FloatOp::Add => op::F64_ADD,
+                    FloatOp::Subtract => op::F64_SUB,
+                    FloatOp::Multiply => op::F64_MUL,
+                    FloatOp::Divide => op::F64_DIV,
+                });
+                self.body.byte(op::I64_REINTERPRET_F64);
+                self.set(dest);
+            }
+            Operation::FloatToInt { src, dest } => {
+                self.get(src);
+                self.body.byte(op::F64_REINTERPRET_I64);
+                self.body.byte(op::PREFIX_FC);
+                self.body.uleb(op::I64_TRUNC_SAT_F64_S);
+                self.set(dest);
+            }
+            Operation::IntToFloat { src, dest } => {
+                self.get(src);
+                self.body.byte(op::F64_CONVERT_I64_S);
+                self.body.byte(op::I64_REINTERPRET_F64);
+                self.set(dest);
+            }
+
+            Operation::Compare { a, b, cond, dest } => {
+                self.get(a);
+                self.get(b);
+                self.compare(cond, idx);
+                self.body.byte(op::I64_EXTEND_I32_U);
+                self.set(dest);
+            }
+            Operation::Select { cond, a, b, dest } => {
+                // select picks its first operand when the condition is nonzero
+                self.get(b);
+                self.get(a);
+                self.get(cond);
+                self.body.byte(op::I64_EQZ);
+                self.body.byte(op::SELECT);
+                self.set(dest);
+            }
+
+            Operation::Branch { label } => self.jump(label, idx),
+            Operation::BranchIf { cond, label } => {
+                self.get(cond);
+                self.body.byte(op::I64_EQZ);
+                self.body.byte(op::I32_EQZ);
+                self.jump_if(label, idx);
+            }
+            Operation::BranchIfNot { cond, label } => {
+                self.get(cond);
+                self.body.byte(op::I64_EQZ);
+                self.jump_if(label, idx);
+            }
+            Operation::Switch {
+                value,
+                ref cases,
+                default,
+            } => {
+                for &(case, label) in cases {
+                    self.get(value);
+                    self.body.byte(op::I64_CONST);
+                    self.body.sleb(case);
+                    self.body.byte(op::I64_EQ);
+                    self.jump_if(label, idx);
+                }
+                self.jump(default, idx);
+            }
+
+            Operation::Return { value } => {
+                self.push_source(value, idx);
+                self.body.byte(op::RETURN);
+            }
+            Operation::Call {
+                ref function,
+                ref args,
+                dest,
+            } => self.call(function, args, dest, idx),
+        }
+    }
+
+    fn binary(&mut self, a: VirtualReg, b: SourceVal, opcode: u8, dest: VirtualReg, idx: usize) {
+        self.get(a);
+        self.push_source(b, idx);
+        self.body.byte(opcode);
+        self.set(dest);
+    }
+
+    /// Divides like the IR does, where WebAssembly would trap instead: dividing by zero gives
+    /// zero, and the signed `i64::MIN / -1` wraps around to `i64::MIN`.
+    fn divide(&mut self, a: VirtualReg, b: VirtualReg, dest: VirtualReg, signed: bool) {
+        self.get(b);
+        self.body.byte(op::I64_EQZ);
+        self.body.byte(op::IF);
+        self.body.byte(encode::I64);
+        self.body.byte(op::I64_CONST);
+        self.body.sleb(0);
+        self.body.byte(op::ELSE);
+
+        if signed {
+            self.get(b);
+            self.body.byte(op::I64_CONST);
+            self.body.sleb(-1);
+            self.body.byte(op::I64_EQ);
+            self.body.byte(op::IF);
+            self.body.byte(encode::I64);
+            self.body.byte(op::I64_CONST);
+            self.body.sleb(0);
+            self.get(a);
+            self.body.byte(op::I64_SUB);
+            self.body.byte(op::ELSE);
+            self.get(a);
+            self.get(b);
+            self.body.byte(op::I64_DIV_S);
+            self.body.byte(op::END);
+        } else {
+            self.get(a);
+            self.get(b);
+            self.body.byte(op::I64_DIV_U);
+        }
+
+        self.body.byte(op::END);
+        self.set(dest);
+    }
+
+    /// Takes the remainder like the IR does, where WebAssembly would trap on a zero divisor:
+    /// `a % 0` is `a`. The signed `i64::MIN % -1` is zero in both.
+    fn remainder(&mut self, a: VirtualReg, b: VirtualReg, dest: VirtualReg, signed: bool) {
+        self.get(b);
+        self.body.byte(op::I64_EQZ);
+        self.body.byte(op::IF);
+        self.body.byte(encode::I64);
+        self.get(a);
+        self.body.byte(op::ELSE);
+        self.get(a);
+        self.get(b);
+        self.body
+            .byte(if signed { op::I64_REM_S } else { op::I64_REM_U });
+        self.body.byte(op::END);
+        self.set(dest);
+    }
+
+    /// Compares the two `i64` values on the stack, leaving an `i32` that is nonzero if `cond`
+    /// holds.
+    fn compare(&mut self, cond: Condition, idx: usize) {
+        let opcode = match cond {
+            Condition::Equal => op::I64_EQ,
+            Condition::NotEqual => op::I64_NE,
+            Condition::SignedLess => op::I64_LT_S,
+            Condition::UnsignedLess => op::I64_LT_U,
+            Condition::SignedGreater => op::I64_GT_S,
+            Condition::UnsignedGreater => op::I64_GT_U,
+            Condition::SignedLessOrEqual => op::I64_LE_S,
+            Condition::UnsignedLessOrEqual => op::I64_LE_U,
+            Condition::SignedGreaterOrEqual => op::I64_GE_S,
+            Condition::UnsignedGreaterOrEqual => op::I64_GE_U,
+            cond => {
+                self.unsupported(idx, &format!("{:?} conditions", cond));
+                op::I64_EQ
+            }
+        };
+
+        self.body.byte(opcode);
+    }
+
+    fn call(&mut self, function: &str, args: &[SourceVal], dest: Option<VirtualReg>, idx: usize) {
+        for &arg in args {
+            self.push_source(arg, idx);
+        }
+
+        match function {
+            "assert" => {
+                self.body.byte(op::I64_EQZ);
+                self.body.byte(op::IF);
+                self.body.byte(encode::EMPTY_BLOCK);
+                self.body.byte(op::UNREACHABLE);
+                self.body.byte(op::END);
+                return;
+            }
+            "abs" => {
+                // x, which is already pushed, if x >= 0, else -x
+                self.body.byte(op::I64_CONST);
+                self.body.sleb(0);
+                self.push_source(args[0], idx);
+                self.body.byte(op::I64_SUB);
+                self.push_source(args[0], idx);
+                self.body.byte(op::I64_CONST);
+                self.body.sleb(0);
+                self.body.byte(op::I64_GE_S);
+                self.body.byte(op::SELECT);
+            }
+            "min" | "max" => {
+                // select between the pushed arguments on how they compare
+                self.push_source(args[0], idx);
+                self.push_source(args[1], idx);
+                self.body.byte(if function == "min" {
+                    op::I64_LT_S
+                } else {
+                    op::I64_GT_S
+                });
+                self.body.byte(op::SELECT);
+            }
+            function => match self.functions.get(function) {
+                Some(&index) => {
+                    self.body.byte(op::CALL);
+                    self.body.uleb(index as u64);
+                }
+                None => {
+                    let span = self.span(idx);
+                    self.asm.report(
+                        span,
+                        format!("call to unknown function {}", function),
+                        "this function is not defined",
+                    );
+                    return;
+                }
+            },
+        }
+
+        match dest {
+            Some(dest) => self.set(dest),
+            None => self.body.byte(op::DROP),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, rc::Rc};
+
+    use super::*;
+    use crate::{Compiler, synthesize::exe::DummyExecutable};
+
+    fn module(source: &str) -> Vec<u8> {
+        let compiler: Compiler<DummyExecutable, WasmAssembler> = Compiler::default();
+        let code = compiler
+            .compile_source(Rc::new(PathBuf::from("main")), source)
+            .unwrap();
+        code.finalize(0, 0).instructions
+    }
+
+    fn read_uleb(bytes: &[u8], pos: &mut usize) -> u64 {
+        let mut value = 0;
+        let mut shift = 0;
+        loop {
+            let byte = bytes[*pos];
+            *pos += 1;
+            value |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return value;
+            }
+        }
+    }
+
+    /// Splits a module into its sections, checking the header and that every section is in
+    /// bounds and in order.
+    fn sections(module: &[u8]) -> Vec<(u8, &[u8])> {
+        assert_eq!(module[0..4], encode::MAGIC);
+        assert_eq!(module[4..8], encode::VERSION);
+
+        let mut sections: Vec<(u8, &[u8])> = Vec::new();
+        let mut pos = 8;
+        while pos < module.len() {
+            let id = module[pos];
+            pos += 1;
+            let size = read_uleb(module, &mut pos) as usize;
+            assert!(pos + size <= module.len(), "section {} is cut off", id);
+
+            if let Some(&(last, _)) = sections.last() {
+                assert!(id > last, "section {} is out of order", id);
+            }
+            sections.push((id, &module[pos..pos + size]));
+            pos += size;
+        }
+
+        sections
+    }
+
+    fn section(module: &[u8], id: u8) -> &[u8] {
+        sections(module)
+            .into_iter()
+            .find(|&(section, _)| section == id)
+            .map(|(_, contents)| contents)
+            .unwrap_or_else(|| panic!("no section {}", id))
+    }
+
+    #[test]
+    fn module_exports_main() {
+        let module = module("fn main() -> i64 { return 42; }");
+
+        let exports = section(&module, encode::EXPORT_SECTION);
+        assert_eq!(exports, [1, 4, b'm', b'a', b'i', b'n', 0x00, 0]);
+    }
+
+    #[test]
+    fn every_function_has_a_body() {
+        let module = module(
+            "
+            fn add(a: i64, b: i64) -> i64 { return a + b; }
+            fn main() -> i64 { x := 0; while x < 10 { x = add(x, 3); } return x; }
+            ",
+        );
+
+        let mut pos = 0;
+        let functions = section(&module, encode::FUNCTION_SECTION);
+        let declared = read_uleb(functions, &mut pos);
+
+        let code = section(&module, encode::CODE_SECTION);
+        pos = 0;
+        assert_eq!(read_uleb(code, &mut pos), declared);
+        for _ in 0..declared {
+            let size = read_uleb(code, &mut pos) as usize;
+            assert_eq!(code[pos + size - 1], op::END, "body does not end with END");
+            pos += size;
+        }
+        assert_eq!(pos, code.len());
+    }
+
+    #[test]
+    fn loops_and_branches_run() {
+        let module = module(
+            "
+            fn add(a: i64, b: i64) -> i64 { return a + b; }
+            fn main() -> i64 {
+                x := 0;
+                while x < 10 { x = add(x, 3); }
+                y := abs(0 - x);
+                if y > 11 { return x + 1; }
+                return x;
+            }
+            ",
+        );
+        wasmparser::validate(&module).unwrap();
+
+        let engine = wasmi::Engine::default();
+        let module = wasmi::Module::new(&engine, &module).unwrap();
+        let mut store = wasmi::Store::new(&engine, ());
+        let instance = wasmi::Linker::new(&engine)
+            .instantiate(&mut store, &module)
+            .unwrap()
+            .start(&mut store)
+            .unwrap();
+        let main = instance.get_typed_func::<(), i64>(&store, MAIN_FN).unwrap();
+        assert_eq!(main.call(&mut store, ()).unwrap(), 13);
+    }
+
+    #[test]
+    fn undefined_functions_are_imported() {
+        let module =
+            module("extern libc; fn ext(x: i64) -> i64; fn main() -> i64 { return ext(1); }");
+
+        let imports = section(&module, encode::IMPORT_SECTION);
+        let mut expected = vec![1, 3];
+        expected.extend(b"env");
+        expected.push(3);
+        expected.extend(b"ext");
+        expected.extend([0x00, 0]);
+        assert_eq!(imports, expected);
+    }
+
+    #[test]
+    fn straight_line_code_has_no_dispatch_loop() {
+        let module = module("fn main() -> i64 { a := 2; b := a * 3; return b - 1; }");
+
+        let code = section(&module, encode::CODE_SECTION);
+        assert!(!code.contains(&op::BR_TABLE));
+    }
+
+    #[test]
+    fn pointers_are_rejected() {
+        let compiler: Compiler<DummyExecutable, WasmAssembler> = Compiler::default();
+        let source = "fn main() -> i64 { a := 1; p := &a; return *p; }";
+        assert!(
+            compiler
+                .compile_source(Rc::new(PathBuf::from("main")), source)
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,163 @@
+pub const MAGIC: [u8; 4] = *b"\0asm";
+pub const VERSION: [u8; 4] = [1, 0, 0, 0];
+
+pub const TYPE_SECTION: u8 = 1;
+pub const IMPORT_SECTION: u8 = 2;
+pub const FUNCTION_SECTION: u8 = 3;
+pub const EXPORT_SECTION: u8 = 7;
+pub const CODE_SECTION: u8 = 10;
+
+pub const I32: u8 = 0x7F;
+pub const I64: u8 = 0x7E;
+/// Type of a block that leaves nothing on the stack.
+pub const EMPTY_BLOCK: u8 = 0x40;
+
+/// Opcodes of the instructions the assembler emits.
+pub mod op {
+    pub const UNREACHABLE: u8 = 0x00;
+    pub const BLOCK: u8 = 0x02;
+    pub const LOOP: u8 = 0x03;
+    pub const IF: u8 = 0x04;
+    pub const ELSE: u8 = 0x05;
+    pub const END: u8 = 0x0B;
+    pub const BR: u8 = 0x0C;
+    pub const BR_TABLE: u8 = 0x0E;
+    pub const RETURN: u8 = 0x0F;
+    pub const CALL: u8 = 0x10;
+    pub const DROP: u8 = 0x1A;
+    pub const SELECT: u8 = 0x1B;
+
+    pub const LOCAL_GET: u8 = 0x20;
+    pub const LOCAL_SET: u8 = 0x21;
+    pub const I32_CONST: u8 = 0x41;
+    pub const I64_CONST: u8 = 0x42;
+
+    pub const I32_EQZ: u8 = 0x45;
+    pub const I64_EQZ: u8 = 0x50;
+    pub const I64_EQ: u8 = 0x51;
+    pub const I64_NE: u8 = 0x52;
+    pub const I64_LT_S: u8 = 0x53;
+    pub const I64_LT_U: u8 = 0x54;
+    pub const I64_GT_S: u8 = 0x55;
+    pub const I64_GT_U: u8 = 0x56;
+    pub const I64_LE_S: u8 = 0x57;
+    pub const I64_LE_U: u8 = 0x58;
+    pub const I64_GE_S: u8 = 0x59;
+    pub const I64_GE_U: u8 = 0x5A;
+
+    pub const I64_ADD: u8 = 0x7C;
+    pub const I64_SUB: u8 = 0x7D;
+    pub const I64_MUL: u8 = 0x7E;
+    pub const I64_DIV_S: u8 = 0x7F;
+    pub const I64_DIV_U: u8 = 0x80;
+    pub const I64_REM_S: u8 = 0x81;
+    pub const I64_REM_U: u8 = 0x82;
+
+    pub const F64_ADD: u8 = 0xA0;
+    pub const F64_SUB: u8 = 0xA1;
+    pub const F64_MUL: u8 = 0xA2;
+    pub const F64_DIV: u8 = 0xA3;
+
+    pub const I64_EXTEND_I32_U: u8 = 0xAD;
+    pub const F64_CONVERT_I64_S: u8 = 0xB9;
+    pub const I64_REINTERPRET_F64: u8 = 0xBD;
+    pub const F64_REINTERPRET_I64: u8 = 0xBF;
+
+    /// Prefix of the instructions that are numbered by a LEB128 integer following it.
+    pub const PREFIX_FC: u8 = 0xFC;
+    /// Saturating `i64.trunc_f64_s`, following [PREFIX_FC].
+    pub const I64_TRUNC_SAT_F64_S: u64 = 7;
+}
+
+/// Bytes of a section or function body being encoded.
+#[derive(Debug, Default)]
+pub struct Section(Vec<u8>);
+
+impl Section {
+    pub fn byte(&mut self, byte: u8) {
+        self.0.push(byte);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.0.extend_from_slice(bytes);
+    }
+
+    /// Appends `value` as an unsigned LEB128 integer.
+    pub fn uleb(&mut self, mut value: u64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            if value == 0 {
+                self.0.push(byte);
+                return;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    /// Appends `value` as a signed LEB128 integer.
+    pub fn sleb(&mut self, mut value: i64) {
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+            let sign_clear = byte & 0x40 == 0;
+            if (value == 0 && sign_clear) || (value == -1 && !sign_clear) {
+                self.0.push(byte);
+                return;
+            }
+            self.0.push(byte | 0x80);
+        }
+    }
+
+    /// Appends a name, prefixed by its length.
+    pub fn name(&mut self, name: &str) {
+        self.uleb(name.len() as u64);
+        self.bytes(name.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+
+    /// Writes the section to `module`, prefixed by its id and size.
+    pub fn write(self, id: u8, module: &mut Vec<u8>) {
+        module.push(id);
+        let mut size = Section::default();
+        size.uleb(self.0.len() as u64);
+        module.extend(size.0);
+        module.extend(self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uleb(value: u64) -> Vec<u8> {
+        let mut section = Section::default();
+        section.uleb(value);
+        section.into_bytes()
+    }
+
+    fn sleb(value: i64) -> Vec<u8> {
+        let mut section = Section::default();
+        section.sleb(value);
+        section.into_bytes()
+    }
+
+    #[test]
+    fn leb128_encoding() {
+        assert_eq!(uleb(0), [0x00]);
+        assert_eq!(uleb(127), [0x7f]);
+        assert_eq!(uleb(128), [0x80, 0x01]);
+        assert_eq!(uleb(624485), [0xe5, 0x8e, 0x26]);
+
+        assert_eq!(sleb(0), [0x00]);
+        assert_eq!(sleb(63), [0x3f]);
+        assert_eq!(sleb(64), [0xc0, 0x00]);
+        assert_eq!(sleb(-1), [0x7f]);
+        assert_eq!(sleb(-64), [0x40]);
+        assert_eq!(sleb(-65), [0xbf, 0x7f]);
+        assert_eq!(sleb(-123456), [0xc0, 0xbb, 0x78]);
+    }
+}
@@ -0,0 +1,413 @@
+use std::collections::{BTreeSet, HashMap};
+
+use crate::{
+    analyze::{ErrorContext, ErrorVec, Span},
+    ir::{IR, Item, Operation, SourceVal, VirtualReg},
+    synthesize::{
+        arch::{AssembleOptions, Assembler, MachineCode, UnfinishedCode},
+        target::Arch,
+    },
+};
+
+pub mod encode;
+
+use encode::{ARG_REGS, Alu, Reg};
+
+const MAIN_FN: &str = "main";
+
+/// Lowers the IR to x86-64 machine code, following the System V calling convention. Every
+/// virtual register gets a slot in the stack frame, and operations load their operands into
+/// RAX and RCX, so no registers have to be allocated. There is no executable format for it yet,
+/// so `main` is the entry point and returns to its caller.
+#[derive(Default)]
+pub struct X64Assembler {
+    code: MachineCode,
+    functions: HashMap<String, usize>,
+    /// Displacements of calls to patch once all functions are placed, with the source of the
+    /// call.
+    fn_calls: Vec<(String, usize, Span)>,
+    options: AssembleOptions,
+    errors: ErrorContext,
+}
+
+impl Assembler for X64Assembler {
+    const ARCH: Arch = Arch::X86_64;
+
+    fn assemble_with_options(
+        ir: IR,
+        options: AssembleOptions,
+    ) -> Result<UnfinishedCode<Self>, ErrorVec> {
+        let mut asm = X64Assembler {
+            errors: ErrorContext::with_color(options.color),
+            options,
+            ..Default::default()
+        };
+
+        asm.assemble_functions(ir);
+
+        if !asm.errors.is_empty() {
+            return Err(asm.errors.take_errors());
+        }
+
+        Ok(UnfinishedCode(asm))
+    }
+
+    fn current_offset(&self) -> usize {
+        self.code.instructions.len()
+    }
+
+    fn str_literals(&self) -> &[String] {
+        &self.code.str_literals
+    }
+
+    fn externs(&self) -> &[String] {
+        &self.code.externs
+    }
+
+    /// Calls are resolved while assembling, and nothing else refers outside of the code.
+    fn take_machine_code(&mut self, _str_literal_offset: usize, _got_offset: usize) -> MachineCode {
+        std::mem::take(&mut self.code)
+    }
+}
+
+impl X64Assembler {
+    fn assemble_functions(&mut self, ir: IR) {
+        for item in &ir.items {
+            self.align_function();
+            let Item::Function { name, .. } = item;
+            self.functions.insert(name.clone(), self.current_offset());
+            FunctionEmitter::new(self, item).emit();
+        }
+
+        for (function, offset, span) in std::mem::take(&mut self.fn_calls) {
+            let Some(&target) = self.functions.get(&function) else {
+                self.report(
+                    span,
+                    format!("call to unknown function {}", function),
+                    "this function is not defined",
+                );
+                continue;
+            };
+
+            // relative to the end of the call
+            let rel = target as i32 - (offset + 4) as i32;
+            self.code.instructions[offset..(offset + 4)].copy_from_slice(&rel.to_le_bytes());
+        }
+
+        self.code.entry_point_offset =
+            self.functions.get(MAIN_FN).copied().unwrap_or_default() as u64;
+        self.code.symbols = self
+            .functions
+            .iter()
+            .map(|(name, &offset)| (name.clone(), offset as u64))
+            .collect();
+    }
+
+    /// Pads the code with `int3` up to the alignment of a function entry.
+    fn align_function(&mut self) {
+        while !self
+            .current_offset()
+            .is_multiple_of(self.options.function_alignment)
+        {
+            self.code.instructions.push(0xCC);
+        }
+    }
+
+    fn report(&mut self, span: Span, message: String, label: impl ToString) {
+        self.errors
+            .error(span.clone())
+            .with_message(message)
+            .with_label(span, label)
+            .report();
+    }
+}
+
+/// Emits one function. Its frame holds a slot for every virtual register, below the saved RBP:
+///
+/// ```text
+/// push rbp
+/// mov rbp, rsp
+/// sub rsp, <frame size>
+/// ...
+/// mov rsp, rbp
+/// pop rbp
+/// ret
+/// ```
+///
+/// Every return tears the frame down on its own, and running off the end returns zero.
+struct FunctionEmitter<'a> {
+    asm: &'a mut X64Assembler,
+    item: &'a Item,
+    slots: HashMap<VirtualReg, usize>,
+}
+
+impl<'a> FunctionEmitter<'a> {
+    fn new(asm: &'a mut X64Assembler, item: &'a Item) -> Self {
+        let Item::Function { args, bb, .. } = item;
+
+        let mut vregs: BTreeSet<VirtualReg> = BTreeSet::new();
+        for op in &bb.ops {
+            let (used, assigned) = op.vregs_used();
+            vregs.extend(used);
+            vregs.extend(assigned);
+        }
+
+        // parameters get the first slots
+        let mut slots: HashMap<VirtualReg, usize> = args
+            .iter()
+            .enumerate()
+            .map(|(i, &vreg)| (vreg, i))
+            .collect();
+        for vreg in vregs {
+            let next = slots.len();
+            slots.entry(vreg).or_insert(next);
+        }
+
+        Self { asm, item, slots }
+    }
+
+    fn emit(mut self) {
+        let Item::Function {
+            name,
+            args,
+            bb,
+            span,
+            ..
+        } = self.item;
+
+        if args.len() > ARG_REGS.len() {
+            self.asm.report(
+                span.clone(),
+                format!("too many parameters for function {}", name),
+                format!("at most {} parameters are supported", ARG_REGS.len()),
+            );
+            return;
+        }
+
+        // keeps the stack 16-byte aligned, as RBP was pushed on top of the return address
+        let frame_size = (self.slots.len() * 8).next_multiple_of(16);
+        encode::push(self.out(), Reg::Rbp);
+        encode::mov(self.out(), Reg::Rbp, Reg::Rsp);
+        if frame_size != 0 {
+            encode::alu_imm(self.out(), Alu::Sub, Reg::Rsp, frame_size as i32);
+        }
+
+        for (&vreg, &reg) in args.iter().zip(ARG_REGS.iter()) {
+            let offset = self.offset(vreg);
+            encode::store(self.out(), reg, offset);
+        }
+
+        for (idx, op) in bb.ops.iter().enumerate() {
+            self.emit_op(op, idx);
+        }
+
+        if !matches!(bb.ops.last(), Some(Operation::Return { .. })) {
+            encode::mov_imm(self.out(), Reg::Rax, 0);
+            self.emit_epilogue();
+        }
+    }
+
+    fn out(&mut self) -> &mut Vec<u8> {
+        &mut self.asm.code.instructions
+    }
+
+    /// Offset of the slot of `vreg` from RBP.
+    fn offset(&self, vreg: VirtualReg) -> i32 {
+        -8 * (self.slots[&vreg] as i32 + 1)
+    }
+
+    fn span(&self, idx: usize) -> Span {
+        let Item::Function { bb, span, .. } = self.item;
+        bb.spans
+            .get(idx)
+            .cloned()
+            .flatten()
+            .unwrap_or_else(|| span.clone())
+    }
+
+    fn unsupported(&mut self, idx: usize, what: &str) {
+        let span = self.span(idx);
+        self.asm.report(
+            span,
+            format!("{} are not supported when compiling to x86-64", what),
+            "while compiling this",
+        );
+    }
+
+    /// Puts the value of `src` in `dest`.
+    fn load_source(&mut self, src: SourceVal, dest: Reg, idx: usize) {
+        match src {
+            SourceVal::Immediate(n) => encode::mov_imm(self.out(), dest, n),
+            SourceVal::VReg(vreg) => {
+                let offset = self.offset(vreg);
+                encode::load(self.out(), dest, offset);
+            }
+            SourceVal::String(_) => self.unsupported(idx, "string literals"),
+        }
+    }
+
+    fn store(&mut self, src: Reg, dest: VirtualReg) {
+        let offset = self.offset(dest);
+        encode::store(self.out(), src, offset);
+    }
+
+    fn emit_op(&mut self, operation: &Operation, idx: usize) {
+        match *operation {
+            Operation::Assign { src, dest } => {
+                self.load_source(src, Reg::Rax, idx);
+                self.store(Reg::Rax, dest);
+            }
+            Operation::Add { a, b, dest } => self.emit_alu(Alu::Add, a, b, dest, idx),
+            Operation::Subtract { a, b, dest } => self.emit_alu(Alu::Sub, a, b, dest, idx),
+            Operation::Return { value } => {
+                self.load_source(value, Reg::Rax, idx);
+                self.emit_epilogue();
+            }
+            Operation::Call {
+                ref function,
+                ref args,
+                dest,
+            } => self.emit_call(function, args, dest, idx),
+
+            Operation::AddressOf { .. }
+            | Operation::LoadPointer { .. }
+            | Operation::StorePointer { .. } => self.unsupported(idx, "pointers"),
+            Operation::ReturnPair { .. } | Operation::SecondResult { .. } => {
+                self.unsupported(idx, "records")
+            }
+            Operation::FloatArithmetic { .. }
+            | Operation::FloatToInt { .. }
+            | Operation::IntToFloat { .. } => self.unsupported(idx, "floats"),
+            Operation::Branch { .. }
+            | Operation::BranchIf { .. }
+            | Operation::BranchIfNot { .. }
+            | Operation::Switch { .. } => self.unsupported(idx, "branches"),
+            Operation::Negate { .. }
+            | Operation::Multiply { .. }
+            | Operation::Divide { .. }
+            | Operation::Modulo { .. }
+            | Operation::Compare { .. }
+            | Operation::Select { .. } => self.unsupported(idx, "these operations"),
+        }
+    }
+
+    fn emit_alu(&mut self, op: Alu, a: VirtualReg, b: SourceVal, dest: VirtualReg, idx: usize) {
+        self.load_source(SourceVal::VReg(a), Reg::Rax, idx);
+
+        match b {
+            SourceVal::Immediate(n) if i32::try_from(n).is_ok() => {
+                encode::alu_imm(self.out(), op, Reg::Rax, n as i32);
+            }
+            b => {
+                self.load_source(b, Reg::Rcx, idx);
+                encode::alu(self.out(), op, Reg::Rax, Reg::Rcx);
+            }
+        }
+
+        self.store(Reg::Rax, dest);
+    }
+
+    fn emit_call(
+        &mut self,
+        function: &str,
+        args: &[SourceVal],
+        dest: Option<VirtualReg>,
+        idx: usize,
+    ) {
+        if args.len() > ARG_REGS.len() {
+            let span = self.span(idx);
+            self.asm.report(
+                span,
+                String::from("too many arguments"),
+                format!("at most {} arguments are supported", ARG_REGS.len()),
+            );
+            return;
+        }
+
+        // every value lives in its slot, so the argument registers can be filled in any order
+        for (&arg, &reg) in args.iter().zip(ARG_REGS.iter()) {
+            self.load_source(arg, reg, idx);
+        }
+
+        let offset = encode::call(self.out());
+        let span = self.span(idx);
+        self.asm.fn_calls.push((function.to_owned(), offset, span));
+
+        if let Some(dest) = dest {
+            self.store(Reg::Rax, dest);
+        }
+    }
+
+    fn emit_epilogue(&mut self) {
+        encode::mov(self.out(), Reg::Rsp, Reg::Rbp);
+        encode::pop(self.out(), Reg::Rbp);
+        encode::ret(self.out());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, rc::Rc};
+
+    use super::*;
+    use crate::{Compiler, synthesize::exe::DummyExecutable};
+
+    fn compile(source: &str) -> Result<MachineCode, ErrorVec> {
+        let compiler: Compiler<DummyExecutable, X64Assembler> = Compiler::default();
+        let code = compiler.compile_source(Rc::new(PathBuf::from("main")), source)?;
+        Ok(code.finalize(0, 0))
+    }
+
+    #[test]
+    fn add_and_return() {
+        let code = compile("fn main() -> i64 { a := 2; return a + 3; }").unwrap();
+
+        #[rustfmt::skip]
+        let expected = [
+            0x55,                                     // push rbp
+            0x48, 0x89, 0xE5,                         // mov rbp, rsp
+            0x48, 0x81, 0xEC, 0x10, 0, 0, 0,          // sub rsp, 16
+            0x48, 0xC7, 0xC0, 0x02, 0, 0, 0,          // mov rax, 2
+            0x48, 0x89, 0x85, 0xF8, 0xFF, 0xFF, 0xFF, // mov [rbp - 8], rax
+            0x48, 0x8B, 0x85, 0xF8, 0xFF, 0xFF, 0xFF, // mov rax, [rbp - 8]
+            0x48, 0x81, 0xC0, 0x03, 0, 0, 0,          // add rax, 3
+            0x48, 0x89, 0x85, 0xF0, 0xFF, 0xFF, 0xFF, // mov [rbp - 16], rax
+            0x48, 0x8B, 0x85, 0xF0, 0xFF, 0xFF, 0xFF, // mov rax, [rbp - 16]
+            0x48, 0x89, 0xEC,                         // mov rsp, rbp
+            0x5D,                                     // pop rbp
+            0xC3,                                     // ret
+        ];
+        assert_eq!(code.instructions, expected);
+    }
+
+    #[test]
+    fn calls_land_on_function_entries() {
+        let code = compile(
+            "
+            fn diff(a: i64, b: i64) -> i64 { return a - b; }
+            fn main() -> i64 { return diff(5, 2); }
+            ",
+        )
+        .unwrap();
+
+        let symbol = |name: &str| code.symbols.iter().find(|(n, _)| n == name).unwrap().1;
+        assert_eq!(code.entry_point_offset, symbol("main"));
+
+        // the prologue of main has no 0xE8 in it
+        let main = symbol("main") as usize;
+        let call = main
+            + code.instructions[main..]
+                .iter()
+                .position(|&b| b == 0xE8)
+                .unwrap();
+        let rel = i32::from_le_bytes(code.instructions[call + 1..call + 5].try_into().unwrap());
+        assert_eq!((call as i64 + 5 + rel as i64) as u64, symbol("diff"));
+    }
+
+    #[test]
+    fn branches_are_rejected() {
+        assert!(
+            compile("fn main() -> i64 { a := 1; while a < 3 { a = a + 1; } return a; }").is_err()
+        );
+    }
+}
@@ -0,0 +1,195 @@
+/// General-purpose registers, numbered as they are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reg {
+    Rax = 0,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    /// The low three bits, which go in the ModRM byte or the opcode.
+    fn low(self) -> u8 {
+        self as u8 & 0b111
+    }
+
+    /// Whether the fourth bit is needed, which goes in the REX prefix.
+    fn extended(self) -> bool {
+        self as u8 >= 8
+    }
+}
+
+/// Registers the first arguments are passed in, in order, by the System V calling convention.
+pub const ARG_REGS: [Reg; 6] = [Reg::Rdi, Reg::Rsi, Reg::Rdx, Reg::Rcx, Reg::R8, Reg::R9];
+
+/// Arithmetic that takes a register and either another register or an immediate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Alu {
+    Add,
+    Sub,
+}
+
+impl Alu {
+    /// Opcode of the form with a register operand.
+    fn opcode(self) -> u8 {
+        match self {
+            Alu::Add => 0x01,
+            Alu::Sub => 0x29,
+        }
+    }
+
+    /// Opcode extension in the ModRM byte of the form with an immediate operand.
+    fn extension(self) -> u8 {
+        match self {
+            Alu::Add => 0,
+            Alu::Sub => 5,
+        }
+    }
+}
+
+/// REX prefix of a 64-bit operation, with `reg` in the reg field of the ModRM byte and `rm` in
+/// the r/m field.
+fn rex_w(reg: Reg, rm: Reg) -> u8 {
+    0x48 | (reg.extended() as u8) << 2 | rm.extended() as u8
+}
+
+fn modrm(mode: u8, reg: u8, rm: u8) -> u8 {
+    mode << 6 | reg << 3 | rm
+}
+
+pub fn push(out: &mut Vec<u8>, reg: Reg) {
+    if reg.extended() {
+        out.push(0x41);
+    }
+    out.push(0x50 + reg.low());
+}
+
+pub fn pop(out: &mut Vec<u8>, reg: Reg) {
+    if reg.extended() {
+        out.push(0x41);
+    }
+    out.push(0x58 + reg.low());
+}
+
+pub fn ret(out: &mut Vec<u8>) {
+    out.push(0xC3);
+}
+
+/// `mov dest, src`
+pub fn mov(out: &mut Vec<u8>, dest: Reg, src: Reg) {
+    out.extend([rex_w(src, dest), 0x89, modrm(0b11, src.low(), dest.low())]);
+}
+
+/// Moves `n` into `dest`, sign-extending a 32-bit immediate when it fits.
+pub fn mov_imm(out: &mut Vec<u8>, dest: Reg, n: i64) {
+    match i32::try_from(n) {
+        Ok(n) => {
+            out.extend([rex_w(Reg::Rax, dest), 0xC7, modrm(0b11, 0, dest.low())]);
+            out.extend(n.to_le_bytes());
+        }
+        Err(_) => {
+            out.extend([rex_w(Reg::Rax, dest), 0xB8 + dest.low()]);
+            out.extend(n.to_le_bytes());
+        }
+    }
+}
+
+/// `mov dest, [rbp + offset]`
+pub fn load(out: &mut Vec<u8>, dest: Reg, offset: i32) {
+    out.extend([
+        rex_w(dest, Reg::Rbp),
+        0x8B,
+        modrm(0b10, dest.low(), Reg::Rbp.low()),
+    ]);
+    out.extend(offset.to_le_bytes());
+}
+
+/// `mov [rbp + offset], src`
+pub fn store(out: &mut Vec<u8>, src: Reg, offset: i32) {
+    out.extend([
+        rex_w(src, Reg::Rbp),
+        0x89,
+        modrm(0b10, src.low(), Reg::Rbp.low()),
+    ]);
+    out.extend(offset.to_le_bytes());
+}
+
+/// `op dest, src`
+pub fn alu(out: &mut Vec<u8>, op: Alu, dest: Reg, src: Reg) {
+    out.extend([
+        rex_w(src, dest),
+        op.opcode(),
+        modrm(0b11, src.low(), dest.low()),
+    ]);
+}
+
+/// `op dest, imm`
+pub fn alu_imm(out: &mut Vec<u8>, op: Alu, dest: Reg, imm: i32) {
+    out.extend([
+        rex_w(Reg::Rax, dest),
+        0x81,
+        modrm(0b11, op.extension(), dest.low()),
+    ]);
+    out.extend(imm.to_le_bytes());
+}
+
+/// Emits a call with a zero displacement, and returns the offset of the displacement so it can
+/// be patched once the callee is placed.
+pub fn call(out: &mut Vec<u8>) -> usize {
+    out.push(0xE8);
+    let offset = out.len();
+    out.extend(0i32.to_le_bytes());
+    offset
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(emit: impl FnOnce(&mut Vec<u8>)) -> Vec<u8> {
+        let mut out = Vec::new();
+        emit(&mut out);
+        out
+    }
+
+    #[test]
+    fn extended_registers_set_rex_bits() {
+        assert_eq!(
+            encode(|out| mov(out, Reg::Rbp, Reg::Rsp)),
+            [0x48, 0x89, 0xE5]
+        );
+        assert_eq!(
+            encode(|out| mov(out, Reg::R9, Reg::Rax)),
+            [0x49, 0x89, 0xC1]
+        );
+        assert_eq!(
+            encode(|out| mov(out, Reg::Rax, Reg::R9)),
+            [0x4C, 0x89, 0xC8]
+        );
+        assert_eq!(encode(|out| push(out, Reg::R12)), [0x41, 0x54]);
+    }
+
+    #[test]
+    fn wide_immediates_use_movabs() {
+        assert_eq!(
+            encode(|out| mov_imm(out, Reg::Rcx, -1)),
+            [0x48, 0xC7, 0xC1, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+        assert_eq!(
+            encode(|out| mov_imm(out, Reg::R8, 1 << 32)),
+            [0x49, 0xB8, 0, 0, 0, 0, 1, 0, 0, 0]
+        );
+    }
+}
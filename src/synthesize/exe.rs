@@ -1,16 +1,35 @@
 use std::{path::Path, process::ExitStatus};
 
-use crate::synthesize::arch::{Assembler, UnfinishedCode};
+use crate::synthesize::{
+    arch::{Assembler, UnfinishedCode},
+    target::Target,
+};
 
 #[cfg(target_os = "macos")]
 pub mod mac;
+pub mod wasm;
 
 pub trait Executable: Default {
     fn with_binary_identifier(self, ident: impl AsRef<str>) -> Self;
 
+    /// Adapts the executable to the system it will run on. The instruction set is decided by
+    /// the assembler that generated the code instead.
+    fn with_target(self, _target: Target) -> Self {
+        self
+    }
+
     fn build<A: Assembler>(&mut self, code: UnfinishedCode<A>, out_path: impl AsRef<Path>);
 
-    fn run(&self) -> Result<ExitStatus, ExecutableError>;
+    fn run(&self) -> Result<ExitStatus, ExecutableError> {
+        self.run_with(&[])
+    }
+
+    /// Runs the executable with the given command line arguments.
+    fn run_with(&self, args: &[&str]) -> Result<ExitStatus, ExecutableError>;
+
+    /// Whether the machine running the compiler can run the executable, so callers can still
+    /// build it but skip running it elsewhere.
+    fn is_runnable_here(&self) -> bool;
 }
 
 #[derive(Default)]
@@ -23,9 +42,13 @@ impl Executable for DummyExecutable {
 
     fn build<A: Assembler>(&mut self, _code: UnfinishedCode<A>, _out_path: impl AsRef<Path>) {}
 
-    fn run(&self) -> Result<ExitStatus, ExecutableError> {
+    fn run_with(&self, _args: &[&str]) -> Result<ExitStatus, ExecutableError> {
         Err(ExecutableError::Dummy)
     }
+
+    fn is_runnable_here(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -36,4 +59,6 @@ pub enum ExecutableError {
     Io(#[from] std::io::Error),
     #[error("you cannot run a dummy executable")]
     Dummy,
+    #[error("the executable cannot run on this machine")]
+    NotRunnable,
 }
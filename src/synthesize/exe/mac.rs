@@ -18,22 +18,77 @@ use crate::synthesize::{
         ExecutableError,
         mac::mach_o::{NList, NListType, SectionFlags},
     },
+    target::{Arch, Os, Target},
 };
 
 use super::{
     Executable,
     mac::mach_o::{
-        DyLinkerCommand, DySymTabCommand, EntryPointCommand, HeaderFlags, LinkEditDataCommand,
-        MemoryPermissions, SectionHeader, SegmentCommand, SymTabCommand,
+        DyLibCommand, DyLinkerCommand, DySymTabCommand, DyldInfoCommand, EntryPointCommand,
+        HeaderFlags, LinkEditDataCommand, MemoryPermissions, SectionHeader, SegmentCommand,
+        SymTabCommand, UuidCommand,
     },
 };
 
-mod mach_o;
+pub mod archive;
+pub mod mach_o;
+pub mod validate;
 
-#[derive(Default)]
 pub struct AppleExecutable {
     binary_identifier: Option<String>,
     path: Option<PathBuf>,
+    position_dependent: bool,
+    page_size: u64,
+    stack_size: u64,
+}
+
+impl Default for AppleExecutable {
+    fn default() -> Self {
+        Self {
+            binary_identifier: None,
+            path: None,
+            position_dependent: false,
+            page_size: PAGE_SIZE,
+            stack_size: 0,
+        }
+    }
+}
+
+impl AppleExecutable {
+    /// Sets the page size that segments are aligned to. Apple Silicon needs 16KB pages, which is
+    /// the default, while Intel Macs use 4KB pages. Must be a power of two.
+    pub fn with_page_size(mut self, page_size: u64) -> Self {
+        assert!(
+            page_size.is_power_of_two(),
+            "page size must be a power of two"
+        );
+
+        self.page_size = page_size;
+        self
+    }
+
+    /// Builds an executable that is always loaded at [TEXT_BASE], instead of at a random
+    /// address chosen by the loader.
+    pub fn with_position_dependent(mut self, position_dependent: bool) -> Self {
+        self.position_dependent = position_dependent;
+        self
+    }
+
+    /// Requests a main thread stack of `stack_size` bytes, instead of the default one. Must be a
+    /// multiple of the [page size](Self::with_page_size), which should be set first.
+    pub fn with_stack_size(mut self, stack_size: u64) -> Self {
+        assert!(
+            stack_size.is_multiple_of(self.page_size),
+            "stack size must be a multiple of the page size"
+        );
+
+        self.stack_size = stack_size;
+        self
+    }
+
+    fn page_align(&self, addr: u64) -> u64 {
+        align(addr, self.page_size)
+    }
 }
 
 impl Executable for AppleExecutable {
@@ -47,18 +102,30 @@ impl Executable for AppleExecutable {
         // LC_SEGMENT (__PAGEZERO)
         // LC_SEGMENT (__TEXT)
         // __text section header
+        // __stubs section header (if there are externs)
         // __cstring section header
+        // LC_SEGMENT (__DATA_CONST) (if there are externs)
+        // __got section header (if there are externs)
         // LC_MAIN
         // LC_LOAD_DYLINKER
+        // LC_UUID
+        // LC_LOAD_DYLIB (if there are externs)
         // LC_SEGMENT_64 (__LINKEDIT)
+        // LC_DYLD_INFO_ONLY (if there are externs)
         // LC_CODE_SIGNATURE
         // LC_DYSYMTAB
         // LC_SYMTAB
         // __text section (code)
+        // __stubs section
         // __cstring section
-        // symbol table (nlists + string table)
+        // __got section
+        // bind info
+        // symbol table (nlists + indirect symbols + string table)
         // code signature
 
+        let extern_count = code.extern_count();
+        let has_externs = extern_count > 0;
+
         let linker_path = b"/usr/lib/dyld";
         let dylinker_cmd_size = align(size_of::<DyLinkerCommand>() + linker_path.len(), 8);
         let path_len_with_padding = dylinker_cmd_size - size_of::<DyLinkerCommand>();
@@ -71,34 +138,73 @@ impl Executable for AppleExecutable {
             path_str_offset: size_of::<DyLinkerCommand>() as u32,
         };
 
-        let text_data_offset = size_of::<Header>()
+        let libsystem_path = b"/usr/lib/libSystem.B.dylib";
+        let dylib_cmd_size = align(size_of::<DyLibCommand>() + libsystem_path.len() + 1, 8);
+        let mut padded_libsystem_path = vec![0u8; dylib_cmd_size - size_of::<DyLibCommand>()];
+        padded_libsystem_path[..libsystem_path.len()].copy_from_slice(libsystem_path);
+
+        let libsystem = DyLibCommand {
+            command: LoadCommand::LoadDyLib,
+            command_size: dylib_cmd_size as u32,
+            path_str_offset: size_of::<DyLibCommand>() as u32,
+            timestamp: 2,
+            current_version: 0x050c6405,       // 1292.100.5
+            compatibility_version: 0x00010000, // 1.0.0
+        };
+
+        let mut text_data_offset = size_of::<Header>()
             + size_of::<SegmentCommand>() // __PAGEZERO
             + size_of::<SegmentCommand>() // __TEXT
             + size_of::<SectionHeader>() // __TEXT,__text
             + size_of::<SectionHeader>() // __TEXT,__cstring
             + size_of::<EntryPointCommand>() // LcEntryPoint
             + dylinker.command_size as usize
+            + size_of::<UuidCommand>()
             + size_of::<SegmentCommand>()
             + size_of::<LinkEditDataCommand>()
             + size_of::<DySymTabCommand>()
             + size_of::<SymTabCommand>();
 
+        if has_externs {
+            text_data_offset += size_of::<SectionHeader>() // __TEXT,__stubs
+                + size_of::<SegmentCommand>() // __DATA_CONST
+                + size_of::<SectionHeader>() // __DATA_CONST,__got
+                + libsystem.command_size as usize
+                + size_of::<DyldInfoCommand>();
+        }
+
+        // functions are aligned relative to the start of the code, so start it on a boundary
+        // at least as large as any alignment we ask for
+        let load_cmds_end = text_data_offset;
+        let text_data_offset = align(text_data_offset, CODE_ALIGN);
+
         let code_size = code.size();
-        let code = code.finalize(text_data_offset + code_size);
+        let str_literal_offset = text_data_offset + code_size;
+        let text_section_end =
+            self.page_align((str_literal_offset + code.str_literals_size()) as u64);
+        let code = code.finalize(str_literal_offset, text_section_end as usize);
 
         let MachineCode {
             instructions,
             entry_point_offset,
             symbols,
             str_literals,
+            externs,
+            stubs_offset,
         } = code;
 
+        let uuid_cmd = UuidCommand {
+            command: LoadCommand::Uuid,
+            command_size: size_of::<UuidCommand>() as u32,
+            uuid: content_uuid(&instructions, &str_literals),
+        };
+
         let pagezero_segment = SegmentCommand {
             command: LoadCommand::Segment,
             command_size: size_of::<SegmentCommand>() as u32,
             segment_name: b"__PAGEZERO\0\0\0\0\0\0".to_owned(),
-            vmaddr: 0x0,         // Located at 0x0 to catch null pointers
-            vmsize: 0x100000000, // u32::MAX + 1 to block lower 32-bit address space
+            vmaddr: 0x0,       // Located at 0x0 to catch null pointers
+            vmsize: TEXT_BASE, // blocks the lower 32-bit address space
             file_offset: 0x0,
             file_size: 0x0,
             max_prot: MemoryPermissions::empty(),
@@ -107,19 +213,20 @@ impl Executable for AppleExecutable {
             flags: 0,
         };
 
+        let text_section_count = if has_externs { 3 } else { 2 };
         let text_segment_size =
-            (size_of::<SegmentCommand>() + size_of::<SectionHeader>() * 2) as u32;
+            (size_of::<SegmentCommand>() + size_of::<SectionHeader>() * text_section_count) as u32;
         let mut text_segment = SegmentCommand {
             command: LoadCommand::Segment,
             command_size: text_segment_size,
             segment_name: b"__TEXT\0\0\0\0\0\0\0\0\0\0".to_owned(),
-            vmaddr: pagezero_segment.vmsize,
+            vmaddr: TEXT_BASE,
             vmsize: 0, // filled in later
             file_offset: 0x0,
             file_size: 0, // filled in later
             max_prot: MemoryPermissions::ReadExecute,
             init_prot: MemoryPermissions::ReadExecute,
-            section_count: 2,
+            section_count: text_section_count as u32,
             flags: 0,
         };
 
@@ -127,9 +234,9 @@ impl Executable for AppleExecutable {
             section_name: b"__text\0\0\0\0\0\0\0\0\0\0".to_owned(),
             segment_name: b"__TEXT\0\0\0\0\0\0\0\0\0\0".to_owned(),
             addr: 0x0, // filled in later
-            size: code_size as u64,
+            size: stubs_offset,
             offset: 0x0, // filled in later
-            align: 0x2,
+            align: CODE_ALIGN.trailing_zeros(),
             reloff: 0,
             nreloc: 0,
             flags: SectionFlags::Regular,
@@ -138,6 +245,21 @@ impl Executable for AppleExecutable {
             _reserved3: 0,
         };
 
+        let mut stubs_section_header = SectionHeader {
+            section_name: b"__stubs\0\0\0\0\0\0\0\0\0".to_owned(),
+            segment_name: b"__TEXT\0\0\0\0\0\0\0\0\0\0".to_owned(),
+            addr: 0x0, // filled in later
+            size: code_size as u64 - stubs_offset,
+            offset: 0x0, // filled in later
+            align: 0x2,
+            reloff: 0,
+            nreloc: 0,
+            flags: SectionFlags::SymbolStubs,
+            _reserved1: 0, // index into indirect symbol table
+            _reserved2: STUB_SIZE,
+            _reserved3: 0,
+        };
+
         let str_literal_size: usize = str_literals.iter().map(|s| s.len() + 1).sum();
         let mut cstring_section_header = SectionHeader {
             section_name: b"__cstring\0\0\0\0\0\0\0".to_owned(),
@@ -158,18 +280,18 @@ impl Executable for AppleExecutable {
             command: LoadCommand::EntryPoint,
             command_size: size_of::<EntryPointCommand>() as u32,
             main_offset: entry_point_offset,
-            stack_size: 0,
+            stack_size: self.stack_size,
         };
 
         text_section_header.offset = text_data_offset as u32;
-        text_section_header.addr = text_segment.vmaddr;
+        text_section_header.addr = text_segment.vmaddr + text_section_header.offset as u64;
+        stubs_section_header.offset = text_section_header.offset + text_section_header.size as u32;
+        stubs_section_header.addr = text_section_header.addr + text_section_header.size;
         cstring_section_header.offset =
-            text_section_header.offset + text_section_header.size as u32;
-        cstring_section_header.addr = text_section_header.addr + text_section_header.size;
+            stubs_section_header.offset + stubs_section_header.size as u32;
+        cstring_section_header.addr = stubs_section_header.addr + stubs_section_header.size;
         entry_point.main_offset += text_section_header.offset as u64;
 
-        let text_section_end =
-            page_align(text_section_header.offset as u64 + text_section_header.size);
         text_segment.file_size = text_section_end;
         text_segment.vmsize = text_section_end;
 
@@ -177,12 +299,48 @@ impl Executable for AppleExecutable {
             - cstring_section_header.offset as usize
             - cstring_section_header.size as usize;
 
+        let got_size = (extern_count * size_of::<u64>()) as u64;
+        let data_const_segment = SegmentCommand {
+            command: LoadCommand::Segment,
+            command_size: (size_of::<SegmentCommand>() + size_of::<SectionHeader>()) as u32,
+            segment_name: b"__DATA_CONST\0\0\0\0".to_owned(),
+            vmaddr: text_segment.vmaddr + text_segment.vmsize,
+            vmsize: self.page_align(got_size),
+            file_offset: text_section_end,
+            file_size: self.page_align(got_size),
+            max_prot: MemoryPermissions::ReadWrite,
+            init_prot: MemoryPermissions::ReadWrite,
+            section_count: 1,
+            flags: 0,
+        };
+
+        let got_section_header = SectionHeader {
+            section_name: b"__got\0\0\0\0\0\0\0\0\0\0\0".to_owned(),
+            segment_name: b"__DATA_CONST\0\0\0\0".to_owned(),
+            addr: data_const_segment.vmaddr,
+            size: got_size,
+            offset: data_const_segment.file_offset as u32,
+            align: 0x3,
+            reloff: 0,
+            nreloc: 0,
+            flags: SectionFlags::NonLazySymbolPointers,
+            _reserved1: extern_count as u32, // index into indirect symbol table
+            _reserved2: 0,
+            _reserved3: 0,
+        };
+
+        let data_const_size = if has_externs {
+            data_const_segment.file_size
+        } else {
+            0
+        };
+
         let mut linkedit_segment = SegmentCommand {
             command: LoadCommand::Segment,
             command_size: size_of::<SegmentCommand>() as u32,
             segment_name: b"__LINKEDIT\0\0\0\0\0\0".to_owned(),
-            vmaddr: text_segment.vmaddr + text_segment.vmsize,
-            vmsize: 0x4000,
+            vmaddr: text_segment.vmaddr + text_segment.vmsize + data_const_size,
+            vmsize: 0,      // filled in later
             file_offset: 0, // filled in later
             file_size: 0,   // filled in later
             max_prot: MemoryPermissions::empty(),
@@ -191,6 +349,21 @@ impl Executable for AppleExecutable {
             flags: 0,
         };
 
+        let mut dyld_info = DyldInfoCommand {
+            command: LoadCommand::DyldInfoOnly,
+            command_size: size_of::<DyldInfoCommand>() as u32,
+            rebase_off: 0,
+            rebase_size: 0,
+            bind_off: 0,  // filled in later
+            bind_size: 0, // filled in later
+            weak_bind_off: 0,
+            weak_bind_size: 0,
+            lazy_bind_off: 0,
+            lazy_bind_size: 0,
+            export_off: 0,
+            export_size: 0,
+        };
+
         let mut code_sig_cmd = LinkEditDataCommand {
             command: LoadCommand::CodeSignature,
             command_size: size_of::<LinkEditDataCommand>() as u32,
@@ -198,23 +371,24 @@ impl Executable for AppleExecutable {
             data_size: 0,   // filled in later
         };
 
-        let dysymtab = DySymTabCommand {
+        let local_count = symbols.len() as u32;
+        let mut dysymtab = DySymTabCommand {
             command: LoadCommand::DySymTab,
             command_size: size_of::<DySymTabCommand>() as u32,
             ilocalsym: 0,
-            nlocalsym: 0,
-            iextdefsym: 0,
+            nlocalsym: local_count,
+            iextdefsym: local_count,
             nextdefsym: 0,
-            iundefsym: 0,
-            nundefsym: 0,
+            iundefsym: local_count,
+            nundefsym: extern_count as u32,
             tocoff: 0,
             ntoc: 0,
             modtaboff: 0,
             nmodtab: 0,
             extrefsymoff: 0,
             nextrefsyms: 0,
-            indirectsymoff: 0,
-            nindirectsyms: 0,
+            indirectsymoff: 0, // filled in later
+            nindirectsyms: 0,  // filled in later
             extreloff: 0,
             nextrel: 0,
             locreloff: 0,
@@ -230,21 +404,43 @@ impl Executable for AppleExecutable {
             strsize: 0,
         };
 
+        let mut load_cmd_count = 9;
+        let mut load_cmd_size = pagezero_segment.command_size
+            + text_segment.command_size
+            + entry_point.command_size
+            + dylinker.command_size
+            + uuid_cmd.command_size
+            + linkedit_segment.command_size
+            + code_sig_cmd.command_size
+            + dysymtab.command_size
+            + symtab.command_size;
+
+        let mut flags = HeaderFlags::DyldLink;
+        if !self.position_dependent {
+            flags |= HeaderFlags::PIE;
+        }
+
+        if has_externs {
+            load_cmd_count += 3;
+            load_cmd_size +=
+                data_const_segment.command_size + libsystem.command_size + dyld_info.command_size;
+            flags |= HeaderFlags::TwoLevel;
+        }
+
+        let (cpu_type, cpu_subtype) = match A::ARCH {
+            Arch::Aarch64 => (mach_o::CpuType::Arm64, mach_o::CpuSubtype::Arm),
+            Arch::X86_64 => (mach_o::CpuType::X86_64, mach_o::CpuSubtype::X86),
+            Arch::Wasm32 => panic!("WebAssembly cannot be put in a Mach-O executable"),
+        };
+
         let header = Header {
             magic: mach_o::Magic::X64,
-            cpu_type: mach_o::CpuType::Arm64,
-            cpu_subtype: mach_o::CpuSubtype::Arm,
+            cpu_type,
+            cpu_subtype,
             file_type: mach_o::FileType::Execute,
-            load_cmd_count: 8,
-            load_cmd_size: pagezero_segment.command_size
-                + text_segment.command_size
-                + entry_point.command_size
-                + dylinker.command_size
-                + linkedit_segment.command_size
-                + code_sig_cmd.command_size
-                + dysymtab.command_size
-                + symtab.command_size,
-            flags: HeaderFlags::PIE | HeaderFlags::DyldLink,
+            load_cmd_count,
+            load_cmd_size,
+            flags,
             _reserved: 0,
         };
 
@@ -255,35 +451,69 @@ impl Executable for AppleExecutable {
         codesign[4..8].copy_from_slice(&superblob_len.to_le_bytes());
         codesign[8..12].copy_from_slice(&superblob_count.to_le_bytes());
 
+        let bind_info = bind_info(&externs);
+
         let mut str_table_size = 1;
-        let (nlists, str_table): (Vec<_>, Vec<_>) = symbols
-            .into_iter()
-            .map(|(label, offset)| {
-                let nlist = NList {
-                    str_table_idx: str_table_size as u32,
-                    n_type: NListType::Sect,
-                    n_sect: 1,
-                    n_desc: 0,
-                    n_value: text_segment.vmaddr + offset,
-                };
-
-                str_table_size += label.len() + 1;
-
-                (nlist, CString::new(label).unwrap())
-            })
-            .unzip();
+        let local_symbols = symbols.into_iter().map(|(label, offset)| {
+            let nlist = NList {
+                str_table_idx: str_table_size as u32,
+                n_type: NListType::Sect,
+                n_sect: 1,
+                n_desc: 0,
+                n_value: text_section_header.addr + offset,
+            };
+
+            str_table_size += label.len() + 1;
+
+            (nlist, CString::new(label).unwrap())
+        });
+
+        let (mut nlists, mut str_table): (Vec<_>, Vec<_>) = local_symbols.unzip();
+
+        for name in externs.iter() {
+            let name = format!("_{}", name);
+
+            nlists.push(NList {
+                str_table_idx: str_table_size as u32,
+                n_type: NListType::UndefExt,
+                n_sect: 0,
+                n_desc: 1 << 8, // library ordinal 1 (libSystem)
+                n_value: 0,
+            });
+
+            str_table_size += name.len() + 1;
+            str_table.push(CString::new(name).unwrap());
+        }
+
+        // Every stub and every GOT slot refers to its external symbol.
+        let indirect_symbols: Vec<u32> = (0..2)
+            .flat_map(|_| local_count..local_count + extern_count as u32)
+            .collect();
 
         let nlists_size = size_of::<NList>() * nlists.len();
+        let indirect_symbols_size = size_of::<u32>() * indirect_symbols.len();
 
-        linkedit_segment.file_offset = text_section_end;
-        linkedit_segment.file_size = (codesign.len() + nlists_size + str_table_size) as u64;
+        linkedit_segment.file_offset = text_section_end + data_const_size;
+        linkedit_segment.file_size = (bind_info.len()
+            + nlists_size
+            + indirect_symbols_size
+            + str_table_size
+            + codesign.len()) as u64;
+        linkedit_segment.vmsize = self.page_align(linkedit_segment.file_size);
 
-        symtab.symoff = linkedit_segment.file_offset as u32;
+        dyld_info.bind_off = linkedit_segment.file_offset as u32;
+        dyld_info.bind_size = bind_info.len() as u32;
+
+        symtab.symoff = dyld_info.bind_off + dyld_info.bind_size;
         symtab.nsyms = nlists.len() as u32;
-        symtab.stroff = symtab.symoff + nlists_size as u32;
+
+        dysymtab.indirectsymoff = symtab.symoff + nlists_size as u32;
+        dysymtab.nindirectsyms = indirect_symbols.len() as u32;
+
+        symtab.stroff = dysymtab.indirectsymoff + indirect_symbols_size as u32;
         symtab.strsize = str_table_size as u32;
 
-        code_sig_cmd.data_offset = symtab.symoff + (nlists_size + str_table_size) as u32;
+        code_sig_cmd.data_offset = symtab.stroff + str_table_size as u32;
         code_sig_cmd.data_size = codesign.len() as u32;
 
         let mut vec: Vec<u8> = Vec::new();
@@ -291,14 +521,30 @@ impl Executable for AppleExecutable {
         vec.extend(bytes_of(&pagezero_segment));
         vec.extend(bytes_of(&text_segment));
         vec.extend(bytes_of(&text_section_header));
+        if has_externs {
+            vec.extend(bytes_of(&stubs_section_header));
+        }
         vec.extend(bytes_of(&cstring_section_header));
+        if has_externs {
+            vec.extend(bytes_of(&data_const_segment));
+            vec.extend(bytes_of(&got_section_header));
+        }
         vec.extend(bytes_of(&entry_point));
         vec.extend(bytes_of(&dylinker));
         vec.extend(&padded_linker_path);
+        vec.extend(bytes_of(&uuid_cmd));
+        if has_externs {
+            vec.extend(bytes_of(&libsystem));
+            vec.extend(&padded_libsystem_path);
+        }
         vec.extend(bytes_of(&linkedit_segment));
+        if has_externs {
+            vec.extend(bytes_of(&dyld_info));
+        }
         vec.extend(bytes_of(&code_sig_cmd));
         vec.extend(bytes_of(&dysymtab));
         vec.extend(bytes_of(&symtab));
+        vec.extend(&vec![0u8; text_data_offset - load_cmds_end]);
         vec.extend(instructions);
         vec.extend(
             str_literals
@@ -306,14 +552,18 @@ impl Executable for AppleExecutable {
                 .flat_map(|s| CString::from_str(s).unwrap().into_bytes_with_nul()),
         );
         vec.extend(&vec![0u8; text_seg_padding]);
+        vec.extend(&vec![0u8; data_const_size as usize]); // GOT, filled in by dyld
+        vec.extend(&bind_info);
         vec.extend(nlists.iter().flat_map(bytes_of));
+        vec.extend(indirect_symbols.iter().flat_map(|i| i.to_le_bytes()));
         vec.push(0); // First byte of string table must be 0, so nlists can point to empty string
         vec.extend(str_table.iter().flat_map(|s| s.to_bytes_with_nul()));
         vec.extend(&codesign);
 
-        let mut file = File::create(out_path).unwrap();
+        if let Err(err) = validate::validate_segments(&vec) {
+            panic!("laid out a malformed executable: {}", err);
+        }
 
-        // file.write_all(&vec).unwrap();
         let signer = MachOSigner::new(&vec).unwrap();
         let mut sign_settings = SigningSettings::default();
         sign_settings.set_binary_identifier(
@@ -322,10 +572,18 @@ impl Executable for AppleExecutable {
                 .as_ref()
                 .expect("apple executables require a binary identifier"),
         );
+
+        let mut signed = Vec::new();
         signer
-            .write_signed_binary(&sign_settings, &mut file)
+            .write_signed_binary(&sign_settings, &mut signed)
             .unwrap();
 
+        if let Err(err) = validate::validate_segments(&signed) {
+            panic!("signing produced a malformed executable: {}", err);
+        }
+
+        File::create(out_path).unwrap().write_all(&signed).unwrap();
+
         std::fs::set_permissions(out_path, Permissions::from_mode(0o755)).unwrap();
 
         self.path = Some(out_path.to_owned());
@@ -336,20 +594,104 @@ impl Executable for AppleExecutable {
         self
     }
 
-    fn run(&self) -> Result<ExitStatus, ExecutableError> {
+    /// Aligns segments to the page size of the target. Mach-O files only run on macOS, so that
+    /// is the page size macOS uses on the target's architecture, whatever the target's system.
+    fn with_target(self, target: Target) -> Self {
+        self.with_page_size(Target::new(target.arch, Os::MacOs).page_size())
+    }
+
+    fn run_with(&self, args: &[&str]) -> Result<ExitStatus, ExecutableError> {
         let Some(path) = self.path.as_ref() else {
             return Err(ExecutableError::NoBuildPath);
         };
 
-        let exit_status = std::process::Command::new(path).status()?;
+        if !self.is_runnable_here() {
+            return Err(ExecutableError::NotRunnable);
+        }
+
+        let exit_status = std::process::Command::new(path).args(args).status()?;
 
         Ok(exit_status)
     }
+
+    /// Mach-O files only run on macOS, and the arm64 code in them only on Apple Silicon.
+    fn is_runnable_here(&self) -> bool {
+        cfg!(all(target_os = "macos", target_arch = "aarch64"))
+    }
 }
 
-fn page_align(addr: u64) -> u64 {
-    const PAGE_ALIGN: u64 = 0x4000;
-    align(addr, PAGE_ALIGN)
+/// Size of a stub in __stubs: ADRP + LDR + BR.
+const STUB_SIZE: u32 = 12;
+
+/// Address of the `__TEXT` segment, right after `__PAGEZERO`. Position-independent executables
+/// are slid from here to a random address at load time. All code addresses PC-relative, so it
+/// runs unchanged either way.
+pub const TEXT_BASE: u64 = 0x100000000;
+
+/// Default page size, as used on Apple Silicon.
+const PAGE_SIZE: u64 = 0x4000;
+
+/// Alignment of the start of the code in the file, and thereby in memory.
+const CODE_ALIGN: usize = 64;
+
+/// Builds the bind opcodes telling dyld to fill each GOT slot with the address of its external
+/// symbol in libSystem.
+fn bind_info(externs: &[String]) -> Vec<u8> {
+    if externs.is_empty() {
+        return Vec::new();
+    }
+
+    const DATA_CONST_SEGMENT_INDEX: u8 = 2;
+
+    let mut info = vec![mach_o::BIND_OPCODE_SET_DYLIB_ORDINAL_IMM | 1];
+    for (i, name) in externs.iter().enumerate() {
+        info.push(mach_o::BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM);
+        info.extend(format!("_{}", name).bytes());
+        info.push(0);
+        info.push(mach_o::BIND_OPCODE_SET_TYPE_IMM | mach_o::BIND_TYPE_POINTER);
+        info.push(mach_o::BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB | DATA_CONST_SEGMENT_INDEX);
+        write_uleb128(&mut info, (i * size_of::<u64>()) as u64);
+        info.push(mach_o::BIND_OPCODE_DO_BIND);
+    }
+    info.push(mach_o::BIND_OPCODE_DONE);
+
+    info.resize(align(info.len(), 8), 0);
+    info
+}
+
+/// Derives a UUID from the code and string literals, so building the same program twice gives
+/// the same UUID. The bytes are hashed with 128-bit FNV-1a, and marked as a name-based UUID.
+fn content_uuid(instructions: &[u8], str_literals: &[String]) -> [u8; 16] {
+    const OFFSET_BASIS: u128 = 0x6c62272e07bb014262b821756295c58d;
+    const PRIME: u128 = 0x0000000001000000000000000000013b;
+
+    let bytes = instructions.iter().chain(
+        str_literals
+            .iter()
+            .flat_map(|s| s.as_bytes().iter().chain(&[0])),
+    );
+    let hash = bytes.fold(OFFSET_BASIS, |hash, &byte| {
+        (hash ^ byte as u128).wrapping_mul(PRIME)
+    });
+
+    let mut uuid = hash.to_be_bytes();
+    uuid[6] = (uuid[6] & 0x0f) | 0x30; // version 3
+    uuid[8] = (uuid[8] & 0x3f) | 0x80; // RFC 4122 variant
+    uuid
+}
+
+fn write_uleb128(buf: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+
+        if n == 0 {
+            buf.push(byte);
+            break;
+        }
+
+        buf.push(byte | 0x80);
+    }
 }
 
 fn align<
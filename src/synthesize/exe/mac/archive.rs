@@ -0,0 +1,277 @@
+use std::{fs, io, path::Path};
+
+use bytemuck::bytes_of;
+
+use crate::synthesize::{
+    arch::{Assembler, MachineCode, UnfinishedCode},
+    exe::mac::{
+        CODE_ALIGN, align,
+        mach_o::{
+            self, BuildVersionCommand, DySymTabCommand, Header, LoadCommand, MemoryPermissions,
+            NList, NListType, SectionFlags, SectionHeader, SegmentCommand, SymTabCommand,
+        },
+    },
+};
+
+/// Name of the member holding the symbol index, sorted by symbol name.
+const SYMDEF_NAME: &str = "__.SYMDEF SORTED";
+
+/// Functions that are part of every program, and are kept out of the symbol index.
+const HIDDEN_FUNCTIONS: [&str; 2] = ["main", "_entry_point"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum ArchiveError {
+    #[error("code in an archive cannot call external functions")]
+    Externs,
+    #[error("code in an archive cannot use string literals")]
+    StringLiterals,
+    #[error("failed to write archive")]
+    Io(#[from] io::Error),
+}
+
+/// Builds a static library that `ld` can link into other programs, holding the code as a
+/// single object file. Every function is exported under its name with a leading underscore,
+/// as C names its symbols, except `main` and the builtins.
+///
+/// The code is placed without relocations, so it may not use string literals or call
+/// external functions.
+pub fn build_archive<A: Assembler>(
+    code: UnfinishedCode<A>,
+    out_path: impl AsRef<Path>,
+) -> Result<(), ArchiveError> {
+    if code.extern_count() > 0 {
+        return Err(ArchiveError::Externs);
+    }
+
+    if code.str_literals_size() > 0 {
+        return Err(ArchiveError::StringLiterals);
+    }
+
+    let out_path = out_path.as_ref();
+    let size = code.size();
+    let code = code.finalize(size, 0);
+
+    let stem = out_path.file_stem().unwrap_or_default().to_string_lossy();
+    let (object, exported) = object_file(code);
+
+    fs::write(
+        out_path,
+        archive(&format!("{}.o", stem), &object, &exported),
+    )?;
+    Ok(())
+}
+
+/// Writes a relocatable object file with the code in `__TEXT,__text`, and returns it with
+/// the names of its exported symbols.
+fn object_file(code: MachineCode) -> (Vec<u8>, Vec<String>) {
+    let MachineCode {
+        instructions,
+        mut symbols,
+        ..
+    } = code;
+
+    // local symbols come first, then the exported ones sorted by name
+    let is_exported = |name: &str| !name.contains("::") && !HIDDEN_FUNCTIONS.contains(&name);
+    symbols.sort_by_key(|(name, _)| (is_exported(name), name.clone()));
+    let local_count = symbols
+        .iter()
+        .filter(|(name, _)| !is_exported(name))
+        .count();
+
+    let load_cmds_size = size_of::<SegmentCommand>()
+        + size_of::<SectionHeader>()
+        + size_of::<BuildVersionCommand>()
+        + size_of::<SymTabCommand>()
+        + size_of::<DySymTabCommand>();
+    let text_offset = align(size_of::<Header>() + load_cmds_size, CODE_ALIGN);
+    let symoff = align(text_offset + instructions.len(), 8);
+
+    let mut str_table = vec![0u8];
+    let mut nlists = Vec::new();
+    let mut exported = Vec::new();
+    for (name, offset) in symbols {
+        let (name, n_type) = if is_exported(&name) {
+            exported.push(format!("_{}", name));
+            (format!("_{}", name), NListType::SectExt)
+        } else {
+            (name, NListType::Sect)
+        };
+
+        nlists.push(NList {
+            str_table_idx: str_table.len() as u32,
+            n_type,
+            n_sect: 1,
+            n_desc: 0,
+            n_value: offset,
+        });
+        str_table.extend(name.bytes());
+        str_table.push(0);
+    }
+
+    let stroff = symoff + size_of::<NList>() * nlists.len();
+
+    let header = Header {
+        magic: mach_o::Magic::X64,
+        cpu_type: mach_o::CpuType::Arm64,
+        cpu_subtype: mach_o::CpuSubtype::Arm,
+        file_type: mach_o::FileType::Object,
+        load_cmd_count: 4,
+        load_cmd_size: load_cmds_size as u32,
+        flags: mach_o::HeaderFlags::empty(),
+        _reserved: 0,
+    };
+
+    // object files have a single unnamed segment holding every section
+    let segment = SegmentCommand {
+        command: LoadCommand::Segment,
+        command_size: (size_of::<SegmentCommand>() + size_of::<SectionHeader>()) as u32,
+        segment_name: [0; 16],
+        vmaddr: 0,
+        vmsize: instructions.len() as u64,
+        file_offset: text_offset as u64,
+        file_size: instructions.len() as u64,
+        max_prot: MemoryPermissions::ReadExecute,
+        init_prot: MemoryPermissions::ReadExecute,
+        section_count: 1,
+        flags: 0,
+    };
+
+    let text_section = SectionHeader {
+        section_name: b"__text\0\0\0\0\0\0\0\0\0\0".to_owned(),
+        segment_name: b"__TEXT\0\0\0\0\0\0\0\0\0\0".to_owned(),
+        addr: 0,
+        size: instructions.len() as u64,
+        offset: text_offset as u32,
+        align: CODE_ALIGN.trailing_zeros(),
+        reloff: 0,
+        nreloc: 0,
+        flags: SectionFlags::Instructions,
+        _reserved1: 0,
+        _reserved2: 0,
+        _reserved3: 0,
+    };
+
+    let build_version = BuildVersionCommand {
+        command: LoadCommand::BuildVersion,
+        command_size: size_of::<BuildVersionCommand>() as u32,
+        platform: mach_o::PLATFORM_MACOS,
+        min_os: 0x000b0000, // 11.0.0
+        sdk: 0,
+        tool_count: 0,
+    };
+
+    let symtab = SymTabCommand {
+        command: LoadCommand::SymTab,
+        command_size: size_of::<SymTabCommand>() as u32,
+        symoff: symoff as u32,
+        nsyms: nlists.len() as u32,
+        stroff: stroff as u32,
+        strsize: str_table.len() as u32,
+    };
+
+    let dysymtab = DySymTabCommand {
+        command: LoadCommand::DySymTab,
+        command_size: size_of::<DySymTabCommand>() as u32,
+        ilocalsym: 0,
+        nlocalsym: local_count as u32,
+        iextdefsym: local_count as u32,
+        nextdefsym: exported.len() as u32,
+        iundefsym: nlists.len() as u32,
+        nundefsym: 0,
+        tocoff: 0,
+        ntoc: 0,
+        modtaboff: 0,
+        nmodtab: 0,
+        extrefsymoff: 0,
+        nextrefsyms: 0,
+        indirectsymoff: 0,
+        nindirectsyms: 0,
+        extreloff: 0,
+        nextrel: 0,
+        locreloff: 0,
+        nlocrel: 0,
+    };
+
+    let mut object = Vec::new();
+    object.extend(bytes_of(&header));
+    object.extend(bytes_of(&segment));
+    object.extend(bytes_of(&text_section));
+    object.extend(bytes_of(&build_version));
+    object.extend(bytes_of(&symtab));
+    object.extend(bytes_of(&dysymtab));
+    object.resize(text_offset, 0);
+    object.extend(instructions);
+    object.resize(symoff, 0);
+    object.extend(nlists.iter().flat_map(bytes_of));
+    object.extend(str_table);
+
+    (object, exported)
+}
+
+/// Wraps `object` in an archive, after an index of the symbols it exports.
+fn archive(object_name: &str, object: &[u8], symbols: &[String]) -> Vec<u8> {
+    let mut symbols = symbols.to_vec();
+    symbols.sort();
+
+    let mut str_table = Vec::new();
+    let mut str_offsets = Vec::new();
+    for symbol in &symbols {
+        str_offsets.push(str_table.len() as u32);
+        str_table.extend(symbol.bytes());
+        str_table.push(0);
+    }
+    str_table.resize(align(str_table.len(), 8), 0);
+
+    // struct ranlib { ran_strx, ran_off } per symbol, each surrounded by its size
+    let symdef_size = 4 + 8 * symbols.len() + 4 + str_table.len();
+    let object_header_offset = b"!<arch>\n".len() + member_header_size(SYMDEF_NAME) + symdef_size;
+
+    let mut symdef = Vec::new();
+    symdef.extend((8 * symbols.len() as u32).to_le_bytes());
+    for str_offset in str_offsets {
+        symdef.extend(str_offset.to_le_bytes());
+        symdef.extend((object_header_offset as u32).to_le_bytes());
+    }
+    symdef.extend((str_table.len() as u32).to_le_bytes());
+    symdef.extend(str_table);
+
+    let mut object = object.to_vec();
+    object.resize(align(object.len(), 8), 0);
+
+    let mut archive = b"!<arch>\n".to_vec();
+    write_member(&mut archive, SYMDEF_NAME, &symdef);
+    write_member(&mut archive, object_name, &object);
+    archive
+}
+
+/// Size of the header of a member named `name`, including the name that follows it. The name
+/// is padded so the contents of the member start 8-byte aligned, as `ld` expects.
+fn member_header_size(name: &str) -> usize {
+    60 + padded_name_len(name)
+}
+
+fn padded_name_len(name: &str) -> usize {
+    // the 60 byte header starts aligned, so the name must end 4 bytes past an alignment
+    align(name.len() + 1 + 4, 8) - 4
+}
+
+/// Appends a member in the BSD format, with its name after the header (`#1/<length>`).
+fn write_member(archive: &mut Vec<u8>, name: &str, contents: &[u8]) {
+    let name_len = padded_name_len(name);
+
+    let header = format!(
+        "{:<16}{:<12}{:<6}{:<6}{:<8}{:<10}`\n",
+        format!("#1/{}", name_len),
+        0,
+        0,
+        0,
+        100644,
+        name_len + contents.len()
+    );
+    archive.extend(header.bytes());
+
+    let mut name = name.as_bytes().to_vec();
+    name.resize(name_len, 0);
+    archive.extend(name);
+    archive.extend(contents);
+}
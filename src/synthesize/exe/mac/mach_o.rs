@@ -1,5 +1,10 @@
 use bitflags::bitflags;
-use bytemuck::NoUninit;
+use bytemuck::{
+    CheckedBitPattern, NoUninit,
+    checked::{self, CheckedCastError},
+};
+
+use crate::synthesize::exe::mac::validate::LayoutError;
 
 // Mach-O header files found here: /Library/Developer/CommandLineTools/SDKs/MacOSX.sdk/usr/include/mach-o
 
@@ -40,7 +45,8 @@ pub enum Magic {
 pub enum CpuType {
     // Any = -1,
     // X86 = 7,
-    // X86_64 = CpuType::X86 as i32 | 64,
+    /// `CPU_TYPE_X86 | CPU_ARCH_ABI64`
+    X86_64 = 0x01000007,
     Arm64 = 0x0100000c,
 }
 
@@ -50,12 +56,14 @@ pub enum CpuSubtype {
     // LittleEndian = 0,
     // BigEndian = 1,
     Arm = 0,
-    // X86 = 3,
+    /// `CPU_SUBTYPE_X86_ALL`
+    X86 = 3,
 }
 
 #[repr(u32)]
 #[derive(NoUninit, Copy, Clone)]
 pub enum FileType {
+    Object = 1,
     Execute = 2,
 }
 
@@ -91,7 +99,7 @@ bitflags! {
 }
 
 #[repr(u32)]
-#[derive(NoUninit, Copy, Clone)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub enum LoadCommand {
     Segment = 0x19,
     EntryPoint = 0x80000028,
@@ -99,10 +107,14 @@ pub enum LoadCommand {
     CodeSignature = 0x1d,
     SymTab = 0x2,
     DySymTab = 0xb,
+    LoadDyLib = 0xc,
+    DyldInfoOnly = 0x80000022,
+    BuildVersion = 0x32,
+    Uuid = 0x1b,
 }
 
 #[repr(C)]
-#[derive(NoUninit, Copy, Clone)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct SegmentCommand {
     pub command: LoadCommand,
     pub command_size: u32,
@@ -118,7 +130,7 @@ pub struct SegmentCommand {
 }
 
 #[repr(transparent)]
-#[derive(NoUninit, Copy, Clone)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct MemoryPermissions(i32);
 
 bitflags! {
@@ -133,7 +145,7 @@ bitflags! {
 }
 
 #[repr(C)]
-#[derive(NoUninit, Clone, Copy)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct SectionHeader {
     pub section_name: [u8; 16],
     pub segment_name: [u8; 16],
@@ -150,14 +162,21 @@ pub struct SectionHeader {
 }
 
 #[repr(u32)]
-#[derive(NoUninit, Clone, Copy)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub enum SectionFlags {
     Regular = 0,
     CStringLiterals = 2,
+    NonLazySymbolPointers = 6,
+    /// Symbol stubs made up only of instructions (`S_SYMBOL_STUBS | S_ATTR_PURE_INSTRUCTIONS |
+    /// S_ATTR_SOME_INSTRUCTIONS`)
+    SymbolStubs = 0x80000408,
+    /// Regular section made up only of instructions (`S_ATTR_PURE_INSTRUCTIONS |
+    /// S_ATTR_SOME_INSTRUCTIONS`)
+    Instructions = 0x80000400,
 }
 
 #[repr(C)]
-#[derive(NoUninit, Copy, Clone)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct EntryPointCommand {
     pub command: LoadCommand,
     pub command_size: u32,
@@ -166,17 +185,65 @@ pub struct EntryPointCommand {
 }
 
 #[repr(C)]
-#[derive(NoUninit, Copy, Clone)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct DyLinkerCommand {
     pub command: LoadCommand,
     pub command_size: u32,
     pub path_str_offset: u32,
 }
 
+#[repr(C)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
+pub struct DyLibCommand {
+    pub command: LoadCommand,
+    pub command_size: u32,
+    pub path_str_offset: u32,
+    pub timestamp: u32,
+    pub current_version: u32,
+    pub compatibility_version: u32,
+}
+
+/// Locations of the information dyld needs to bind and rebase the image, all within __LINKEDIT.
+#[repr(C)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
+pub struct DyldInfoCommand {
+    pub command: LoadCommand,
+    pub command_size: u32,
+    pub rebase_off: u32,
+    pub rebase_size: u32,
+    pub bind_off: u32,
+    pub bind_size: u32,
+    pub weak_bind_off: u32,
+    pub weak_bind_size: u32,
+    pub lazy_bind_off: u32,
+    pub lazy_bind_size: u32,
+    pub export_off: u32,
+    pub export_size: u32,
+}
+
+// Opcodes of the bind info stream. The low 4 bits are an immediate operand.
+pub const BIND_OPCODE_DONE: u8 = 0x00;
+pub const BIND_OPCODE_SET_DYLIB_ORDINAL_IMM: u8 = 0x10;
+pub const BIND_OPCODE_SET_SYMBOL_TRAILING_FLAGS_IMM: u8 = 0x40;
+pub const BIND_OPCODE_SET_TYPE_IMM: u8 = 0x50;
+pub const BIND_OPCODE_SET_SEGMENT_AND_OFFSET_ULEB: u8 = 0x70;
+pub const BIND_OPCODE_DO_BIND: u8 = 0x90;
+
+pub const BIND_TYPE_POINTER: u8 = 1;
+
 pub const CSMAGIC_EMBEDDED_SIGNATURE: u32 = 0xfade0cc0;
 
+/// Identifies the image, so that tools can match it with its debug symbols and crash reports.
 #[repr(C)]
-#[derive(NoUninit, Copy, Clone)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
+pub struct UuidCommand {
+    pub command: LoadCommand,
+    pub command_size: u32,
+    pub uuid: [u8; 16],
+}
+
+#[repr(C)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct LinkEditDataCommand {
     pub command: LoadCommand,
     pub command_size: u32,
@@ -185,7 +252,7 @@ pub struct LinkEditDataCommand {
 }
 
 #[repr(C)]
-#[derive(NoUninit, Clone, Copy)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct SymTabCommand {
     pub command: LoadCommand,
     pub command_size: u32,
@@ -196,7 +263,7 @@ pub struct SymTabCommand {
 }
 
 #[repr(C)]
-#[derive(NoUninit, Copy, Clone)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
 pub struct DySymTabCommand {
     pub command: LoadCommand,
     pub command_size: u32,
@@ -233,5 +300,122 @@ pub struct NList {
 #[repr(u8)]
 #[derive(NoUninit, Clone, Copy)]
 pub enum NListType {
+    /// Undefined external symbol, resolved by dyld
+    UndefExt = 0x1,
     Sect = 0xe,
+    /// Defined in a section and visible to other object files
+    SectExt = 0xf,
+}
+
+#[repr(C)]
+#[derive(NoUninit, CheckedBitPattern, Copy, Clone, Debug)]
+pub struct BuildVersionCommand {
+    pub command: LoadCommand,
+    pub command_size: u32,
+    pub platform: u32,
+    /// Minimum OS version, encoded as `xxxx.yy.zz` in nibbles
+    pub min_os: u32,
+    pub sdk: u32,
+    pub tool_count: u32,
+}
+
+pub const PLATFORM_MACOS: u32 = 1;
+
+/// A load command read back from a Mach-O file.
+#[derive(Debug, Clone)]
+pub enum ParsedCommand {
+    Segment(SegmentCommand, Vec<SectionHeader>),
+    EntryPoint(EntryPointCommand),
+    DyLinker(DyLinkerCommand),
+    DyLib(DyLibCommand),
+    CodeSignature(LinkEditDataCommand),
+    SymTab(SymTabCommand),
+    DySymTab(DySymTabCommand),
+    DyldInfo(DyldInfoCommand),
+    BuildVersion(BuildVersionCommand),
+    Uuid(UuidCommand),
+    /// A command that is not written by us, and so is not read either.
+    Unknown {
+        command: u32,
+        command_size: u32,
+    },
+}
+
+impl ParsedCommand {
+    /// Size of the command, including everything that follows its fixed part, such as sections
+    /// and paths.
+    pub fn command_size(&self) -> u32 {
+        match self {
+            ParsedCommand::Segment(cmd, _) => cmd.command_size,
+            ParsedCommand::EntryPoint(cmd) => cmd.command_size,
+            ParsedCommand::DyLinker(cmd) => cmd.command_size,
+            ParsedCommand::DyLib(cmd) => cmd.command_size,
+            ParsedCommand::CodeSignature(cmd) => cmd.command_size,
+            ParsedCommand::SymTab(cmd) => cmd.command_size,
+            ParsedCommand::DySymTab(cmd) => cmd.command_size,
+            ParsedCommand::DyldInfo(cmd) => cmd.command_size,
+            ParsedCommand::BuildVersion(cmd) => cmd.command_size,
+            ParsedCommand::Uuid(cmd) => cmd.command_size,
+            ParsedCommand::Unknown { command_size, .. } => *command_size,
+        }
+    }
+}
+
+/// Reads the load commands that follow the header of a 64-bit Mach-O file.
+pub fn parse_load_commands(bytes: &[u8]) -> Result<Vec<ParsedCommand>, LayoutError> {
+    fn read<T: CheckedBitPattern>(bytes: &[u8], offset: usize) -> Result<T, LayoutError> {
+        let bytes = bytes
+            .get(offset..offset + size_of::<T>())
+            .ok_or(LayoutError::Truncated)?;
+
+        checked::try_pod_read_unaligned(bytes).map_err(|err| match err {
+            CheckedCastError::InvalidBitPattern => LayoutError::Malformed(offset),
+            _ => LayoutError::Truncated,
+        })
+    }
+
+    let command_count: u32 = read(bytes, 16)?;
+
+    let mut commands = Vec::new();
+    let mut offset = size_of::<Header>();
+    for _ in 0..command_count {
+        let command: u32 = read(bytes, offset)?;
+        let command_size: u32 = read(bytes, offset + 4)?;
+        if command_size == 0 {
+            return Err(LayoutError::Truncated);
+        }
+
+        // the command is read again as its type, which fails for types we don't know
+        let parsed = match read::<LoadCommand>(bytes, offset) {
+            Ok(LoadCommand::Segment) => {
+                let segment: SegmentCommand = read(bytes, offset)?;
+                let sections = (0..segment.section_count as usize)
+                    .map(|i| {
+                        let section_offset =
+                            offset + size_of::<SegmentCommand>() + i * size_of::<SectionHeader>();
+                        read(bytes, section_offset)
+                    })
+                    .collect::<Result<_, _>>()?;
+                ParsedCommand::Segment(segment, sections)
+            }
+            Ok(LoadCommand::EntryPoint) => ParsedCommand::EntryPoint(read(bytes, offset)?),
+            Ok(LoadCommand::LoadDyLinker) => ParsedCommand::DyLinker(read(bytes, offset)?),
+            Ok(LoadCommand::LoadDyLib) => ParsedCommand::DyLib(read(bytes, offset)?),
+            Ok(LoadCommand::CodeSignature) => ParsedCommand::CodeSignature(read(bytes, offset)?),
+            Ok(LoadCommand::SymTab) => ParsedCommand::SymTab(read(bytes, offset)?),
+            Ok(LoadCommand::DySymTab) => ParsedCommand::DySymTab(read(bytes, offset)?),
+            Ok(LoadCommand::DyldInfoOnly) => ParsedCommand::DyldInfo(read(bytes, offset)?),
+            Ok(LoadCommand::BuildVersion) => ParsedCommand::BuildVersion(read(bytes, offset)?),
+            Ok(LoadCommand::Uuid) => ParsedCommand::Uuid(read(bytes, offset)?),
+            Err(_) => ParsedCommand::Unknown {
+                command,
+                command_size,
+            },
+        };
+
+        commands.push(parsed);
+        offset += command_size as usize;
+    }
+
+    Ok(commands)
 }
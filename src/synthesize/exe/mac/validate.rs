@@ -0,0 +1,254 @@
+use std::ops::Range;
+
+use crate::synthesize::exe::mac::mach_o::{ParsedCommand, parse_load_commands};
+
+/// A problem with how the segments of a Mach-O file are laid out.
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum LayoutError {
+    #[error("load commands extend past the end of the file")]
+    Truncated,
+    #[error("the load command at {0:#x} is malformed")]
+    Malformed(usize),
+    #[error("segment {0} extends past the end of the file")]
+    OutOfBounds(String),
+    #[error("segment {0} is mapped into less memory than it takes up in the file")]
+    VmSizeTooSmall(String),
+    #[error("segment {0} does not start where the previous segment ends, at {1:#x}")]
+    NotContiguous(String, u64),
+    #[error("the last {0:#x} bytes of the file are not part of any segment")]
+    Uncovered(u64),
+    #[error("the symbol table extends past the end of the file")]
+    SymbolsOutOfBounds,
+}
+
+/// A segment as described by its `LC_SEGMENT_64` command.
+#[derive(Debug)]
+pub struct Segment {
+    pub name: String,
+    pub vmsize: u64,
+    pub file: Range<u64>,
+}
+
+/// A symbol as described by its `nlist_64` entry in the symbol table.
+#[derive(Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub n_type: u8,
+    pub n_sect: u8,
+    pub value: u64,
+}
+
+/// Reads the UUIDs from the `LC_UUID` commands of a 64-bit Mach-O file. A well-formed file has
+/// at most one.
+pub fn uuids(file: &[u8]) -> Result<Vec<[u8; 16]>, LayoutError> {
+    let uuids = parse_load_commands(file)?
+        .into_iter()
+        .filter_map(|command| match command {
+            ParsedCommand::Uuid(uuid) => Some(uuid.uuid),
+            _ => None,
+        })
+        .collect();
+
+    Ok(uuids)
+}
+
+/// Reads the segments from the load commands of a 64-bit Mach-O file.
+pub fn segments(file: &[u8]) -> Result<Vec<Segment>, LayoutError> {
+    let segments = parse_load_commands(file)?
+        .into_iter()
+        .filter_map(|command| match command {
+            ParsedCommand::Segment(segment, _) => Some(Segment {
+                name: String::from_utf8_lossy(&segment.segment_name)
+                    .trim_end_matches('\0')
+                    .to_owned(),
+                vmsize: segment.vmsize,
+                file: segment.file_offset..segment.file_offset + segment.file_size,
+            }),
+            _ => None,
+        })
+        .collect();
+
+    Ok(segments)
+}
+
+/// Reads the symbols of a 64-bit Mach-O file from the symbol table and string table that its
+/// `LC_SYMTAB` command points to.
+pub fn symbols(file: &[u8]) -> Result<Vec<Symbol>, LayoutError> {
+    const NLIST_SIZE: usize = 16;
+
+    let Some(symtab) = parse_load_commands(file)?
+        .into_iter()
+        .find_map(|command| match command {
+            ParsedCommand::SymTab(symtab) => Some(symtab),
+            _ => None,
+        })
+    else {
+        return Ok(Vec::new());
+    };
+
+    let nlists =
+        symtab.symoff as usize..symtab.symoff as usize + symtab.nsyms as usize * NLIST_SIZE;
+    let strings = symtab.stroff as usize..symtab.stroff as usize + symtab.strsize as usize;
+    let (Some(nlists), Some(strings)) = (file.get(nlists), file.get(strings)) else {
+        return Err(LayoutError::SymbolsOutOfBounds);
+    };
+
+    nlists
+        .chunks_exact(NLIST_SIZE)
+        .map(|nlist| {
+            let str_table_idx = u32::from_le_bytes(nlist[0..4].try_into().unwrap()) as usize;
+            let name = strings
+                .get(str_table_idx..)
+                .and_then(|name| name.split(|&b| b == 0).next())
+                .ok_or(LayoutError::SymbolsOutOfBounds)?;
+
+            Ok(Symbol {
+                name: String::from_utf8_lossy(name).into_owned(),
+                n_type: nlist[4],
+                n_sect: nlist[5],
+                value: u64::from_le_bytes(nlist[8..16].try_into().unwrap()),
+            })
+        })
+        .collect()
+}
+
+/// Checks that the segments of a 64-bit Mach-O file lie within it, and together cover every
+/// byte of it exactly once. Segments without any file contents, such as `__PAGEZERO`, are
+/// ignored.
+pub fn validate_segments(file: &[u8]) -> Result<(), LayoutError> {
+    let mut segments = segments(file)?;
+    segments.retain(|segment| !segment.file.is_empty());
+    segments.sort_by_key(|segment| segment.file.start);
+
+    let mut end = 0;
+    for segment in segments {
+        if segment.file.end > file.len() as u64 {
+            return Err(LayoutError::OutOfBounds(segment.name));
+        }
+
+        if segment.vmsize < segment.file.end - segment.file.start {
+            return Err(LayoutError::VmSizeTooSmall(segment.name));
+        }
+
+        if segment.file.start != end {
+            return Err(LayoutError::NotContiguous(segment.name, end));
+        }
+
+        end = segment.file.end;
+    }
+
+    match file.len() as u64 - end {
+        0 => Ok(()),
+        uncovered => Err(LayoutError::Uncovered(uncovered)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::synthesize::exe::mac::mach_o::{Header, LoadCommand};
+
+    /// Encodes an `LC_SEGMENT_64` command without sections.
+    fn segment(name: &str, vmsize: u64, file_offset: u64, file_size: u64) -> Vec<u8> {
+        let mut command = vec![0u8; 72];
+        command[0..4].copy_from_slice(&(LoadCommand::Segment as u32).to_le_bytes());
+        command[4..8].copy_from_slice(&72u32.to_le_bytes());
+        command[8..8 + name.len()].copy_from_slice(name.as_bytes());
+        command[32..40].copy_from_slice(&vmsize.to_le_bytes());
+        command[40..48].copy_from_slice(&file_offset.to_le_bytes());
+        command[48..56].copy_from_slice(&file_size.to_le_bytes());
+        command
+    }
+
+    /// Encodes an `LC_SYMTAB` command.
+    fn symtab(symoff: u32, nsyms: u32, stroff: u32, strsize: u32) -> Vec<u8> {
+        [
+            LoadCommand::SymTab as u32,
+            24,
+            symoff,
+            nsyms,
+            stroff,
+            strsize,
+        ]
+        .iter()
+        .flat_map(|n| n.to_le_bytes())
+        .collect()
+    }
+
+    fn file(segments: &[Vec<u8>], len: usize) -> Vec<u8> {
+        let mut file = vec![0u8; size_of::<Header>()];
+        file[16..20].copy_from_slice(&(segments.len() as u32).to_le_bytes());
+        file.extend(segments.iter().flatten());
+        file.resize(len, 0);
+        file
+    }
+
+    #[test]
+    fn tiled_segments() {
+        let file = file(
+            &[
+                segment("__PAGEZERO", 1 << 32, 0, 0),
+                segment("__TEXT", 0x4000, 0, 0x4000),
+                segment("__LINKEDIT", 0x4000, 0x4000, 0x100),
+            ],
+            0x4100,
+        );
+        assert_eq!(validate_segments(&file), Ok(()));
+    }
+
+    #[test]
+    fn gap_between_segments() {
+        let file = file(
+            &[
+                segment("__TEXT", 0x4000, 0, 0x4000),
+                segment("__LINKEDIT", 0x4000, 0x4010, 0x100),
+            ],
+            0x4110,
+        );
+        assert_eq!(
+            validate_segments(&file),
+            Err(LayoutError::NotContiguous("__LINKEDIT".to_owned(), 0x4000))
+        );
+    }
+
+    #[test]
+    fn bytes_after_last_segment() {
+        let file = file(&[segment("__TEXT", 0x4000, 0, 0x4000)], 0x4010);
+        assert_eq!(validate_segments(&file), Err(LayoutError::Uncovered(0x10)));
+    }
+
+    #[test]
+    fn segment_larger_than_mapping() {
+        let file = file(&[segment("__TEXT", 0x1000, 0, 0x4000)], 0x4000);
+        assert_eq!(
+            validate_segments(&file),
+            Err(LayoutError::VmSizeTooSmall("__TEXT".to_owned()))
+        );
+    }
+
+    #[test]
+    fn read_symbols() {
+        let mut file = file(&[symtab(0x100, 1, 0x110, 8)], 0x118);
+        file[0x100..0x104].copy_from_slice(&1u32.to_le_bytes());
+        file[0x104] = 0xe;
+        file[0x105] = 1;
+        file[0x108..0x110].copy_from_slice(&0x1_0000_4000u64.to_le_bytes());
+        file[0x111..0x116].copy_from_slice(b"main\0");
+
+        assert_eq!(
+            symbols(&file),
+            Ok(vec![Symbol {
+                name: "main".to_owned(),
+                n_type: 0xe,
+                n_sect: 1,
+                value: 0x1_0000_4000,
+            }])
+        );
+    }
+
+    #[test]
+    fn symbol_table_past_end() {
+        let file = file(&[symtab(0x100, 4, 0x140, 8)], 0x120);
+        assert_eq!(symbols(&file), Err(LayoutError::SymbolsOutOfBounds));
+    }
+}
@@ -0,0 +1,45 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::ExitStatus,
+};
+
+use crate::synthesize::{
+    arch::{Assembler, UnfinishedCode},
+    exe::{Executable, ExecutableError},
+};
+
+/// Writes the module assembled by [crate::synthesize::arch::wasm::WasmAssembler] to a `.wasm`
+/// file. The module needs a WebAssembly runtime, so it cannot be run directly.
+#[derive(Default)]
+pub struct WasmModule {
+    path: Option<PathBuf>,
+}
+
+impl WasmModule {
+    /// Path the module was written to, if it was built.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+}
+
+impl Executable for WasmModule {
+    fn with_binary_identifier(self, _ident: impl AsRef<str>) -> Self {
+        self
+    }
+
+    fn build<A: Assembler>(&mut self, code: UnfinishedCode<A>, out_path: impl AsRef<Path>) {
+        let out_path = out_path.as_ref();
+        let code = code.finalize(0, 0);
+        fs::write(out_path, code.instructions).unwrap();
+        self.path = Some(out_path.to_path_buf());
+    }
+
+    fn run_with(&self, _args: &[&str]) -> Result<ExitStatus, ExecutableError> {
+        Err(ExecutableError::NotRunnable)
+    }
+
+    fn is_runnable_here(&self) -> bool {
+        false
+    }
+}
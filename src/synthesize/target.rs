@@ -0,0 +1,179 @@
+use std::{env, fmt, str::FromStr};
+
+/// Instruction set that code is generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arch {
+    Aarch64,
+    X86_64,
+    Wasm32,
+}
+
+/// Operating system that an executable is built for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Os {
+    MacOs,
+    Linux,
+    /// No operating system, or one without a name here.
+    Unknown,
+}
+
+impl Os {
+    /// The operating system the compiler is running on.
+    pub fn host() -> Self {
+        match env::consts::OS {
+            "macos" => Os::MacOs,
+            "linux" => Os::Linux,
+            _ => Os::Unknown,
+        }
+    }
+}
+
+/// What to compile for, written as a target triple like `aarch64-apple-darwin`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Target {
+    pub arch: Arch,
+    pub os: Os,
+}
+
+impl Target {
+    pub const fn new(arch: Arch, os: Os) -> Self {
+        Self { arch, os }
+    }
+
+    /// The machine the compiler is running on, or an error if code cannot be generated for its
+    /// architecture.
+    pub fn host() -> Result<Self, TargetError> {
+        let arch = match env::consts::ARCH {
+            "aarch64" => Arch::Aarch64,
+            "x86_64" => Arch::X86_64,
+            "wasm32" => Arch::Wasm32,
+            other => return Err(TargetError::UnknownArch(other.to_owned())),
+        };
+
+        Ok(Self {
+            arch,
+            os: Os::host(),
+        })
+    }
+
+    /// Size of the pages that segments are aligned to. Apple Silicon uses 16KB pages, while
+    /// everything else is assumed to use 4KB pages.
+    pub fn page_size(&self) -> u64 {
+        match (self.arch, self.os) {
+            (Arch::Aarch64, Os::MacOs) => 0x4000,
+            _ => 0x1000,
+        }
+    }
+}
+
+impl fmt::Display for Arch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Arch::Aarch64 => "aarch64",
+            Arch::X86_64 => "x86_64",
+            Arch::Wasm32 => "wasm32",
+        })
+    }
+}
+
+impl fmt::Display for Os {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Os::MacOs => "macos",
+            Os::Linux => "linux",
+            Os::Unknown => "unknown",
+        })
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.os {
+            Os::MacOs => write!(f, "{}-apple-darwin", self.arch),
+            Os::Linux => write!(f, "{}-unknown-linux", self.arch),
+            Os::Unknown => write!(f, "{}-unknown-unknown", self.arch),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum TargetError {
+    #[error("unknown architecture {0}")]
+    UnknownArch(String),
+    #[error("unknown operating system {0}")]
+    UnknownOs(String),
+}
+
+/// Parses a target triple, `arch-vendor-os` with an optional environment after it. The vendor
+/// is ignored, and a bare architecture has no operating system.
+impl FromStr for Target {
+    type Err = TargetError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('-');
+
+        let arch = match parts.next().unwrap_or_default() {
+            "aarch64" | "arm64" => Arch::Aarch64,
+            "x86_64" => Arch::X86_64,
+            "wasm32" => Arch::Wasm32,
+            other => return Err(TargetError::UnknownArch(other.to_owned())),
+        };
+
+        let os = match parts.nth(1) {
+            None | Some("unknown" | "none") => Os::Unknown,
+            Some("darwin" | "macos") => Os::MacOs,
+            Some("linux") => Os::Linux,
+            Some(other) => return Err(TargetError::UnknownOs(other.to_owned())),
+        };
+
+        Ok(Self { arch, os })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_matches_env_consts() {
+        let Ok(host) = Target::host() else {
+            return;
+        };
+        assert_eq!(host.arch.to_string(), env::consts::ARCH);
+        if host.os != Os::Unknown {
+            assert_eq!(host.os.to_string(), env::consts::OS);
+        }
+    }
+
+    #[test]
+    fn parse_triples() {
+        assert_eq!(
+            "aarch64-apple-darwin".parse(),
+            Ok(Target::new(Arch::Aarch64, Os::MacOs))
+        );
+        assert_eq!(
+            "arm64-apple-macos".parse(),
+            Ok(Target::new(Arch::Aarch64, Os::MacOs))
+        );
+        assert_eq!(
+            "x86_64-unknown-linux-gnu".parse(),
+            Ok(Target::new(Arch::X86_64, Os::Linux))
+        );
+        assert_eq!("wasm32".parse(), Ok(Target::new(Arch::Wasm32, Os::Unknown)));
+        assert_eq!(
+            "riscv64-unknown-linux".parse::<Target>(),
+            Err(TargetError::UnknownArch("riscv64".to_owned()))
+        );
+    }
+
+    #[test]
+    fn display_round_trips() {
+        for target in [
+            Target::new(Arch::Aarch64, Os::MacOs),
+            Target::new(Arch::X86_64, Os::Linux),
+            Target::new(Arch::Wasm32, Os::Unknown),
+        ] {
+            assert_eq!(target.to_string().parse(), Ok(target));
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Checks the exact instructions generated for small programs, instead of only what they
+//! compute. These are expected to change along with codegen, and should then be updated by
+//! hand, so a regression in code quality is a deliberate decision rather than unnoticed.
+
+use std::{path::PathBuf, rc::Rc};
+
+use istind::{
+    Compiler,
+    synthesize::{
+        arch::arm::{ArmAssembler, disasm},
+        exe::DummyExecutable,
+    },
+};
+
+/// Disassembles the instructions of `function` in the code generated for `source`.
+fn disassemble(source: &str, function: &str) -> Vec<String> {
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler
+        .compile_source(Rc::new(PathBuf::from("main")), source)
+        .unwrap();
+    let size = code.size();
+    let code = code.finalize(size, 0);
+
+    let start = code
+        .symbols
+        .iter()
+        .find(|(name, _)| name == function)
+        .unwrap_or_else(|| panic!("no function {}", function))
+        .1;
+    let end = code
+        .symbols
+        .iter()
+        .map(|&(_, offset)| offset)
+        .filter(|&offset| offset > start)
+        .min()
+        .unwrap_or(code.instructions.len() as u64);
+
+    disasm::instructions(&code)[start as usize / 4..end as usize / 4].to_vec()
+}
+
+#[test]
+fn return_zero() {
+    assert_eq!(
+        disassemble("fn main() -> i64 { return 0; }", "main"),
+        [
+            "stp x29, x30, [sp, #-16]!",
+            "mov x29, sp",
+            "movz x0, #0",
+            "b .+4",
+            "ldp x29, x30, [sp], #16",
+            "ret",
+        ]
+    );
+}
+
+#[test]
+fn return_variable() {
+    assert_eq!(
+        disassemble("fn main() -> i64 { a := 2; return a; }", "main"),
+        [
+            "stp x29, x30, [sp, #-16]!",
+            "mov x29, sp",
+            "sub sp, sp, #16",
+            "movz x15, #2",
+            "str x15, [sp, #0]",
+            "ldr x14, [sp, #0]",
+            "mov x0, x14",
+            "b .+4",
+            "add sp, sp, #16",
+            "ldp x29, x30, [sp], #16",
+            "ret",
+        ]
+    );
+}
+
+#[test]
+fn constant_arithmetic_is_folded() {
+    let instrs = disassemble("fn main() -> i64 { return 1 + 2 + 3; }", "main");
+
+    assert!(
+        !instrs.iter().any(|instr| instr.starts_with("add x")),
+        "{:#?}",
+        instrs
+    );
+    assert!(
+        instrs.iter().any(|instr| instr.ends_with(", #6")),
+        "{:#?}",
+        instrs
+    );
+}
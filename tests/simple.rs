@@ -1,37 +1,164 @@
-use std::{fs, path::Path, rc::Rc};
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    rc::Rc,
+};
 
+use bytemuck::bytes_of;
 use istind::{
-    Compiler,
-    synthesize::exe::{DummyExecutable, Executable, mac::AppleExecutable},
+    CompileError, Compiler,
+    files::STDIN_NAME,
+    ir::optimize::Optimizations,
+    synthesize::{
+        arch::{AssembleOptions, RegisterAllocator, arm::ArmAssembler},
+        exe::{
+            DummyExecutable, Executable, ExecutableError,
+            mac::{
+                AppleExecutable,
+                archive::build_archive,
+                mach_o::{Header, ParsedCommand, parse_load_commands},
+                validate::{segments, symbols, uuids, validate_segments},
+            },
+        },
+        target::{Arch, Os, Target},
+    },
 };
 
-fn mod_main() -> Rc<String> {
-    Rc::new(String::from("main"))
+fn mod_main() -> Rc<PathBuf> {
+    Rc::new(PathBuf::from("main"))
 }
 
 fn compiles(source: &str) {
-    let compiler: Compiler<DummyExecutable> = Compiler::default();
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
     assert!(compiler.compile_source(mod_main(), source).is_ok());
 }
 
 fn fails(source: &str) {
-    let compiler: Compiler<DummyExecutable> = Compiler::default();
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
     assert!(compiler.compile_source(mod_main(), source).is_err());
 }
 
-fn runs(test_name: &str, expect_exit_code: i32, source: &str) {
-    let compiler: Compiler<DummyExecutable> = Compiler::default();
+fn build(test_name: &str, source: &str) -> AppleExecutable {
+    build_with_options(test_name, source, AssembleOptions::default())
+}
+
+fn build_with_options(test_name: &str, source: &str, options: AssembleOptions) -> AppleExecutable {
+    let compiler: Compiler<DummyExecutable, ArmAssembler> =
+        Compiler::default().with_assemble_options(options);
     let code = compiler.compile_source(mod_main(), source).unwrap();
 
     fs::create_dir_all("ctarget/test").unwrap();
 
     let mut exe = AppleExecutable::default().with_binary_identifier("istind.test");
     exe.build(code, Path::new("ctarget/test").join(test_name));
-    let status = exe.run().unwrap();
+    exe
+}
+
+fn runs(test_name: &str, expect_exit_code: i32, source: &str) {
+    runs_with(test_name, &[], expect_exit_code, source);
+}
+
+/// Runs the program and checks its exit code, or only checks that it was built correctly if it
+/// cannot run on this machine.
+fn runs_with(test_name: &str, args: &[&str], expect_exit_code: i32, source: &str) {
+    runs_with_options(
+        test_name,
+        args,
+        AssembleOptions::default(),
+        expect_exit_code,
+        source,
+    );
+}
 
+fn runs_with_options(
+    test_name: &str,
+    args: &[&str],
+    options: AssembleOptions,
+    expect_exit_code: i32,
+    source: &str,
+) {
+    let exe = build_with_options(test_name, source, options);
+    if !exe.is_runnable_here() {
+        assert_valid(test_name);
+        return;
+    }
+
+    let status = exe.run_with(args).unwrap();
     assert_eq!(status.code(), Some(expect_exit_code));
 }
 
+fn assert_valid(test_name: &str) {
+    let bytes = fs::read(Path::new("ctarget/test").join(test_name)).unwrap();
+    assert_eq!(validate_segments(&bytes), Ok(()), "{}", test_name);
+}
+
+/// Hex-encodes the machine code generated for `source`, one instruction per line.
+fn machine_code_hex(source: &str) -> String {
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler.compile_source(mod_main(), source).unwrap();
+    let size = code.size();
+
+    code.finalize(size, 0)
+        .instructions
+        .chunks(4)
+        .map(|instr| {
+            let hex: String = instr.iter().map(|byte| format!("{:02x}", byte)).collect();
+            hex + "\n"
+        })
+        .collect()
+}
+
+/// Compares the machine code generated for `source` with the snapshot in `tests/golden`. Run
+/// with `UPDATE_GOLDEN=1` to write the snapshots instead, after an intended change to codegen.
+fn matches_golden(name: &str, source: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name)
+        .with_extension("hex");
+    let actual = machine_code_hex(source);
+
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing snapshot {}, run with UPDATE_GOLDEN=1",
+            path.display()
+        )
+    });
+    assert_eq!(actual, expected, "machine code for {} changed", name);
+}
+
+/// Asserts that the program is killed by a signal instead of exiting normally.
+fn traps(test_name: &str, source: &str) {
+    let exe = build(test_name, source);
+    if !exe.is_runnable_here() {
+        assert_valid(test_name);
+        return;
+    }
+
+    let status = exe.run().unwrap();
+    assert_eq!(status.code(), None);
+}
+
+/// Runs the program and checks what it writes to stdout.
+fn prints(test_name: &str, expect_stdout: &str, source: &str) {
+    let exe = build(test_name, source);
+    if !exe.is_runnable_here() {
+        assert_valid(test_name);
+        return;
+    }
+
+    let output = Command::new(Path::new("ctarget/test").join(test_name))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), expect_stdout);
+}
+
 #[test]
 fn minimal_implicit() {
     fails("fn main() {}");
@@ -77,6 +204,22 @@ fn assignment() {
     );
 }
 
+#[test]
+fn reassign_in_place() {
+    runs(
+        "reassign_in_place",
+        3,
+        "
+        fn main() -> i64 {
+            a := 1;
+            a = a + 1;
+            a = a + 1;
+            return a;
+        }
+        ",
+    );
+}
+
 #[test]
 fn addition() {
     runs(
@@ -91,3 +234,1292 @@ fn addition() {
         ",
     );
 }
+
+#[test]
+fn main_returns_character() {
+    runs(
+        "main_returns_character",
+        90,
+        "
+        fn main() -> char {
+            return 'Z';
+        }
+        ",
+    );
+}
+
+#[test]
+fn main_returns_escaped_character() {
+    runs(
+        "main_returns_escaped_character",
+        9,
+        "fn main() -> char { return '\\t'; }",
+    );
+    runs(
+        "main_returns_uppercase_character",
+        65,
+        "fn main() -> char { return 'A'; }",
+    );
+}
+
+#[test]
+fn character_cast_to_integer() {
+    runs(
+        "character_cast_to_integer",
+        91,
+        "
+        fn main() -> i64 {
+            c := 'Z';
+            return c as i64 + 1;
+        }
+        ",
+    );
+}
+
+#[test]
+fn trailing_expression_returns() {
+    runs("trailing_expression_returns", 7, "fn main() { 7 }");
+    runs(
+        "trailing_expression_after_statements",
+        42,
+        "
+        fn main() -> i64 {
+            x := 40;
+            x + 2
+        }
+        ",
+    );
+}
+
+#[test]
+fn switch_selects_case() {
+    runs(
+        "switch_selects_case",
+        218,
+        "
+        fn pick(x: i64) -> i64 {
+            switch x {
+                0 => { return 10; },
+                1 => { return 20; },
+                3 => { return 40; },
+                4 => { return 50; },
+                _ => { return 99; },
+            }
+            return 0;
+        }
+
+        fn main() -> i64 {
+            return pick(1) + pick(2) + pick(7);
+        }
+        ",
+    );
+}
+
+#[test]
+fn sparse_switch_selects_case() {
+    runs(
+        "sparse_switch_selects_case",
+        33,
+        "
+        fn main() -> i64 {
+            x := 100;
+            y := 0;
+            switch x {
+                1 => { y = 11; }
+                100 => { y = 33; }
+                _ => { y = 55; }
+            }
+            return y;
+        }
+        ",
+    );
+}
+
+#[test]
+fn switch_duplicate_case() {
+    fails("fn main() -> i64 { switch 1 { 1 => {}, 1 => {} } return 0; }");
+}
+
+#[test]
+fn trailing_expression_type_mismatch() {
+    fails("fn main() -> i64 { 'a' }");
+}
+
+#[test]
+fn return_type_mismatch() {
+    fails(
+        "
+        fn f() -> i64 {
+            return 'a';
+        }
+
+        fn main() -> i64 {
+            return f();
+        }
+        ",
+    );
+}
+
+#[test]
+fn assert_passes() {
+    runs(
+        "assert_passes",
+        0,
+        "
+        fn main() -> i64 {
+            assert(1 < 2);
+            return 0;
+        }
+        ",
+    );
+}
+
+#[test]
+fn prelude_abs() {
+    runs(
+        "prelude_abs",
+        5,
+        "
+        fn main() -> i64 {
+            x := 0 - 5;
+            return abs(x);
+        }
+        ",
+    );
+}
+
+#[test]
+fn prelude_min_max() {
+    runs(
+        "prelude_min_max",
+        7,
+        "
+        fn main() -> i64 {
+            a := max(3, 7);
+            b := min(a, 9);
+            return b;
+        }
+        ",
+    );
+}
+
+#[test]
+fn prelude_println() {
+    prints(
+        "prelude_println",
+        "-42\n0\n1234567890-9223372036854775808\n",
+        "
+        fn main() -> i64 {
+            println(-42);
+            println(0);
+            print(1234567890);
+            println(-9223372036854775807 - 1);
+            return 0;
+        }
+        ",
+    );
+    fails("fn main() -> i64 { println(true); return 0; }");
+}
+
+#[test]
+fn prelude_wrong_argument_count() {
+    fails("fn main() -> i64 { return max(1); }");
+}
+
+#[test]
+fn assert_traps() {
+    traps(
+        "assert_traps",
+        "
+        fn main() -> i64 {
+            assert(2 < 1);
+            return 0;
+        }
+        ",
+    );
+}
+
+#[test]
+fn main_returns_argc() {
+    runs_with(
+        "main_returns_argc",
+        &["a", "b"],
+        3,
+        "
+        fn main(argc: i64, argv: i64) -> i64 {
+            return argc;
+        }
+        ",
+    );
+}
+
+#[test]
+fn main_too_many_params() {
+    fails(
+        "
+        fn main(argc: i64, argv: i64, envp: i64) -> i64 {
+            return 0;
+        }
+        ",
+    );
+}
+
+#[test]
+fn main_param_type_mismatch() {
+    fails(
+        "
+        fn main(argc: bool) -> i64 {
+            return 0;
+        }
+        ",
+    );
+}
+
+#[test]
+fn call_libc_exit() {
+    runs(
+        "call_libc_exit",
+        3,
+        "
+        extern libc;
+
+        fn exit(status: i64);
+
+        fn main() -> i64 {
+            exit(3);
+            return 0;
+        }
+        ",
+    );
+}
+
+#[test]
+fn extern_without_libc_import() {
+    fails("fn exit(status: i64); fn main() -> i64 { exit(3); return 0; }");
+}
+
+#[test]
+fn signed_division_rounds_toward_zero() {
+    runs(
+        "signed_division_rounds_toward_zero",
+        0,
+        "
+        fn main() -> i64 {
+            a := 0 - 7;
+            b := 0 - 2;
+            assert(a / 2 == 0 - 3);
+            assert(7 / b == 0 - 3);
+            assert(a / b == 3);
+
+            r := a - a / 2 * 2;
+            assert(r == 0 - 1);
+            return 0;
+        }
+        ",
+    );
+}
+
+#[test]
+fn add_large_immediate() {
+    runs(
+        "add_large_immediate",
+        3,
+        "
+        fn main() -> i64 {
+            a := 3;
+            b := a + 8192;
+            c := b - 4096 - 4092;
+            return c + 0 - 4;
+        }
+        ",
+    );
+}
+
+#[test]
+fn sibling_blocks_redeclare() {
+    runs(
+        "sibling_blocks_redeclare",
+        5,
+        "
+        fn main() -> i64 {
+            x := 0;
+            if x == 0 {
+                a := 2;
+                x = x + a;
+            }
+            if x == 2 {
+                a := 3;
+                x = x + a;
+            }
+            return x;
+        }
+        ",
+    );
+}
+
+#[test]
+fn block_variable_out_of_scope() {
+    fails(
+        "
+        fn main() -> i64 {
+            if true {
+                a := 2;
+            }
+            return a;
+        }
+        ",
+    );
+}
+
+#[test]
+fn branch_on_zero() {
+    runs(
+        "branch_on_zero",
+        7,
+        "
+        fn main() -> i64 {
+            x := 3;
+            y := 0;
+            if x == 0 {
+                return 1;
+            }
+            if x != 0 {
+                y = 4;
+            }
+            while x != 0 {
+                x = x - 1;
+                y = y + 1;
+            }
+            return y;
+        }
+        ",
+    );
+}
+
+#[test]
+fn break_skips_code() {
+    runs(
+        "break_skips_code",
+        2,
+        "
+        fn main() -> i64 {
+            x := 1;
+            outer: {
+                x = 2;
+                inner: {
+                    if x == 2 {
+                        break outer;
+                    }
+                    x = 10;
+                }
+                x = 20;
+            }
+            return x;
+        }
+        ",
+    );
+}
+
+#[test]
+fn break_unknown_label() {
+    fails(
+        "
+        fn main() -> i64 {
+            block: {
+                break other;
+            }
+            return 0;
+        }
+        ",
+    );
+}
+
+#[test]
+fn ternary_picks_first() {
+    runs(
+        "ternary_picks_first",
+        10,
+        "
+        fn main() -> i64 {
+            return 1 ? 10 : 20;
+        }
+        ",
+    );
+}
+
+#[test]
+fn ternary_calls_one_arm() {
+    runs(
+        "ternary_calls_one_arm",
+        27,
+        "
+        extern libc;
+
+        fn exit(status: i64);
+
+        fn main() -> i64 {
+            x := 3;
+            a := x < 2 ? exit_with(1) : x + 4;
+            b := x == 3 ? twenty() : exit_with(2);
+            return a + b;
+        }
+
+        fn exit_with(code: i64) -> i64 {
+            exit(code);
+            return code;
+        }
+
+        fn twenty() -> i64 {
+            return 20;
+        }
+        ",
+    );
+}
+
+#[test]
+fn ternary_mismatched_arms() {
+    fails("fn main() -> i64 { return 1 ? 2 : 'c'; }");
+}
+
+#[test]
+fn golden_return_zero() {
+    matches_golden("return_zero", "fn main() -> i64 { return 0; }");
+}
+
+#[test]
+fn golden_return_one() {
+    matches_golden("return_one", "fn main() -> i64 { return 1; }");
+}
+
+#[test]
+fn golden_return_variable() {
+    matches_golden("return_variable", "fn main() -> i64 { a := 2; return a; }");
+}
+
+#[test]
+fn const_fn_folded() {
+    runs(
+        "const_fn_folded",
+        49,
+        "
+        const fn square(x: i64) -> i64 {
+            return x * x;
+        }
+
+        fn main() -> i64 {
+            return square(7);
+        }
+        ",
+    );
+}
+
+#[test]
+fn const_fn_calls_non_const() {
+    fails(
+        "
+        fn one() -> i64 { return 1; }
+        const fn two() -> i64 { return one() + 1; }
+        fn main() -> i64 { return two(); }
+        ",
+    );
+}
+
+#[test]
+fn position_dependent_executable() {
+    /// `MH_PIE` in the flags of the Mach-O header, which start at byte 24.
+    const MH_PIE: u32 = 0x200000;
+    let header_flags = |path: &Path| {
+        let bytes = fs::read(path).unwrap();
+        u32::from_le_bytes(bytes[24..28].try_into().unwrap())
+    };
+
+    let source = "fn main() -> i64 { return 4; }";
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler.compile_source(mod_main(), source).unwrap();
+
+    fs::create_dir_all("ctarget/test").unwrap();
+    let path = Path::new("ctarget/test").join("position_dependent_executable");
+
+    let mut exe = AppleExecutable::default()
+        .with_binary_identifier("istind.test")
+        .with_position_dependent(true);
+    exe.build(code, &path);
+
+    assert_eq!(header_flags(&path) & MH_PIE, 0);
+    assert_eq!(exe.run().unwrap().code(), Some(4));
+
+    let pie = build("position_independent_executable", source);
+    let pie_path = Path::new("ctarget/test").join("position_independent_executable");
+    assert_ne!(header_flags(&pie_path) & MH_PIE, 0);
+    assert_eq!(pie.run().unwrap().code(), Some(4));
+}
+
+#[test]
+fn executable_with_stack_size() {
+    const STACK_SIZE: u64 = 64 << 20;
+    const LC_MAIN: u32 = 0x80000028;
+
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler
+        .compile_source(mod_main(), "fn main() -> i64 { return 6; }")
+        .unwrap();
+
+    fs::create_dir_all("ctarget/test").unwrap();
+    let path = Path::new("ctarget/test").join("executable_with_stack_size");
+    let mut exe = AppleExecutable::default()
+        .with_binary_identifier("istind.test")
+        .with_stack_size(STACK_SIZE);
+    exe.build(code, &path);
+
+    let bytes = fs::read(&path).unwrap();
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+    // entry_point_command: cmd, cmdsize, entryoff, stacksize
+    let mut offset = 32;
+    let mut stack_size = None;
+    for _ in 0..u32_at(16) {
+        if u32_at(offset) == LC_MAIN {
+            stack_size = Some(u64_at(offset + 16));
+        }
+        offset += u32_at(offset + 4) as usize;
+    }
+    assert_eq!(stack_size, Some(STACK_SIZE));
+
+    if exe.is_runnable_here() {
+        assert_eq!(exe.run().unwrap().code(), Some(6));
+    } else {
+        assert_valid("executable_with_stack_size");
+    }
+}
+
+#[test]
+#[should_panic(expected = "stack size must be a multiple of the page size")]
+fn unaligned_stack_size() {
+    let _ = AppleExecutable::default().with_stack_size(1000);
+}
+
+#[test]
+fn load_commands_round_trip() {
+    build("load_commands_round_trip", "fn main() -> i64 { return 3; }");
+    let bytes = fs::read("ctarget/test/load_commands_round_trip").unwrap();
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+
+    let commands = parse_load_commands(&bytes).unwrap();
+    assert_eq!(commands.len() as u32, u32_at(16));
+
+    // every command encodes back to the bytes it was read from
+    let mut offset = size_of::<Header>();
+    for command in &commands {
+        let encoded = match command {
+            ParsedCommand::Segment(segment, sections) => {
+                let mut encoded = bytes_of(segment).to_vec();
+                encoded.extend(sections.iter().flat_map(bytes_of));
+                encoded
+            }
+            ParsedCommand::EntryPoint(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::DyLinker(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::DyLib(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::CodeSignature(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::SymTab(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::DySymTab(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::DyldInfo(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::BuildVersion(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::Uuid(cmd) => bytes_of(cmd).to_vec(),
+            ParsedCommand::Unknown { .. } => Vec::new(),
+        };
+        assert_eq!(
+            encoded,
+            bytes[offset..offset + encoded.len()],
+            "{:?}",
+            command
+        );
+
+        offset += command.command_size() as usize;
+    }
+    assert_eq!(offset - size_of::<Header>(), u32_at(20) as usize);
+
+    let segment_names: Vec<String> = commands
+        .iter()
+        .filter_map(|command| match command {
+            ParsedCommand::Segment(segment, _) => Some(
+                String::from_utf8_lossy(&segment.segment_name)
+                    .trim_end_matches('\0')
+                    .to_owned(),
+            ),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(segment_names, ["__PAGEZERO", "__TEXT", "__LINKEDIT"]);
+    assert!(
+        commands
+            .iter()
+            .any(|command| matches!(command, ParsedCommand::EntryPoint(_)))
+    );
+}
+
+#[test]
+fn default_target_uses_arm64_pages() {
+    fs::create_dir_all("ctarget/test").unwrap();
+    let source = Path::new("ctarget/test").join("default_target_uses_arm64_pages.is");
+    fs::write(&source, "fn main() -> i64 { return 6; }").unwrap();
+
+    // whatever the host is, arm64 code is only loaded from 16KB aligned segments
+    let compiler: Compiler<AppleExecutable, ArmAssembler> = Compiler::default();
+    assert_eq!(compiler.target().arch, Arch::Aarch64);
+    compiler
+        .compile(source, "ctarget/test/default_target_uses_arm64_pages")
+        .unwrap();
+
+    let bytes = fs::read("ctarget/test/default_target_uses_arm64_pages").unwrap();
+    let segments = segments(&bytes).unwrap();
+    for segment in &segments {
+        assert_eq!(segment.file.start % 0x4000, 0, "{}", segment.name);
+    }
+    let text = segments.iter().find(|segment| segment.name == "__TEXT");
+    assert_eq!(text.unwrap().vmsize % 0x4000, 0);
+    assert_valid("default_target_uses_arm64_pages");
+}
+
+#[test]
+fn cli_builds_without_target() {
+    fs::create_dir_all("ctarget/test").unwrap();
+    let source = Path::new("ctarget/test").join("cli_builds_without_target.is");
+    fs::write(&source, "fn main() { return 3; }").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_istind"))
+        .args(["--color", "never", "build"])
+        .arg(&source)
+        .arg("-o")
+        .arg(Path::new("ctarget/test").join("cli_builds_without_target"))
+        .output()
+        .unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(!stderr.contains("error:"), "{}", stderr);
+
+    assert_valid("cli_builds_without_target");
+}
+
+#[test]
+fn symbol_table_lists_functions() {
+    const N_SECT: u8 = 0xe;
+
+    let source = "
+        fn add(a: i64, b: i64) -> i64 { return a + b; }
+        fn main() -> i64 { return add(1, 2); }
+    ";
+    build("symbol_table_lists_functions", source);
+    let bytes = fs::read("ctarget/test/symbol_table_lists_functions").unwrap();
+
+    let text = parse_load_commands(&bytes)
+        .unwrap()
+        .into_iter()
+        .find_map(|command| match command {
+            ParsedCommand::Segment(_, sections) => sections
+                .into_iter()
+                .find(|section| section.section_name.starts_with(b"__text\0")),
+            _ => None,
+        })
+        .unwrap();
+
+    // the offsets of the functions in the code don't depend on where it is placed
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler.compile_source(mod_main(), source).unwrap();
+    let size = code.size();
+    let code = code.finalize(size, 0);
+    let offset = |name: &str| {
+        code.symbols
+            .iter()
+            .find(|(symbol, _)| symbol == name)
+            .unwrap()
+            .1
+    };
+
+    let symbols = symbols(&bytes).unwrap();
+    for name in ["main", "add"] {
+        let symbol = symbols
+            .iter()
+            .find(|symbol| symbol.name == name)
+            .unwrap_or_else(|| panic!("no symbol {} in {:?}", name, symbols));
+        assert_eq!(symbol.n_type, N_SECT);
+        assert_eq!(symbol.n_sect, 1);
+        assert_eq!(symbol.value, text.addr + offset(name));
+    }
+}
+
+#[test]
+fn executable_uuid_is_reproducible() {
+    let build_uuid = |test_name: &str, source: &str| {
+        build(test_name, source);
+        assert_valid(test_name);
+
+        let bytes = fs::read(Path::new("ctarget/test").join(test_name)).unwrap();
+        let uuids = uuids(&bytes).unwrap();
+        assert_eq!(uuids.len(), 1, "{}", test_name);
+        uuids[0]
+    };
+
+    let first = build_uuid("uuid_first", "fn main() -> i64 { return 1; }");
+    let second = build_uuid("uuid_second", "fn main() -> i64 { return 1; }");
+    let other = build_uuid("uuid_other", "fn main() -> i64 { return 2; }");
+
+    assert_eq!(first, second);
+    assert_ne!(first, other);
+}
+
+#[test]
+fn executable_for_explicit_target() {
+    const CPU_TYPE_ARM64: u32 = 0x0100000c;
+
+    fs::create_dir_all("ctarget/test").unwrap();
+    let source = Path::new("ctarget/test").join("executable_for_explicit_target.is");
+    fs::write(&source, "fn main() -> i64 { return 6; }").unwrap();
+
+    let compiler: Compiler<AppleExecutable, ArmAssembler> =
+        Compiler::default().with_target(Target::new(Arch::Aarch64, Os::MacOs));
+    compiler
+        .compile(source, "ctarget/test/executable_for_explicit_target")
+        .unwrap();
+
+    let bytes = fs::read("ctarget/test/executable_for_explicit_target").unwrap();
+    assert_eq!(
+        u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+        CPU_TYPE_ARM64
+    );
+    assert_valid("executable_for_explicit_target");
+}
+
+#[test]
+fn missing_source_file_is_reported() {
+    let compiler: Compiler<AppleExecutable, ArmAssembler> = Compiler::default();
+    let result = compiler.compile("ctarget/test/missing.is", "ctarget/test/missing");
+
+    assert!(
+        matches!(result, Err(CompileError::Read(ref path, _)) if path.ends_with("missing.is")),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn compile_source_without_a_file() {
+    fs::create_dir_all("ctarget/test").unwrap();
+
+    let compiler: Compiler<AppleExecutable, ArmAssembler> = Compiler::default();
+    compiler
+        .compile_str(
+            STDIN_NAME,
+            "fn main() -> i64 { return 7; }",
+            "ctarget/test/compile_source_without_a_file",
+        )
+        .unwrap();
+    assert_valid("compile_source_without_a_file");
+
+    // the errors are shown with the source given, since there is no file to read it from
+    let compiler: Compiler<AppleExecutable, ArmAssembler> = Compiler::default();
+    let result = compiler.compile_str(
+        STDIN_NAME,
+        "fn main() -> i64 { return x; }",
+        "ctarget/test/compile_source_without_a_file_fails",
+    );
+    assert!(
+        matches!(result, Err(CompileError::Compile(_))),
+        "{:?}",
+        result
+    );
+}
+
+#[test]
+fn compile_and_run_returns_exit_code() {
+    let compiler: Compiler<AppleExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler.compile_and_run("fn main() -> i64 { return 5; }");
+    assert_eq!(code.unwrap(), 5);
+}
+
+#[test]
+fn inlined_functions_run() {
+    let optimizations = Optimizations {
+        inline_functions: true,
+        ..Default::default()
+    };
+    let compiler: Compiler<AppleExecutable, ArmAssembler> =
+        Compiler::default().with_optimizations(optimizations);
+
+    let code = compiler.compile_and_run(
+        "
+        fn add(a: i64, b: i64) -> i64 { return a + b; }
+
+        @inline
+        fn clamp(x: i64) -> i64 {
+            if x > 10 {
+                return 10;
+            }
+            return x;
+        }
+
+        @noinline
+        fn one() -> i64 { return 1; }
+
+        fn main() -> i64 {
+            x := add(1, 2);
+            return x + clamp(x + 20) + clamp(x) + one();
+        }
+        ",
+    );
+    assert_eq!(code.unwrap(), 17);
+}
+
+#[test]
+fn segments_cover_whole_file() {
+    let sources = [
+        (
+            "segments_cover_whole_file",
+            "fn main() -> i64 { return 0; }",
+        ),
+        (
+            "segments_cover_whole_file_externs",
+            "extern libc; fn exit(status: i64); fn main() -> i64 { exit(3); return 0; }",
+        ),
+    ];
+
+    for (name, source) in sources {
+        build(name, source);
+        assert_valid(name);
+    }
+}
+
+#[test]
+fn static_library_archive() {
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler
+        .compile_source(
+            mod_main(),
+            "
+            fn add(a: i64, b: i64) -> i64 { return a + b; }
+            fn twice(x: i64) -> i64 { return add(x, x); }
+            fn main() -> i64 { return twice(2); }
+            ",
+        )
+        .unwrap();
+
+    fs::create_dir_all("ctarget/test").unwrap();
+    let path = Path::new("ctarget/test").join("libistind.a");
+    build_archive(code, &path).unwrap();
+
+    let output = |command: &[&str]| {
+        let output = Command::new(command[0])
+            .args(&command[1..])
+            .arg(&path)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?} failed", command);
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    let members = output(&["ar", "t"]);
+    assert_eq!(members.lines().collect::<Vec<_>>(), ["libistind.o"]);
+
+    let symbols = output(&["nm"]);
+    for symbol in ["T _add", "T _twice"] {
+        assert!(
+            symbols.contains(symbol),
+            "{} missing from\n{}",
+            symbol,
+            symbols
+        );
+    }
+    assert!(!symbols.contains("T _main"));
+}
+
+#[test]
+fn string_length() {
+    runs(
+        "string_length",
+        5,
+        "fn main() -> i64 { s := \"hello\"; return strlen(s); }",
+    );
+    runs(
+        "empty_string_length",
+        0,
+        "fn main() -> i64 { return strlen(\"\"); }",
+    );
+}
+
+#[test]
+fn archive_rejects_string_literals() {
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler
+        .compile_source(
+            mod_main(),
+            "
+            fn main() -> i64 {
+                s := \"hi\";
+                return 0;
+            }
+            ",
+        )
+        .unwrap();
+
+    let path = Path::new("ctarget/test").join("libstrings.a");
+    assert!(build_archive(code, path).is_err());
+}
+
+#[test]
+fn segments_aligned_to_page_size() {
+    const PAGE_SIZE: u64 = 0x1000;
+    const LC_SEGMENT_64: u32 = 0x19;
+
+    let compiler: Compiler<DummyExecutable, ArmAssembler> = Compiler::default();
+    let code = compiler
+        .compile_source(mod_main(), "fn main() -> i64 { return 0; }")
+        .unwrap();
+
+    fs::create_dir_all("ctarget/test").unwrap();
+    let path = Path::new("ctarget/test").join("segments_aligned_to_page_size");
+    AppleExecutable::default()
+        .with_page_size(PAGE_SIZE)
+        .build(code, &path);
+
+    let bytes = fs::read(&path).unwrap();
+    let u32_at = |i: usize| u32::from_le_bytes(bytes[i..i + 4].try_into().unwrap());
+    let u64_at = |i: usize| u64::from_le_bytes(bytes[i..i + 8].try_into().unwrap());
+
+    // segment_command_64: cmd, cmdsize, segname[16], vmaddr, vmsize, fileoff, filesize, ...
+    let mut offset = 32;
+    let mut segments = Vec::new();
+    for _ in 0..u32_at(16) {
+        if u32_at(offset) == LC_SEGMENT_64 {
+            let name = String::from_utf8_lossy(&bytes[offset + 8..offset + 24]);
+            let name = name.trim_end_matches('\0').to_owned();
+            let (vmaddr, vmsize, file_offset) = (
+                u64_at(offset + 24),
+                u64_at(offset + 32),
+                u64_at(offset + 40),
+            );
+            segments.push((name, vmaddr, vmsize, file_offset));
+        }
+        offset += u32_at(offset + 4) as usize;
+    }
+
+    // a program this small fits in a single small page
+    let text = segments.iter().find(|(name, ..)| name == "__TEXT").unwrap();
+    assert_eq!(text.2, PAGE_SIZE);
+
+    for (name, vmaddr, _, file_offset) in segments {
+        assert_eq!(
+            vmaddr % PAGE_SIZE,
+            0,
+            "{} is not page aligned in memory",
+            name
+        );
+        assert_eq!(
+            file_offset % PAGE_SIZE,
+            0,
+            "{} is not page aligned in the file",
+            name
+        );
+    }
+}
+
+#[test]
+fn large_generated_source() {
+    let mut source: String = (0..500)
+        .map(|i| format!("fn f{i}(x: i64) -> i64 {{\n    // ünïcode\n    return x + {i};\n}}\n"))
+        .collect();
+    source.push_str("fn main() -> i64 {\n    total := 0;\n");
+    for i in 0..500 {
+        source.push_str(&format!("    total = total + f{i}(1);\n"));
+    }
+    source.push_str("    return total;\n}\n");
+
+    compiles(&source);
+}
+
+#[test]
+fn expression_bodied_function() {
+    runs(
+        "expression_bodied_function",
+        42,
+        "
+        fn answer() -> i64 => 42;
+        fn main() -> i64 { return answer(); }
+        ",
+    );
+}
+
+#[test]
+fn run_before_build() {
+    let exe = AppleExecutable::default();
+
+    assert!(matches!(exe.run(), Err(ExecutableError::NoBuildPath)));
+}
+
+#[test]
+fn runnable_here() {
+    assert!(!DummyExecutable.is_runnable_here());
+    assert_eq!(
+        AppleExecutable::default().is_runnable_here(),
+        cfg!(all(target_os = "macos", target_arch = "aarch64"))
+    );
+}
+
+#[test]
+fn float_arithmetic() {
+    runs(
+        "float_arithmetic",
+        4,
+        "fn main() -> i64 { return (1.5 + 2.5) as i64; }",
+    );
+    runs(
+        "float_arithmetic_ops",
+        7,
+        "
+        fn half(x: f64) -> f64 { return x / 2.0; }
+        fn main() -> i64 {
+            x := 7.5 * 2.0 - 1.0;
+            return half(x) as i64;
+        }
+        ",
+    );
+}
+
+#[test]
+fn if_else() {
+    runs(
+        "if_else",
+        12,
+        "
+        fn sign(x: i64) -> i64 {
+            if x < 0 { return 0 - 1; } else if x == 0 { return 0; } else { return 1; }
+        }
+        fn main() -> i64 {
+            a := 0;
+            if sign(3) == 1 { a = 10; } else { a = 20; }
+            if sign(0) != 0 { a = a + 100; } else { b := 2; a = a + b; }
+            return a + sign(0 - 4) + sign(9);
+        }
+        ",
+    );
+}
+
+#[test]
+fn record_return() {
+    runs(
+        "record_return",
+        74,
+        "
+        fn divmod(a: i64, b: i64) -> (i64, i64) {
+            return (a / b, a - a / b * b);
+        }
+        fn main() -> i64 {
+            p := divmod(47, 10);
+            q := (p.1, p.0);
+            return q.0 * 10 + q.1;
+        }
+        ",
+    );
+}
+
+#[test]
+fn named_record_fields() {
+    runs(
+        "named_record_fields",
+        34,
+        "
+        fn point() -> (x: i64, y: i64) {
+            return (3, 4);
+        }
+        fn main() -> i64 {
+            p := point();
+            return p.x * 10 + point().y;
+        }
+        ",
+    );
+}
+
+#[test]
+fn wide_constants() {
+    runs(
+        "wide_constants",
+        42,
+        "fn main() -> i64 { a := 70000; b := 4294967297; return a - 69960 + b - 4294967295; }",
+    );
+    runs("negative_constant", 254, "fn main() -> i64 { return -2; }");
+}
+
+#[test]
+fn remainder() {
+    runs("remainder", 2, "fn main() -> i64 { return 17 % 5; }");
+    runs(
+        "negative_remainder",
+        254,
+        "fn main() -> i64 { a := -17; return a % 5; }",
+    );
+}
+
+#[test]
+fn negative_intermediates() {
+    runs(
+        "negative_intermediates",
+        5,
+        "fn main() -> i64 { a := 10; b := 0 - a; return b + 15; }",
+    );
+    runs(
+        "negative_wide_constant",
+        5,
+        "fn main() -> i64 { x := -70000; return x + 70005; }",
+    );
+}
+
+#[test]
+fn negation() {
+    runs(
+        "negation",
+        5,
+        "
+        fn neg(x: i64) -> i64 { return -x; }
+        fn main() -> i64 { a := 2; return 1 - -a + neg(- -2) + -(-4); }
+        ",
+    );
+    fails("fn main() -> i64 { a := true; return -a; }");
+}
+
+#[test]
+fn allocators_agree_with_calls_and_spills() {
+    // more values are live across the last call than there are registers to keep them in
+    let source = "
+        fn add(a: i64, b: i64) -> i64 { return a + b; }
+        fn main() -> i64 {
+            a := add(1, 0);
+            b := add(2, 0);
+            c := add(3, 0);
+            d := add(4, 0);
+            e := add(5, 0);
+            f := add(6, 0);
+            g := add(7, 0);
+            h := add(8, 0);
+            i := add(9, 0);
+            j := add(10, 0);
+            k := add(11, 0);
+            l := add(12, 0);
+            m := add(13, 0);
+            n := add(14, 0);
+            o := add(15, 0);
+            p := add(16, 0);
+            q := add(17, 0);
+            r := add(18, 0);
+            s := add(19, 0);
+            t := add(20, 0);
+            u := add(a, t);
+            return a + b + c + d + e + f + g + h + i + j + k + l + m + n + o + p + q + r + s + t - u;
+        }
+        ";
+
+    for (name, allocator) in [
+        ("allocators_agree_linear", RegisterAllocator::Linear),
+        ("allocators_agree_graph", RegisterAllocator::Graph),
+    ] {
+        let options = AssembleOptions {
+            allocator,
+            ..Default::default()
+        };
+        runs_with_options(name, &[], options, 189, source);
+    }
+}
+
+#[test]
+fn overflow_checks_trap_at_runtime() {
+    // the operands are arguments, so the addition is not folded at compile time
+    let source = "
+        fn add(a: i64, b: i64) -> i64 { return a + b; }
+        fn main() -> i64 {
+            if add(9223372036854775807, 1) < 0 {
+                return 1;
+            }
+            return 2;
+        }
+        ";
+    let options = AssembleOptions {
+        overflow_checks: true,
+        ..Default::default()
+    };
+    let checked = build_with_options("overflow_checks_trap", source, options);
+    let wrapping = build("overflow_wraps", source);
+    if !checked.is_runnable_here() {
+        assert_valid("overflow_checks_trap");
+        assert_valid("overflow_wraps");
+        return;
+    }
+
+    // killed by the breakpoint, so there is no exit code
+    assert_eq!(checked.run_with(&[]).unwrap().code(), None);
+    assert_eq!(wrapping.run_with(&[]).unwrap().code(), Some(1));
+}
+
+#[test]
+fn overflow_checks_trap_on_constants() {
+    let options = AssembleOptions {
+        overflow_checks: true,
+        ..Default::default()
+    };
+    let exe = build_with_options(
+        "overflow_checks_trap_on_constants",
+        "fn main() -> i64 { a := 9223372036854775807; return a + 1; }",
+        options,
+    );
+    if !exe.is_runnable_here() {
+        assert_valid("overflow_checks_trap_on_constants");
+        return;
+    }
+
+    // killed by the breakpoint, so there is no exit code
+    assert_eq!(exe.run_with(&[]).unwrap().code(), None);
+}
+
+#[test]
+fn common_subexpressions_run() {
+    let optimizations = Optimizations {
+        eliminate_common_subexpressions: true,
+        ..Default::default()
+    };
+    let compiler: Compiler<AppleExecutable, ArmAssembler> =
+        Compiler::default().with_optimizations(optimizations);
+
+    let code = compiler.compile_and_run(
+        "
+        fn f(a: i64, b: i64) -> i64 {
+            x := (a + b) * (a + b);
+            a = a + 1;
+            return x - (a + b) * 2;
+        }
+        fn main() -> i64 { return f(2, 3); }
+        ",
+    );
+    assert_eq!(code.unwrap(), 13);
+}
+
+#[test]
+fn comparison_result() {
+    runs(
+        "comparison_result",
+        1,
+        "fn main() -> i64 { return (1 < 2) as i64; }",
+    );
+    runs(
+        "comparison_result_sum",
+        2,
+        "fn main() -> i64 { a := 3; return (a >= 3) as i64 + (a != 4) as i64 + (a > 3) as i64; }",
+    );
+}
+
+#[test]
+fn second_parameter() {
+    runs(
+        "second_parameter",
+        7,
+        "
+        fn second(a: i64, b: i64) -> i64 { return b; }
+        fn main() -> i64 { return second(3, 7); }
+        ",
+    );
+}